@@ -0,0 +1,147 @@
+use crate::config::AccessConfig;
+
+/// Separates the identity of a worker from its secret token in `user_identity`, as in
+/// `address.worker:token`.
+pub const TOKEN_SEPARATOR: char = ':';
+
+/// Why a `user_identity` may not open channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessDenied {
+    /// The identity is neither in the allowlist nor has a token.
+    UnknownUser,
+    /// The identity has a token, which is missing or wrong.
+    InvalidToken,
+}
+
+impl AccessDenied {
+    /// Error code of the `OpenMiningChannelError` answering the rejected channel.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            AccessDenied::UnknownUser => "unknown-user",
+            AccessDenied::InvalidToken => "invalid-access-token", //note: non-standard error code
+        }
+    }
+}
+
+/// Splits `user_identity` into the identity and the token after the last [`TOKEN_SEPARATOR`].
+pub fn split_token(user_identity: &str) -> (&str, Option<&str>) {
+    match user_identity.rsplit_once(TOKEN_SEPARATOR) {
+        Some((identity, token)) => (identity, Some(token)),
+        None => (user_identity, None),
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters and `?` any single
+/// one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` in the pattern, and of the text it matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // let the last `*` match one more character
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Checks `user_identity` against `config`, returning the identity channels are opened with,
+/// stripped of its token so it never shows up in logs, the dashboard or the block journal.
+///
+/// Identities with a token must present it, all others must match an allowed identity.
+pub fn authorize(config: &AccessConfig, user_identity: &str) -> Result<String, AccessDenied> {
+    let (identity, token) = split_token(user_identity);
+    if let Some(expected_token) = config.tokens.get(identity) {
+        return match token {
+            Some(token) if constant_time_eq(token.as_bytes(), expected_token.as_bytes()) => {
+                Ok(identity.to_string())
+            }
+            _ => Err(AccessDenied::InvalidToken),
+        };
+    }
+    if config
+        .allowed_identities
+        .iter()
+        .any(|pattern| glob_match(pattern, identity))
+    {
+        return Ok(identity.to_string());
+    }
+    Err(AccessDenied::UnknownUser)
+}
+
+/// Compares two secrets without leaking how much of them matched through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("alice", "alice"));
+        assert!(!glob_match("alice", "alice.bitaxe"));
+        assert!(glob_match("alice.*", "alice.bitaxe"));
+        assert!(glob_match("alice.*", "alice."));
+        assert!(!glob_match("alice.*", "bob.bitaxe"));
+        assert!(glob_match("*.bitaxe", "bob.bitaxe"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("rig-??", "rig-01"));
+        assert!(!glob_match("rig-??", "rig-1"));
+        assert!(glob_match("a*b*c", "axxbyybc"));
+        assert!(!glob_match("a*b*c", "axxbyyb"));
+    }
+
+    #[test]
+    fn test_authorize() {
+        let config = AccessConfig {
+            allowed_identities: vec!["alice.*".to_string()],
+            tokens: HashMap::from([("bob.bitaxe".to_string(), "hunter2".to_string())]),
+        };
+        assert_eq!(
+            authorize(&config, "alice.bitaxe"),
+            Ok("alice.bitaxe".to_string())
+        );
+        assert_eq!(
+            authorize(&config, "mallory"),
+            Err(AccessDenied::UnknownUser)
+        );
+        assert_eq!(
+            authorize(&config, "bob.bitaxe:hunter2"),
+            Ok("bob.bitaxe".to_string())
+        );
+        assert_eq!(
+            authorize(&config, "bob.bitaxe:hunter3"),
+            Err(AccessDenied::InvalidToken)
+        );
+        assert_eq!(
+            authorize(&config, "bob.bitaxe"),
+            Err(AccessDenied::InvalidToken)
+        );
+        // tokens of other identities don't open the allowlist
+        assert_eq!(
+            authorize(&config, "bob.nerdminer:hunter2"),
+            Err(AccessDenied::UnknownUser)
+        );
+    }
+}
@@ -0,0 +1,456 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::BanConfig;
+
+/// Misbehavior clients are scored for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offense {
+    InvalidShare,
+    StaleShare,
+    InvalidJobId,
+    DifficultyTooLow,
+    DuplicateShare,
+    UnknownChannel,
+    ProtocolError,
+}
+
+impl Offense {
+    /// Offense behind the error code of a `SubmitSharesError`, one per `ShareValidationError`
+    /// variant along with messages on unknown channels, which `UpdateChannelError` and
+    /// `SetCustomMiningJobError` report too. Custom jobs without a mining job token are protocol
+    /// errors, as work selection is never negotiated.
+    pub fn from_error_code(error_code: &str) -> Option<Self> {
+        match error_code {
+            "invalid-share" => Some(Offense::InvalidShare),
+            "stale-share" => Some(Offense::StaleShare),
+            "invalid-job-id" => Some(Offense::InvalidJobId),
+            "difficulty-too-low" => Some(Offense::DifficultyTooLow),
+            "duplicate-share" => Some(Offense::DuplicateShare),
+            "invalid-channel-id" => Some(Offense::UnknownChannel),
+            "invalid-mining-job-token" => Some(Offense::ProtocolError),
+            _ => None,
+        }
+    }
+
+    fn score(&self, config: &BanConfig) -> f64 {
+        let scores = &config.scores;
+        match self {
+            Offense::InvalidShare => scores.invalid_share,
+            Offense::StaleShare => scores.stale_share,
+            Offense::InvalidJobId => scores.invalid_job_id,
+            Offense::DifficultyTooLow => scores.difficulty_too_low,
+            Offense::DuplicateShare => scores.duplicate_share,
+            Offense::UnknownChannel => scores.unknown_channel,
+            Offense::ProtocolError => scores.protocol_error,
+        }
+    }
+}
+
+impl fmt::Display for Offense {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Offense::InvalidShare => "invalid shares",
+            Offense::StaleShare => "stale shares",
+            Offense::InvalidJobId => "shares on unknown jobs",
+            Offense::DifficultyTooLow => "shares below target",
+            Offense::DuplicateShare => "duplicate shares",
+            Offense::UnknownChannel => "messages on unknown channels",
+            Offense::ProtocolError => "protocol errors",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Client, worker or address a score and a ban apply to.
+///
+/// Workers are the `user_identity` of channels authenticated by their access token, stripped of
+/// it, so their bans hold across reconnections. Other identities can be claimed by anyone, so
+/// only the client and the address using them are scored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BanTarget {
+    Client(u32),
+    Worker(String),
+    Ip(IpAddr),
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanTarget::Client(client_id) => write!(f, "client {}", client_id),
+            BanTarget::Worker(worker) => write!(f, "worker {}", worker),
+            BanTarget::Ip(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Ban {
+    pub target: BanTarget,
+    pub reason: String,
+    pub banned_at: u64,  // unix time
+    pub expires_at: u64, // unix time
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Score {
+    points: f64,
+    updated: SystemTime,
+}
+
+/// Scores of clients, workers and addresses, and the ones currently banned.
+///
+/// Offenses of a client are scored against the client, its address, and the worker of the
+/// channel they happened on, or every worker of the client when they didn't happen on a known
+/// channel. Scores and bans of workers and addresses outlive the connections of the client,
+/// until they decay or expire.
+#[derive(Debug, Clone)]
+pub struct BanList {
+    config: BanConfig,
+    addresses: HashMap<u32, IpAddr>,      // by client id
+    workers: HashMap<(u32, u32), String>, // by client and channel id
+    scores: HashMap<BanTarget, Score>,
+    bans: HashMap<BanTarget, Ban>,
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl BanList {
+    pub fn new(config: BanConfig) -> Self {
+        Self {
+            config,
+            addresses: HashMap::new(),
+            workers: HashMap::new(),
+            scores: HashMap::new(),
+            bans: HashMap::new(),
+        }
+    }
+
+    pub fn add_client_address(&mut self, client_id: u32, address: IpAddr) {
+        self.addresses.insert(client_id, address);
+    }
+
+    /// Adds a channel opened by `worker`, the `user_identity` of the channel without its token,
+    /// which must have been authenticated by it.
+    pub fn add_channel(&mut self, client_id: u32, channel_id: u32, worker: String) {
+        self.workers.insert((client_id, channel_id), worker);
+    }
+
+    pub fn remove_channel(&mut self, client_id: u32, channel_id: u32) {
+        self.workers.remove(&(client_id, channel_id));
+    }
+
+    /// Forgets a disconnected client, along with its own score and ban. The scores and bans of
+    /// its workers and its address outlive it.
+    pub fn remove_client(&mut self, client_id: u32) {
+        self.addresses.remove(&client_id);
+        self.scores.remove(&BanTarget::Client(client_id));
+        self.bans.remove(&BanTarget::Client(client_id));
+        self.workers
+            .retain(|(channel_client_id, _), _| *channel_client_id != client_id);
+    }
+
+    fn is_banned(&self, target: &BanTarget, now: SystemTime) -> bool {
+        self.bans
+            .get(target)
+            .is_some_and(|ban| ban.expires_at > unix_time(now))
+    }
+
+    /// Whether `client_id`, its address, or the worker of any of its channels, is banned.
+    pub fn is_client_banned(&self, client_id: u32, now: SystemTime) -> bool {
+        self.is_banned(&BanTarget::Client(client_id), now)
+            || self
+                .addresses
+                .get(&client_id)
+                .is_some_and(|address| self.is_ip_banned(*address, now))
+            || self
+                .workers
+                .iter()
+                .filter(|((channel_client_id, _), _)| *channel_client_id == client_id)
+                .any(|(_, worker)| self.is_worker_banned(worker, now))
+    }
+
+    /// Whether `client_id`, its address, or the worker of `channel_id`, is banned.
+    pub fn is_channel_banned(&self, client_id: u32, channel_id: u32, now: SystemTime) -> bool {
+        self.is_banned(&BanTarget::Client(client_id), now)
+            || self
+                .addresses
+                .get(&client_id)
+                .is_some_and(|address| self.is_ip_banned(*address, now))
+            || self
+                .workers
+                .get(&(client_id, channel_id))
+                .is_some_and(|worker| self.is_worker_banned(worker, now))
+    }
+
+    pub fn is_worker_banned(&self, worker: &str, now: SystemTime) -> bool {
+        self.is_banned(&BanTarget::Worker(worker.to_string()), now)
+    }
+
+    pub fn is_ip_banned(&self, address: IpAddr, now: SystemTime) -> bool {
+        self.is_banned(&BanTarget::Ip(address), now)
+    }
+
+    /// Scores `offense` of `client_id` on `channel_id`, if it happened on a channel, against the
+    /// client, its workers and its address. Returns the bans it caused.
+    pub fn record(
+        &mut self,
+        client_id: u32,
+        channel_id: Option<u32>,
+        offense: Offense,
+        now: SystemTime,
+    ) -> Vec<Ban> {
+        let channel_worker =
+            channel_id.and_then(|channel_id| self.workers.get(&(client_id, channel_id)));
+        let mut workers: Vec<&String> = match channel_worker {
+            Some(worker) => vec![worker],
+            None => self
+                .workers
+                .iter()
+                .filter(|((channel_client_id, _), _)| *channel_client_id == client_id)
+                .map(|(_, worker)| worker)
+                .collect(),
+        };
+        workers.sort();
+        workers.dedup();
+        let mut targets: Vec<(BanTarget, f64, u64)> = vec![(
+            BanTarget::Client(client_id),
+            self.config.client_ban_score,
+            self.config.client_ban_duration,
+        )];
+        targets.extend(workers.into_iter().map(|worker| {
+            (
+                BanTarget::Worker(worker.clone()),
+                self.config.client_ban_score,
+                self.config.client_ban_duration,
+            )
+        }));
+        if let Some(address) = self.addresses.get(&client_id) {
+            targets.push((
+                BanTarget::Ip(*address),
+                self.config.ip_ban_score,
+                self.config.ip_ban_duration,
+            ));
+        }
+
+        let points = offense.score(&self.config);
+        let half_life = self.config.score_half_life as f64;
+        let mut bans = Vec::new();
+        for (target, ban_score, ban_duration) in targets {
+            if self.is_banned(&target, now) {
+                continue;
+            }
+            let score = self.scores.entry(target.clone()).or_insert(Score {
+                points: 0.0,
+                updated: now,
+            });
+            let elapsed = now
+                .duration_since(score.updated)
+                .unwrap_or_default()
+                .as_secs_f64();
+            score.points = score.points * 0.5f64.powf(elapsed / half_life) + points;
+            score.updated = now;
+            if score.points < ban_score {
+                continue;
+            }
+
+            self.scores.remove(&target);
+            let ban = Ban {
+                target: target.clone(),
+                reason: format!("{}", offense),
+                banned_at: unix_time(now),
+                expires_at: unix_time(now + Duration::from_secs(ban_duration)),
+            };
+            self.bans.insert(target, ban.clone());
+            bans.push(ban);
+        }
+        bans
+    }
+
+    /// Connected clients a ban disconnects.
+    pub fn banned_clients(&self, ban: &Ban) -> Vec<u32> {
+        let mut client_ids: Vec<u32> = match &ban.target {
+            BanTarget::Client(client_id) => vec![*client_id],
+            BanTarget::Worker(banned_worker) => self
+                .workers
+                .iter()
+                .filter(|(_, worker)| *worker == banned_worker)
+                .map(|((client_id, _), _)| *client_id)
+                .collect(),
+            BanTarget::Ip(ip) => self
+                .addresses
+                .iter()
+                .filter(|(_, address)| *address == ip)
+                .map(|(client_id, _)| *client_id)
+                .collect(),
+        };
+        client_ids.sort();
+        client_ids.dedup();
+        client_ids
+    }
+
+    /// Bans that didn't expire yet, soonest to expire first. Expired bans are dropped, along
+    /// with scores that decayed for ten half lives.
+    pub fn active_bans(&mut self, now: SystemTime) -> Vec<Ban> {
+        let forgotten_after = Duration::from_secs(self.config.score_half_life.saturating_mul(10));
+        self.scores.retain(|_, score| {
+            now.duration_since(score.updated).unwrap_or_default() < forgotten_after
+        });
+        let now = unix_time(now);
+        self.bans.retain(|_, ban| ban.expires_at > now);
+        let mut bans: Vec<Ban> = self.bans.values().cloned().collect();
+        bans.sort_by_key(|ban| ban.expires_at);
+        bans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ban_list() -> BanList {
+        let mut ban_list = BanList::new(BanConfig {
+            client_ban_score: 30.0,
+            ip_ban_score: 50.0,
+            client_ban_duration: 60,
+            ip_ban_duration: 600,
+            score_half_life: 60,
+            ..BanConfig::default()
+        });
+        ban_list.add_client_address(1, "10.0.0.1".parse().unwrap());
+        ban_list.add_client_address(2, "10.0.0.1".parse().unwrap());
+        ban_list.add_channel(1, 1, "alice".to_string());
+        ban_list.add_channel(1, 2, "alice.rig2".to_string());
+        ban_list.add_channel(2, 1, "bob".to_string());
+        ban_list
+    }
+
+    #[test]
+    fn test_worker_ban() {
+        let mut ban_list = ban_list();
+        let now = SystemTime::now();
+        assert!(ban_list
+            .record(1, Some(1), Offense::DuplicateShare, now)
+            .is_empty());
+        assert!(ban_list
+            .record(1, Some(1), Offense::DuplicateShare, now)
+            .is_empty());
+        let bans = ban_list.record(1, Some(1), Offense::DuplicateShare, now);
+        let targets: Vec<BanTarget> = bans.iter().map(|ban| ban.target.clone()).collect();
+        assert_eq!(
+            targets,
+            vec![BanTarget::Client(1), BanTarget::Worker("alice".to_string())]
+        );
+        assert_eq!(ban_list.banned_clients(&bans[1]), vec![1]);
+        assert!(ban_list.is_client_banned(1, now));
+        assert!(ban_list.is_channel_banned(1, 1, now));
+        assert!(!ban_list.is_client_banned(2, now));
+
+        // the ban holds when the worker reconnects
+        ban_list.remove_client(1);
+        ban_list.add_channel(3, 1, "alice".to_string());
+        assert!(ban_list.is_client_banned(3, now));
+        assert!(ban_list.is_worker_banned("alice", now + Duration::from_secs(59)));
+        assert!(!ban_list.is_client_banned(3, now + Duration::from_secs(60)));
+        assert!(ban_list
+            .active_bans(now + Duration::from_secs(60))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_offense_without_channel() {
+        let mut ban_list = ban_list();
+        let now = SystemTime::now();
+        // scored against every worker of the client
+        ban_list.record(1, None, Offense::ProtocolError, now);
+        let bans = ban_list.record(1, Some(7), Offense::ProtocolError, now);
+        let targets: Vec<BanTarget> = bans.iter().map(|ban| ban.target.clone()).collect();
+        assert_eq!(
+            targets,
+            vec![
+                BanTarget::Client(1),
+                BanTarget::Worker("alice".to_string()),
+                BanTarget::Worker("alice.rig2".to_string()),
+                BanTarget::Ip("10.0.0.1".parse().unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_score_decay() {
+        let mut ban_list = ban_list();
+        let now = SystemTime::now();
+        // a few stale shares after every block never add up
+        for minute in 0..100 {
+            let now = now + Duration::from_secs(minute * 60);
+            for _ in 0..10 {
+                assert!(ban_list
+                    .record(2, Some(1), Offense::StaleShare, now)
+                    .is_empty());
+            }
+        }
+        ban_list.add_channel(3, 1, "carol".to_string());
+        ban_list.record(3, Some(1), Offense::DuplicateShare, now);
+        ban_list.record(3, Some(1), Offense::DuplicateShare, now);
+        // halved twice, before it would have reached the ban score
+        let later = now + Duration::from_secs(120);
+        assert!(ban_list
+            .record(3, Some(1), Offense::DuplicateShare, later)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_ip_ban() {
+        let mut ban_list = ban_list();
+        let now = SystemTime::now();
+        ban_list.record(1, Some(2), Offense::DuplicateShare, now);
+        ban_list.record(2, Some(1), Offense::DuplicateShare, now);
+        ban_list.record(1, Some(2), Offense::DuplicateShare, now);
+        ban_list.record(2, Some(1), Offense::DuplicateShare, now);
+        let bans = ban_list.record(1, Some(2), Offense::InvalidShare, now);
+        let targets: Vec<BanTarget> = bans.iter().map(|ban| ban.target.clone()).collect();
+        assert_eq!(
+            targets,
+            vec![
+                BanTarget::Client(1),
+                BanTarget::Worker("alice.rig2".to_string()),
+                BanTarget::Ip("10.0.0.1".parse().unwrap())
+            ]
+        );
+        assert_eq!(ban_list.banned_clients(&bans[2]), vec![1, 2]);
+        assert!(ban_list.is_client_banned(2, now));
+        assert!(ban_list.is_ip_banned("10.0.0.1".parse().unwrap(), now));
+        assert!(!ban_list.is_ip_banned("10.0.0.2".parse().unwrap(), now));
+
+        // the address stays banned after its connections are gone, the client doesn't
+        ban_list.remove_client(1);
+        ban_list.remove_client(2);
+        assert_eq!(ban_list.active_bans(now).len(), 2);
+        assert!(ban_list.is_ip_banned("10.0.0.1".parse().unwrap(), now));
+    }
+
+    #[test]
+    fn test_unauthenticated_identity() {
+        let mut ban_list = ban_list();
+        let now = SystemTime::now();
+        // two clients on the same unauthenticated identity, which isn't a worker
+        ban_list.add_client_address(3, "10.0.0.3".parse().unwrap());
+        ban_list.add_client_address(4, "10.0.0.4".parse().unwrap());
+        ban_list.record(3, Some(1), Offense::DuplicateShare, now);
+        ban_list.record(3, Some(1), Offense::DuplicateShare, now);
+        let bans = ban_list.record(3, Some(1), Offense::DuplicateShare, now);
+        let targets: Vec<BanTarget> = bans.iter().map(|ban| ban.target.clone()).collect();
+        assert_eq!(targets, vec![BanTarget::Client(3)]);
+        assert_eq!(ban_list.banned_clients(&bans[0]), vec![3]);
+        assert!(ban_list.is_channel_banned(3, 1, now));
+        assert!(!ban_list.is_client_banned(4, now));
+    }
+}
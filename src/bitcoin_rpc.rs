@@ -0,0 +1,193 @@
+use anyhow::{anyhow, Result};
+use bitcoin::BlockHash;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::config::PlebLotteryBitcoinRpcConfig;
+
+/// Error code of Bitcoin Core for unknown blocks, transactions or addresses.
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+
+/// Minimal JSON-RPC client for the Bitcoin Core RPC interface.
+#[derive(Debug, Clone)]
+pub struct BitcoinRpcClient {
+    url: String,
+    auth: BitcoinRpcAuth,
+    http: reqwest::Client,
+    request_id: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Clone)]
+enum BitcoinRpcAuth {
+    None,
+    UserPass(String, String),
+    CookieFile(PathBuf),
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct BlockHeaderResult {
+    confirmations: i64,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl BitcoinRpcClient {
+    pub fn new(config: &PlebLotteryBitcoinRpcConfig) -> Result<Self> {
+        let auth = match (&config.rpc_user, &config.rpc_password, &config.cookie_file) {
+            (Some(user), Some(password), None) => {
+                BitcoinRpcAuth::UserPass(user.clone(), password.clone())
+            }
+            (None, None, Some(cookie_file)) => BitcoinRpcAuth::CookieFile(cookie_file.clone()),
+            (None, None, None) => BitcoinRpcAuth::None,
+            _ => {
+                return Err(anyhow!(
+                    "Bitcoin RPC needs either both rpc_user and rpc_password, or cookie_file"
+                ))
+            }
+        };
+        Ok(Self {
+            url: config.url.clone(),
+            auth,
+            http: reqwest::Client::new(),
+            request_id: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// The cookie is read on every call, as it changes whenever the node restarts.
+    fn credentials(&self) -> Result<Option<(String, String)>> {
+        match &self.auth {
+            BitcoinRpcAuth::None => Ok(None),
+            BitcoinRpcAuth::UserPass(user, password) => Ok(Some((user.clone(), password.clone()))),
+            BitcoinRpcAuth::CookieFile(cookie_file) => {
+                let cookie = fs::read_to_string(cookie_file).map_err(|e| {
+                    anyhow!(
+                        "Failed to read RPC cookie file {}: {}",
+                        cookie_file.display(),
+                        e
+                    )
+                })?;
+                let (user, password) = cookie
+                    .trim()
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Invalid RPC cookie file {}", cookie_file.display()))?;
+                Ok(Some((user.to_string(), password.to_string())))
+            }
+        }
+    }
+
+    pub async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let response = self.request(method, params).await?;
+        if let Some(error) = response.error {
+            return Err(anyhow!(
+                "Bitcoin RPC {} failed: {} (code {})",
+                method,
+                error.message,
+                error.code
+            ));
+        }
+        serde_json::from_value(response.result.unwrap_or(Value::Null)).map_err(|e| {
+            anyhow!(
+                "Bitcoin RPC {} returned an unexpected result: {}",
+                method,
+                e
+            )
+        })
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<RpcResponse> {
+        let id = self.request_id.fetch_add(1, Ordering::Relaxed);
+        let mut request = self.http.post(&self.url).json(&json!({
+            "jsonrpc": "1.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }));
+        if let Some((user, password)) = self.credentials()? {
+            request = request.basic_auth(user, Some(password));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow!("Bitcoin RPC {} failed: {}", method, e))?;
+        // Bitcoin Core answers RPC errors with an HTTP error status and a JSON body
+        let status = response.status();
+        response.json().await.map_err(|e| {
+            anyhow!(
+                "Bitcoin RPC {} failed: HTTP {} with invalid body: {}",
+                method,
+                status,
+                e
+            )
+        })
+    }
+
+    /// Calls `getblocktemplate` with the segwit rule. With a `longpollid`, the node only answers
+    /// once the template changes.
+    pub async fn get_block_template<T: DeserializeOwned>(
+        &self,
+        longpollid: Option<&str>,
+    ) -> Result<T> {
+        let mut request = json!({ "rules": ["segwit"] });
+        if let Some(longpollid) = longpollid {
+            request["longpollid"] = json!(longpollid);
+        }
+        self.call("getblocktemplate", json!([request])).await
+    }
+
+    /// Calls `getblockheader` for the confirmations of `block_hash`: -1 if it is not in the best
+    /// chain, or `None` if the node doesn't know it.
+    pub async fn get_block_confirmations(&self, block_hash: &BlockHash) -> Result<Option<i64>> {
+        let response = self
+            .request("getblockheader", json!([block_hash.to_string()]))
+            .await?;
+        match response.error {
+            Some(error) if error.code == RPC_INVALID_ADDRESS_OR_KEY => Ok(None),
+            Some(error) => Err(anyhow!(
+                "Bitcoin RPC getblockheader failed: {} (code {})",
+                error.message,
+                error.code
+            )),
+            None => {
+                let header: BlockHeaderResult = serde_json::from_value(
+                    response.result.unwrap_or(Value::Null),
+                )
+                .map_err(|e| {
+                    anyhow!(
+                        "Bitcoin RPC getblockheader returned an unexpected result: {}",
+                        e
+                    )
+                })?;
+                Ok(Some(header.confirmations))
+            }
+        }
+    }
+
+    /// Calls `getblockhash` for the hash of the block at `height` in the best chain.
+    pub async fn get_block_hash(&self, height: u64) -> Result<BlockHash> {
+        let block_hash: String = self.call("getblockhash", json!([height])).await?;
+        BlockHash::from_str(&block_hash)
+            .map_err(|e| anyhow!("Bitcoin RPC getblockhash returned an invalid hash: {}", e))
+    }
+
+    /// Calls `submitblock` with a hex serialized block. Returns the rejection reason, if any.
+    pub async fn submit_block(&self, block_hex: &str) -> Result<Option<String>> {
+        self.call("submitblock", json!([block_hex])).await
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Result};
+use bitcoin::{BlockHash, TxMerkleNode};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::error;
+
+/// A block found by one of the channels, as written to the [`BlockJournal`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FoundBlock {
+    pub height: u64,
+    pub block_hash: BlockHash,
+    pub version: i32,
+    pub prev_hash: BlockHash,
+    pub merkle_root: TxMerkleNode,
+    pub time: u32,
+    pub bits: u32,
+    pub nonce: u32,
+    pub template_id: Option<u64>, // none for custom jobs
+    pub coinbase_tx: String,      // hex serialized
+    pub reward: u64,              // sats paid by the coinbase outputs
+    pub client_id: u32,
+    pub channel_id: u32,
+    pub user_identity: String,
+    pub timestamp: u64, // unix time the block was found
+    /// Why the block failed the independent check before it was submitted, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification_error: Option<String>,
+}
+
+/// Append-only journal of found blocks, one JSON entry per line.
+///
+/// Entries are never rewritten, so a crash can at worst truncate the last line, which is
+/// skipped when the journal is read back.
+#[derive(Debug, Clone)]
+pub struct BlockJournal {
+    journal_file: PathBuf,
+}
+
+impl BlockJournal {
+    /// Opens the journal at `journal_file`, returning the blocks already in it.
+    pub fn load(journal_file: PathBuf) -> Result<(Self, Vec<FoundBlock>)> {
+        let contents = match fs::read_to_string(&journal_file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(anyhow!(
+                    "Failed to read found blocks from {}: {}",
+                    journal_file.display(),
+                    e
+                ))
+            }
+        };
+        let found_blocks = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(i, line)| match serde_json::from_str(line) {
+                Ok(found_block) => Some(found_block),
+                Err(e) => {
+                    error!(
+                        "Skipping invalid entry on line {} of {}: {}",
+                        i + 1,
+                        journal_file.display(),
+                        e
+                    );
+                    None
+                }
+            })
+            .collect();
+        let journal = Self { journal_file };
+        // terminate a truncated last entry, so the next one starts on its own line
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            journal.write("\n")?;
+        }
+        Ok((journal, found_blocks))
+    }
+
+    /// Appends `found_block` to the journal, and makes sure it reached the disk.
+    pub fn append(&self, found_block: &FoundBlock) -> Result<()> {
+        let line = serde_json::to_string(found_block)?;
+        self.write(&format!("{line}\n"))
+    }
+
+    fn write(&self, data: &str) -> Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.journal_file)
+            .and_then(|mut file| {
+                file.write_all(data.as_bytes())?;
+                file.sync_data()
+            })
+            .map_err(|e| {
+                anyhow!(
+                    "Failed to write found block to {}: {}",
+                    self.journal_file.display(),
+                    e
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    fn found_block(height: u64) -> FoundBlock {
+        FoundBlock {
+            height,
+            block_hash: BlockHash::from_byte_array([height as u8; 32]),
+            version: 0x2000_0000,
+            prev_hash: BlockHash::all_zeros(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: 1_700_000_000,
+            bits: 0x207fffff,
+            nonce: 42,
+            template_id: Some(7),
+            coinbase_tx: "00".to_string(),
+            reward: 5_000_000_000,
+            client_id: 1,
+            channel_id: 2,
+            user_identity: "username".to_string(),
+            timestamp: 1_700_000_001,
+            verification_error: None,
+        }
+    }
+
+    #[test]
+    fn test_journal_is_appended() {
+        let journal_file =
+            std::env::temp_dir().join(format!("pleblottery-found-blocks-{}", std::process::id()));
+        let _ = fs::remove_file(&journal_file);
+
+        let (journal, found_blocks) = BlockJournal::load(journal_file.clone()).unwrap();
+        assert!(found_blocks.is_empty());
+        journal.append(&found_block(1)).unwrap();
+        journal.append(&found_block(2)).unwrap();
+
+        // a truncated last entry is skipped
+        let mut file = OpenOptions::new().append(true).open(&journal_file).unwrap();
+        file.write_all(b"{\"height\":3,").unwrap();
+
+        let (journal, found_blocks) = BlockJournal::load(journal_file.clone()).unwrap();
+        assert_eq!(found_blocks, vec![found_block(1), found_block(2)]);
+        journal.append(&found_block(4)).unwrap();
+
+        let (_, found_blocks) = BlockJournal::load(journal_file.clone()).unwrap();
+        assert_eq!(
+            found_blocks,
+            vec![found_block(1), found_block(2), found_block(4)]
+        );
+        fs::remove_file(journal_file).unwrap();
+    }
+}
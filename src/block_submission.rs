@@ -0,0 +1,221 @@
+use anyhow::{anyhow, Result};
+use bitcoin::block::{Header, Version};
+use bitcoin::consensus::encode::{deserialize, serialize_hex};
+use bitcoin::hashes::Hash;
+use bitcoin::{Block, BlockHash, CompactTarget, Transaction, TxMerkleNode, Witness};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+use sv2_services::roles_logic_sv2::template_distribution_sv2::{SetNewPrevHash, SubmitSolution};
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::bitcoin_rpc::BitcoinRpcClient;
+use crate::state::SharedStateHandle;
+
+/// Start of the script of the segwit commitment output (BIP141).
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Rebuilds the full block mined by `solution` on top of `prev_hash`, with `transactions`
+/// following the coinbase.
+pub fn assemble_block(
+    prev_hash: BlockHash,
+    bits: CompactTarget,
+    transactions: &[Transaction],
+    solution: &SubmitSolution<'_>,
+) -> Result<Block> {
+    let mut coinbase: Transaction = deserialize(&solution.coinbase_tx.to_vec())
+        .map_err(|e| anyhow!("Invalid coinbase in solution: {}", e))?;
+    // the segwit commitment is made to a zeroed witness reserved value
+    let has_witness_commitment = coinbase.output.iter().any(|output| {
+        output
+            .script_pubkey
+            .as_bytes()
+            .starts_with(&WITNESS_COMMITMENT_HEADER)
+    });
+    if has_witness_commitment && coinbase.input[0].witness.is_empty() {
+        coinbase.input[0].witness = Witness::from_slice(&[[0u8; 32]]);
+    }
+
+    let mut txdata = vec![coinbase];
+    txdata.extend(transactions.iter().cloned());
+    let mut block = Block {
+        header: Header {
+            version: Version::from_consensus(solution.version as i32),
+            prev_blockhash: prev_hash,
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: solution.header_timestamp,
+            bits,
+            nonce: solution.header_nonce,
+        },
+        txdata,
+    };
+    block.header.merkle_root = block.compute_merkle_root().expect("block has a coinbase");
+    Ok(block)
+}
+
+/// Path a found block was propagated through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmissionPath {
+    /// `SubmitSolution` to the Template Provider.
+    TemplateProvider,
+    /// `submitblock` to the Bitcoin node.
+    BitcoinRpc,
+}
+
+impl fmt::Display for SubmissionPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmissionPath::TemplateProvider => write!(f, "Template Provider"),
+            SubmissionPath::BitcoinRpc => write!(f, "Bitcoin RPC"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmissionResult {
+    /// Queued for the Template Provider connection, which reports neither whether it was sent
+    /// nor whether the Template Provider took it, as `SubmitSolution` has no answer.
+    Queued,
+    Accepted,
+    Rejected(String),
+    Failed(String),
+}
+
+impl fmt::Display for SubmissionResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmissionResult::Queued => write!(f, "queued"),
+            SubmissionResult::Accepted => write!(f, "accepted"),
+            SubmissionResult::Rejected(reason) => write!(f, "rejected: {}", reason),
+            SubmissionResult::Failed(error) => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+/// Outcome of propagating a found block through one [`SubmissionPath`].
+#[derive(Debug, Clone)]
+pub struct BlockSubmission {
+    pub template_id: u64,
+    pub block_hash: Option<BlockHash>, // unknown when the block could not be rebuilt
+    pub path: SubmissionPath,
+    pub result: SubmissionResult,
+    pub time: SystemTime,
+}
+
+pub async fn record_block_submission(
+    shared_state: &SharedStateHandle,
+    template_id: u64,
+    block_hash: Option<BlockHash>,
+    path: SubmissionPath,
+    result: SubmissionResult,
+) {
+    match &result {
+        SubmissionResult::Queued | SubmissionResult::Accepted => {
+            info!("Block of template {} via {}: {}", template_id, path, result)
+        }
+        SubmissionResult::Rejected(_) | SubmissionResult::Failed(_) => {
+            error!("Block of template {} via {}: {}", template_id, path, result)
+        }
+    }
+    shared_state
+        .write()
+        .await
+        .block_submissions
+        .push(BlockSubmission {
+            template_id,
+            block_hash,
+            path,
+            result,
+            time: SystemTime::now(),
+        });
+}
+
+/// Submits found blocks to a Bitcoin node via `submitblock`.
+///
+/// With a Template Provider, the transactions of every template are requested from it ahead of
+/// time, so the full block can be rebuilt even if the Template Provider is gone by the time a
+/// block is found.
+#[derive(Debug, Clone)]
+pub struct BlockSubmitter {
+    rpc: BitcoinRpcClient,
+    shared_state: SharedStateHandle,
+    template_transactions: Arc<RwLock<HashMap<u64, Vec<Transaction>>>>,
+}
+
+impl BlockSubmitter {
+    pub fn new(rpc: BitcoinRpcClient, shared_state: SharedStateHandle) -> Self {
+        Self {
+            rpc,
+            shared_state,
+            template_transactions: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub async fn add_template_transactions(
+        &self,
+        template_id: u64,
+        transactions: Vec<Transaction>,
+    ) {
+        self.template_transactions
+            .write()
+            .await
+            .insert(template_id, transactions);
+    }
+
+    /// Forgets the transactions of templates built on an older chain tip.
+    pub async fn retain_template(&self, template_id: u64) {
+        self.template_transactions
+            .write()
+            .await
+            .retain(|id, _| *id == template_id);
+    }
+
+    /// Rebuilds the block mined by `solution` from the transactions of its template.
+    pub async fn assemble_block(
+        &self,
+        prev_hash: &SetNewPrevHash<'_>,
+        solution: &SubmitSolution<'_>,
+    ) -> Result<Block> {
+        let template_transactions = self.template_transactions.read().await;
+        let transactions = template_transactions
+            .get(&solution.template_id)
+            .ok_or_else(|| {
+                anyhow!(
+                    "transactions of template {} are unknown",
+                    solution.template_id
+                )
+            })?;
+        let prev_hash_bytes: [u8; 32] = prev_hash
+            .prev_hash
+            .to_vec()
+            .try_into()
+            .map_err(|_| anyhow!("prev hash must be 32 bytes"))?;
+        assemble_block(
+            BlockHash::from_byte_array(prev_hash_bytes),
+            CompactTarget::from_consensus(prev_hash.n_bits),
+            transactions,
+            solution,
+        )
+    }
+
+    /// Submits `block` and records the result.
+    pub async fn submit_block(&self, template_id: u64, block: Block) {
+        let block_hash = block.block_hash();
+        info!("Submitting block {} to the Bitcoin node", block_hash);
+        let result = match self.rpc.submit_block(&serialize_hex(&block)).await {
+            Ok(None) => SubmissionResult::Accepted,
+            Ok(Some(reason)) => SubmissionResult::Rejected(reason),
+            Err(e) => SubmissionResult::Failed(e.to_string()),
+        };
+        record_block_submission(
+            &self.shared_state,
+            template_id,
+            Some(block_hash),
+            SubmissionPath::BitcoinRpc,
+            result,
+        )
+        .await;
+    }
+}
@@ -0,0 +1,307 @@
+use bitcoin::BlockHash;
+use std::fmt;
+use tracing::{error, info, warn};
+
+use crate::bitcoin_rpc::BitcoinRpcClient;
+use crate::block_journal::FoundBlock;
+use crate::state::SharedStateHandle;
+
+/// Confirmations after which a coinbase output can be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Blocks the chain has to move past a stale block before it's taken as orphaned, as a reorg
+/// may still bring it back until then.
+pub const ORPHAN_DEPTH: u64 = 6;
+
+/// Whether a found block made it into the best chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FoundBlockStatus {
+    /// The chain has not reached the height of the block yet.
+    #[default]
+    Pending,
+    /// In the best chain, with up to [`COINBASE_MATURITY`] confirmations.
+    Accepted { confirmations: u32 },
+    /// Another block took its place in the best chain, less than [`ORPHAN_DEPTH`] blocks ago.
+    Stale,
+    /// Another block took its place in the best chain, which moved [`ORPHAN_DEPTH`] blocks past
+    /// it.
+    Orphaned,
+    /// The chain moved past the block while nobody was watching (e.g. before a restart), and
+    /// there is no Bitcoin node to ask.
+    Unknown,
+}
+
+impl FoundBlockStatus {
+    pub fn is_mature(&self) -> bool {
+        matches!(self, FoundBlockStatus::Accepted { confirmations } if *confirmations >= COINBASE_MATURITY)
+    }
+
+    /// Status of a block found at `height` after the chain tip moved to `tip_hash` at
+    /// `tip_height`.
+    pub fn on_chain_tip(
+        self,
+        height: u64,
+        block_hash: BlockHash,
+        tip_height: u64,
+        tip_hash: BlockHash,
+    ) -> Self {
+        if tip_height < height {
+            return self;
+        }
+        if tip_height == height {
+            return match tip_hash == block_hash {
+                true => FoundBlockStatus::Accepted { confirmations: 1 },
+                false => FoundBlockStatus::Stale,
+            };
+        }
+        match self {
+            FoundBlockStatus::Accepted { .. } => FoundBlockStatus::Accepted {
+                confirmations: confirmations(tip_height - height + 1),
+            },
+            FoundBlockStatus::Pending | FoundBlockStatus::Unknown => FoundBlockStatus::Unknown,
+            FoundBlockStatus::Stale => stale(height, tip_height),
+            FoundBlockStatus::Orphaned => FoundBlockStatus::Orphaned,
+        }
+    }
+
+    /// Status of a block at `height` from the confirmations reported by a Bitcoin node whose
+    /// best chain ends at `tip_height`, where -1 means it is not in the best chain.
+    fn from_rpc_confirmations(rpc_confirmations: i64, height: u64, tip_height: u64) -> Self {
+        match rpc_confirmations {
+            rpc_confirmations if rpc_confirmations > 0 => FoundBlockStatus::Accepted {
+                confirmations: confirmations(rpc_confirmations as u64),
+            },
+            _ => stale(height, tip_height),
+        }
+    }
+}
+
+/// Status of a block at `height` that isn't in the best chain ending at `tip_height`.
+fn stale(height: u64, tip_height: u64) -> FoundBlockStatus {
+    match tip_height >= height + ORPHAN_DEPTH {
+        true => FoundBlockStatus::Orphaned,
+        false => FoundBlockStatus::Stale,
+    }
+}
+
+fn confirmations(confirmations: u64) -> u32 {
+    confirmations.min(COINBASE_MATURITY as u64) as u32
+}
+
+impl fmt::Display for FoundBlockStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FoundBlockStatus::Pending => write!(f, "pending"),
+            FoundBlockStatus::Accepted { confirmations } if *confirmations >= COINBASE_MATURITY => {
+                write!(f, "mature")
+            }
+            FoundBlockStatus::Accepted { confirmations } => write!(
+                f,
+                "accepted ({}/{} confirmations)",
+                confirmations, COINBASE_MATURITY
+            ),
+            FoundBlockStatus::Stale => write!(f, "stale"),
+            FoundBlockStatus::Orphaned => write!(f, "orphaned"),
+            FoundBlockStatus::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Follows found blocks until they are mature or orphaned.
+///
+/// Every new chain tip from `SetNewPrevHash` is compared against the found blocks. With a
+/// Bitcoin node, its view of the best chain takes precedence, which also catches reorgs and
+/// blocks found before a restart. Stale blocks are followed until the chain is
+/// [`ORPHAN_DEPTH`] blocks past them, in case a reorg brings them back.
+#[derive(Debug, Clone)]
+pub struct BlockTracker {
+    shared_state: SharedStateHandle,
+    rpc: Option<BitcoinRpcClient>,
+}
+
+impl BlockTracker {
+    pub fn new(shared_state: SharedStateHandle) -> Self {
+        Self {
+            shared_state,
+            rpc: None,
+        }
+    }
+
+    /// Asks the Bitcoin node behind `rpc` whether found blocks are in the best chain.
+    pub fn with_rpc(mut self, rpc: BitcoinRpcClient) -> Self {
+        self.rpc = Some(rpc);
+        self
+    }
+
+    /// Updates the status of every found block that is neither mature nor orphaned, now that
+    /// the best chain ends at `tip_hash`, at `tip_height`.
+    ///
+    /// The Bitcoin node, if any, is asked in the background, so new jobs aren't held back.
+    pub async fn on_chain_tip(&self, tip_height: u64, tip_hash: BlockHash) {
+        let found_blocks = self.tracked_blocks().await;
+        for (found_block, status) in &found_blocks {
+            let new_status = status.on_chain_tip(
+                found_block.height,
+                found_block.block_hash,
+                tip_height,
+                tip_hash,
+            );
+            self.set_status(found_block, *status, new_status).await;
+        }
+
+        if let Some(rpc) = self.rpc.clone() {
+            let tracker = self.clone();
+            tokio::spawn(async move {
+                for (found_block, _) in tracker.tracked_blocks().await {
+                    // blocks unknown to the Bitcoin node keep the status from the chain tip
+                    match rpc.get_block_confirmations(&found_block.block_hash).await {
+                        Ok(Some(rpc_confirmations)) => {
+                            let status = tracker.status(&found_block).await;
+                            let new_status = FoundBlockStatus::from_rpc_confirmations(
+                                rpc_confirmations,
+                                found_block.height,
+                                tip_height,
+                            );
+                            tracker.set_status(&found_block, status, new_status).await;
+                        }
+                        Ok(None) => {}
+                        Err(e) => warn!(
+                            "Failed to ask the Bitcoin node about block {}: {}",
+                            found_block.block_hash, e
+                        ),
+                    }
+                }
+            });
+        }
+    }
+
+    /// Found blocks that are neither mature nor orphaned, with their status.
+    async fn tracked_blocks(&self) -> Vec<(FoundBlock, FoundBlockStatus)> {
+        let state = self.shared_state.read().await;
+        state
+            .found_blocks
+            .iter()
+            .map(|found_block| {
+                let status = state
+                    .found_block_statuses
+                    .get(&found_block.block_hash)
+                    .copied()
+                    .unwrap_or_default();
+                (found_block.clone(), status)
+            })
+            .filter(|(_, status)| !status.is_mature() && *status != FoundBlockStatus::Orphaned)
+            .collect()
+    }
+
+    async fn status(&self, found_block: &FoundBlock) -> FoundBlockStatus {
+        self.shared_state
+            .read()
+            .await
+            .found_block_statuses
+            .get(&found_block.block_hash)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    async fn set_status(
+        &self,
+        found_block: &FoundBlock,
+        status: FoundBlockStatus,
+        new_status: FoundBlockStatus,
+    ) {
+        if new_status == status {
+            return;
+        }
+        match new_status {
+            FoundBlockStatus::Orphaned => error!(
+                "Block {} at height {} was orphaned ❌",
+                found_block.block_hash, found_block.height
+            ),
+            _ => info!(
+                "Block {} at height {} is {}",
+                found_block.block_hash, found_block.height, new_status
+            ),
+        }
+        self.shared_state
+            .write()
+            .await
+            .found_block_statuses
+            .insert(found_block.block_hash, new_status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_status_follows_chain_tip() {
+        let block_hash = BlockHash::from_byte_array([1; 32]);
+        let other_hash = BlockHash::from_byte_array([2; 32]);
+
+        let status = FoundBlockStatus::Pending;
+        assert_eq!(
+            status.on_chain_tip(100, block_hash, 99, other_hash),
+            FoundBlockStatus::Pending
+        );
+        let status = status.on_chain_tip(100, block_hash, 100, block_hash);
+        assert_eq!(status, FoundBlockStatus::Accepted { confirmations: 1 });
+        let status = status.on_chain_tip(100, block_hash, 150, other_hash);
+        assert_eq!(status, FoundBlockStatus::Accepted { confirmations: 51 });
+        let status = status.on_chain_tip(100, block_hash, 250, other_hash);
+        assert!(status.is_mature());
+
+        // a competing block at the same height
+        let status = FoundBlockStatus::Pending.on_chain_tip(100, block_hash, 100, other_hash);
+        assert_eq!(status, FoundBlockStatus::Stale);
+        assert_eq!(
+            status.on_chain_tip(100, block_hash, 105, other_hash),
+            FoundBlockStatus::Stale
+        );
+        assert_eq!(
+            status.on_chain_tip(100, block_hash, 106, other_hash),
+            FoundBlockStatus::Orphaned
+        );
+        // the tip at the height of the block was never seen
+        assert_eq!(
+            FoundBlockStatus::Pending.on_chain_tip(100, block_hash, 101, other_hash),
+            FoundBlockStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn test_status_from_rpc_confirmations() {
+        assert_eq!(
+            FoundBlockStatus::from_rpc_confirmations(3, 100, 102),
+            FoundBlockStatus::Accepted { confirmations: 3 }
+        );
+        assert!(FoundBlockStatus::from_rpc_confirmations(1_000, 100, 1_100).is_mature());
+        assert_eq!(
+            FoundBlockStatus::from_rpc_confirmations(-1, 100, 101),
+            FoundBlockStatus::Stale
+        );
+        assert_eq!(
+            FoundBlockStatus::from_rpc_confirmations(-1, 100, 106),
+            FoundBlockStatus::Orphaned
+        );
+    }
+
+    #[test]
+    fn test_stale_block_reorged_back() {
+        let block_hash = BlockHash::from_byte_array([1; 32]);
+        let other_hash = BlockHash::from_byte_array([2; 32]);
+
+        // a competing block wins the race at first
+        let status = FoundBlockStatus::Pending.on_chain_tip(100, block_hash, 100, other_hash);
+        assert_eq!(status, FoundBlockStatus::Stale);
+        // until a reorg makes the block the tip at its height again
+        let status = status.on_chain_tip(100, block_hash, 100, block_hash);
+        assert_eq!(status, FoundBlockStatus::Accepted { confirmations: 1 });
+        // or the Bitcoin node finds it in the best chain, past the competing tip
+        assert_eq!(
+            FoundBlockStatus::from_rpc_confirmations(2, 100, 101),
+            FoundBlockStatus::Accepted { confirmations: 2 }
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{anyhow, Result};
+use bitcoin::block::{Header, Version};
+use bitcoin::consensus::encode::deserialize;
+use bitcoin::hashes::Hash;
+use bitcoin::{Amount, BlockHash, CompactTarget, ScriptBuf, Transaction, TxMerkleNode};
+use sv2_services::roles_logic_sv2::mining_sv2::{NewExtendedMiningJob, NewMiningJob};
+use sv2_services::roles_logic_sv2::template_distribution_sv2::SetNewPrevHash;
+
+use crate::utils::merkle_root_from_path;
+
+/// Header and coinbase of a found block, rebuilt from the job it was mined on rather than taken
+/// from the channel that reported it.
+#[derive(Debug, Clone)]
+pub struct RebuiltBlock {
+    pub header: Header,
+    pub coinbase: Transaction,
+}
+
+/// Header fields sent by the miner in `SubmitSharesStandard` or `SubmitSharesExtended`.
+#[derive(Debug, Clone, Copy)]
+pub struct SubmittedHeaderFields {
+    pub version: u32,
+    pub ntime: u32,
+    pub nonce: u32,
+}
+
+fn header(
+    prev_hash: &SetNewPrevHash<'_>,
+    merkle_root: TxMerkleNode,
+    submitted: SubmittedHeaderFields,
+) -> Result<Header> {
+    let prev_hash_bytes: [u8; 32] = prev_hash
+        .prev_hash
+        .to_vec()
+        .try_into()
+        .map_err(|_| anyhow!("prev hash must be 32 bytes"))?;
+    Ok(Header {
+        version: Version::from_consensus(submitted.version as i32),
+        prev_blockhash: BlockHash::from_byte_array(prev_hash_bytes),
+        merkle_root,
+        time: submitted.ntime,
+        bits: CompactTarget::from_consensus(prev_hash.n_bits),
+        nonce: submitted.nonce,
+    })
+}
+
+impl RebuiltBlock {
+    /// Rebuilds a block mined on an extended job, from the coinbase prefix and suffix of the job
+    /// around the full extranonce.
+    pub fn from_extended_job(
+        job: &NewExtendedMiningJob<'_>,
+        extranonce_prefix: &[u8],
+        extranonce: &[u8],
+        prev_hash: &SetNewPrevHash<'_>,
+        submitted: SubmittedHeaderFields,
+    ) -> Result<Self> {
+        let mut coinbase_tx = job.coinbase_tx_prefix.to_vec();
+        coinbase_tx.extend_from_slice(extranonce_prefix);
+        coinbase_tx.extend_from_slice(extranonce);
+        coinbase_tx.extend_from_slice(&job.coinbase_tx_suffix.to_vec());
+        let coinbase: Transaction = deserialize(&coinbase_tx)
+            .map_err(|e| anyhow!("job {} has an invalid coinbase: {}", job.job_id, e))?;
+
+        let merkle_root =
+            merkle_root_from_path(coinbase.compute_txid(), &job.merkle_path.to_vec())?;
+        Ok(Self {
+            header: header(prev_hash, merkle_root, submitted)?,
+            coinbase,
+        })
+    }
+
+    /// Rebuilds a block mined on a standard job. Standard jobs only carry the merkle root, so the
+    /// coinbase reported by the channel is checked against it through the merkle path of the
+    /// template.
+    pub fn from_standard_job(
+        job: &NewMiningJob<'_>,
+        coinbase_tx: &[u8],
+        template_merkle_path: &[Vec<u8>],
+        prev_hash: &SetNewPrevHash<'_>,
+        submitted: SubmittedHeaderFields,
+    ) -> Result<Self> {
+        let coinbase: Transaction =
+            deserialize(coinbase_tx).map_err(|e| anyhow!("invalid coinbase: {}", e))?;
+        let job_merkle_root: [u8; 32] = job
+            .merkle_root
+            .to_vec()
+            .try_into()
+            .map_err(|_| anyhow!("merkle root of job {} must be 32 bytes", job.job_id))?;
+        let job_merkle_root = TxMerkleNode::from_byte_array(job_merkle_root);
+
+        let merkle_root = merkle_root_from_path(coinbase.compute_txid(), template_merkle_path)?;
+        if merkle_root != job_merkle_root {
+            return Err(anyhow!(
+                "coinbase {} does not belong to job {}",
+                coinbase.compute_txid(),
+                job.job_id
+            ));
+        }
+        Ok(Self {
+            header: header(prev_hash, job_merkle_root, submitted)?,
+            coinbase,
+        })
+    }
+
+    /// Checks that the double-SHA256 of the header meets the target of its nbits, and that the
+    /// coinbase pays one of `payout_scripts`. Returns the block hash.
+    pub fn verify(&self, payout_scripts: &[ScriptBuf]) -> Result<BlockHash> {
+        let block_hash = self.header.block_hash();
+        if self.header.validate_pow(self.header.target()).is_err() {
+            return Err(anyhow!(
+                "block hash {} does not meet the target of nbits {:08x}",
+                block_hash,
+                self.header.bits.to_consensus()
+            ));
+        }
+        let pays_payout_script = self.coinbase.output.iter().any(|output| {
+            payout_scripts.contains(&output.script_pubkey) && output.value > Amount::ZERO
+        });
+        if !pays_payout_script {
+            let payout_scripts: Vec<String> = payout_scripts
+                .iter()
+                .map(|payout_script| payout_script.to_hex_string())
+                .collect();
+            return Err(anyhow!(
+                "coinbase of block {} does not pay {}",
+                block_hash,
+                payout_scripts.join(" or ")
+            ));
+        }
+        Ok(block_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::consensus::encode::serialize;
+    use bitcoin::hashes::sha256d;
+    use bitcoin::{transaction, OutPoint, Sequence, TxIn, TxOut, Witness};
+
+    fn coinbase(payout_script: &ScriptBuf) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(vec![0x01, 0x64, 0xaa, 0xbb, 0xcc, 0xdd]),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(5_000_000_000),
+                script_pubkey: payout_script.clone(),
+            }],
+        }
+    }
+
+    fn prev_hash() -> SetNewPrevHash<'static> {
+        SetNewPrevHash {
+            template_id: 1,
+            prev_hash: [7u8; 32].into(),
+            header_timestamp: 1_700_000_000,
+            n_bits: 0x207fffff,
+            target: [0xffu8; 32].into(),
+        }
+    }
+
+    /// Rolls the nonce until the header meets the regtest target.
+    fn mine(mut header: Header) -> SubmittedHeaderFields {
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        SubmittedHeaderFields {
+            version: header.version.to_consensus() as u32,
+            ntime: header.time,
+            nonce: header.nonce,
+        }
+    }
+
+    fn extended_job(payout_script: &ScriptBuf) -> (NewExtendedMiningJob<'static>, Vec<u8>) {
+        let coinbase_tx = serialize(&coinbase(payout_script));
+        // the last 4 bytes of the script_sig are the extranonce
+        let extranonce_start = coinbase_tx
+            .windows(4)
+            .position(|window| window == [0xaa, 0xbb, 0xcc, 0xdd])
+            .unwrap();
+        let job = NewExtendedMiningJob {
+            channel_id: 1,
+            job_id: 1,
+            min_ntime: None.into(),
+            version: 0x2000_0000,
+            version_rolling_allowed: true,
+            merkle_path: vec![[3u8; 32].into()].try_into().unwrap(),
+            coinbase_tx_prefix: coinbase_tx[..extranonce_start].to_vec().try_into().unwrap(),
+            coinbase_tx_suffix: coinbase_tx[extranonce_start + 4..]
+                .to_vec()
+                .try_into()
+                .unwrap(),
+        };
+        (job, vec![0xaa, 0xbb, 0xcc, 0xdd])
+    }
+
+    #[test]
+    fn test_verify_extended_job() {
+        let payout_script = ScriptBuf::from_bytes(vec![0x51]);
+        let (job, extranonce) = extended_job(&payout_script);
+        let unmined = RebuiltBlock::from_extended_job(
+            &job,
+            &extranonce[..1],
+            &extranonce[1..],
+            &prev_hash(),
+            SubmittedHeaderFields {
+                version: 0x2000_0000,
+                ntime: 1_700_000_000,
+                nonce: 0,
+            },
+        )
+        .unwrap();
+
+        let mut engine = sha256d::Hash::engine();
+        bitcoin::hashes::HashEngine::input(
+            &mut engine,
+            &unmined.coinbase.compute_txid().to_byte_array(),
+        );
+        bitcoin::hashes::HashEngine::input(&mut engine, &[3u8; 32]);
+        assert_eq!(
+            unmined.header.merkle_root.to_byte_array(),
+            sha256d::Hash::from_engine(engine).to_byte_array()
+        );
+
+        let submitted = mine(unmined.header);
+        let block = RebuiltBlock::from_extended_job(
+            &job,
+            &extranonce[..1],
+            &extranonce[1..],
+            &prev_hash(),
+            submitted,
+        )
+        .unwrap();
+        assert_eq!(
+            block.verify(&[payout_script.clone()]).unwrap(),
+            block.header.block_hash()
+        );
+        // paying somewhere else
+        assert!(block.verify(&[ScriptBuf::from_bytes(vec![0x52])]).is_err());
+        // a nonce that doesn't meet the target
+        let block = RebuiltBlock::from_extended_job(
+            &job,
+            &extranonce[..1],
+            &extranonce[1..],
+            &prev_hash(),
+            SubmittedHeaderFields {
+                nonce: submitted.nonce.wrapping_add(1),
+                ..submitted
+            },
+        )
+        .unwrap();
+        if block.header.validate_pow(block.header.target()).is_err() {
+            assert!(block.verify(&[payout_script.clone()]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_verify_standard_job() {
+        let payout_script = ScriptBuf::from_bytes(vec![0x51]);
+        let coinbase_tx = serialize(&coinbase(&payout_script));
+        let merkle_path = vec![vec![3u8; 32]];
+        let merkle_root =
+            merkle_root_from_path(coinbase(&payout_script).compute_txid(), &merkle_path).unwrap();
+        let job = NewMiningJob {
+            channel_id: 1,
+            job_id: 1,
+            min_ntime: None.into(),
+            version: 0x2000_0000,
+            merkle_root: merkle_root.to_byte_array().into(),
+        };
+
+        let unmined = RebuiltBlock::from_standard_job(
+            &job,
+            &coinbase_tx,
+            &merkle_path,
+            &prev_hash(),
+            SubmittedHeaderFields {
+                version: 0x2000_0000,
+                ntime: 1_700_000_000,
+                nonce: 0,
+            },
+        )
+        .unwrap();
+        assert_eq!(unmined.header.merkle_root, merkle_root);
+        let submitted = mine(unmined.header);
+        let block = RebuiltBlock::from_standard_job(
+            &job,
+            &coinbase_tx,
+            &merkle_path,
+            &prev_hash(),
+            submitted,
+        )
+        .unwrap();
+        assert!(block.verify(&[payout_script.clone()]).is_ok());
+
+        // a coinbase that isn't the one of the job
+        let other_coinbase_tx = serialize(&coinbase(&ScriptBuf::from_bytes(vec![0x52])));
+        assert!(RebuiltBlock::from_standard_job(
+            &job,
+            &other_coinbase_tx,
+            &merkle_path,
+            &prev_hash(),
+            submitted,
+        )
+        .is_err());
+    }
+}
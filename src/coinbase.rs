@@ -0,0 +1,281 @@
+use bitcoin::constants::WITNESS_SCALE_FACTOR;
+use bitcoin::hashes::Hash;
+use bitcoin::script::PushBytesBuf;
+use bitcoin::{transaction::TxOut, Amount, PubkeyHash, ScriptBuf, WScriptHash};
+
+/// Denominator of [`CoinbaseOutputSplit::basis_points`].
+pub const BASIS_POINTS_TOTAL: u64 = 10_000;
+
+/// Maximum number of bytes carried by the OP_RETURN output, as relayed by default by Bitcoin Core.
+pub const MAX_OP_RETURN_DATA_SIZE: usize = 80;
+
+/// Builds the script of a zero-value OP_RETURN output carrying `data`.
+pub fn op_return_script(data: &[u8]) -> Result<ScriptBuf, String> {
+    if data.len() > MAX_OP_RETURN_DATA_SIZE {
+        return Err(format!(
+            "OP_RETURN data must have at most {} bytes, got {}",
+            MAX_OP_RETURN_DATA_SIZE,
+            data.len()
+        ));
+    }
+    let data = PushBytesBuf::try_from(data.to_vec()).map_err(|e| e.to_string())?;
+    Ok(ScriptBuf::new_op_return(data))
+}
+
+/// A fixed share of the coinbase reward that is always paid to `script_pubkey`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinbaseOutputSplit {
+    pub script_pubkey: ScriptBuf,
+    pub basis_points: u16,
+}
+
+/// Builds the coinbase outputs for `coinbase_tx_value_remaining`.
+///
+/// Every split gets its share of the reward (rounded down), and the remainder goes to
+/// `payout_script`, which is always the first output. Splits whose share would be dust are
+/// skipped and their share is added to the remainder. If set, the zero-value `op_return` output
+/// comes last.
+pub fn build_coinbase_outputs(
+    coinbase_tx_value_remaining: u64,
+    payout_script: &ScriptBuf,
+    splits: &[CoinbaseOutputSplit],
+    op_return: Option<&ScriptBuf>,
+) -> Vec<TxOut> {
+    let mut split_outputs = Vec::with_capacity(splits.len());
+    let mut remainder = coinbase_tx_value_remaining;
+
+    for split in splits {
+        let value = (coinbase_tx_value_remaining as u128 * split.basis_points as u128
+            / BASIS_POINTS_TOTAL as u128) as u64;
+        if value < split.script_pubkey.minimal_non_dust().to_sat() {
+            continue;
+        }
+        remainder -= value;
+        split_outputs.push(TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: split.script_pubkey.clone(),
+        });
+    }
+
+    let mut outputs = vec![TxOut {
+        value: Amount::from_sat(remainder),
+        script_pubkey: payout_script.clone(),
+    }];
+    outputs.extend(split_outputs);
+    if let Some(op_return) = op_return {
+        outputs.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: op_return.clone(),
+        });
+    }
+    outputs
+}
+
+/// Size in bytes of the serialized `outputs`, as reported to the Template Provider via
+/// `CoinbaseOutputConstraints.coinbase_output_max_additional_size`.
+pub fn coinbase_outputs_size(outputs: &[TxOut]) -> u32 {
+    outputs.iter().map(|output| output.size() as u32).sum()
+}
+
+/// Sigop cost of `outputs`, as reported to the Template Provider via
+/// `CoinbaseOutputConstraints.coinbase_output_max_additional_sigops`.
+///
+/// Output scripts are counted as legacy sigops, which weigh `WITNESS_SCALE_FACTOR` each
+/// towards the block sigop cost limit.
+pub fn coinbase_outputs_sigops(outputs: &[TxOut]) -> u16 {
+    outputs
+        .iter()
+        .map(|output| (output.script_pubkey.count_sigops_legacy() * WITNESS_SCALE_FACTOR) as u16)
+        .sum()
+}
+
+/// Computes the `(coinbase_output_max_additional_size, coinbase_output_max_additional_sigops)`
+/// constraints for the outputs we add to the coinbase.
+///
+/// If `any_payout_script` is set, the payout script is only known once a channel is opened,
+/// so room is reserved for the largest and the most sigop-heavy standard address types.
+pub fn coinbase_output_constraints(
+    payout_script: &ScriptBuf,
+    splits: &[CoinbaseOutputSplit],
+    op_return: Option<&ScriptBuf>,
+    any_payout_script: bool,
+) -> (u32, u16) {
+    let mut payout_scripts = vec![payout_script.clone()];
+    if any_payout_script {
+        // P2WSH and P2TR have the largest scripts, P2PKH has the most sigops
+        payout_scripts.push(ScriptBuf::new_p2wsh(&WScriptHash::all_zeros()));
+        payout_scripts.push(ScriptBuf::new_p2pkh(&PubkeyHash::all_zeros()));
+    }
+
+    payout_scripts
+        .iter()
+        .map(|payout_script| {
+            // every split gets an output when the reward is as large as it can be
+            let outputs = build_coinbase_outputs(
+                Amount::MAX_MONEY.to_sat(),
+                payout_script,
+                splits,
+                op_return,
+            );
+            (
+                coinbase_outputs_size(&outputs),
+                coinbase_outputs_sigops(&outputs),
+            )
+        })
+        .fold((0, 0), |(max_size, max_sigops), (size, sigops)| {
+            (max_size.max(size), max_sigops.max(sigops))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Address;
+    use std::str::FromStr;
+
+    fn script(address: &str) -> ScriptBuf {
+        Address::from_str(address)
+            .unwrap()
+            .assume_checked()
+            .script_pubkey()
+    }
+
+    #[test]
+    fn test_build_coinbase_outputs_without_splits() {
+        let payout_script = script("tb1qw8rnkgnk7s48h6w5c0mg7we7gvzykeyp2sze82");
+        let outputs = build_coinbase_outputs(312_500_000, &payout_script, &[], None);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].value.to_sat(), 312_500_000);
+        assert_eq!(outputs[0].script_pubkey, payout_script);
+    }
+
+    #[test]
+    fn test_build_coinbase_outputs_with_splits() {
+        let payout_script = script("tb1qw8rnkgnk7s48h6w5c0mg7we7gvzykeyp2sze82");
+        let splits = vec![
+            CoinbaseOutputSplit {
+                script_pubkey: script("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn"),
+                basis_points: 3_000,
+            },
+            CoinbaseOutputSplit {
+                script_pubkey: script(
+                    "tb1pktwvz28qttg8k6r9wkzrp75lek4tnl6qn9wezfz3l8nhy57q886qf9azpd",
+                ),
+                basis_points: 1,
+            },
+        ];
+        let outputs = build_coinbase_outputs(312_500_001, &payout_script, &splits, None);
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[1].value.to_sat(), 93_750_000);
+        assert_eq!(outputs[2].value.to_sat(), 31_250);
+        // rounding leftovers go to the payout script
+        assert_eq!(outputs[0].value.to_sat(), 312_500_001 - 93_750_000 - 31_250);
+        let total: u64 = outputs.iter().map(|o| o.value.to_sat()).sum();
+        assert_eq!(total, 312_500_001);
+    }
+
+    #[test]
+    fn test_build_coinbase_outputs_skips_dust() {
+        let payout_script = script("tb1qw8rnkgnk7s48h6w5c0mg7we7gvzykeyp2sze82");
+        let splits = vec![CoinbaseOutputSplit {
+            script_pubkey: script("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn"),
+            basis_points: 1,
+        }];
+        // 0.01% of 5_000_000 sats is 500 sats, below the P2PKH dust limit
+        let outputs = build_coinbase_outputs(5_000_000, &payout_script, &splits, None);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].value.to_sat(), 5_000_000);
+    }
+
+    #[test]
+    fn test_coinbase_output_constraints_various_types() {
+        let cases = vec![
+            // (address, expected size, expected sigops, description)
+            // size is 8 bytes value + 1 byte script length + script
+            ("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", 34, 4, "P2PKH"),
+            ("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", 32, 0, "P2SH"),
+            (
+                "bc1qryhgpmfv03qjhhp2dj8nw8g4ewg08jzmgy3cyx",
+                31,
+                0,
+                "P2WPKH",
+            ),
+            (
+                "bc1p2m7q0yn78rjqh200dz0kut5xcxdnfxk4wcsau7zydnrv9ns875eq37vmkz",
+                43,
+                0,
+                "P2TR",
+            ),
+        ];
+
+        for (address, expected_size, expected_sigops, description) in cases {
+            let constraints = coinbase_output_constraints(&script(address), &[], None, false);
+            assert_eq!(
+                constraints,
+                (expected_size, expected_sigops),
+                "Failed: {}",
+                description
+            );
+        }
+    }
+
+    #[test]
+    fn test_coinbase_output_constraints_multiple_outputs() {
+        let splits = vec![
+            CoinbaseOutputSplit {
+                script_pubkey: script("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"),
+                basis_points: 100,
+            },
+            CoinbaseOutputSplit {
+                script_pubkey: script(
+                    "bc1p2m7q0yn78rjqh200dz0kut5xcxdnfxk4wcsau7zydnrv9ns875eq37vmkz",
+                ),
+                basis_points: 100,
+            },
+        ];
+        let constraints = coinbase_output_constraints(
+            &script("bc1qryhgpmfv03qjhhp2dj8nw8g4ewg08jzmgy3cyx"),
+            &splits,
+            None,
+            false,
+        );
+        assert_eq!(constraints, (31 + 34 + 43, 4));
+    }
+
+    #[test]
+    fn test_coinbase_output_constraints_any_payout_script() {
+        let constraints = coinbase_output_constraints(
+            &script("bc1qryhgpmfv03qjhhp2dj8nw8g4ewg08jzmgy3cyx"),
+            &[],
+            None,
+            true,
+        );
+        // room for a P2TR/P2WSH output, and for the sigops of a P2PKH output
+        assert_eq!(constraints, (43, 4));
+    }
+
+    #[test]
+    fn test_op_return_output() {
+        let payout_script = script("tb1qw8rnkgnk7s48h6w5c0mg7we7gvzykeyp2sze82");
+        let op_return = op_return_script(b"plebs be hashin").unwrap();
+        assert_eq!(
+            op_return.to_hex_string(),
+            "6a0f706c6562732062652068617368696e"
+        );
+
+        let outputs = build_coinbase_outputs(312_500_000, &payout_script, &[], Some(&op_return));
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].value.to_sat(), 312_500_000);
+        assert_eq!(outputs[1].value, Amount::ZERO);
+        assert_eq!(outputs[1].script_pubkey, op_return);
+
+        // 8 bytes value + 1 byte script length + OP_RETURN OP_PUSHBYTES_15 + 15 bytes
+        let constraints = coinbase_output_constraints(&payout_script, &[], Some(&op_return), false);
+        assert_eq!(constraints, (31 + 26, 0));
+
+        // OP_RETURN OP_PUSHDATA1 80 + 80 bytes
+        let op_return = op_return_script(&[0xab; MAX_OP_RETURN_DATA_SIZE]).unwrap();
+        assert_eq!(op_return.len(), 83);
+        assert!(op_return_script(&[0xab; MAX_OP_RETURN_DATA_SIZE + 1]).is_err());
+    }
+}
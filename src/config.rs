@@ -1,13 +1,22 @@
-use bitcoin::Address;
+use crate::access::TOKEN_SEPARATOR;
+use crate::coinbase::{
+    coinbase_output_constraints, op_return_script, CoinbaseOutputSplit, BASIS_POINTS_TOTAL,
+};
+use crate::descriptor::PayoutDescriptor;
+use crate::sv1_server::MIN_EXTRANONCE2_SIZE;
+use bitcoin::hex::FromHex;
+use bitcoin::{Address, Network};
 use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
 use std::fs;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use sv2_services::client::service::config::Sv2ClientServiceConfig;
 use sv2_services::client::service::config::Sv2ClientServiceTemplateDistributionConfig;
 use sv2_services::key_utils::Secp256k1PublicKey;
 use sv2_services::key_utils::Secp256k1SecretKey;
+use sv2_services::roles_logic_sv2::mining_sv2::MAX_EXTRANONCE_LEN;
 use sv2_services::server::service::config::Sv2ServerServiceConfig;
 use sv2_services::server::service::config::Sv2ServerServiceMiningConfig;
 use sv2_services::server::service::config::Sv2ServerTcpConfig;
@@ -18,10 +27,333 @@ pub struct PlebLotteryMiningServerConfig {
     pub priv_key: Secp256k1SecretKey,
     pub cert_validity: u64,
     pub inactivity_limit: u64,
+    pub network: Network,
     pub coinbase_output_script: bitcoin::ScriptBuf,
+    pub coinbase_output_descriptor: Option<PayoutDescriptor>,
+    pub payout_rotation: PayoutRotation,
+    pub payout_index_file: PathBuf,
+    pub found_blocks_file: PathBuf,
+    pub user_identity_payout: UserIdentityPayoutMode,
+    pub coinbase_output_splits: Vec<CoinbaseOutputSplit>,
+    pub coinbase_op_return: Option<bitcoin::ScriptBuf>,
     pub coinbase_tag: String,
+    pub coinbase_worker_tag_size: usize,
+    pub extranonce_counter_size: usize,
     pub share_batch_size: usize,
     pub expected_shares_per_minute: f32,
+    pub template_sanity: TemplateSanityConfig,
+    pub vardiff: VardiffConfig,
+    pub sv1: Option<Sv1Config>,
+    pub access: Option<AccessConfig>,
+    pub ban: Option<BanConfig>,
+    pub limits: LimitsConfig,
+    pub share_rate_limit: Option<ShareRateLimitConfig>,
+}
+
+/// Checks templates and prev hashes from the Template Provider must pass before jobs are built on
+/// them.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct TemplateSanityConfig {
+    /// Reject templates paying less than the block subsidy at their BIP34 height.
+    #[serde(default = "default_true")]
+    pub check_subsidy: bool,
+    /// Reject templates whose height doesn't follow the current chain tip.
+    #[serde(default = "default_true")]
+    pub check_height: bool,
+    /// Reject prev hashes whose header timestamp is further than `max_time_drift` seconds from
+    /// local time.
+    #[serde(default = "default_true")]
+    pub check_time_drift: bool,
+    #[serde(default = "default_max_time_drift")]
+    pub max_time_drift: u64,
+    /// Reject prev hashes changing nbits outside of a difficulty adjustment.
+    #[serde(default = "default_true")]
+    pub check_nbits: bool,
+}
+
+impl Default for TemplateSanityConfig {
+    fn default() -> Self {
+        Self {
+            check_subsidy: true,
+            check_height: true,
+            check_time_drift: true,
+            max_time_drift: default_max_time_drift(),
+            check_nbits: true,
+        }
+    }
+}
+
+/// Periodic retargeting of channels towards `expected_shares_per_minute`, based on the shares
+/// they actually submit.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct VardiffConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Seconds between share rate measurements.
+    #[serde(default = "default_vardiff_interval")]
+    pub interval: u64,
+    /// Channels are only retargeted when their share rate is off from
+    /// `expected_shares_per_minute` by more than this fraction.
+    #[serde(default = "default_vardiff_hysteresis")]
+    pub hysteresis: f32,
+    /// Bounds on the difficulty of retargeted channels, where difficulty 1 takes 2^32 hashes
+    /// per share.
+    pub min_difficulty: Option<f64>,
+    pub max_difficulty: Option<f64>,
+}
+
+impl Default for VardiffConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: default_vardiff_interval(),
+            hysteresis: default_vardiff_hysteresis(),
+            min_difficulty: None,
+            max_difficulty: None,
+        }
+    }
+}
+
+/// Stratum V1 listener for miners without Stratum V2 support, mapping every connection onto an
+/// extended channel.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Sv1Config {
+    pub listening_port: u16,
+    /// Nominal hashrate channels are opened with, unless the miner suggests a difficulty.
+    #[serde(default = "default_sv1_nominal_hashrate")]
+    pub nominal_hashrate: f32,
+}
+
+/// Restricts which `user_identity` may open channels. Without it, every client reaching
+/// `listening_port` can mine.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct AccessConfig {
+    /// Identities allowed to open channels, where `*` matches any run of characters and `?` any
+    /// single one.
+    #[serde(default)]
+    pub allowed_identities: Vec<String>,
+    /// Secret tokens by identity, which workers append to their `user_identity` as
+    /// `identity:token`.
+    #[serde(default)]
+    pub tokens: HashMap<String, String>,
+}
+
+/// Caps on connections and channels, so a single client can't exhaust the extranonce prefixes
+/// of the mining server. Unset limits don't apply.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct LimitsConfig {
+    /// Connected clients, Stratum V1 and Stratum V2 ones alike.
+    pub max_clients: Option<usize>,
+    /// Standard and extended channels of a single client.
+    pub max_channels_per_client: Option<usize>,
+    /// Connections from a single address, Stratum V1 and Stratum V2 ones alike.
+    pub max_connections_per_ip: Option<usize>,
+}
+
+/// Token buckets limiting the share rate of every channel, and of every client, to a multiple of
+/// the rate expected from the target of the channel and `expected_shares_per_minute`. Shares
+/// over the limit are rejected before they take any lock of the mining server.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ShareRateLimitConfig {
+    /// Multiple of its expected share rate a channel may submit shares at, leaving room for
+    /// hashrate above the nominal one until vardiff catches up.
+    #[serde(default = "default_share_rate_factor")]
+    pub rate_factor: f64,
+    /// Seconds of shares at that rate submitted at once, as after a new job.
+    #[serde(default = "default_share_burst_seconds")]
+    pub burst_seconds: f64,
+}
+
+impl Default for ShareRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            rate_factor: default_share_rate_factor(),
+            burst_seconds: default_share_burst_seconds(),
+        }
+    }
+}
+
+/// Automatic banning of clients misbehaving on their shares or the protocol. Every offense adds
+/// to the score of the client, of its address, and of the worker it happened on if an access
+/// token authenticated it (the `user_identity` of its channel, without its token), which decays
+/// over time.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BanConfig {
+    /// Score at which a client is disconnected, or a worker banned, disconnecting every client with
+    /// a channel of it.
+    #[serde(default = "default_client_ban_score")]
+    pub client_ban_score: f64,
+    /// Score at which every connection from an address is disconnected, and the address banned.
+    #[serde(default = "default_ip_ban_score")]
+    pub ip_ban_score: f64,
+    /// Seconds a client or a worker stays banned.
+    #[serde(default = "default_client_ban_duration")]
+    pub client_ban_duration: u64,
+    /// Seconds an address stays banned.
+    #[serde(default = "default_ip_ban_duration")]
+    pub ip_ban_duration: u64,
+    /// Seconds it takes a score to halve.
+    #[serde(default = "default_score_half_life")]
+    pub score_half_life: u64,
+    #[serde(default)]
+    pub scores: OffenseScores,
+}
+
+impl Default for BanConfig {
+    fn default() -> Self {
+        Self {
+            client_ban_score: default_client_ban_score(),
+            ip_ban_score: default_ip_ban_score(),
+            client_ban_duration: default_client_ban_duration(),
+            ip_ban_duration: default_ip_ban_duration(),
+            score_half_life: default_score_half_life(),
+            scores: OffenseScores::default(),
+        }
+    }
+}
+
+/// Score of every offense. Stale shares are light, as every miner submits a few of them after a
+/// new block.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OffenseScores {
+    pub invalid_share: f64,
+    pub stale_share: f64,
+    pub invalid_job_id: f64,
+    pub difficulty_too_low: f64,
+    pub duplicate_share: f64,
+    pub unknown_channel: f64,
+    pub protocol_error: f64,
+}
+
+impl Default for OffenseScores {
+    fn default() -> Self {
+        Self {
+            invalid_share: 10.0,
+            stale_share: 1.0,
+            invalid_job_id: 5.0,
+            difficulty_too_low: 5.0,
+            duplicate_share: 10.0,
+            unknown_channel: 10.0,
+            protocol_error: 25.0,
+        }
+    }
+}
+
+fn default_share_rate_factor() -> f64 {
+    10.0
+}
+
+fn default_share_burst_seconds() -> f64 {
+    30.0
+}
+
+fn default_client_ban_score() -> f64 {
+    100.0
+}
+
+fn default_ip_ban_score() -> f64 {
+    300.0
+}
+
+fn default_client_ban_duration() -> u64 {
+    10 * 60
+}
+
+fn default_ip_ban_duration() -> u64 {
+    60 * 60
+}
+
+fn default_score_half_life() -> u64 {
+    60
+}
+
+fn default_sv1_nominal_hashrate() -> f32 {
+    1e12
+}
+
+fn default_vardiff_interval() -> u64 {
+    60
+}
+
+fn default_vardiff_hysteresis() -> f32 {
+    0.3
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_time_drift() -> u64 {
+    7200
+}
+
+/// Controls whether the `user_identity` of a channel is used as its coinbase payout address.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserIdentityPayoutMode {
+    /// Every channel pays `coinbase_output_script`.
+    #[default]
+    Disabled,
+    /// Channels whose `user_identity` is not a valid address pay `coinbase_output_script`.
+    Fallback,
+    /// Channels whose `user_identity` is not a valid address are rejected.
+    Reject,
+}
+
+/// Controls when a new address is derived from `coinbase_output_descriptor`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutRotation {
+    /// Rotate after every block found.
+    #[default]
+    BlockFound,
+    /// Rotate on every new prev hash.
+    PrevHash,
+}
+
+fn default_payout_index_file() -> PathBuf {
+    PathBuf::from("payout_index")
+}
+
+fn default_found_blocks_file() -> PathBuf {
+    PathBuf::from("found_blocks.jsonl")
+}
+
+fn default_extranonce_counter_size() -> usize {
+    8
+}
+
+/// Prepended to `coinbase_tag` in the extranonce prefix of every channel.
+pub const COINBASE_TAG_PREFIX: &str = "pleblottery ";
+
+/// Largest `extranonce_counter_size`, i.e. a `u64` counter.
+pub const MAX_EXTRANONCE_COUNTER_SIZE: usize = 8;
+
+/// Name of `network` as used in the `network` config value.
+pub fn network_name(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "mainnet",
+        Network::Testnet => "testnet3",
+        Network::Testnet4 => "testnet4",
+        Network::Signet => "signet",
+        Network::Regtest => "regtest",
+        _ => "unknown",
+    }
+}
+
+/// Parses the `network` config value.
+pub fn parse_network(network: &str) -> Result<Network, String> {
+    match network {
+        "mainnet" => Ok(Network::Bitcoin),
+        "testnet3" => Ok(Network::Testnet),
+        "testnet4" => Ok(Network::Testnet4),
+        "signet" => Ok(Network::Signet),
+        "regtest" => Ok(Network::Regtest),
+        _ => Err(format!(
+            "Invalid network: {network} (expected one of mainnet, testnet3, testnet4, signet, regtest)"
+        )),
+    }
 }
 
 impl<'de> Deserialize<'de> for PlebLotteryMiningServerConfig {
@@ -29,6 +361,13 @@ impl<'de> Deserialize<'de> for PlebLotteryMiningServerConfig {
     where
         D: Deserializer<'de>,
     {
+        #[derive(Deserialize)]
+        struct SplitHelper {
+            address: String,
+            percent: Option<f64>,
+            basis_points: Option<u16>,
+        }
+
         #[derive(Deserialize)]
         struct Helper {
             listening_port: u16,
@@ -36,34 +375,248 @@ impl<'de> Deserialize<'de> for PlebLotteryMiningServerConfig {
             priv_key: Secp256k1SecretKey,
             cert_validity: u64,
             inactivity_limit: u64,
-            coinbase_output_address: String,
+            network: String,
+            coinbase_output_address: Option<String>,
+            coinbase_output_descriptor: Option<String>,
+            #[serde(default)]
+            payout_rotation: PayoutRotation,
+            #[serde(default = "default_payout_index_file")]
+            payout_index_file: PathBuf,
+            #[serde(default = "default_found_blocks_file")]
+            found_blocks_file: PathBuf,
+            #[serde(default)]
+            user_identity_payout: UserIdentityPayoutMode,
+            #[serde(default)]
+            coinbase_output_splits: Vec<SplitHelper>,
+            coinbase_op_return: Option<String>,
+            coinbase_op_return_hex: Option<String>,
             coinbase_tag: String,
+            #[serde(default)]
+            coinbase_worker_tag_size: usize,
+            #[serde(default = "default_extranonce_counter_size")]
+            extranonce_counter_size: usize,
             share_batch_size: usize,
             expected_shares_per_minute: f32,
+            #[serde(default)]
+            template_sanity: TemplateSanityConfig,
+            #[serde(default)]
+            vardiff: VardiffConfig,
+            sv1: Option<Sv1Config>,
+            access: Option<AccessConfig>,
+            ban: Option<BanConfig>,
+            #[serde(default)]
+            limits: LimitsConfig,
+            share_rate_limit: Option<ShareRateLimitConfig>,
         }
         let helper = Helper::deserialize(deserializer).map_err(|e| {
             serde::de::Error::custom(format!("Failed to deserialize mining server config: {e}"))
         })?;
 
-        if helper.coinbase_tag.len() > 8 {
+        if helper.extranonce_counter_size == 0
+            || helper.extranonce_counter_size > MAX_EXTRANONCE_COUNTER_SIZE
+        {
+            return Err(serde::de::Error::custom(format!(
+                "extranonce_counter_size must be between 1 and {MAX_EXTRANONCE_COUNTER_SIZE} bytes"
+            )));
+        }
+        let extranonce_prefix_size = COINBASE_TAG_PREFIX.len()
+            + helper.coinbase_tag.len()
+            + helper.coinbase_worker_tag_size
+            + helper.extranonce_counter_size;
+        if extranonce_prefix_size > MAX_EXTRANONCE_LEN {
+            return Err(serde::de::Error::custom(format!(
+                "coinbase_tag ({} bytes), coinbase_worker_tag_size and extranonce_counter_size must add up to at most {} bytes",
+                helper.coinbase_tag.len(),
+                MAX_EXTRANONCE_LEN - COINBASE_TAG_PREFIX.len()
+            )));
+        }
+
+        if helper.sv1.is_some()
+            && MAX_EXTRANONCE_LEN - extranonce_prefix_size < MIN_EXTRANONCE2_SIZE
+        {
+            return Err(serde::de::Error::custom(format!(
+                "the Stratum V1 listener needs at least {} bytes of extranonce rollable by miners, shorten coinbase_tag, coinbase_worker_tag_size or extranonce_counter_size",
+                MIN_EXTRANONCE2_SIZE
+            )));
+        }
+
+        if let Some(access) = &helper.access {
+            for (identity, token) in &access.tokens {
+                if identity.contains(TOKEN_SEPARATOR) || token.is_empty() {
+                    return Err(serde::de::Error::custom(format!(
+                        "the access token of {} must be non-empty, and its identity can't contain '{}'",
+                        identity, TOKEN_SEPARATOR
+                    )));
+                }
+            }
+        }
+
+        if let Some(ban) = &helper.ban {
+            let scores = &ban.scores;
+            if ban.client_ban_score <= 0.0
+                || ban.ip_ban_score <= 0.0
+                || ban.score_half_life == 0
+                || [
+                    scores.invalid_share,
+                    scores.stale_share,
+                    scores.invalid_job_id,
+                    scores.difficulty_too_low,
+                    scores.duplicate_share,
+                    scores.unknown_channel,
+                    scores.protocol_error,
+                ]
+                .iter()
+                .any(|score| *score < 0.0)
+            {
+                return Err(serde::de::Error::custom(
+                    "ban scores must be positive, offense scores can't be negative, and score_half_life must be at least 1 second",
+                ));
+            }
+        }
+
+        let limits = &helper.limits;
+        if [
+            limits.max_clients,
+            limits.max_channels_per_client,
+            limits.max_connections_per_ip,
+        ]
+        .contains(&Some(0))
+        {
+            return Err(serde::de::Error::custom(
+                "connection and channel limits must be at least 1",
+            ));
+        }
+
+        if let Some(share_rate_limit) = &helper.share_rate_limit {
+            if share_rate_limit.rate_factor <= 0.0 || share_rate_limit.burst_seconds <= 0.0 {
+                return Err(serde::de::Error::custom(
+                    "share rate_factor and burst_seconds must be positive",
+                ));
+            }
+        }
+
+        let network = parse_network(&helper.network).map_err(serde::de::Error::custom)?;
+
+        let (coinbase_output_script, coinbase_output_descriptor) = match (
+            helper.coinbase_output_address,
+            helper.coinbase_output_descriptor,
+        ) {
+            (Some(address), None) => {
+                let address = Address::from_str(&address)
+                    .map_err(|e| {
+                        serde::de::Error::custom(format!("Invalid coinbase output address: {e}"))
+                    })?
+                    .require_network(network)
+                    .map_err(|e| {
+                        serde::de::Error::custom(format!("Invalid coinbase output address: {e}"))
+                    })?;
+                (address.script_pubkey(), None)
+            }
+            (None, Some(descriptor)) => {
+                let descriptor = PayoutDescriptor::parse(&descriptor, network).map_err(|e| {
+                    serde::de::Error::custom(format!("Invalid coinbase output descriptor: {e}"))
+                })?;
+                // every address derived from the descriptor has the same script type, so the
+                // first one stands in for the others until the handler loads the actual index
+                let script = descriptor.script_pubkey(0).map_err(|e| {
+                    serde::de::Error::custom(format!("Invalid coinbase output descriptor: {e}"))
+                })?;
+                (script, Some(descriptor))
+            }
+            _ => return Err(serde::de::Error::custom(
+                "exactly one of coinbase_output_address or coinbase_output_descriptor must be set",
+            )),
+        };
+
+        let mut coinbase_output_splits = Vec::with_capacity(helper.coinbase_output_splits.len());
+        for split in helper.coinbase_output_splits {
+            let split_address = Address::from_str(&split.address)
+                .map_err(|e| {
+                    serde::de::Error::custom(format!("Invalid coinbase output split address: {e}"))
+                })?
+                .require_network(network)
+                .map_err(|e| {
+                    serde::de::Error::custom(format!("Invalid coinbase output split address: {e}"))
+                })?;
+            let basis_points = match (split.percent, split.basis_points) {
+                (Some(percent), None) if percent > 0.0 && percent < 100.0 => {
+                    (percent * 100.0).round() as u16
+                }
+                (None, Some(basis_points)) => basis_points,
+                _ => {
+                    return Err(serde::de::Error::custom(format!(
+                        "coinbase output split for {} must have either percent (between 0 and 100) or basis_points",
+                        split.address
+                    )))
+                }
+            };
+            if basis_points == 0 {
+                return Err(serde::de::Error::custom(format!(
+                    "coinbase output split for {} must have a non-zero share",
+                    split.address
+                )));
+            }
+            coinbase_output_splits.push(CoinbaseOutputSplit {
+                script_pubkey: split_address.script_pubkey(),
+                basis_points,
+            });
+        }
+
+        let total_basis_points: u64 = coinbase_output_splits
+            .iter()
+            .map(|split| split.basis_points as u64)
+            .sum();
+        if total_basis_points >= BASIS_POINTS_TOTAL {
             return Err(serde::de::Error::custom(
-                "coinbase_tag must have at most 8 characters",
+                "coinbase output splits must add up to less than 100%, the remainder is paid to the coinbase output address",
             ));
         }
 
-        let address = Address::from_str(&helper.coinbase_output_address)
-            .map_err(|e| serde::de::Error::custom(format!("Invalid coinbase output address: {e}")))?
-            .assume_checked();
+        let coinbase_op_return_data =
+            match (helper.coinbase_op_return, helper.coinbase_op_return_hex) {
+                (None, None) => None,
+                (Some(text), None) => Some(text.into_bytes()),
+                (None, Some(hex)) => Some(Vec::<u8>::from_hex(&hex).map_err(|e| {
+                    serde::de::Error::custom(format!("Invalid coinbase_op_return_hex: {e}"))
+                })?),
+                (Some(_), Some(_)) => {
+                    return Err(serde::de::Error::custom(
+                        "at most one of coinbase_op_return or coinbase_op_return_hex can be set",
+                    ))
+                }
+            };
+        let coinbase_op_return = coinbase_op_return_data
+            .map(|data| op_return_script(&data))
+            .transpose()
+            .map_err(|e| serde::de::Error::custom(format!("Invalid coinbase OP_RETURN: {e}")))?;
+
         Ok(PlebLotteryMiningServerConfig {
             listening_port: helper.listening_port,
             pub_key: helper.pub_key,
             priv_key: helper.priv_key,
             cert_validity: helper.cert_validity,
             inactivity_limit: helper.inactivity_limit,
-            coinbase_output_script: address.script_pubkey(),
+            network,
+            coinbase_output_script,
+            coinbase_output_descriptor,
+            payout_rotation: helper.payout_rotation,
+            payout_index_file: helper.payout_index_file,
+            found_blocks_file: helper.found_blocks_file,
+            user_identity_payout: helper.user_identity_payout,
+            coinbase_output_splits,
+            coinbase_op_return,
             coinbase_tag: helper.coinbase_tag,
+            coinbase_worker_tag_size: helper.coinbase_worker_tag_size,
+            extranonce_counter_size: helper.extranonce_counter_size,
             share_batch_size: helper.share_batch_size,
             expected_shares_per_minute: helper.expected_shares_per_minute,
+            template_sanity: helper.template_sanity,
+            vardiff: helper.vardiff,
+            sv1: helper.sv1,
+            access: helper.access,
+            ban: helper.ban,
+            limits: helper.limits,
+            share_rate_limit: helper.share_rate_limit,
         })
     }
 }
@@ -71,7 +624,53 @@ impl<'de> Deserialize<'de> for PlebLotteryMiningServerConfig {
 #[derive(Clone, Deserialize, Debug)]
 pub struct PlebLotteryTemplateDistributionClientConfig {
     pub server_addr: SocketAddr,
+    #[serde(default)]
+    pub fallback_server_addrs: Vec<SocketAddr>,
     pub auth_pk: Option<Secp256k1PublicKey>,
+    pub silence_timeout: Option<u64>,
+    #[serde(default = "default_reconnect_min_delay")]
+    pub reconnect_min_delay: u64,
+    #[serde(default = "default_reconnect_max_delay")]
+    pub reconnect_max_delay: u64,
+}
+
+fn default_reconnect_min_delay() -> u64 {
+    1
+}
+
+fn default_reconnect_max_delay() -> u64 {
+    60
+}
+
+impl Default for PlebLotteryTemplateDistributionClientConfig {
+    fn default() -> Self {
+        Self {
+            server_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8442),
+            fallback_server_addrs: vec![],
+            auth_pk: None,
+            silence_timeout: None,
+            reconnect_min_delay: default_reconnect_min_delay(),
+            reconnect_max_delay: default_reconnect_max_delay(),
+        }
+    }
+}
+
+/// Connection to the JSON-RPC interface of a Bitcoin node.
+#[derive(Clone, Deserialize, Debug)]
+pub struct PlebLotteryBitcoinRpcConfig {
+    pub url: String,
+    pub rpc_user: Option<String>,
+    pub rpc_password: Option<String>,
+    pub cookie_file: Option<PathBuf>,
+    /// Get templates via `getblocktemplate` instead of from the Template Provider.
+    #[serde(default)]
+    pub getblocktemplate: bool,
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+}
+
+fn default_poll_interval() -> u64 {
+    5
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -82,7 +681,9 @@ pub struct PlebLotteryWebConfig {
 #[derive(Clone, Deserialize, Debug)]
 pub struct PleblotteryConfig {
     pub mining_server_config: PlebLotteryMiningServerConfig,
+    #[serde(default)]
     pub template_distribution_config: PlebLotteryTemplateDistributionClientConfig,
+    pub bitcoin_rpc_config: Option<PlebLotteryBitcoinRpcConfig>,
     pub web_config: PlebLotteryWebConfig,
 }
 
@@ -120,8 +721,47 @@ impl From<PlebLotteryMiningServerConfig> for Sv2ServerServiceConfig {
     }
 }
 
-impl From<PlebLotteryTemplateDistributionClientConfig> for Sv2ClientServiceConfig {
-    fn from(config: PlebLotteryTemplateDistributionClientConfig) -> Self {
+impl PlebLotteryMiningServerConfig {
+    /// Tag placed at the start of the extranonce prefix of every channel.
+    pub fn full_coinbase_tag(&self) -> String {
+        format!("{}{}", COINBASE_TAG_PREFIX, self.coinbase_tag)
+    }
+
+    /// Number of extranonce bytes left for miners to roll on extended channels.
+    pub fn rollable_extranonce_size(&self) -> usize {
+        MAX_EXTRANONCE_LEN.saturating_sub(
+            self.full_coinbase_tag().len()
+                + self.coinbase_worker_tag_size
+                + self.extranonce_counter_size,
+        )
+    }
+
+    /// Coinbase output constraints for the outputs built from this config.
+    pub fn coinbase_output_constraints(&self) -> (u32, u16) {
+        coinbase_output_constraints(
+            &self.coinbase_output_script,
+            &self.coinbase_output_splits,
+            self.coinbase_op_return.as_ref(),
+            self.user_identity_payout != UserIdentityPayoutMode::Disabled,
+        )
+    }
+}
+
+impl PlebLotteryTemplateDistributionClientConfig {
+    /// Template Providers in order of priority, `server_addr` first.
+    pub fn server_addrs(&self) -> Vec<SocketAddr> {
+        let mut server_addrs = vec![self.server_addr];
+        server_addrs.extend(self.fallback_server_addrs.iter().copied());
+        server_addrs
+    }
+
+    /// Builds the `Sv2ClientServiceConfig` connecting to `server_addr`, reserving
+    /// `coinbase_output_constraints` in the coinbase of every template.
+    pub fn into_sv2_client_service_config(
+        self,
+        server_addr: SocketAddr,
+        coinbase_output_constraints: (u32, u16),
+    ) -> Sv2ClientServiceConfig {
         Sv2ClientServiceConfig {
             min_supported_version: 2,
             max_supported_version: 2,
@@ -134,9 +774,9 @@ impl From<PlebLotteryTemplateDistributionClientConfig> for Sv2ClientServiceConfi
             mining_config: None,
             job_declaration_config: None,
             template_distribution_config: Some(Sv2ClientServiceTemplateDistributionConfig {
-                server_addr: config.server_addr,
-                auth_pk: config.auth_pk,
-                coinbase_output_constraints: (1, 1), // todo: fix this
+                server_addr,
+                auth_pk: self.auth_pk,
+                coinbase_output_constraints,
                 setup_connection_flags: 0,
             }),
         }
@@ -163,10 +803,27 @@ mod tests {
             priv_key: dummy_priv_key(),
             cert_validity: 3600,
             inactivity_limit: 300,
+            network: Network::Bitcoin,
             coinbase_output_script: address.script_pubkey(),
+            coinbase_output_descriptor: None,
+            payout_rotation: PayoutRotation::BlockFound,
+            payout_index_file: default_payout_index_file(),
+            found_blocks_file: default_found_blocks_file(),
+            user_identity_payout: UserIdentityPayoutMode::Disabled,
+            coinbase_output_splits: vec![],
+            coinbase_op_return: None,
             coinbase_tag: "test".to_string(),
+            coinbase_worker_tag_size: 0,
+            extranonce_counter_size: default_extranonce_counter_size(),
             share_batch_size: 10,
             expected_shares_per_minute: 1.0,
+            template_sanity: TemplateSanityConfig::default(),
+            vardiff: VardiffConfig::default(),
+            sv1: None,
+            access: None,
+            ban: None,
+            limits: LimitsConfig::default(),
+            share_rate_limit: None,
         }
     }
 
@@ -0,0 +1,265 @@
+use anyhow::{anyhow, Result};
+use bitcoin::bip32::{ChildNumber, Xpub};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Network, NetworkKind, ScriptBuf};
+use std::fmt;
+use std::str::FromStr;
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Script type of a [`PayoutDescriptor`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayoutDescriptorKind {
+    /// `pkh(KEY)`
+    Pkh,
+    /// `wpkh(KEY)`
+    Wpkh,
+    /// `sh(wpkh(KEY))`
+    ShWpkh,
+    /// `tr(KEY)`, key path only
+    Tr,
+}
+
+/// A single key output descriptor ranged over its last derivation step, e.g.
+/// `wpkh([d34db33f/84h/0h/0h]xpub.../0/*)`.
+///
+/// Only the subset needed to rotate the coinbase payout address is supported: `pkh`, `wpkh`,
+/// `sh(wpkh)` and key path only `tr`, with an extended public key followed by unhardened
+/// derivation steps ending in `/*`. The key origin is ignored and the checksum is verified if
+/// present.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayoutDescriptor {
+    pub kind: PayoutDescriptorKind,
+    pub xpub: Xpub,
+    pub path: Vec<ChildNumber>,
+    descriptor: String,
+}
+
+impl PayoutDescriptor {
+    /// Parses `descriptor`, making sure its extended public key belongs to `network`.
+    pub fn parse(descriptor: &str, network: Network) -> Result<Self> {
+        let descriptor = descriptor.trim();
+        let body = match descriptor.split_once('#') {
+            Some((body, checksum)) => {
+                if descriptor_checksum(body)? != checksum {
+                    return Err(anyhow!("Invalid descriptor checksum"));
+                }
+                body
+            }
+            None => descriptor,
+        };
+
+        let (kind, key) = if let Some(key) = strip_wrapper(body, "sh(wpkh(", "))") {
+            (PayoutDescriptorKind::ShWpkh, key)
+        } else if let Some(key) = strip_wrapper(body, "wpkh(", ")") {
+            (PayoutDescriptorKind::Wpkh, key)
+        } else if let Some(key) = strip_wrapper(body, "pkh(", ")") {
+            (PayoutDescriptorKind::Pkh, key)
+        } else if let Some(key) = strip_wrapper(body, "tr(", ")") {
+            if key.contains(',') {
+                return Err(anyhow!(
+                    "tr() descriptors with a script tree are not supported"
+                ));
+            }
+            (PayoutDescriptorKind::Tr, key)
+        } else {
+            return Err(anyhow!(
+                "Unsupported descriptor (expected one of pkh, wpkh, sh(wpkh) or tr)"
+            ));
+        };
+
+        // the key origin only matters to the wallet
+        let key = match key.strip_prefix('[') {
+            Some(key) => {
+                key.split_once(']')
+                    .ok_or_else(|| anyhow!("Unterminated key origin"))?
+                    .1
+            }
+            None => key,
+        };
+
+        let mut steps = key.split('/');
+        let xpub = Xpub::from_str(steps.next().unwrap_or_default())
+            .map_err(|e| anyhow!("Invalid extended public key: {e}"))?;
+        if xpub.network != NetworkKind::from(network) {
+            return Err(anyhow!(
+                "Extended public key does not belong to the configured network"
+            ));
+        }
+
+        let steps: Vec<&str> = steps.collect();
+        match steps.split_last() {
+            Some((&"*", steps)) => {
+                let path = steps
+                    .iter()
+                    .map(|step| {
+                        step.parse::<u32>()
+                            .ok()
+                            .and_then(|index| ChildNumber::from_normal_idx(index).ok())
+                            .ok_or_else(|| {
+                                anyhow!("Invalid derivation step {step} (only unhardened steps can be derived from an extended public key)")
+                            })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(PayoutDescriptor {
+                    kind,
+                    xpub,
+                    path,
+                    descriptor: body.to_string(),
+                })
+            }
+            _ => Err(anyhow!(
+                "Descriptor must be ranged, i.e. its key must end in /*"
+            )),
+        }
+    }
+
+    /// Derives the script pubkey at `index` of the ranged step.
+    pub fn script_pubkey(&self, index: u32) -> Result<ScriptBuf> {
+        let secp = Secp256k1::verification_only();
+        let mut path = self.path.clone();
+        path.push(ChildNumber::from_normal_idx(index)?);
+        let xpub = self.xpub.derive_pub(&secp, &path)?;
+        Ok(match self.kind {
+            PayoutDescriptorKind::Pkh => ScriptBuf::new_p2pkh(&xpub.to_pub().pubkey_hash()),
+            PayoutDescriptorKind::Wpkh => ScriptBuf::new_p2wpkh(&xpub.to_pub().wpubkey_hash()),
+            PayoutDescriptorKind::ShWpkh => ScriptBuf::new_p2sh(
+                &ScriptBuf::new_p2wpkh(&xpub.to_pub().wpubkey_hash()).script_hash(),
+            ),
+            PayoutDescriptorKind::Tr => ScriptBuf::new_p2tr(&secp, xpub.to_x_only_pub(), None),
+        })
+    }
+}
+
+impl fmt::Display for PayoutDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.descriptor)
+    }
+}
+
+fn strip_wrapper<'a>(descriptor: &'a str, prefix: &str, suffix: &str) -> Option<&'a str> {
+    descriptor.strip_prefix(prefix)?.strip_suffix(suffix)
+}
+
+fn poly_mod(mut c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    c = ((c & 0x7ffffffff) << 5) ^ val;
+    for (bit, generator) in [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if c0 & (1 << bit) != 0 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// Computes the BIP380 checksum of `descriptor`.
+fn descriptor_checksum(descriptor: &str) -> Result<String> {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = INPUT_CHARSET
+            .find(ch)
+            .ok_or_else(|| anyhow!("Invalid character {ch:?} in descriptor"))?
+            as u64;
+        c = poly_mod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = poly_mod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = poly_mod(c, class);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Address;
+
+    // BIP84 and BIP86 test vectors for the "abandon ... about" mnemonic, account 0
+    const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    const BIP86_XPUB: &str = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+
+    fn address(descriptor: &str, index: u32) -> String {
+        let descriptor = PayoutDescriptor::parse(descriptor, Network::Bitcoin).unwrap();
+        Address::from_script(&descriptor.script_pubkey(index).unwrap(), Network::Bitcoin)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_descriptor_checksum() {
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert!(PayoutDescriptor::parse(
+            &format!("wpkh({BIP84_XPUB}/0/*)#aaaaaaaa"),
+            Network::Bitcoin
+        )
+        .is_err());
+        let body = format!("wpkh({BIP84_XPUB}/0/*)");
+        let checksum = descriptor_checksum(&body).unwrap();
+        assert!(PayoutDescriptor::parse(&format!("{body}#{checksum}"), Network::Bitcoin).is_ok());
+    }
+
+    #[test]
+    fn test_derive_wpkh() {
+        let descriptor = format!("wpkh([73c5da0a/84h/0h/0h]{BIP84_XPUB}/0/*)");
+        assert_eq!(
+            address(&descriptor, 0),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        assert_eq!(
+            address(&descriptor, 1),
+            "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g"
+        );
+    }
+
+    #[test]
+    fn test_derive_tr() {
+        assert_eq!(
+            address(&format!("tr({BIP86_XPUB}/0/*)"), 0),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+    }
+
+    #[test]
+    fn test_invalid_descriptors() {
+        for descriptor in [
+            format!("wpkh({BIP84_XPUB}/0/0)"),
+            format!("wpkh({BIP84_XPUB}/0h/*)"),
+            format!("wsh({BIP84_XPUB}/0/*)"),
+            format!("tr({BIP86_XPUB}/0/*,pk({BIP86_XPUB}/1/*))"),
+        ] {
+            assert!(
+                PayoutDescriptor::parse(&descriptor, Network::Bitcoin).is_err(),
+                "{descriptor}"
+            );
+        }
+        // mainnet xpub on testnet4
+        assert!(
+            PayoutDescriptor::parse(&format!("wpkh({BIP84_XPUB}/0/*)"), Network::Testnet4).is_err()
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use sv2_services::roles_logic_sv2::mining_sv2::ExtendedExtranonce;
+use tokio::sync::RwLock;
+
+/// Hands out the extranonce prefixes of new channels, reusing the prefixes of closed channels
+/// before new ones are taken from the counter of `ExtendedExtranonce`.
+#[derive(Debug)]
+pub struct ExtranoncePrefixFactory {
+    extended_extranonce: ExtendedExtranonce,
+    released_prefixes: Vec<Vec<u8>>,
+}
+
+impl ExtranoncePrefixFactory {
+    pub fn new(extended_extranonce: ExtendedExtranonce) -> Self {
+        Self {
+            extended_extranonce,
+            released_prefixes: Vec::new(),
+        }
+    }
+
+    /// Extranonce prefix for a new channel.
+    pub fn next_prefix(&mut self) -> Result<Vec<u8>> {
+        if let Some(prefix) = self.released_prefixes.pop() {
+            return Ok(prefix);
+        }
+        self.extended_extranonce
+            .next_prefix_standard()
+            .map(|prefix| prefix.to_vec())
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+
+    /// Makes the extranonce prefix of a closed channel available to new channels.
+    pub fn release_prefix(&mut self, prefix: Vec<u8>) {
+        self.released_prefixes.push(prefix);
+    }
+}
+
+/// Extranonce prefix taken for a channel that is still being opened. It goes back to its
+/// factory when dropped, so every rejection or error on the way releases it, unless the channel
+/// opened and [`ExtranoncePrefixLease::keep`] handed it over.
+#[derive(Debug)]
+pub struct ExtranoncePrefixLease {
+    factory: Arc<RwLock<ExtranoncePrefixFactory>>,
+    prefix: Option<Vec<u8>>,
+}
+
+impl ExtranoncePrefixLease {
+    pub async fn take(factory: &Arc<RwLock<ExtranoncePrefixFactory>>) -> Result<Self> {
+        let prefix = factory.write().await.next_prefix()?;
+        Ok(Self {
+            factory: factory.clone(),
+            prefix: Some(prefix),
+        })
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        self.prefix.as_ref().expect("prefix is only taken on drop")
+    }
+
+    pub fn prefix_mut(&mut self) -> &mut [u8] {
+        self.prefix.as_mut().expect("prefix is only taken on drop")
+    }
+
+    /// Keeps the prefix for an opened channel, which releases it once closed.
+    pub fn keep(mut self) {
+        self.prefix = None;
+    }
+}
+
+impl Drop for ExtranoncePrefixLease {
+    fn drop(&mut self) {
+        let Some(prefix) = self.prefix.take() else {
+            return;
+        };
+        // drop can't wait for the lock, which only a concurrent open or close holds
+        match self.factory.try_write() {
+            Ok(mut factory) => factory.release_prefix(prefix),
+            Err(_) => {
+                let factory = self.factory.clone();
+                tokio::spawn(async move { factory.write().await.release_prefix(prefix) });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_released_prefixes_are_reused() {
+        let extended_extranonce = ExtendedExtranonce::new(0..0, 0..8, 8..32, None).unwrap();
+        let mut factory = ExtranoncePrefixFactory::new(extended_extranonce);
+
+        let first = factory.next_prefix().unwrap();
+        let second = factory.next_prefix().unwrap();
+        assert_ne!(first, second);
+
+        factory.release_prefix(first.clone());
+        assert_eq!(factory.next_prefix().unwrap(), first);
+        let third = factory.next_prefix().unwrap();
+        assert_ne!(third, first);
+        assert_ne!(third, second);
+    }
+
+    #[tokio::test]
+    async fn test_lease_releases_prefix_unless_kept() {
+        let extended_extranonce = ExtendedExtranonce::new(0..0, 0..8, 8..32, None).unwrap();
+        let factory = Arc::new(RwLock::new(ExtranoncePrefixFactory::new(
+            extended_extranonce,
+        )));
+
+        let lease = ExtranoncePrefixLease::take(&factory).await.unwrap();
+        let first = lease.prefix().to_vec();
+        drop(lease);
+        let lease = ExtranoncePrefixLease::take(&factory).await.unwrap();
+        assert_eq!(lease.prefix(), first.as_slice());
+
+        lease.keep();
+        let lease = ExtranoncePrefixLease::take(&factory).await.unwrap();
+        assert_ne!(lease.prefix(), first.as_slice());
+    }
+}
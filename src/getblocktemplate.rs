@@ -0,0 +1,439 @@
+use anyhow::{anyhow, Result};
+use bitcoin::consensus::encode::{deserialize_hex, serialize};
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::{Amount, Block, BlockHash, CompactTarget, ScriptBuf, Target, Transaction, TxOut};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sv2_services::roles_logic_sv2::codec_sv2::binary_sv2::{Seq0255, U256};
+use sv2_services::roles_logic_sv2::template_distribution_sv2::{
+    NewTemplate, SetNewPrevHash, SubmitSolution,
+};
+use sv2_services::server::service::event::Sv2ServerEvent;
+use sv2_services::server::service::subprotocols::mining::trigger::MiningServerTrigger;
+use sv2_services::server::service::Sv2ServerService;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::{Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
+use tower::{Service, ServiceExt};
+use tracing::{info, warn};
+
+use crate::bitcoin_rpc::BitcoinRpcClient;
+use crate::block_submission::{
+    assemble_block, record_block_submission, BlockSubmitter, SubmissionPath, SubmissionResult,
+};
+use crate::state::{SharedStateHandle, TemplateProviderStatus};
+use crate::sv2_handlers::mining_server_handler::PlebLotteryMiningServerHandler;
+
+/// `getblocktemplate` result, as returned by Bitcoin Core.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetBlockTemplateResult {
+    pub version: u32,
+    pub previousblockhash: String,
+    pub transactions: Vec<GetBlockTemplateTransaction>,
+    pub coinbasevalue: u64,
+    pub longpollid: Option<String>,
+    pub curtime: u32,
+    pub bits: String,
+    pub height: u64,
+    pub default_witness_commitment: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetBlockTemplateTransaction {
+    pub data: String,
+}
+
+/// A decoded `getblocktemplate` result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockTemplate {
+    pub version: u32,
+    pub prev_hash: BlockHash,
+    pub transactions: Vec<Transaction>,
+    pub coinbase_value: u64,
+    pub longpollid: Option<String>,
+    pub curtime: u32,
+    pub bits: CompactTarget,
+    pub height: u64,
+    pub witness_commitment: Option<ScriptBuf>,
+}
+
+impl TryFrom<GetBlockTemplateResult> for BlockTemplate {
+    type Error = anyhow::Error;
+
+    fn try_from(result: GetBlockTemplateResult) -> Result<Self> {
+        let transactions = result
+            .transactions
+            .iter()
+            .map(|transaction| deserialize_hex::<Transaction>(&transaction.data))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("Invalid transaction in block template: {}", e))?;
+        let bits = u32::from_str_radix(&result.bits, 16)
+            .map_err(|e| anyhow!("Invalid bits in block template: {}", e))?;
+        let witness_commitment = result
+            .default_witness_commitment
+            .map(|commitment| ScriptBuf::from_hex(&commitment))
+            .transpose()
+            .map_err(|e| anyhow!("Invalid witness commitment in block template: {}", e))?;
+        Ok(Self {
+            version: result.version,
+            prev_hash: BlockHash::from_str(&result.previousblockhash)
+                .map_err(|e| anyhow!("Invalid previous block hash in block template: {}", e))?,
+            transactions,
+            coinbase_value: result.coinbasevalue,
+            longpollid: result.longpollid,
+            curtime: result.curtime,
+            bits: CompactTarget::from_consensus(bits),
+            height: result.height,
+            witness_commitment,
+        })
+    }
+}
+
+impl BlockTemplate {
+    /// Whether `other` would produce the same jobs, regardless of `longpollid` and `curtime`.
+    pub fn same_work(&self, other: &BlockTemplate) -> bool {
+        self.version == other.version
+            && self.prev_hash == other.prev_hash
+            && self.bits == other.bits
+            && self.coinbase_value == other.coinbase_value
+            && self.transactions == other.transactions
+    }
+
+    /// Outputs the node requires in the coinbase, i.e. the segwit commitment.
+    fn required_coinbase_outputs(&self) -> Vec<TxOut> {
+        self.witness_commitment
+            .iter()
+            .map(|commitment| TxOut {
+                value: Amount::ZERO,
+                script_pubkey: commitment.clone(),
+            })
+            .collect()
+    }
+
+    /// Hashes needed to compute the merkle root from the coinbase txid, in internal byte order.
+    pub fn merkle_path(&self) -> Vec<[u8; 32]> {
+        let mut level: Vec<[u8; 32]> = self
+            .transactions
+            .iter()
+            .map(|transaction| transaction.compute_txid().to_byte_array())
+            .collect();
+        let mut merkle_path = Vec::new();
+        while let Some((sibling, rest)) = level.split_first() {
+            merkle_path.push(*sibling);
+            // the last hash of an odd level is paired with itself
+            level = rest
+                .chunks(2)
+                .map(|pair| {
+                    let mut engine = sha256d::Hash::engine();
+                    engine.input(&pair[0]);
+                    engine.input(&pair[pair.len() - 1]);
+                    sha256d::Hash::from_engine(engine).to_byte_array()
+                })
+                .collect();
+        }
+        merkle_path
+    }
+
+    pub fn new_template(&self, template_id: u64, future_template: bool) -> NewTemplate<'static> {
+        let coinbase_prefix = bitcoin::script::Builder::new()
+            .push_int(self.height as i64)
+            .into_script()
+            .into_bytes();
+        let required_outputs = self.required_coinbase_outputs();
+        let coinbase_tx_outputs: Vec<u8> = required_outputs.iter().flat_map(serialize).collect();
+        let merkle_path: Vec<U256<'static>> = self
+            .merkle_path()
+            .into_iter()
+            .map(|hash| hash.to_vec().try_into().expect("hash must be 32 bytes"))
+            .collect();
+
+        NewTemplate {
+            template_id,
+            future_template,
+            version: self.version,
+            coinbase_tx_version: 2,
+            coinbase_prefix: coinbase_prefix
+                .try_into()
+                .expect("BIP34 height must fit in coinbase prefix"),
+            coinbase_tx_input_sequence: u32::MAX,
+            coinbase_tx_value_remaining: self.coinbase_value,
+            coinbase_tx_outputs_count: required_outputs.len() as u32,
+            coinbase_tx_outputs: coinbase_tx_outputs
+                .try_into()
+                .expect("coinbase outputs must fit in a template"),
+            coinbase_tx_locktime: 0,
+            merkle_path: Seq0255::new(merkle_path).expect("merkle path must fit in a template"),
+        }
+    }
+
+    pub fn set_new_prev_hash(&self, template_id: u64) -> SetNewPrevHash<'static> {
+        SetNewPrevHash {
+            template_id,
+            prev_hash: self
+                .prev_hash
+                .to_byte_array()
+                .to_vec()
+                .try_into()
+                .expect("block hash must be 32 bytes"),
+            header_timestamp: self.curtime,
+            n_bits: self.bits.to_consensus(),
+            target: Target::from_compact(self.bits)
+                .to_le_bytes()
+                .to_vec()
+                .try_into()
+                .expect("target must be 32 bytes"),
+        }
+    }
+
+    /// Rebuilds the full block mined by `solution` on top of this template.
+    pub fn assemble_block(&self, solution: &SubmitSolution<'_>) -> Result<Block> {
+        assemble_block(self.prev_hash, self.bits, &self.transactions, solution)
+    }
+}
+
+/// Template source polling a Bitcoin node over JSON-RPC, as an alternative to a Template
+/// Provider.
+///
+/// Templates are fed to the mining server as `NewTemplate` / `SetNewPrevHash` events, and
+/// solutions are submitted back with `submitblock`. Long polling is used when the node supports
+/// it, otherwise the node is polled every `poll_interval`.
+#[derive(Clone)]
+pub struct GetBlockTemplateSource {
+    rpc: BitcoinRpcClient,
+    block_submitter: BlockSubmitter,
+    server_service: Sv2ServerService<PlebLotteryMiningServerHandler>,
+    solutions: Arc<Mutex<UnboundedReceiver<SubmitSolution<'static>>>>,
+    shared_state: SharedStateHandle,
+    poll_interval: Duration,
+    template_id_factory: Arc<AtomicU64>,
+    last_template: Arc<RwLock<Option<BlockTemplate>>>,
+    templates: Arc<RwLock<HashMap<u64, BlockTemplate>>>, // templates on the current chain tip
+}
+
+impl GetBlockTemplateSource {
+    pub fn new(
+        rpc: BitcoinRpcClient,
+        server_service: Sv2ServerService<PlebLotteryMiningServerHandler>,
+        solutions: UnboundedReceiver<SubmitSolution<'static>>,
+        shared_state: SharedStateHandle,
+        poll_interval: Duration,
+    ) -> Self {
+        Self {
+            block_submitter: BlockSubmitter::new(rpc.clone(), shared_state.clone()),
+            rpc,
+            server_service,
+            solutions: Arc::new(Mutex::new(solutions)),
+            shared_state,
+            poll_interval,
+            template_id_factory: Arc::new(AtomicU64::new(1)),
+            last_template: Arc::new(RwLock::new(None)),
+            templates: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Fails if the node never served a template, as that is most likely a configuration error.
+    pub async fn run(&self, cancellation_token: CancellationToken) -> Result<()> {
+        tokio::select! {
+            result = self.poll_loop() => result,
+            _ = self.submit_loop() => Ok(()),
+            _ = cancellation_token.cancelled() => Ok(()),
+        }
+    }
+
+    async fn poll_loop(&self) -> Result<()> {
+        let mut longpollid: Option<String> = None;
+        loop {
+            let result = self
+                .rpc
+                .get_block_template::<GetBlockTemplateResult>(longpollid.as_deref())
+                .await
+                .and_then(BlockTemplate::try_from);
+            match result {
+                Ok(template) => {
+                    self.set_connected().await;
+                    longpollid = template.longpollid.clone();
+                    self.update_template(template).await?;
+                    if longpollid.is_some() {
+                        continue;
+                    }
+                }
+                Err(e) => {
+                    longpollid = None;
+                    let mut state = self.shared_state.write().await;
+                    if state.template_provider_status == TemplateProviderStatus::Connecting {
+                        return Err(anyhow!("Failed to get a block template: {}", e));
+                    }
+                    warn!("Failed to get a block template: {}", e);
+                    state.template_provider_status = TemplateProviderStatus::Reconnecting;
+                    state.template_provider_last_error = Some(e.to_string());
+                    state.template_provider_reconnect_attempts += 1;
+                }
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    async fn set_connected(&self) {
+        let mut state = self.shared_state.write().await;
+        state.template_provider_last_message = Some(Instant::now());
+        if state.template_provider_status != TemplateProviderStatus::Connected {
+            info!("Getting block templates from the Bitcoin node");
+            state.template_provider_status = TemplateProviderStatus::Connected;
+            state.template_provider_reconnect_attempts = 0;
+        }
+    }
+
+    /// Sends `template` to the mining server, unless it is the same work as the last one.
+    async fn update_template(&self, template: BlockTemplate) -> Result<()> {
+        let mut last_template = self.last_template.write().await;
+        let new_prev_hash = match last_template.as_ref() {
+            Some(last_template) if last_template.same_work(&template) => return Ok(()),
+            Some(last_template) => last_template.prev_hash != template.prev_hash,
+            None => true,
+        };
+
+        let template_id = self.template_id_factory.fetch_add(1, Ordering::Relaxed);
+        let new_template = template.new_template(template_id, new_prev_hash);
+        info!("New block template from the Bitcoin node: {}", new_template);
+        if new_prev_hash {
+            info!("Current Block Height: {}", template.height - 1);
+            let set_new_prev_hash = template.set_new_prev_hash(template_id);
+            {
+                let mut templates = self.templates.write().await;
+                templates.clear();
+                templates.insert(template_id, template.clone());
+            }
+            self.send_to_server(MiningServerTrigger::NewTemplate(new_template))
+                .await?;
+            self.send_to_server(MiningServerTrigger::SetNewPrevHash(set_new_prev_hash))
+                .await?;
+        } else {
+            self.templates
+                .write()
+                .await
+                .insert(template_id, template.clone());
+            self.send_to_server(MiningServerTrigger::NewTemplate(new_template))
+                .await?;
+        }
+        *last_template = Some(template);
+        Ok(())
+    }
+
+    async fn send_to_server(&self, trigger: MiningServerTrigger<'static>) -> Result<()> {
+        let mut server_service = self.server_service.clone();
+        server_service
+            .ready()
+            .await
+            .map_err(|e| anyhow!("Mining server is not ready: {:?}", e))?
+            .call(Sv2ServerEvent::MiningTrigger(trigger))
+            .await
+            .map_err(|e| anyhow!("Mining server failed to handle block template: {:?}", e))?;
+        Ok(())
+    }
+
+    async fn submit_loop(&self) {
+        let mut solutions = self.solutions.lock().await;
+        while let Some(solution) = solutions.recv().await {
+            let template = self
+                .templates
+                .read()
+                .await
+                .get(&solution.template_id)
+                .cloned();
+            let block = match template {
+                Some(template) => template.assemble_block(&solution),
+                None => Err(anyhow!(
+                    "block template {} is unknown",
+                    solution.template_id
+                )),
+            };
+            match block {
+                Ok(block) => {
+                    self.block_submitter
+                        .submit_block(solution.template_id, block)
+                        .await
+                }
+                Err(e) => {
+                    record_block_submission(
+                        &self.shared_state,
+                        solution.template_id,
+                        None,
+                        SubmissionPath::BitcoinRpc,
+                        SubmissionResult::Failed(e.to_string()),
+                    )
+                    .await
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::merkle_root_from_path;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction;
+    use bitcoin::{OutPoint, Sequence, TxIn, Witness};
+
+    fn dummy_transaction(lock_time: u32) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::from_consensus(lock_time),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn dummy_template(n_transactions: u32) -> BlockTemplate {
+        BlockTemplate {
+            version: 0x2000_0000,
+            prev_hash: BlockHash::all_zeros(),
+            transactions: (0..n_transactions).map(dummy_transaction).collect(),
+            coinbase_value: 5_000_000_000,
+            longpollid: None,
+            curtime: 1_700_000_000,
+            bits: CompactTarget::from_consensus(0x207fffff),
+            height: 100,
+            witness_commitment: None,
+        }
+    }
+
+    #[test]
+    fn test_merkle_path_matches_merkle_root() {
+        for n_transactions in 0..8 {
+            let template = dummy_template(n_transactions);
+            let coinbase = dummy_transaction(u32::MAX);
+
+            let merkle_path: Vec<Vec<u8>> = template
+                .merkle_path()
+                .iter()
+                .map(|hash| hash.to_vec())
+                .collect();
+            let root = merkle_root_from_path(coinbase.compute_txid(), &merkle_path).unwrap();
+
+            let solution = SubmitSolution {
+                template_id: 1,
+                version: template.version,
+                header_timestamp: template.curtime,
+                header_nonce: 0,
+                coinbase_tx: serialize(&coinbase).try_into().unwrap(),
+            };
+            let block = template.assemble_block(&solution).unwrap();
+            assert_eq!(block.header.merkle_root, root);
+            assert_eq!(block.txdata.len(), n_transactions as usize + 1);
+        }
+    }
+}
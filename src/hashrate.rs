@@ -0,0 +1,289 @@
+use bitcoin::Target;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Windows hashrate is measured over, by label.
+pub const HASHRATE_WINDOWS: [(&str, Duration); 4] = [
+    ("1m", Duration::from_secs(60)),
+    ("5m", Duration::from_secs(5 * 60)),
+    ("1h", Duration::from_secs(60 * 60)),
+    ("24h", Duration::from_secs(24 * 60 * 60)),
+];
+
+/// Every window is split into this many buckets, which slide out one at a time.
+const BUCKETS_PER_WINDOW: u32 = 60;
+
+/// Expected hashes per share at difficulty 1.
+pub const HASHES_PER_DIFFICULTY: f64 = 4_294_967_296.0;
+
+/// Expected number of hashes behind a share meeting `target`, as little-endian bytes.
+pub fn share_work(target: &[u8]) -> f64 {
+    let Ok(target) = <[u8; 32]>::try_from(target) else {
+        return 0.0;
+    };
+    Target::from_le_bytes(target).difficulty_float() * HASHES_PER_DIFFICULTY
+}
+
+pub fn format_hashrate(hashrate: f64) -> String {
+    let (value, unit) = if hashrate >= 1e12 {
+        (hashrate / 1e12, "Th/s")
+    } else if hashrate >= 1e9 {
+        (hashrate / 1e9, "Gh/s")
+    } else if hashrate >= 1e6 {
+        (hashrate / 1e6, "Mh/s")
+    } else if hashrate >= 1e3 {
+        (hashrate / 1e3, "Kh/s")
+    } else {
+        (hashrate, "h/s")
+    };
+    format!("{:.2} {}", value, unit)
+}
+
+#[derive(Debug, Clone)]
+struct Window {
+    bucket_length: Duration,
+    buckets: VecDeque<(u64, f64)>, // work by bucket index
+}
+
+impl Window {
+    fn new(length: Duration) -> Self {
+        Self {
+            bucket_length: length / BUCKETS_PER_WINDOW,
+            buckets: VecDeque::new(),
+        }
+    }
+
+    fn bucket_index(&self, since: Duration) -> u64 {
+        (since.as_millis() / self.bucket_length.as_millis()) as u64
+    }
+
+    fn expire(&mut self, index: u64) {
+        while let Some((oldest, _)) = self.buckets.front() {
+            if oldest + (BUCKETS_PER_WINDOW as u64) > index {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    fn add_work(&mut self, since: Duration, work: f64) {
+        let index = self.bucket_index(since);
+        match self.buckets.back_mut() {
+            Some((last, last_work)) if *last == index => *last_work += work,
+            _ => self.buckets.push_back((index, work)),
+        }
+        self.expire(index);
+    }
+
+    fn work(&self, since: Duration) -> f64 {
+        let index = self.bucket_index(since);
+        self.buckets
+            .iter()
+            .filter(|(bucket, _)| bucket + (BUCKETS_PER_WINDOW as u64) > index)
+            .map(|(_, work)| work)
+            .sum()
+    }
+}
+
+/// Hashrate measured from the work of accepted shares over each of [`HASHRATE_WINDOWS`].
+#[derive(Debug, Clone)]
+pub struct HashrateEstimator {
+    started: Instant,
+    windows: Vec<Window>,
+}
+
+impl HashrateEstimator {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            windows: HASHRATE_WINDOWS
+                .iter()
+                .map(|(_, length)| Window::new(*length))
+                .collect(),
+        }
+    }
+
+    pub fn add_work(&mut self, now: Instant, work: f64) {
+        let since = now.saturating_duration_since(self.started);
+        for window in &mut self.windows {
+            window.add_work(since, work);
+        }
+    }
+
+    /// Hashrate over each of [`HASHRATE_WINDOWS`]. Windows longer than the time since the
+    /// estimator started are measured over that time instead, so new channels aren't
+    /// underestimated.
+    pub fn hashrates(&self, now: Instant) -> [f64; HASHRATE_WINDOWS.len()] {
+        let since = now.saturating_duration_since(self.started);
+        let mut hashrates = [0.0; HASHRATE_WINDOWS.len()];
+        for (i, (window, (_, length))) in self.windows.iter().zip(HASHRATE_WINDOWS).enumerate() {
+            let seconds = length.min(since).as_secs_f64();
+            if seconds > 0.0 {
+                hashrates[i] = window.work(since) / seconds;
+            }
+        }
+        hashrates
+    }
+
+    /// Whether no work was added over the longest window.
+    pub fn is_idle(&self, now: Instant) -> bool {
+        let since = now.saturating_duration_since(self.started);
+        self.windows
+            .last()
+            .is_none_or(|window| window.work(since) == 0.0)
+    }
+}
+
+/// Measured hashrate, in total and by client, channel and worker identity.
+#[derive(Debug, Clone)]
+pub struct MeasuredHashrate {
+    pub total: HashrateEstimator,
+    pub clients: HashMap<u32, HashrateEstimator>,
+    pub channels: HashMap<(u32, u32), HashrateEstimator>, // by client and channel id
+    pub workers: HashMap<String, HashrateEstimator>,      // by user_identity
+}
+
+impl Default for MeasuredHashrate {
+    fn default() -> Self {
+        Self {
+            total: HashrateEstimator::new(Instant::now()),
+            clients: HashMap::new(),
+            channels: HashMap::new(),
+            workers: HashMap::new(),
+        }
+    }
+}
+
+impl MeasuredHashrate {
+    /// Accounts the `work` of a share accepted on `channel_id` of `client_id`.
+    pub fn add_share(
+        &mut self,
+        client_id: u32,
+        channel_id: u32,
+        user_identity: &str,
+        work: f64,
+        now: Instant,
+    ) {
+        self.total.add_work(now, work);
+        self.clients
+            .entry(client_id)
+            .or_insert_with(|| HashrateEstimator::new(now))
+            .add_work(now, work);
+        self.channels
+            .entry((client_id, channel_id))
+            .or_insert_with(|| HashrateEstimator::new(now))
+            .add_work(now, work);
+        self.workers
+            .entry(user_identity.to_string())
+            .or_insert_with(|| HashrateEstimator::new(now))
+            .add_work(now, work);
+    }
+
+    pub fn remove_channel(&mut self, client_id: u32, channel_id: u32) {
+        self.channels.remove(&(client_id, channel_id));
+    }
+
+    /// Forgets a disconnected client and its channels, along with workers that stopped
+    /// submitting shares.
+    pub fn remove_client(&mut self, client_id: u32, now: Instant) {
+        self.clients.remove(&client_id);
+        self.channels
+            .retain(|(channel_client_id, _), _| *channel_client_id != client_id);
+        self.workers.retain(|_, estimator| !estimator.is_idle(now));
+    }
+}
+
+/// Measured hashrate over each of [`HASHRATE_WINDOWS`], next to the nominal one.
+#[derive(Debug, Clone, Serialize)]
+pub struct HashrateReport {
+    pub nominal: f64,
+    pub measured: HashMap<&'static str, f64>,
+}
+
+impl HashrateReport {
+    pub fn new(nominal: f64, estimator: Option<&HashrateEstimator>, now: Instant) -> Self {
+        let hashrates = estimator
+            .map(|estimator| estimator.hashrates(now))
+            .unwrap_or_default();
+        Self {
+            nominal,
+            measured: HASHRATE_WINDOWS
+                .iter()
+                .zip(hashrates)
+                .map(|((label, _), hashrate)| (*label, hashrate))
+                .collect(),
+        }
+    }
+
+    /// Measured hashrate over every window, such as `1.00 Th/s / 1.10 Th/s / ...`.
+    pub fn format_measured(&self) -> String {
+        HASHRATE_WINDOWS
+            .iter()
+            .map(|(label, _)| format_hashrate(self.measured.get(label).copied().unwrap_or(0.0)))
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_work() {
+        assert_eq!(
+            share_work(&Target::MAX.to_le_bytes()),
+            HASHES_PER_DIFFICULTY
+        );
+        assert_eq!(share_work(&[0u8; 31]), 0.0);
+    }
+
+    #[test]
+    fn test_hashrate_windows() {
+        let start = Instant::now();
+        let mut estimator = HashrateEstimator::new(start);
+        // 1 Gh/s for ten minutes, one share every second
+        for second in 1..=600 {
+            estimator.add_work(start + Duration::from_secs(second), 1e9);
+        }
+        let now = start + Duration::from_secs(600);
+        let [one_minute, five_minutes, one_hour, one_day] = estimator.hashrates(now);
+        assert!((one_minute - 1e9).abs() / 1e9 < 0.05);
+        assert!((five_minutes - 1e9).abs() / 1e9 < 0.05);
+        // measured over the ten minutes since the estimator started
+        assert!((one_hour - 1e9).abs() / 1e9 < 0.05);
+        assert!((one_day - 1e9).abs() / 1e9 < 0.05);
+
+        // five minutes without shares
+        let now = now + Duration::from_secs(300);
+        let [one_minute, five_minutes, one_hour, _] = estimator.hashrates(now);
+        assert_eq!(one_minute, 0.0);
+        assert!(five_minutes < 0.05e9);
+        assert!((one_hour - 600e9 / 900.0).abs() / 1e9 < 0.05);
+        assert!(!estimator.is_idle(now));
+        assert!(estimator.is_idle(now + Duration::from_secs(24 * 60 * 60)));
+    }
+
+    #[test]
+    fn test_measured_hashrate() {
+        let start = Instant::now();
+        let mut measured = MeasuredHashrate::default();
+        measured.add_share(1, 1, "alice", 1e9, start);
+        measured.add_share(1, 2, "bob", 1e9, start);
+        measured.add_share(2, 1, "alice", 1e9, start);
+        assert_eq!(measured.clients.len(), 2);
+        assert_eq!(measured.channels.len(), 3);
+        assert_eq!(measured.workers.len(), 2);
+
+        measured.remove_channel(1, 2);
+        assert_eq!(measured.channels.len(), 2);
+        measured.remove_client(1, start);
+        assert_eq!(measured.clients.len(), 1);
+        assert_eq!(measured.channels.len(), 1);
+        // bob still submitted shares recently
+        assert_eq!(measured.workers.len(), 2);
+        measured.remove_client(2, start + Duration::from_secs(25 * 60 * 60));
+        assert!(measured.workers.is_empty());
+    }
+}
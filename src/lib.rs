@@ -1,7 +1,26 @@
+pub mod access;
+pub mod ban;
+pub mod bitcoin_rpc;
+pub mod block_journal;
+pub mod block_submission;
+pub mod block_tracker;
+pub mod block_verification;
 pub mod cli;
+pub mod coinbase;
 pub mod config;
+pub mod descriptor;
+pub mod extranonce;
+pub mod getblocktemplate;
+pub mod hashrate;
+pub mod payout;
+pub mod rate_limit;
 pub mod service;
 pub mod state;
+pub mod sv1_server;
+pub mod sv2_gate;
 pub mod sv2_handlers;
+pub mod template_provider_relay;
+pub mod template_sanity;
 pub mod utils;
+pub mod vardiff;
 pub mod web;
@@ -20,12 +20,25 @@ async fn main() -> anyhow::Result<()> {
 
     let shared_state: SharedStateHandle = SharedStateHandle::default();
 
-    let mut pleblottery_service = PlebLotteryService::new(
-        config.mining_server_config,
-        config.template_distribution_config,
-        shared_state.clone(),
-    )
-    .await?;
+    let mut pleblottery_service = match config.bitcoin_rpc_config {
+        Some(bitcoin_rpc_config) if bitcoin_rpc_config.getblocktemplate => {
+            PlebLotteryService::new_with_getblocktemplate(
+                config.mining_server_config,
+                bitcoin_rpc_config,
+                shared_state.clone(),
+            )
+            .await?
+        }
+        bitcoin_rpc_config => {
+            PlebLotteryService::new_with_submitblock(
+                config.mining_server_config,
+                config.template_distribution_config,
+                bitcoin_rpc_config,
+                shared_state.clone(),
+            )
+            .await?
+        }
+    };
 
     // Use tokio::select to wait for either service completion or Ctrl+C
     tokio::select! {
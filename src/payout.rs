@@ -0,0 +1,132 @@
+use anyhow::{anyhow, Result};
+use bitcoin::ScriptBuf;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::descriptor::PayoutDescriptor;
+
+/// Rotates the coinbase payout script over the addresses of a [`PayoutDescriptor`].
+///
+/// The next derivation index is written to `index_file` as soon as a rotation is scheduled, so
+/// an address that may have been paid is never reused after a restart.
+#[derive(Debug)]
+pub struct PayoutRotator {
+    descriptor: PayoutDescriptor,
+    index_file: PathBuf,
+    index: u32,
+    script: ScriptBuf,
+    pending: bool,
+}
+
+impl PayoutRotator {
+    /// Starts from the index persisted in `index_file`, or from index 0 if it does not exist.
+    pub fn load(descriptor: PayoutDescriptor, index_file: PathBuf) -> Result<Self> {
+        let index = match fs::read_to_string(&index_file) {
+            Ok(contents) => contents
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| anyhow!("Invalid payout index in {}: {}", index_file.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => {
+                return Err(anyhow!(
+                    "Failed to read payout index from {}: {}",
+                    index_file.display(),
+                    e
+                ))
+            }
+        };
+        let script = descriptor.script_pubkey(index)?;
+        let rotator = Self {
+            descriptor,
+            index_file,
+            index,
+            script,
+            pending: false,
+        };
+        rotator.persist(index)?;
+        Ok(rotator)
+    }
+
+    /// Script currently paid by new jobs.
+    pub fn script(&self) -> &ScriptBuf {
+        &self.script
+    }
+
+    /// Derivation index of `script`.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Schedules a rotation to the next index, which takes effect on the next call to
+    /// [`PayoutRotator::rotate_if_pending`].
+    pub fn schedule(&mut self) -> Result<()> {
+        if !self.pending {
+            self.persist(self.next_index()?)?;
+            self.pending = true;
+        }
+        Ok(())
+    }
+
+    /// Moves to the next index if a rotation was scheduled. Returns whether the script changed.
+    pub fn rotate_if_pending(&mut self) -> Result<bool> {
+        if !self.pending {
+            return Ok(false);
+        }
+        let index = self.next_index()?;
+        self.script = self.descriptor.script_pubkey(index)?;
+        self.index = index;
+        self.pending = false;
+        Ok(true)
+    }
+
+    fn next_index(&self) -> Result<u32> {
+        self.index
+            .checked_add(1)
+            .ok_or_else(|| anyhow!("Payout descriptor is out of derivation indexes"))
+    }
+
+    fn persist(&self, index: u32) -> Result<()> {
+        fs::write(&self.index_file, format!("{index}\n")).map_err(|e| {
+            anyhow!(
+                "Failed to write payout index to {}: {}",
+                self.index_file.display(),
+                e
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Network;
+
+    const XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+    #[test]
+    fn test_rotation_is_persisted() {
+        let descriptor =
+            PayoutDescriptor::parse(&format!("wpkh({XPUB}/0/*)"), Network::Bitcoin).unwrap();
+        let index_file =
+            std::env::temp_dir().join(format!("pleblottery-payout-index-{}", std::process::id()));
+        let _ = fs::remove_file(&index_file);
+
+        let mut rotator = PayoutRotator::load(descriptor.clone(), index_file.clone()).unwrap();
+        assert_eq!(rotator.index(), 0);
+        assert!(!rotator.rotate_if_pending().unwrap());
+
+        rotator.schedule().unwrap();
+        rotator.schedule().unwrap();
+        // the scheduled index is persisted before it is used
+        assert_eq!(fs::read_to_string(&index_file).unwrap().trim(), "1");
+        assert_eq!(rotator.index(), 0);
+
+        assert!(rotator.rotate_if_pending().unwrap());
+        assert_eq!(rotator.index(), 1);
+        assert_eq!(rotator.script(), &descriptor.script_pubkey(1).unwrap());
+
+        let rotator = PayoutRotator::load(descriptor, index_file.clone()).unwrap();
+        assert_eq!(rotator.index(), 1);
+        fs::remove_file(index_file).unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+use crate::config::ShareRateLimitConfig;
+use crate::hashrate::share_work;
+
+/// Shares per minute expected from a channel with `nominal_hashrate` on `target`, as
+/// little-endian bytes.
+pub fn expected_share_rate(nominal_hashrate: f32, target: &[u8]) -> f64 {
+    let work = share_work(target);
+    if work <= 0.0 {
+        return 0.0;
+    }
+    nominal_hashrate as f64 * 60.0 / work
+}
+
+/// Limit a share went over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareRateLimit {
+    Channel,
+    Client,
+}
+
+impl fmt::Display for ShareRateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShareRateLimit::Channel => write!(f, "channel"),
+            ShareRateLimit::Client => write!(f, "client"),
+        }
+    }
+}
+
+/// A share refused by the [`ShareRateLimiter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    pub limit: ShareRateLimit,
+    /// Whether it's the first refused share since the client last got one through.
+    pub first: bool,
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    rate: f64, // tokens per second
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Full bucket refilling at `rate` shares per second, holding `burst_seconds` of them and
+    /// at least one.
+    fn new(rate: f64, burst_seconds: f64, now: Instant) -> Self {
+        let capacity = (rate * burst_seconds).max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Changes the rate of the bucket, keeping the tokens it holds up to its new capacity.
+    fn resize(&mut self, rate: f64, burst_seconds: f64, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.capacity = (rate * burst_seconds).max(1.0);
+        self.tokens = self.tokens.min(self.capacity);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ClientLimit {
+    bucket: TokenBucket,
+    channels: HashMap<u32, TokenBucket>, // by channel id
+    rate_limited_shares: u64,
+    limited: bool, // whether the last share was refused
+}
+
+/// Token buckets limiting the shares of every channel, and of every client as a whole, so a
+/// flood of shares is refused before it takes the locks of the mining server.
+///
+/// Channels may submit shares at `rate_factor` times the rate expected from their target, but
+/// never less than `rate_factor` times `expected_shares_per_minute`. Clients may submit at the
+/// sum of the rates of their channels, and at the rate of a single channel before they open
+/// any, which limits shares on unknown channels too.
+#[derive(Debug, Clone)]
+pub struct ShareRateLimiter {
+    config: ShareRateLimitConfig,
+    expected_shares_per_minute: f64,
+    clients: HashMap<u32, ClientLimit>,
+    rate_limited_shares: u64,
+}
+
+impl ShareRateLimiter {
+    pub fn new(config: ShareRateLimitConfig, expected_shares_per_minute: f32) -> Self {
+        Self {
+            config,
+            expected_shares_per_minute: expected_shares_per_minute as f64,
+            clients: HashMap::new(),
+            rate_limited_shares: 0,
+        }
+    }
+
+    /// Shares per second a channel expected to submit `share_rate` shares a minute may submit.
+    fn channel_rate(&self, share_rate: f64) -> f64 {
+        share_rate.max(self.expected_shares_per_minute) * self.config.rate_factor / 60.0
+    }
+
+    fn client_limit(&mut self, client_id: u32, now: Instant) -> &mut ClientLimit {
+        let rate = self.channel_rate(0.0);
+        let burst_seconds = self.config.burst_seconds;
+        self.clients
+            .entry(client_id)
+            .or_insert_with(|| ClientLimit {
+                bucket: TokenBucket::new(rate, burst_seconds, now),
+                channels: HashMap::new(),
+                rate_limited_shares: 0,
+                limited: false,
+            })
+    }
+
+    /// Resizes the bucket of `client_id` to the sum of the rates of its channels.
+    fn resize_client(&mut self, client_id: u32, now: Instant) {
+        let min_rate = self.channel_rate(0.0);
+        let burst_seconds = self.config.burst_seconds;
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            let rate: f64 = client.channels.values().map(|bucket| bucket.rate).sum();
+            client.bucket.resize(rate.max(min_rate), burst_seconds, now);
+        }
+    }
+
+    /// Sizes the bucket of `channel_id` for a channel expected to submit `share_rate` shares a
+    /// minute, on open and whenever its target changes.
+    pub fn set_channel(&mut self, client_id: u32, channel_id: u32, share_rate: f64, now: Instant) {
+        let rate = self.channel_rate(share_rate);
+        let burst_seconds = self.config.burst_seconds;
+        let client = self.client_limit(client_id, now);
+        let opened = match client.channels.get_mut(&channel_id) {
+            Some(bucket) => {
+                bucket.resize(rate, burst_seconds, now);
+                None
+            }
+            None => {
+                let bucket = TokenBucket::new(rate, burst_seconds, now);
+                let tokens = bucket.tokens;
+                client.channels.insert(channel_id, bucket);
+                Some(tokens)
+            }
+        };
+        self.resize_client(client_id, now);
+        // a new channel brings a full burst to its client
+        if let (Some(tokens), Some(client)) = (opened, self.clients.get_mut(&client_id)) {
+            client.bucket.tokens = (client.bucket.tokens + tokens).min(client.bucket.capacity);
+        }
+    }
+
+    pub fn remove_channel(&mut self, client_id: u32, channel_id: u32, now: Instant) {
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.channels.remove(&channel_id);
+        }
+        self.resize_client(client_id, now);
+    }
+
+    pub fn remove_client(&mut self, client_id: u32) {
+        self.clients.remove(&client_id);
+    }
+
+    /// Takes a share of `channel_id` out of the buckets of the channel and of `client_id`.
+    /// Shares on channels without a bucket only count against the client.
+    pub fn take_share(
+        &mut self,
+        client_id: u32,
+        channel_id: u32,
+        now: Instant,
+    ) -> Result<(), RateLimited> {
+        let client = self.client_limit(client_id, now);
+        client.bucket.refill(now);
+        let mut channel = client.channels.get_mut(&channel_id);
+        if let Some(channel) = channel.as_mut() {
+            channel.refill(now);
+        }
+
+        let limit = if channel.as_ref().is_some_and(|channel| channel.tokens < 1.0) {
+            Some(ShareRateLimit::Channel)
+        } else if client.bucket.tokens < 1.0 {
+            Some(ShareRateLimit::Client)
+        } else {
+            None
+        };
+        let Some(limit) = limit else {
+            if let Some(channel) = channel {
+                channel.tokens -= 1.0;
+            }
+            client.bucket.tokens -= 1.0;
+            client.limited = false;
+            return Ok(());
+        };
+
+        let first = !client.limited;
+        client.limited = true;
+        client.rate_limited_shares += 1;
+        self.rate_limited_shares += 1;
+        Err(RateLimited { limit, first })
+    }
+
+    /// Shares refused since startup.
+    pub fn rate_limited_shares(&self) -> u64 {
+        self.rate_limited_shares
+    }
+
+    /// Shares of `client_id` refused since it connected.
+    pub fn client_rate_limited_shares(&self, client_id: u32) -> u64 {
+        self.clients
+            .get(&client_id)
+            .map_or(0, |client| client.rate_limited_shares)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn rate_limiter() -> ShareRateLimiter {
+        // 60 shares a minute, so a share a second with a burst of 10
+        ShareRateLimiter::new(
+            ShareRateLimitConfig {
+                rate_factor: 2.0,
+                burst_seconds: 10.0,
+            },
+            30.0,
+        )
+    }
+
+    #[test]
+    fn test_channel_limit() {
+        let mut rate_limiter = rate_limiter();
+        let now = Instant::now();
+        rate_limiter.set_channel(1, 1, 30.0, now);
+        rate_limiter.set_channel(1, 2, 30.0, now);
+        for _ in 0..10 {
+            assert!(rate_limiter.take_share(1, 1, now).is_ok());
+        }
+        assert_eq!(
+            rate_limiter.take_share(1, 1, now),
+            Err(RateLimited {
+                limit: ShareRateLimit::Channel,
+                first: true
+            })
+        );
+        assert!(!rate_limiter.take_share(1, 1, now).unwrap_err().first);
+        // other channels have buckets of their own
+        assert!(rate_limiter.take_share(1, 2, now).is_ok());
+        // refilled at a share a second
+        let later = now + Duration::from_secs(3);
+        for _ in 0..3 {
+            assert!(rate_limiter.take_share(1, 1, later).is_ok());
+        }
+        assert!(rate_limiter.take_share(1, 1, later).is_err());
+        assert_eq!(rate_limiter.rate_limited_shares(), 3);
+        assert_eq!(rate_limiter.client_rate_limited_shares(1), 3);
+    }
+
+    #[test]
+    fn test_client_limit() {
+        let mut rate_limiter = rate_limiter();
+        let now = Instant::now();
+        // shares on unknown channels are limited to the rate of a single channel
+        for _ in 0..10 {
+            assert!(rate_limiter.take_share(1, 7, now).is_ok());
+        }
+        assert_eq!(
+            rate_limiter.take_share(1, 7, now).unwrap_err().limit,
+            ShareRateLimit::Client
+        );
+
+        // a channel expected to submit more shares than expected_shares_per_minute
+        rate_limiter.set_channel(2, 1, 300.0, now);
+        for _ in 0..100 {
+            assert!(rate_limiter.take_share(2, 1, now).is_ok());
+        }
+        assert!(rate_limiter.take_share(2, 1, now).is_err());
+
+        // closing the channel shrinks the client back to a single channel
+        rate_limiter.remove_channel(2, 1, now);
+        let later = now + Duration::from_secs(60);
+        for _ in 0..10 {
+            assert!(rate_limiter.take_share(2, 1, later).is_ok());
+        }
+        assert!(rate_limiter.take_share(2, 1, later).is_err());
+    }
+
+    #[test]
+    fn test_expected_share_rate() {
+        // difficulty 1 takes 2^32 hashes per share
+        let target = bitcoin::Target::MAX_ATTAINABLE_MAINNET.to_le_bytes();
+        let share_rate = expected_share_rate(4_294_967_296.0, &target);
+        assert!((share_rate - 60.0).abs() < 0.01);
+        assert_eq!(expected_share_rate(1e12, &[]), 0.0);
+    }
+}
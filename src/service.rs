@@ -1,24 +1,55 @@
+use crate::bitcoin_rpc::BitcoinRpcClient;
+use crate::block_submission::BlockSubmitter;
+use crate::config::network_name;
+use crate::config::PlebLotteryBitcoinRpcConfig;
 use crate::config::PlebLotteryMiningServerConfig;
 use crate::config::PlebLotteryTemplateDistributionClientConfig;
-use crate::state::SharedStateHandle;
+use crate::getblocktemplate::GetBlockTemplateSource;
+use crate::state::{SharedStateHandle, TemplateProviderStatus};
+use crate::sv1_server::Sv1Server;
+use crate::sv2_gate::{self, Sv2Gate};
 use crate::sv2_handlers::mining_server_handler::PlebLotteryMiningServerHandler;
 use crate::sv2_handlers::template_distribution_client_handler::PlebLotteryTemplateDistributionClientHandler;
+use crate::template_provider_relay::TemplateProviderRelay;
 use anyhow::{anyhow, Result};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::Network;
 use sv2_services::client::service::config::Sv2ClientServiceConfig;
 use sv2_services::client::service::subprotocols::mining::handler::NullSv2MiningClientHandler;
 use sv2_services::client::service::Sv2ClientService;
 use sv2_services::server::service::config::Sv2ServerServiceConfig;
+use sv2_services::server::service::event::Sv2ServerEvent;
 use sv2_services::server::service::Sv2ServerService;
 use sv2_services::Sv2Service;
+use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
+use tower::{Service, ServiceExt};
 
-use tracing::debug;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+type TemplateDistributionClientService =
+    Sv2ClientService<NullSv2MiningClientHandler, PlebLotteryTemplateDistributionClientHandler>;
+
+#[derive(Clone)]
+enum TemplateSource {
+    TemplateProvider {
+        client_service: TemplateDistributionClientService,
+        relay: TemplateProviderRelay,
+        reconnect_min_delay: Duration,
+        reconnect_max_delay: Duration,
+    },
+    GetBlockTemplate(GetBlockTemplateSource),
+}
 
 #[derive(Clone)]
 pub struct PlebLotteryService {
     server_service: Sv2ServerService<PlebLotteryMiningServerHandler>,
-    client_service:
-        Sv2ClientService<NullSv2MiningClientHandler, PlebLotteryTemplateDistributionClientHandler>,
+    mining_server_handler: PlebLotteryMiningServerHandler, // shares its state with the server service
+    sv1_server: Option<Sv1Server>,
+    sv2_gate: Option<Sv2Gate>,
+    template_source: TemplateSource,
+    shared_state: SharedStateHandle,
     cancellation_token: CancellationToken,
 }
 
@@ -28,19 +59,70 @@ impl PlebLotteryService {
         template_distribution_client_config: PlebLotteryTemplateDistributionClientConfig,
         shared_state: SharedStateHandle,
     ) -> Result<Self> {
-        let server_config: Sv2ServerServiceConfig = mining_server_config.clone().into();
-        let client_config: Sv2ClientServiceConfig = template_distribution_client_config.into();
+        Self::new_with_submitblock(
+            mining_server_config,
+            template_distribution_client_config,
+            None,
+            shared_state,
+        )
+        .await
+    }
+
+    /// Builds a service getting templates from a Template Provider, which also submits found
+    /// blocks to the Bitcoin node of `bitcoin_rpc_config` (if set) via `submitblock`.
+    pub async fn new_with_submitblock(
+        mining_server_config: PlebLotteryMiningServerConfig,
+        template_distribution_client_config: PlebLotteryTemplateDistributionClientConfig,
+        bitcoin_rpc_config: Option<PlebLotteryBitcoinRpcConfig>,
+        shared_state: SharedStateHandle,
+    ) -> Result<Self> {
+        let mut server_config: Sv2ServerServiceConfig = mining_server_config.clone().into();
+        let reconnect_min_delay =
+            Duration::from_secs(template_distribution_client_config.reconnect_min_delay);
+        let reconnect_max_delay =
+            Duration::from_secs(template_distribution_client_config.reconnect_max_delay);
+        let template_provider_relay = TemplateProviderRelay::bind(
+            template_distribution_client_config.server_addrs(),
+            template_distribution_client_config
+                .silence_timeout
+                .map(Duration::from_secs),
+            shared_state.clone(),
+        )
+        .await?;
+        let client_config: Sv2ClientServiceConfig = template_distribution_client_config
+            .into_sv2_client_service_config(
+                template_provider_relay.local_addr()?,
+                mining_server_config.coinbase_output_constraints(),
+            );
 
         let cancellation_token = CancellationToken::new();
 
-        let mining_server_handler = PlebLotteryMiningServerHandler::new(
-            shared_state,
-            mining_server_config.coinbase_output_script,
-            mining_server_config.coinbase_tag,
-            mining_server_config.share_batch_size,
-            mining_server_config.expected_shares_per_minute,
+        let rpc = bitcoin_rpc_config
+            .map(|bitcoin_rpc_config| BitcoinRpcClient::new(&bitcoin_rpc_config))
+            .transpose()?;
+        if let Some(rpc) = &rpc {
+            Self::check_node_network(rpc, mining_server_config.network).await?;
+        }
+        let block_submitter = rpc
+            .clone()
+            .map(|rpc| BlockSubmitter::new(rpc, shared_state.clone()));
+
+        let mut mining_server_handler =
+            PlebLotteryMiningServerHandler::new(shared_state.clone(), mining_server_config.clone())
+                .await?;
+        if let Some(block_submitter) = &block_submitter {
+            mining_server_handler =
+                mining_server_handler.with_block_submitter(block_submitter.clone());
+        }
+        if let Some(rpc) = rpc {
+            mining_server_handler = mining_server_handler.with_block_tracker_rpc(rpc);
+        }
+        let sv2_gate = Self::bind_sv2_gate(
+            &mining_server_config,
+            &mut server_config,
+            &mining_server_handler,
         )
-        .await;
+        .await?;
         let template_distribution_client_handler =
             PlebLotteryTemplateDistributionClientHandler::new(
                 client_config
@@ -55,30 +137,167 @@ impl PlebLotteryService {
                     .expect("Template distribution config must be set")
                     .coinbase_output_constraints
                     .1,
+                shared_state.clone(),
+                block_submitter,
             );
 
         let (server_service, sibling_server_io) = Sv2ServerService::new_with_sibling_io(
             server_config.clone(),
-            mining_server_handler,
+            mining_server_handler.clone(),
             cancellation_token.clone(),
         )
         .map_err(|_| anyhow::anyhow!("Failed to create server service"))?;
+        // the client gets its own token, so losing the Template Provider doesn't stop the server
         let client_service = Sv2ClientService::new_from_sibling_io(
             client_config.clone(),
             NullSv2MiningClientHandler,
             template_distribution_client_handler,
             sibling_server_io,
-            cancellation_token.clone(),
+            cancellation_token.child_token(),
         )
         .map_err(|_| anyhow::anyhow!("Failed to create client service"))?;
+        let sv1_server = Self::bind_sv1_server(
+            &mining_server_config,
+            &mining_server_handler,
+            &server_service,
+        )
+        .await?;
 
         Ok(Self {
             server_service,
-            client_service,
+            mining_server_handler,
+            sv1_server,
+            sv2_gate,
+            template_source: TemplateSource::TemplateProvider {
+                client_service,
+                relay: template_provider_relay,
+                reconnect_min_delay,
+                reconnect_max_delay,
+            },
+            shared_state,
             cancellation_token,
         })
     }
 
+    /// Builds a service getting templates from a Bitcoin node via `getblocktemplate`, instead of
+    /// from a Template Provider.
+    pub async fn new_with_getblocktemplate(
+        mining_server_config: PlebLotteryMiningServerConfig,
+        bitcoin_rpc_config: PlebLotteryBitcoinRpcConfig,
+        shared_state: SharedStateHandle,
+    ) -> Result<Self> {
+        let mut server_config: Sv2ServerServiceConfig = mining_server_config.clone().into();
+        let rpc = BitcoinRpcClient::new(&bitcoin_rpc_config)?;
+        Self::check_node_network(&rpc, mining_server_config.network).await?;
+
+        let cancellation_token = CancellationToken::new();
+
+        let (solution_sender, solutions) = mpsc::unbounded_channel();
+        let mining_server_handler =
+            PlebLotteryMiningServerHandler::new(shared_state.clone(), mining_server_config.clone())
+                .await?
+                .with_solution_sender(solution_sender)
+                .with_block_tracker_rpc(rpc.clone());
+        let sv2_gate = Self::bind_sv2_gate(
+            &mining_server_config,
+            &mut server_config,
+            &mining_server_handler,
+        )
+        .await?;
+
+        let server_service = Sv2ServerService::new(
+            server_config,
+            mining_server_handler.clone(),
+            cancellation_token.clone(),
+        )
+        .map_err(|_| anyhow::anyhow!("Failed to create server service"))?;
+        let source = GetBlockTemplateSource::new(
+            rpc,
+            server_service.clone(),
+            solutions,
+            shared_state.clone(),
+            Duration::from_secs(bitcoin_rpc_config.poll_interval),
+        );
+        let sv1_server = Self::bind_sv1_server(
+            &mining_server_config,
+            &mining_server_handler,
+            &server_service,
+        )
+        .await?;
+
+        Ok(Self {
+            server_service,
+            mining_server_handler,
+            sv1_server,
+            sv2_gate,
+            template_source: TemplateSource::GetBlockTemplate(source),
+            shared_state,
+            cancellation_token,
+        })
+    }
+
+    /// Refuses to start with a Bitcoin node whose genesis block isn't the one of `network`.
+    /// A node that can't be reached yet is only warned about.
+    async fn check_node_network(rpc: &BitcoinRpcClient, network: Network) -> Result<()> {
+        match rpc.get_block_hash(0).await {
+            Ok(block_hash) if block_hash != genesis_block(network).block_hash() => Err(anyhow!(
+                "Bitcoin node is not on {}: its genesis block is {}",
+                network_name(network),
+                block_hash
+            )),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                warn!("Could not check the network of the Bitcoin node: {}", e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Binds the Stratum V1 listener, if enabled in `mining_server_config`.
+    async fn bind_sv1_server(
+        mining_server_config: &PlebLotteryMiningServerConfig,
+        mining_server_handler: &PlebLotteryMiningServerHandler,
+        server_service: &Sv2ServerService<PlebLotteryMiningServerHandler>,
+    ) -> Result<Option<Sv1Server>> {
+        let Some(sv1_config) = &mining_server_config.sv1 else {
+            return Ok(None);
+        };
+        let sv1_server = Sv1Server::bind(
+            sv1_config,
+            mining_server_config.rollable_extranonce_size(),
+            Duration::from_secs(mining_server_config.inactivity_limit),
+            mining_server_handler.clone(),
+            server_service.clone(),
+        )
+        .await?;
+        Ok(Some(sv1_server))
+    }
+
+    /// Binds the Stratum V2 gate on the mining server port, moving the server service behind it
+    /// to a loopback address, if bans or the connection limits need to see Stratum V2
+    /// connections.
+    async fn bind_sv2_gate(
+        mining_server_config: &PlebLotteryMiningServerConfig,
+        server_config: &mut Sv2ServerServiceConfig,
+        mining_server_handler: &PlebLotteryMiningServerHandler,
+    ) -> Result<Option<Sv2Gate>> {
+        if mining_server_config.ban.is_none()
+            && mining_server_config.limits.max_clients.is_none()
+            && mining_server_config.limits.max_connections_per_ip.is_none()
+        {
+            return Ok(None);
+        }
+        let server_address = sv2_gate::server_address()?;
+        let sv2_gate = Sv2Gate::bind(
+            mining_server_config.listening_port,
+            server_address,
+            mining_server_handler.clone(),
+        )
+        .await?;
+        server_config.tcp_config.listen_address = server_address;
+        Ok(Some(sv2_gate))
+    }
+
     pub async fn start(&mut self) -> Result<()> {
         tokio::select! {
             result = self.server_service.start() => {
@@ -87,10 +306,31 @@ impl PlebLotteryService {
                     return Err(anyhow!("Failed to start server service: {:?}", e));
                 }
             }
-            result = self.client_service.start() => {
+            result = Self::run_template_source(
+                &mut self.template_source,
+                &self.shared_state,
+                &self.cancellation_token,
+            ) => {
                 if let Err(e) = result {
                     self.cancellation_token.cancel();
-                    return Err(anyhow!("Failed to start client service: {:?}", e));
+                    return Err(e);
+                }
+            }
+            _ = Self::run_vardiff(
+                &self.server_service,
+                &self.mining_server_handler,
+                &self.cancellation_token,
+            ) => {}
+            result = Self::run_sv1_server(self.sv1_server.as_ref(), &self.cancellation_token) => {
+                if let Err(e) = result {
+                    self.cancellation_token.cancel();
+                    return Err(e);
+                }
+            }
+            result = Self::run_sv2_gate(self.sv2_gate.as_ref(), &self.cancellation_token) => {
+                if let Err(e) = result {
+                    self.cancellation_token.cancel();
+                    return Err(e);
                 }
             }
         }
@@ -98,6 +338,145 @@ impl PlebLotteryService {
         Ok(())
     }
 
+    /// Periodically retargets the channels of the mining server, sending `SetTarget` to their
+    /// clients. Runs until cancelled, or forever if vardiff is disabled.
+    async fn run_vardiff(
+        server_service: &Sv2ServerService<PlebLotteryMiningServerHandler>,
+        mining_server_handler: &PlebLotteryMiningServerHandler,
+        cancellation_token: &CancellationToken,
+    ) {
+        if !mining_server_handler.vardiff.enabled {
+            return std::future::pending().await;
+        }
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            mining_server_handler.vardiff.interval.max(1),
+        ));
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => return,
+                _ = interval.tick() => {}
+            }
+            for messages in mining_server_handler.retarget_channels().await {
+                let client_id = messages.client_id;
+                let mut server_service = server_service.clone();
+                let result = match server_service.ready().await {
+                    Ok(server_service) => {
+                        server_service
+                            .call(Sv2ServerEvent::SendMessagesToClient(Box::new(messages)))
+                            .await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    warn!("Failed to send SetTarget to client {}: {:?}", client_id, e);
+                }
+            }
+        }
+    }
+
+    /// Serves Stratum V1 miners until cancelled, or forever if there's no Stratum V1 listener.
+    async fn run_sv1_server(
+        sv1_server: Option<&Sv1Server>,
+        cancellation_token: &CancellationToken,
+    ) -> Result<()> {
+        match sv1_server {
+            Some(sv1_server) => sv1_server.run(cancellation_token.clone()).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Relays Stratum V2 miners until cancelled, or forever if they connect to the server service
+    /// directly.
+    async fn run_sv2_gate(
+        sv2_gate: Option<&Sv2Gate>,
+        cancellation_token: &CancellationToken,
+    ) -> Result<()> {
+        match sv2_gate {
+            Some(sv2_gate) => sv2_gate.run(cancellation_token.clone()).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn run_template_source(
+        template_source: &mut TemplateSource,
+        shared_state: &SharedStateHandle,
+        cancellation_token: &CancellationToken,
+    ) -> Result<()> {
+        match template_source {
+            TemplateSource::TemplateProvider {
+                client_service,
+                relay,
+                reconnect_min_delay,
+                reconnect_max_delay,
+            } => {
+                tokio::select! {
+                    result = Self::run_client_service(
+                        client_service,
+                        shared_state,
+                        *reconnect_min_delay,
+                        *reconnect_max_delay,
+                        cancellation_token,
+                    ) => result,
+                    result = relay.run(cancellation_token.clone()) => result,
+                }
+            }
+            TemplateSource::GetBlockTemplate(source) => {
+                source.run(cancellation_token.clone()).await
+            }
+        }
+    }
+
+    /// Runs the Template Distribution client, reconnecting with exponential backoff whenever the
+    /// connection to the Template Provider is lost. Meanwhile, the mining server keeps its
+    /// clients connected, mining on their last job.
+    ///
+    /// Fails if the Template Provider never served a template, as that is most likely a
+    /// configuration error.
+    async fn run_client_service(
+        client_service: &mut TemplateDistributionClientService,
+        shared_state: &SharedStateHandle,
+        reconnect_min_delay: Duration,
+        reconnect_max_delay: Duration,
+        cancellation_token: &CancellationToken,
+    ) -> Result<()> {
+        let mut delay = reconnect_min_delay;
+        loop {
+            let result = client_service.start().await;
+            if cancellation_token.is_cancelled() {
+                return Ok(());
+            }
+            let error = match result {
+                Ok(()) => "connection closed".to_string(),
+                Err(e) => format!("{:?}", e),
+            };
+
+            let attempt = {
+                let mut state = shared_state.write().await;
+                match state.template_provider_status {
+                    TemplateProviderStatus::Connecting => {
+                        return Err(anyhow!("Failed to start client service: {}", error));
+                    }
+                    TemplateProviderStatus::Connected => delay = reconnect_min_delay,
+                    TemplateProviderStatus::Reconnecting => {}
+                }
+                state.template_provider_status = TemplateProviderStatus::Reconnecting;
+                state.template_provider_last_error = Some(error.clone());
+                state.template_provider_reconnect_attempts += 1;
+                state.template_provider_reconnect_attempts
+            };
+
+            warn!(
+                "Lost connection to the Template Provider: {}. Reconnecting in {:?} (attempt {})",
+                error, delay, attempt
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = cancellation_token.cancelled() => return Ok(()),
+            }
+            delay = (delay * 2).min(reconnect_max_delay);
+        }
+    }
+
     pub async fn shutdown(&mut self) -> Result<()> {
         debug!("Shutting down PlebLotteryService");
         self.cancellation_token.cancel();
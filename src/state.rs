@@ -1,21 +1,59 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Instant};
 
+use bitcoin::{BlockHash, Network};
 use sv2_services::roles_logic_sv2::template_distribution_sv2::{NewTemplate, SetNewPrevHash};
 use tokio::sync::RwLock;
 
+use crate::ban::Ban;
+use crate::block_journal::FoundBlock;
+use crate::block_submission::BlockSubmission;
+use crate::block_tracker::FoundBlockStatus;
+use crate::hashrate::{format_hashrate, MeasuredHashrate};
+use crate::rate_limit::ShareRateLimiter;
 use crate::sv2_handlers::mining_server_handler::PleblotteryMiningClient;
 
+/// Status of the connection to the Template Provider.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateProviderStatus {
+    /// No template received yet.
+    #[default]
+    Connecting,
+    /// Templates are being received.
+    Connected,
+    /// The connection was lost, retrying with exponential backoff.
+    Reconnecting,
+}
+
 #[derive(Default, Debug, Clone)]
 /// Represents the state of the application (shared with the web server), containing optional
 /// information about the latest template and the latest previous hash.
 pub struct SharedState {
+    pub network: Option<Network>,
+    pub network_mismatch: Option<String>,
+    pub rejected_templates: u64, // templates and prev hashes failing the template sanity checks
+    pub last_rejected_template: Option<String>,
+    pub template_provider_status: TemplateProviderStatus,
+    pub template_provider_last_error: Option<String>,
+    pub template_provider_reconnect_attempts: u32,
+    pub template_provider_last_message: Option<Instant>,
+    pub active_template_provider: Option<SocketAddr>,
+    pub payout_address: Option<String>,
+    pub payout_derivation_index: Option<u32>,
     pub latest_template: Option<NewTemplate<'static>>,
     pub latest_prev_hash: Option<SetNewPrevHash<'static>>,
     pub total_clients: u32,
+    pub rejected_channel_opens: u64, // channels refused by the access configuration or the limits
+    pub last_rejected_channel_open: Option<String>,
+    pub bans: Vec<Ban>, // as of the last ban, expired ones are filtered out on display
     pub total_shares_submitted: u64,
+    pub share_rate_limiter: Option<Arc<RwLock<ShareRateLimiter>>>, // counts the shares it rejected
     pub best_share: f64,
-    pub total_hashrate: f32,
+    pub total_hashrate: f32, // sum of the nominal hashrate of every channel
+    pub measured_hashrate: MeasuredHashrate, // from the work of accepted shares
     pub blocks_found: u64,
+    pub found_blocks: Vec<FoundBlock>,
+    pub found_block_statuses: HashMap<BlockHash, FoundBlockStatus>, // blocks without a status are pending
+    pub block_submissions: Vec<BlockSubmission>,
     pub clients: Arc<RwLock<HashMap<u32, Arc<RwLock<PleblotteryMiningClient>>>>>,
 }
 impl SharedState {
@@ -32,18 +70,7 @@ impl SharedState {
         format!("{:.2}{}", value, suffix)
     }
     pub fn format_hashrate(&self) -> String {
-        let (value, unit) = if self.total_hashrate >= 1e12 {
-            (self.total_hashrate / 1e12, "Th/s")
-        } else if self.total_hashrate >= 1e9 {
-            (self.total_hashrate / 1e9, "Gh/s")
-        } else if self.total_hashrate >= 1e6 {
-            (self.total_hashrate / 1e6, "Mh/s")
-        } else if self.total_hashrate >= 1e3 {
-            (self.total_hashrate / 1e3, "Kh/s")
-        } else {
-            (self.total_hashrate, "h/s")
-        };
-        format!("{:.2} {}", value, unit)
+        format_hashrate(self.total_hashrate as f64)
     }
 }
 
@@ -0,0 +1,923 @@
+use anyhow::{anyhow, Result};
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::Target;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use sv2_services::roles_logic_sv2::mining_sv2::{
+    NewExtendedMiningJob, OpenExtendedMiningChannel, SubmitSharesExtended,
+};
+use sv2_services::roles_logic_sv2::parsers::{AnyMessage, Mining};
+use sv2_services::server::service::event::{Sv2ServerEvent, Sv2ServerEventError};
+use sv2_services::server::service::outcome::Sv2ServerOutcome;
+use sv2_services::server::service::subprotocols::mining::handler::Sv2MiningServerHandler;
+use sv2_services::server::service::Sv2ServerService;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tower::{Service, ServiceExt};
+use tracing::{error, info, warn};
+
+use crate::access::split_token;
+use crate::ban::Offense;
+use crate::config::Sv1Config;
+use crate::sv2_handlers::mining_server_handler::PlebLotteryMiningServerHandler;
+use crate::vardiff::difficulty_to_hashrate;
+
+/// Client ids of Stratum V1 connections start here, away from the ids of the server service.
+pub const SV1_CLIENT_ID_START: u32 = 1 << 31;
+
+/// Smallest extranonce2 size Stratum V1 firmware commonly accepts.
+pub const MIN_EXTRANONCE2_SIZE: usize = 2;
+
+/// General purpose bits of the block version, as per BIP320.
+pub const VERSION_ROLLING_MASK: u32 = 0x1fff_e000;
+
+/// Longest request line accepted from a Stratum V1 client.
+const MAX_LINE_LENGTH: u64 = 16 * 1024;
+
+// error codes of Stratum V1 responses
+const ERROR_OTHER: i32 = 20;
+const ERROR_JOB_NOT_FOUND: i32 = 21;
+const ERROR_DUPLICATE_SHARE: i32 = 22;
+const ERROR_LOW_DIFFICULTY: i32 = 23;
+const ERROR_UNAUTHORIZED: i32 = 24;
+const ERROR_NOT_SUBSCRIBED: i32 = 25;
+
+/// Error of a Stratum V1 response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sv1Error {
+    pub code: i32,
+    pub message: String,
+}
+
+impl Sv1Error {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Error for a share rejected with the `error_code` of a `SubmitSharesError`.
+    pub fn from_submit_shares_error(error_code: &str) -> Self {
+        let code = match error_code {
+            "stale-share" | "invalid-job-id" => ERROR_JOB_NOT_FOUND,
+            "duplicate-share" => ERROR_DUPLICATE_SHARE,
+            "difficulty-too-low" => ERROR_LOW_DIFFICULTY,
+            "invalid-channel-id" => ERROR_UNAUTHORIZED,
+            _ => ERROR_OTHER,
+        };
+        Self::new(code, error_code)
+    }
+}
+
+/// JSON-RPC request sent by a Stratum V1 client.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Sv1Request {
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Vec<Value>,
+}
+
+pub fn response(id: Value, result: Value) -> Value {
+    json!({ "id": id, "result": result, "error": null })
+}
+
+pub fn error_response(id: Value, error: Sv1Error) -> Value {
+    json!({ "id": id, "result": null, "error": [error.code, error.message, null] })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "id": null, "method": method, "params": params })
+}
+
+fn hex_param(params: &[Value], index: usize) -> Result<&str, Sv1Error> {
+    params
+        .get(index)
+        .and_then(Value::as_str)
+        .ok_or_else(|| Sv1Error::new(ERROR_OTHER, format!("missing parameter {}", index)))
+}
+
+fn hex_u32_param(params: &[Value], index: usize) -> Result<u32, Sv1Error> {
+    let hex = hex_param(params, index)?;
+    u32::from_str_radix(hex, 16)
+        .map_err(|_| Sv1Error::new(ERROR_OTHER, format!("invalid parameter {}: {}", index, hex)))
+}
+
+/// Prev hash as sent in `mining.notify`: the bytes of the header field, with every 4 byte word
+/// reversed.
+pub fn sv1_prev_hash(prev_hash: &[u8]) -> String {
+    prev_hash
+        .chunks(4)
+        .flat_map(|word| word.iter().rev())
+        .copied()
+        .collect::<Vec<u8>>()
+        .to_lower_hex_string()
+}
+
+/// Difficulty of `target`, as little-endian bytes, for `mining.set_difficulty`.
+pub fn target_to_difficulty(target: &[u8]) -> f64 {
+    match <[u8; 32]>::try_from(target) {
+        Ok(target) => Target::from_le_bytes(target).difficulty_float(),
+        Err(_) => 0.0,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Sv1Channel {
+    channel_id: u32,
+    extranonce_prefix: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct PrevHash {
+    prev_hash: Vec<u8>,
+    nbits: u32,
+}
+
+/// Stratum V1 side of a connection mapped onto an extended channel: translates requests into
+/// Mining Protocol messages, and the messages for the channel back into notifications.
+///
+/// The extranonce prefix of the channel is only known once the miner authorizes, after it
+/// subscribed, so it is sent at the end of `coinb1`. The extranonce1 is left empty, and the
+/// extranonce2 is the whole extranonce rollable on the channel.
+#[derive(Debug, Clone)]
+pub struct Sv1Session {
+    client_id: u32,
+    extranonce2_size: usize,
+    subscribed: bool,
+    version_rolling_mask: Option<u32>,
+    suggested_difficulty: Option<f64>,
+    channel: Option<Sv1Channel>,
+    future_jobs: HashMap<u32, NewExtendedMiningJob<'static>>,
+    jobs: HashMap<u32, NewExtendedMiningJob<'static>>, // jobs on the current prev hash
+    prev_hash: Option<PrevHash>,
+    last_notify: Option<(u32, u32)>, // job id and ntime
+    sequence_number: u32,
+}
+
+impl Sv1Session {
+    pub fn new(client_id: u32, extranonce2_size: usize) -> Self {
+        Self {
+            client_id,
+            extranonce2_size,
+            subscribed: false,
+            version_rolling_mask: None,
+            suggested_difficulty: None,
+            channel: None,
+            future_jobs: HashMap::new(),
+            jobs: HashMap::new(),
+            prev_hash: None,
+            last_notify: None,
+            sequence_number: 0,
+        }
+    }
+
+    pub fn is_authorized(&self) -> bool {
+        self.channel.is_some()
+    }
+
+    /// Id of the channel the connection is mapped onto, once it authorized.
+    pub fn channel_id(&self) -> Option<u32> {
+        self.channel.as_ref().map(|channel| channel.channel_id)
+    }
+
+    /// Result of `mining.configure`. Only the `version-rolling` extension is supported.
+    pub fn configure(&mut self, params: &[Value]) -> Value {
+        let extensions = params
+            .first()
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+        let mut result = serde_json::Map::new();
+        for extension in extensions.iter().filter_map(Value::as_str) {
+            if extension != "version-rolling" {
+                result.insert(extension.to_string(), json!(false));
+                continue;
+            }
+            let requested_mask = params
+                .get(1)
+                .and_then(|options| options.get("version-rolling.mask"))
+                .and_then(Value::as_str)
+                .and_then(|mask| u32::from_str_radix(mask, 16).ok())
+                .unwrap_or(VERSION_ROLLING_MASK);
+            let mask = requested_mask & VERSION_ROLLING_MASK;
+            self.version_rolling_mask = Some(mask);
+            result.insert("version-rolling".to_string(), json!(true));
+            result.insert(
+                "version-rolling.mask".to_string(),
+                json!(format!("{:08x}", mask)),
+            );
+        }
+        Value::Object(result)
+    }
+
+    /// Result of `mining.subscribe`.
+    pub fn subscribe(&mut self) -> Value {
+        self.subscribed = true;
+        let subscription_id = format!("{:08x}", self.client_id);
+        json!([
+            [
+                ["mining.set_difficulty", subscription_id],
+                ["mining.notify", subscription_id]
+            ],
+            "",
+            self.extranonce2_size
+        ])
+    }
+
+    /// Handles `mining.suggest_difficulty`, which sets the difficulty the channel is opened with.
+    pub fn suggest_difficulty(&mut self, params: &[Value]) {
+        if let Some(difficulty) = params.first().and_then(Value::as_f64) {
+            if difficulty > 0.0 {
+                self.suggested_difficulty = Some(difficulty);
+            }
+        }
+    }
+
+    /// `OpenExtendedMiningChannel` for a `mining.authorize` of `user_identity`, with the nominal
+    /// hashrate for the suggested difficulty if any, or `nominal_hashrate` otherwise.
+    pub fn open_channel(
+        &mut self,
+        user_identity: &str,
+        nominal_hashrate: f32,
+        expected_shares_per_minute: f32,
+    ) -> Result<OpenExtendedMiningChannel<'static>, Sv1Error> {
+        if !self.subscribed {
+            return Err(Sv1Error::new(ERROR_NOT_SUBSCRIBED, "not subscribed"));
+        }
+        let nominal_hash_rate = self
+            .suggested_difficulty
+            .map(|difficulty| difficulty_to_hashrate(difficulty, expected_shares_per_minute))
+            .unwrap_or(nominal_hashrate);
+        Ok(OpenExtendedMiningChannel {
+            request_id: self.client_id,
+            user_identity: user_identity
+                .to_string()
+                .try_into()
+                .map_err(|_| Sv1Error::new(ERROR_UNAUTHORIZED, "user identity is too long"))?,
+            nominal_hash_rate,
+            max_target: [0xff; 32].into(),
+            min_extranonce_size: self.extranonce2_size as u16,
+        })
+    }
+
+    /// `SubmitSharesExtended` for the params of a `mining.submit`: worker name, job id,
+    /// extranonce2, ntime, nonce and optionally the rolled version bits.
+    pub fn submit(&mut self, params: &[Value]) -> Result<SubmitSharesExtended<'static>, Sv1Error> {
+        let channel_id = match &self.channel {
+            Some(channel) => channel.channel_id,
+            None => return Err(Sv1Error::new(ERROR_UNAUTHORIZED, "unauthorized worker")),
+        };
+        let job_id = hex_u32_param(params, 1)?;
+        let job = self
+            .jobs
+            .get(&job_id)
+            .ok_or_else(|| Sv1Error::new(ERROR_JOB_NOT_FOUND, "job not found"))?;
+        let extranonce = Vec::<u8>::from_hex(hex_param(params, 2)?)
+            .ok()
+            .filter(|extranonce| extranonce.len() == self.extranonce2_size)
+            .ok_or_else(|| Sv1Error::new(ERROR_OTHER, "invalid extranonce2"))?;
+        let ntime = hex_u32_param(params, 3)?;
+        let nonce = hex_u32_param(params, 4)?;
+        let version = match (params.get(5), self.version_rolling_mask) {
+            (Some(_), Some(mask)) if job.version_rolling_allowed => {
+                (job.version & !mask) | (hex_u32_param(params, 5)? & mask)
+            }
+            _ => job.version,
+        };
+
+        self.sequence_number += 1;
+        Ok(SubmitSharesExtended {
+            channel_id,
+            sequence_number: self.sequence_number,
+            job_id,
+            nonce,
+            ntime,
+            version,
+            extranonce: extranonce
+                .try_into()
+                .map_err(|_| Sv1Error::new(ERROR_OTHER, "invalid extranonce2"))?,
+        })
+    }
+
+    fn is_own_channel(&self, channel_id: u32) -> bool {
+        self.channel
+            .as_ref()
+            .is_some_and(|channel| channel.channel_id == channel_id)
+    }
+
+    fn set_difficulty(&self, target: &[u8]) -> Value {
+        notification(
+            "mining.set_difficulty",
+            json!([target_to_difficulty(target)]),
+        )
+    }
+
+    fn notify(&self, job_id: u32, ntime: u32, clean_jobs: bool) -> Option<Value> {
+        let (Some(channel), Some(prev_hash), Some(job)) =
+            (&self.channel, &self.prev_hash, self.jobs.get(&job_id))
+        else {
+            return None;
+        };
+        let mut coinb1 = job.coinbase_tx_prefix.to_vec();
+        coinb1.extend_from_slice(&channel.extranonce_prefix);
+        let merkle_branch: Vec<String> = job
+            .merkle_path
+            .to_vec()
+            .iter()
+            .map(|hash| hash.to_lower_hex_string())
+            .collect();
+        Some(notification(
+            "mining.notify",
+            json!([
+                format!("{:x}", job.job_id),
+                sv1_prev_hash(&prev_hash.prev_hash),
+                coinb1.to_lower_hex_string(),
+                job.coinbase_tx_suffix.to_vec().to_lower_hex_string(),
+                merkle_branch,
+                format!("{:08x}", job.version),
+                format!("{:08x}", prev_hash.nbits),
+                format!("{:08x}", ntime),
+                clean_jobs
+            ]),
+        ))
+    }
+
+    /// Notifications for a message sent to the client of this session.
+    pub fn on_message(&mut self, message: AnyMessage<'static>) -> Vec<Value> {
+        match message {
+            AnyMessage::Mining(Mining::OpenExtendedMiningChannelSuccess(m)) => {
+                if m.extranonce_size as usize != self.extranonce2_size {
+                    error!(
+                        "Channel {} of Stratum V1 client {} rolls {} bytes of extranonce, instead of {}",
+                        m.channel_id, self.client_id, m.extranonce_size, self.extranonce2_size
+                    );
+                }
+                self.channel = Some(Sv1Channel {
+                    channel_id: m.channel_id,
+                    extranonce_prefix: m.extranonce_prefix.to_vec(),
+                });
+                vec![self.set_difficulty(&m.target.to_vec())]
+            }
+            AnyMessage::Mining(Mining::NewExtendedMiningJob(job))
+                if self.is_own_channel(job.channel_id) =>
+            {
+                let job_id = job.job_id;
+                match job.min_ntime.clone().into_inner() {
+                    None => {
+                        self.future_jobs.insert(job_id, job);
+                        vec![]
+                    }
+                    Some(min_ntime) => {
+                        self.jobs.insert(job_id, job);
+                        self.last_notify = Some((job_id, min_ntime));
+                        self.notify(job_id, min_ntime, false).into_iter().collect()
+                    }
+                }
+            }
+            AnyMessage::Mining(Mining::SetNewPrevHash(m)) if self.is_own_channel(m.channel_id) => {
+                self.prev_hash = Some(PrevHash {
+                    prev_hash: m.prev_hash.to_vec(),
+                    nbits: m.nbits,
+                });
+                self.jobs.clear();
+                let Some(job) = self.future_jobs.remove(&m.job_id) else {
+                    warn!(
+                        "Stratum V1 client {} got a prev hash for unknown future job {}",
+                        self.client_id, m.job_id
+                    );
+                    return vec![];
+                };
+                self.future_jobs.clear();
+                self.jobs.insert(m.job_id, job);
+                self.last_notify = Some((m.job_id, m.min_ntime));
+                self.notify(m.job_id, m.min_ntime, true)
+                    .into_iter()
+                    .collect()
+            }
+            AnyMessage::Mining(Mining::SetTarget(m)) if self.is_own_channel(m.channel_id) => {
+                // miners apply a new difficulty from the next job on
+                let mut notifications = vec![self.set_difficulty(&m.maximum_target.to_vec())];
+                if let Some((job_id, ntime)) = self.last_notify {
+                    notifications.extend(self.notify(job_id, ntime, false));
+                }
+                notifications
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Takes the messages `event` sends to `client_id` into `messages`, and returns the events left
+/// for the server service.
+fn take_client_messages(
+    event: Sv2ServerEvent<'static>,
+    client_id: u32,
+    messages: &mut Vec<AnyMessage<'static>>,
+) -> Vec<Sv2ServerEvent<'static>> {
+    match event {
+        Sv2ServerEvent::SendMessagesToClient(messages_to_client)
+            if messages_to_client.client_id == client_id =>
+        {
+            messages.extend(messages_to_client.messages);
+            vec![]
+        }
+        Sv2ServerEvent::MultipleEvents(events) => (*events)
+            .into_iter()
+            .flat_map(|event| take_client_messages(event, client_id, messages))
+            .collect(),
+        event => vec![event],
+    }
+}
+
+/// Stratum V1 listener for miners without Stratum V2 support.
+///
+/// Every connection is added as a client of the mining server handler, and mapped onto an
+/// extended channel once it authorizes, so its shares go through the same validation, stats and
+/// block found path as the ones of native Stratum V2 miners.
+#[derive(Clone)]
+pub struct Sv1Server {
+    listener: Arc<TcpListener>,
+    handler: PlebLotteryMiningServerHandler,
+    server_service: Sv2ServerService<PlebLotteryMiningServerHandler>,
+    next_client_id: Arc<AtomicU32>,
+    extranonce2_size: usize,
+    nominal_hashrate: f32,
+    inactivity_limit: Duration,
+}
+
+impl Sv1Server {
+    pub async fn bind(
+        config: &Sv1Config,
+        extranonce2_size: usize,
+        inactivity_limit: Duration,
+        handler: PlebLotteryMiningServerHandler,
+        server_service: Sv2ServerService<PlebLotteryMiningServerHandler>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, config.listening_port))
+            .await
+            .map_err(|e| anyhow!("Failed to bind Stratum V1 listener: {}", e))?;
+        info!(
+            "Listening for Stratum V1 miners on port {}",
+            config.listening_port
+        );
+        Ok(Self {
+            listener: Arc::new(listener),
+            handler,
+            server_service,
+            next_client_id: Arc::new(AtomicU32::new(SV1_CLIENT_ID_START)),
+            extranonce2_size,
+            nominal_hashrate: config.nominal_hashrate,
+            inactivity_limit,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub async fn run(&self, cancellation_token: CancellationToken) -> Result<()> {
+        loop {
+            let (stream, peer) = tokio::select! {
+                result = self.listener.accept() => result
+                    .map_err(|e| anyhow!("Stratum V1 listener failed to accept: {}", e))?,
+                _ = cancellation_token.cancelled() => return Ok(()),
+            };
+            if !self.admit(peer).await {
+                continue;
+            }
+            let server = self.clone();
+            let cancellation_token = cancellation_token.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = server.serve(stream, peer) => {}
+                    _ = cancellation_token.cancelled() => {}
+                }
+            });
+        }
+    }
+
+    /// Whether a connection from `peer` is served, counting it as pending until its client is
+    /// added if so.
+    async fn admit(&self, peer: SocketAddr) -> bool {
+        if self.handler.is_ip_banned(peer.ip()).await {
+            warn!("Refused Stratum V1 connection from banned address {}", peer);
+            return false;
+        }
+        if let Err(reason) = self.handler.admit_connection(peer.ip()).await {
+            warn!("Refused Stratum V1 connection from {}, {}", peer, reason);
+            return false;
+        }
+        true
+    }
+
+    async fn serve(&self, stream: TcpStream, peer: SocketAddr) {
+        let client_id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut handler = self.handler.clone();
+        handler.add_sv1_client(client_id, peer.ip(), sender).await;
+        info!("Stratum V1 client {} connected from {}", client_id, peer);
+
+        match self.connection_loop(client_id, stream, &mut receiver).await {
+            Ok(()) => info!("Stratum V1 client {} disconnected", client_id),
+            Err(e) => warn!("Stratum V1 client {} disconnected: {}", client_id, e),
+        }
+        handler.remove_sv1_client(client_id).await;
+    }
+
+    async fn connection_loop(
+        &self,
+        client_id: u32,
+        stream: TcpStream,
+        receiver: &mut UnboundedReceiver<AnyMessage<'static>>,
+    ) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut session = Sv1Session::new(client_id, self.extranonce2_size);
+        let mut last_request = Instant::now();
+        // read_until keeps what it read when another branch completes first
+        let mut line = Vec::new();
+        loop {
+            let mut limited_reader = (&mut reader).take(MAX_LINE_LENGTH);
+            let messages = tokio::select! {
+                read = limited_reader.read_until(b'\n', &mut line) => {
+                    if read? == 0 {
+                        return Ok(());
+                    }
+                    if line.len() as u64 > MAX_LINE_LENGTH {
+                        self.record_offense(client_id, None, Offense::ProtocolError).await;
+                        return Err(anyhow!("request longer than {} bytes", MAX_LINE_LENGTH));
+                    }
+                    if line.last() != Some(&b'\n') {
+                        continue;
+                    }
+                    last_request = Instant::now();
+                    let request_line = std::mem::take(&mut line);
+                    let request_line = String::from_utf8_lossy(&request_line);
+                    if request_line.trim().is_empty() {
+                        continue;
+                    }
+                    let request: Sv1Request = match serde_json::from_str(&request_line) {
+                        Ok(request) => request,
+                        Err(e) => {
+                            self.record_offense(client_id, None, Offense::ProtocolError).await;
+                            return Err(anyhow!(
+                                "invalid request {:?}: {}",
+                                request_line.trim(),
+                                e
+                            ));
+                        }
+                    };
+                    self.handle_request(&mut session, request).await
+                }
+                message = receiver.recv() => match message {
+                    Some(message) => session.on_message(message),
+                    None => return Ok(()),
+                },
+                _ = tokio::time::sleep_until(last_request + self.inactivity_limit) => {
+                    return Err(anyhow!("no request for {:?}", self.inactivity_limit));
+                }
+            };
+            for message in messages {
+                writer
+                    .write_all(format!("{}\n", message).as_bytes())
+                    .await?;
+            }
+        }
+    }
+
+    async fn handle_request(&self, session: &mut Sv1Session, request: Sv1Request) -> Vec<Value> {
+        let Sv1Request { id, method, params } = request;
+        match method.as_str() {
+            "mining.configure" => vec![response(id, session.configure(&params))],
+            "mining.subscribe" => vec![response(id, session.subscribe())],
+            "mining.extranonce.subscribe" => vec![response(id, json!(false))],
+            "mining.suggest_difficulty" => {
+                session.suggest_difficulty(&params);
+                vec![response(id, json!(true))]
+            }
+            "mining.authorize" => self.authorize(session, id, &params).await,
+            "mining.submit" => self.submit(session, id, &params).await,
+            _ => vec![error_response(
+                id,
+                Sv1Error::new(ERROR_OTHER, format!("unsupported method {}", method)),
+            )],
+        }
+    }
+
+    async fn authorize(&self, session: &mut Sv1Session, id: Value, params: &[Value]) -> Vec<Value> {
+        // shares of every worker of the connection are submitted on its first channel
+        if session.is_authorized() {
+            return vec![response(id, json!(true))];
+        }
+        let user_identity = params.first().and_then(Value::as_str).unwrap_or_default();
+        let open_channel = match session.open_channel(
+            user_identity,
+            self.nominal_hashrate,
+            self.handler.expected_shares_per_minute,
+        ) {
+            Ok(open_channel) => open_channel,
+            Err(e) => return vec![error_response(id, e)],
+        };
+        let result = self
+            .handler
+            .handle_open_extended_mining_channel(session.client_id, open_channel)
+            .await;
+        let messages = match self.client_messages(session.client_id, result).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!(
+                    "Error opening channel for Stratum V1 client {}: {}",
+                    session.client_id, e
+                );
+                return vec![error_response(id, Sv1Error::new(ERROR_OTHER, e))];
+            }
+        };
+
+        for message in &messages {
+            if let AnyMessage::Mining(Mining::OpenMiningChannelError(e)) = message {
+                let error_code = String::from_utf8_lossy(&e.error_code.to_vec()).to_string();
+                return vec![error_response(
+                    id,
+                    Sv1Error::new(ERROR_UNAUTHORIZED, error_code),
+                )];
+            }
+        }
+        info!(
+            "Stratum V1 client {} authorized as {}",
+            session.client_id,
+            split_token(user_identity).0
+        );
+        let mut notifications = vec![response(id, json!(true))];
+        for message in messages {
+            notifications.extend(session.on_message(message));
+        }
+        notifications
+    }
+
+    async fn submit(&self, session: &mut Sv1Session, id: Value, params: &[Value]) -> Vec<Value> {
+        let submit_shares = match session.submit(params) {
+            Ok(submit_shares) => submit_shares,
+            Err(e) => {
+                let offense = match e.code {
+                    ERROR_JOB_NOT_FOUND => Offense::InvalidJobId,
+                    _ => Offense::ProtocolError,
+                };
+                self.record_offense(session.client_id, session.channel_id(), offense)
+                    .await;
+                return vec![error_response(id, e)];
+            }
+        };
+        let result = self
+            .handler
+            .handle_submit_shares_extended(session.client_id, submit_shares)
+            .await;
+        let messages = match self.client_messages(session.client_id, result).await {
+            Ok(messages) => messages,
+            Err(e) => return vec![error_response(id, Sv1Error::new(ERROR_OTHER, e))],
+        };
+
+        let mut notifications = Vec::new();
+        let mut result = Ok(());
+        for message in messages {
+            match message {
+                AnyMessage::Mining(Mining::SubmitSharesError(e)) => {
+                    result = Err(Sv1Error::from_submit_shares_error(
+                        &String::from_utf8_lossy(&e.error_code.to_vec()),
+                    ));
+                }
+                message => notifications.extend(session.on_message(message)),
+            }
+        }
+        let reply = match result {
+            Ok(()) => response(id, json!(true)),
+            Err(e) => error_response(id, e),
+        };
+        std::iter::once(reply).chain(notifications).collect()
+    }
+
+    /// Scores `offense`, sending the `CloseChannel` messages of any Stratum V2 client it bans
+    /// through the server service.
+    async fn record_offense(&self, client_id: u32, channel_id: Option<u32>, offense: Offense) {
+        let messages_to_clients = self
+            .handler
+            .record_offense(client_id, channel_id, offense)
+            .await;
+        if messages_to_clients.is_empty() {
+            return;
+        }
+        let event = Sv2ServerEvent::SendMessagesToClients(Box::new(messages_to_clients));
+        let mut server_service = self.server_service.clone();
+        let result = match server_service.ready().await {
+            Ok(server_service) => server_service.call(event).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!(
+                "Failed to close channels banned by Stratum V1 client {}: {:?}",
+                client_id, e
+            );
+        }
+    }
+
+    /// Messages a handler call sends to `client_id`. Any other event it triggers, such as
+    /// propagating a block solution, is handed to the server service.
+    async fn client_messages(
+        &self,
+        client_id: u32,
+        result: Result<Sv2ServerOutcome<'static>, Sv2ServerEventError>,
+    ) -> Result<Vec<AnyMessage<'static>>, String> {
+        let event = match result {
+            Ok(Sv2ServerOutcome::TriggerNewEvent(event)) => *event,
+            Ok(_) => return Ok(vec![]),
+            Err(e) => return Err(format!("{:?}", e)),
+        };
+        let mut messages = Vec::new();
+        for event in take_client_messages(event, client_id, &mut messages) {
+            let mut server_service = self.server_service.clone();
+            let result = match server_service.ready().await {
+                Ok(server_service) => server_service.call(event).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!(
+                    "Failed to handle event of Stratum V1 client {}: {:?}",
+                    client_id, e
+                );
+            }
+        }
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sv2_services::roles_logic_sv2::mining_sv2::{
+        OpenExtendedMiningChannelSuccess, SetNewPrevHash, SetTarget,
+    };
+
+    fn job(job_id: u32, min_ntime: Option<u32>) -> NewExtendedMiningJob<'static> {
+        NewExtendedMiningJob {
+            channel_id: 2,
+            job_id,
+            min_ntime: min_ntime.into(),
+            version: 0x2000_0000,
+            version_rolling_allowed: true,
+            merkle_path: vec![[3u8; 32].into()].try_into().unwrap(),
+            coinbase_tx_prefix: vec![0xaa, 0xbb].try_into().unwrap(),
+            coinbase_tx_suffix: vec![0xcc, 0xdd].try_into().unwrap(),
+        }
+    }
+
+    fn authorized_session() -> Sv1Session {
+        let mut session = Sv1Session::new(SV1_CLIENT_ID_START, 4);
+        session.subscribe();
+        session.open_channel("worker", 1e12, 10.0).unwrap();
+        session.on_message(AnyMessage::Mining(
+            Mining::OpenExtendedMiningChannelSuccess(OpenExtendedMiningChannelSuccess {
+                request_id: SV1_CLIENT_ID_START,
+                channel_id: 2,
+                target: Target::MAX.to_le_bytes().into(),
+                extranonce_size: 4,
+                extranonce_prefix: vec![0x01, 0x02].try_into().unwrap(),
+            }),
+        ));
+        session
+    }
+
+    #[test]
+    fn test_subscribe_and_configure() {
+        let mut session = Sv1Session::new(SV1_CLIENT_ID_START, 4);
+        assert_eq!(
+            session.open_channel("worker", 1e12, 10.0).unwrap_err().code,
+            ERROR_NOT_SUBSCRIBED
+        );
+        assert_eq!(session.subscribe()[2], json!(4));
+        assert_eq!(
+            session.configure(&[
+                json!(["version-rolling", "minimum-difficulty"]),
+                json!({ "version-rolling.mask": "ffffffff" }),
+            ]),
+            json!({
+                "version-rolling": true,
+                "version-rolling.mask": "1fffe000",
+                "minimum-difficulty": false,
+            })
+        );
+
+        session.suggest_difficulty(&[json!(1.0)]);
+        let open_channel = session.open_channel("worker", 1e12, 60.0).unwrap();
+        assert_eq!(open_channel.nominal_hash_rate, 4_294_967_296.0);
+        assert_eq!(open_channel.min_extranonce_size, 4);
+    }
+
+    #[test]
+    fn test_notify() {
+        let mut session = authorized_session();
+        assert!(session
+            .on_message(AnyMessage::Mining(Mining::NewExtendedMiningJob(job(
+                1, None
+            ))))
+            .is_empty());
+
+        let mut prev_hash = [0u8; 32];
+        prev_hash[..4].copy_from_slice(&[1, 2, 3, 4]);
+        let notifications =
+            session.on_message(AnyMessage::Mining(Mining::SetNewPrevHash(SetNewPrevHash {
+                channel_id: 2,
+                job_id: 1,
+                prev_hash: prev_hash.into(),
+                min_ntime: 0x6500_0000,
+                nbits: 0x207f_ffff,
+            })));
+        assert_eq!(notifications.len(), 1);
+        let params = &notifications[0]["params"];
+        assert_eq!(notifications[0]["method"], "mining.notify");
+        assert_eq!(params[0], "1");
+        assert!(params[1].as_str().unwrap().starts_with("04030201"));
+        // the extranonce prefix ends coinb1
+        assert_eq!(params[2], "aabb0102");
+        assert_eq!(params[3], "ccdd");
+        assert_eq!(params[4][0], "03".repeat(32));
+        assert_eq!(params[5], "20000000");
+        assert_eq!(params[6], "207fffff");
+        assert_eq!(params[7], "65000000");
+        assert_eq!(params[8], true);
+
+        // jobs of other channels are ignored
+        let mut other_job = job(2, Some(0x6500_0001));
+        other_job.channel_id = 1;
+        assert!(session
+            .on_message(AnyMessage::Mining(Mining::NewExtendedMiningJob(other_job)))
+            .is_empty());
+
+        // a new difficulty is followed by the last job
+        let notifications = session.on_message(AnyMessage::Mining(Mining::SetTarget(SetTarget {
+            channel_id: 2,
+            maximum_target: Target::MAX.to_le_bytes().into(),
+        })));
+        assert_eq!(notifications[0]["method"], "mining.set_difficulty");
+        assert_eq!(notifications[0]["params"][0], 1.0);
+        assert_eq!(notifications[1]["params"][8], false);
+    }
+
+    #[test]
+    fn test_submit() {
+        let mut session = authorized_session();
+        session.configure(&[json!(["version-rolling"])]);
+        session.on_message(AnyMessage::Mining(Mining::NewExtendedMiningJob(job(
+            7,
+            Some(0x6500_0000),
+        ))));
+
+        let submit_shares = session
+            .submit(&[
+                json!("worker"),
+                json!("7"),
+                json!("00000001"),
+                json!("65000001"),
+                json!("deadbeef"),
+                json!("1fffe000"),
+            ])
+            .unwrap();
+        assert_eq!(submit_shares.channel_id, 2);
+        assert_eq!(submit_shares.job_id, 7);
+        assert_eq!(submit_shares.sequence_number, 1);
+        assert_eq!(submit_shares.extranonce.to_vec(), vec![0, 0, 0, 1]);
+        assert_eq!(submit_shares.ntime, 0x6500_0001);
+        assert_eq!(submit_shares.nonce, 0xdead_beef);
+        assert_eq!(submit_shares.version, 0x3fff_e000);
+
+        let params = [
+            json!("worker"),
+            json!("8"),
+            json!("00000001"),
+            json!("65000001"),
+            json!("deadbeef"),
+        ];
+        assert_eq!(
+            session.submit(&params).unwrap_err().code,
+            ERROR_JOB_NOT_FOUND
+        );
+        let mut params = params;
+        params[1] = json!("7");
+        params[2] = json!("0001");
+        assert_eq!(session.submit(&params).unwrap_err().code, ERROR_OTHER);
+
+        assert_eq!(
+            Sv1Error::from_submit_shares_error("difficulty-too-low").code,
+            ERROR_LOW_DIFFICULTY
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use anyhow::{anyhow, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{oneshot, Mutex};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::sv2_handlers::mining_server_handler::PlebLotteryMiningServerHandler;
+
+const BUFFER_SIZE: usize = 8192;
+const SETUP_TIMEOUT: Duration = Duration::from_secs(5);
+// time a client goes quiet for after its `SetupConnection`
+const SETUP_QUIET: Duration = Duration::from_millis(100);
+// ElligatorSwift encoded ephemeral key of the client
+const INITIATOR_HANDSHAKE_SIZE: usize = 64;
+// ephemeral key, encrypted static key and encrypted certificate of the server
+const RESPONDER_HANDSHAKE_SIZE: usize = 234;
+// encrypted header, and the MAC of the payload
+const MIN_FRAME_SIZE: usize = 22 + 16;
+
+/// Free loopback address for the server service to listen on behind the gate.
+pub fn server_address() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .map_err(|e| anyhow!("Failed to pick an address for the Stratum V2 server: {}", e))?;
+    Ok(listener.local_addr()?)
+}
+
+/// TCP gate in front of the Stratum V2 listener of the server service, which tells the mining
+/// server handler neither the address of a connection nor lets it drop one.
+///
+/// The gate accepts Stratum V2 miners on the mining server port, refuses banned addresses and
+/// addresses over their connection limit, and relays the (encrypted) stream to the server
+/// service on a loopback address. Once the server service adds the client of a connection on its
+/// `SetupConnection`, the gate maps the client onto the connection, so its offenses are scored by
+/// address and it's dropped when banned. Connections that aren't set up are refused.
+///
+/// Setups are forwarded one at a time, as the client id is only known from the next client the
+/// handler adds, so a `SetupConnection` is read in full before it's forwarded.
+#[derive(Clone)]
+pub struct Sv2Gate {
+    listener: Arc<TcpListener>,
+    server_address: SocketAddr,
+    handler: PlebLotteryMiningServerHandler,
+    setup: Arc<Mutex<()>>,
+}
+
+impl Sv2Gate {
+    pub async fn bind(
+        listening_port: u16,
+        server_address: SocketAddr,
+        handler: PlebLotteryMiningServerHandler,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, listening_port))
+            .await
+            .map_err(|e| anyhow!("Failed to bind Stratum V2 listener: {}", e))?;
+        Ok(Self {
+            listener: Arc::new(listener),
+            server_address,
+            handler,
+            setup: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub async fn run(&self, cancellation_token: CancellationToken) -> Result<()> {
+        loop {
+            let (stream, peer) = tokio::select! {
+                result = self.listener.accept() => result
+                    .map_err(|e| anyhow!("Stratum V2 listener failed to accept: {}", e))?,
+                _ = cancellation_token.cancelled() => return Ok(()),
+            };
+            if !self.admit(peer).await {
+                continue;
+            }
+            let gate = self.clone();
+            let connection = cancellation_token.child_token();
+            tokio::spawn(async move {
+                if let Err(e) = gate.serve(stream, peer, connection).await {
+                    warn!("Stratum V2 connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    /// Whether a connection from `peer` is relayed, counting it as pending if so.
+    async fn admit(&self, peer: SocketAddr) -> bool {
+        if self.handler.is_ip_banned(peer.ip()).await {
+            warn!("Refused Stratum V2 connection from banned address {}", peer);
+            return false;
+        }
+        if let Err(reason) = self.handler.admit_connection(peer.ip()).await {
+            warn!("Refused Stratum V2 connection from {}, {}", peer, reason);
+            return false;
+        }
+        true
+    }
+
+    async fn serve(
+        &self,
+        stream: TcpStream,
+        peer: SocketAddr,
+        connection: CancellationToken,
+    ) -> Result<()> {
+        let mapped = AtomicBool::new(false);
+        let result = match TcpStream::connect(self.server_address).await {
+            Ok(server) => tokio::select! {
+                result = self.relay(stream, server, peer, &connection, &mapped) => result,
+                _ = connection.cancelled() => {
+                    info!("Dropped Stratum V2 connection from {}", peer);
+                    Ok(())
+                }
+            },
+            Err(e) => Err(e.into()),
+        };
+        if !mapped.load(Ordering::Relaxed) {
+            self.handler.remove_pending_connection(peer.ip()).await;
+        }
+        result
+    }
+
+    async fn relay(
+        &self,
+        stream: TcpStream,
+        server: TcpStream,
+        peer: SocketAddr,
+        connection: &CancellationToken,
+        mapped: &AtomicBool,
+    ) -> Result<()> {
+        let (mut client_reader, mut client_writer) = stream.into_split();
+        let (mut server_reader, mut server_writer) = server.into_split();
+
+        // the Noise handshake, in messages of a fixed size
+        let mut initiator_message = [0u8; INITIATOR_HANDSHAKE_SIZE];
+        client_reader.read_exact(&mut initiator_message).await?;
+        server_writer.write_all(&initiator_message).await?;
+        let mut responder_message = [0u8; RESPONDER_HANDSHAKE_SIZE];
+        server_reader.read_exact(&mut responder_message).await?;
+        client_writer.write_all(&responder_message).await?;
+
+        let Some(setup_connection) = read_setup_connection(&mut client_reader).await? else {
+            warn!(
+                "Refused Stratum V2 connection from {}, which didn't set up in {:?}",
+                peer, SETUP_TIMEOUT
+            );
+            return Ok(());
+        };
+        let (client_id, answer) = self
+            .set_up(&mut server_writer, &mut server_reader, &setup_connection)
+            .await?;
+        client_writer.write_all(&answer).await?;
+        let Some(client_id) = client_id else {
+            warn!(
+                "Refused Stratum V2 connection from {}, which the server didn't set up",
+                peer
+            );
+            return Ok(());
+        };
+        // from now on, the connection counts as one of the client addresses
+        mapped.store(true, Ordering::Relaxed);
+        self.handler
+            .add_sv2_connection(client_id, peer.ip(), connection.clone())
+            .await;
+        info!("Stratum V2 client {} connected from {}", client_id, peer);
+
+        tokio::select! {
+            result = tokio::io::copy(&mut client_reader, &mut server_writer) => {
+                result?;
+            }
+            result = tokio::io::copy(&mut server_reader, &mut client_writer) => {
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forwards the `SetupConnection` of a client to the server service, returning the id of the
+    /// client it adds, unless the server closes the connection or takes too long, along with what
+    /// the server answered meanwhile.
+    ///
+    /// Only the server is waited for while setting up, the client being answered afterwards. A
+    /// connection that isn't set up is closed on the server, which is waited for before the next
+    /// setup, so a late client of it can't be taken for the next one.
+    async fn set_up(
+        &self,
+        server_writer: &mut OwnedWriteHalf,
+        server_reader: &mut OwnedReadHalf,
+        setup_connection: &[u8],
+    ) -> Result<(Option<u32>, Vec<u8>)> {
+        let _setup = self.setup.lock().await;
+        let (sender, mut receiver) = oneshot::channel();
+        *self.handler.pending_sv2_client.lock().await = Some(sender);
+        let mut answer = Vec::new();
+        let result: std::io::Result<Option<u32>> = async {
+            server_writer.write_all(setup_connection).await?;
+            let mut buffer = vec![0u8; BUFFER_SIZE];
+            let timeout = tokio::time::sleep(SETUP_TIMEOUT);
+            tokio::pin!(timeout);
+            loop {
+                tokio::select! {
+                    client_id = &mut receiver => return Ok(client_id.ok()),
+                    read = server_reader.read(&mut buffer) => {
+                        let read = read?;
+                        if read == 0 {
+                            return Ok(None);
+                        }
+                        answer.extend_from_slice(&buffer[..read]);
+                    }
+                    _ = &mut timeout => {
+                        // the server may still add the client until it drops the connection
+                        server_writer.shutdown().await?;
+                        while server_reader.read(&mut buffer).await? > 0 {}
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+        .await;
+        self.handler.pending_sv2_client.lock().await.take();
+        Ok((result?, answer))
+    }
+}
+
+/// Reads the `SetupConnection` of a client, the first frame after the handshake, unless the
+/// client closes the connection or takes too long.
+///
+/// The length of the frame is in its encrypted header, so the frame is taken to be complete once
+/// the client goes quiet, waiting for the answer.
+async fn read_setup_connection(client_reader: &mut OwnedReadHalf) -> Result<Option<Vec<u8>>> {
+    let deadline = Instant::now() + SETUP_TIMEOUT;
+    let mut setup_connection = Vec::new();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let quiet = if setup_connection.len() < MIN_FRAME_SIZE {
+            deadline
+        } else {
+            deadline.min(Instant::now() + SETUP_QUIET)
+        };
+        match tokio::time::timeout_at(quiet, client_reader.read(&mut buffer)).await {
+            Ok(read) => {
+                let read = read?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                setup_connection.extend_from_slice(&buffer[..read]);
+            }
+            Err(_) if quiet < deadline => return Ok(Some(setup_connection)),
+            Err(_) => return Ok(None),
+        }
+    }
+}
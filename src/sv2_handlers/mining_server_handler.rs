@@ -9,14 +9,16 @@ use sv2_services::roles_logic_sv2::channels::server::share_accounting::{
     ShareValidationError, ShareValidationResult,
 };
 use sv2_services::roles_logic_sv2::channels::server::standard::StandardChannel;
+use sv2_services::roles_logic_sv2::codec_sv2::binary_sv2::U256;
 use sv2_services::roles_logic_sv2::mining_sv2::NewExtendedMiningJob;
 use sv2_services::roles_logic_sv2::mining_sv2::NewMiningJob;
 use sv2_services::roles_logic_sv2::mining_sv2::OpenExtendedMiningChannelSuccess;
 use sv2_services::roles_logic_sv2::mining_sv2::UpdateChannelError;
 use sv2_services::roles_logic_sv2::mining_sv2::{
     CloseChannel, OpenExtendedMiningChannel, OpenMiningChannelError, OpenStandardMiningChannel,
-    OpenStandardMiningChannelSuccess, SetCustomMiningJob, SubmitSharesError, SubmitSharesExtended,
-    SubmitSharesStandard, SubmitSharesSuccess, UpdateChannel, MAX_EXTRANONCE_LEN,
+    OpenStandardMiningChannelSuccess, SetCustomMiningJob, SetCustomMiningJobError, SetTarget,
+    SubmitSharesError, SubmitSharesExtended, SubmitSharesStandard, SubmitSharesSuccess,
+    UpdateChannel, MAX_EXTRANONCE_LEN,
 };
 use sv2_services::roles_logic_sv2::mining_sv2::{
     ExtendedExtranonce, SetNewPrevHash as SetNewPrevHashMp,
@@ -30,15 +32,48 @@ use sv2_services::server::service::event::Sv2ServerEvent;
 use sv2_services::server::service::event::Sv2ServerEventError;
 use sv2_services::server::service::outcome::Sv2ServerOutcome;
 use sv2_services::server::service::subprotocols::mining::handler::Sv2MiningServerHandler;
-use tokio::sync::RwLock;
-
-use crate::state::SharedStateHandle;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{oneshot, Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
+
+use crate::access;
+use crate::ban::{BanList, Offense};
+use crate::bitcoin_rpc::BitcoinRpcClient;
+use crate::block_journal::{BlockJournal, FoundBlock};
+use crate::block_submission::{
+    record_block_submission, BlockSubmitter, SubmissionPath, SubmissionResult,
+};
+use crate::block_tracker::BlockTracker;
+use crate::block_verification::{RebuiltBlock, SubmittedHeaderFields};
+use crate::coinbase::{build_coinbase_outputs, CoinbaseOutputSplit};
+use crate::config::{
+    network_name, AccessConfig, LimitsConfig, PayoutRotation, PlebLotteryMiningServerConfig,
+    TemplateSanityConfig, UserIdentityPayoutMode, VardiffConfig,
+};
+use crate::extranonce::{ExtranoncePrefixFactory, ExtranoncePrefixLease};
+use crate::hashrate::share_work;
+use crate::payout::PayoutRotator;
+use crate::rate_limit::{expected_share_rate, ShareRateLimiter};
+use crate::state::{SharedStateHandle, TemplateProviderStatus};
+use crate::template_sanity::{check_nbits, check_subsidy, check_template_height, check_time_drift};
+use crate::utils::{
+    bip34_block_height, check_chain_tip_network, merkle_root_from_path,
+    user_identity_payout_script, worker_tag,
+};
+use crate::vardiff::{retarget, VardiffState};
 
-use bitcoin::{transaction::TxOut, Amount};
-use std::collections::HashMap;
+use bitcoin::block::{Header, Version};
+use bitcoin::consensus::encode::{deserialize, serialize_hex};
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    transaction::TxOut, Address, BlockHash, CompactTarget, Network, ScriptBuf, Transaction,
+};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
 
 #[derive(Debug)]
 pub struct PleblotteryMiningClient {
@@ -48,73 +83,537 @@ pub struct PleblotteryMiningClient {
     pub group_channel: Option<Arc<RwLock<GroupChannel<'static>>>>, // only one group per client, all standard channels belong to it
     pub standard_channels: Arc<RwLock<HashMap<u32, Arc<RwLock<StandardChannel<'static>>>>>>,
     pub extended_channels: Arc<RwLock<HashMap<u32, Arc<RwLock<ExtendedChannel<'static>>>>>>,
+    pub payout_scripts: Arc<RwLock<HashMap<u32, ScriptBuf>>>, // channels paying to the address in their user_identity
+    pub over_client_limit: bool, // added with max_clients reached, so it may not open channels
 }
 
 #[derive(Debug, Clone)]
 pub struct PlebLotteryMiningServerHandler {
     pub clients: Arc<RwLock<HashMap<u32, Arc<RwLock<PleblotteryMiningClient>>>>>,
     pub shared_state: SharedStateHandle,
-    pub coinbase_output_script: bitcoin::ScriptBuf,
+    pub network: Network,
+    pub coinbase_output_script: ScriptBuf,
+    pub payout_rotator: Option<Arc<RwLock<PayoutRotator>>>, // rotates coinbase_output_script over coinbase_output_descriptor
+    pub payout_rotation: PayoutRotation,
+    pub user_identity_payout: UserIdentityPayoutMode,
+    pub access: Option<AccessConfig>, // who may open channels, everyone when unset
+    pub ban_list: Option<Arc<RwLock<BanList>>>, // scores and bans of clients, when banning is enabled
+    pub limits: LimitsConfig,
+    pub client_addresses: Arc<RwLock<HashMap<u32, IpAddr>>>, // known for Stratum V1 clients, and Stratum V2 clients behind the gate
+    pub share_rate_limiter: Option<Arc<RwLock<ShareRateLimiter>>>, // when share rate limiting is enabled
+    pub coinbase_output_splits: Vec<CoinbaseOutputSplit>,
+    pub coinbase_op_return: Option<ScriptBuf>,
+    pub solution_sender: Option<UnboundedSender<SubmitSolution<'static>>>, // set when templates come from getblocktemplate
+    pub block_submitter: Option<BlockSubmitter>, // redundant submitblock next to the Template Provider
+    pub block_journal: BlockJournal,
+    pub block_tracker: BlockTracker,
     pub future_templates: Arc<RwLock<HashMap<u64, NewTemplate<'static>>>>,
+    pub tip_templates: Arc<RwLock<HashMap<u64, NewTemplate<'static>>>>, // templates built on the last prev hash
+    pub template_payout_scripts: Arc<RwLock<HashMap<u64, ScriptBuf>>>, // coinbase output script when each template arrived
     pub last_activated_future_template: Arc<RwLock<Option<NewTemplate<'static>>>>,
     pub last_prev_hash: Arc<RwLock<Option<SetNewPrevHash<'static>>>>,
-    pub extranonce_prefix_factory_standard: Arc<RwLock<ExtendedExtranonce>>,
-    pub extranonce_prefix_factory_extended: Arc<RwLock<ExtendedExtranonce>>,
+    pub extranonce_prefix_factory_standard: Arc<RwLock<ExtranoncePrefixFactory>>,
+    pub extranonce_prefix_factory_extended: Arc<RwLock<ExtranoncePrefixFactory>>,
+    pub worker_tag_range: std::ops::Range<usize>, // where the worker tag is written in the extranonce prefix
     pub share_batch_size: usize,
     pub expected_shares_per_minute: f32,
+    pub template_sanity: TemplateSanityConfig,
+    pub vardiff: VardiffConfig,
+    pub vardiff_states: Arc<RwLock<HashMap<(u32, u32), VardiffState>>>, // accepted shares at the last measurement, by client and channel id
+    pub sv1_clients: Arc<RwLock<HashMap<u32, UnboundedSender<AnyMessage<'static>>>>>, // Stratum V1 connections, not served by the server service
+    pub sv2_connections: Arc<RwLock<HashMap<u32, CancellationToken>>>, // Stratum V2 connections relayed by the gate, cancelled to drop them
+    pub pending_sv2_client: Arc<Mutex<Option<oneshot::Sender<u32>>>>, // told the id of the next Stratum V2 client, while the gate sets up a connection
+    pub pending_connections: Arc<RwLock<HashMap<IpAddr, usize>>>, // connections admitted before their client is known, by address
+}
+
+/// Offense behind the `SubmitSharesError`, `UpdateChannelError` or `SetCustomMiningJobError`
+/// answering a message, if any.
+fn error_offense(outcome: &Sv2ServerOutcome<'static>) -> Option<Offense> {
+    let Sv2ServerOutcome::TriggerNewEvent(event) = outcome else {
+        return None;
+    };
+    let Sv2ServerEvent::SendMessagesToClient(messages_to_client) = event.as_ref() else {
+        return None;
+    };
+    messages_to_client
+        .messages
+        .iter()
+        .find_map(|message| match message {
+            AnyMessage::Mining(Mining::SubmitSharesError(e)) => {
+                Offense::from_error_code(&String::from_utf8_lossy(e.error_code.as_ref()))
+            }
+            AnyMessage::Mining(Mining::UpdateChannelError(e)) => {
+                Offense::from_error_code(&String::from_utf8_lossy(e.error_code.as_ref()))
+            }
+            AnyMessage::Mining(Mining::SetCustomMiningJobError(e)) => {
+                Offense::from_error_code(&String::from_utf8_lossy(e.error_code.as_ref()))
+            }
+            _ => None,
+        })
 }
 
 impl PlebLotteryMiningServerHandler {
     pub async fn new(
         shared_state: SharedStateHandle,
-        coinbase_output_script: bitcoin::ScriptBuf,
-        coinbase_tag: String,
-        share_batch_size: usize,
-        expected_shares_per_minute: f32,
-    ) -> Self {
+        config: PlebLotteryMiningServerConfig,
+    ) -> anyhow::Result<Self> {
         let range_0 = std::ops::Range { start: 0, end: 0 };
 
-        let full_coinbase_tag = format!("pleblottery {}", coinbase_tag);
+        // extranonce layout: full coinbase tag | worker tag | channel counter | rollable by miners
+        let full_coinbase_tag = config.full_coinbase_tag();
+        let worker_tag_range = std::ops::Range {
+            start: full_coinbase_tag.len(),
+            end: full_coinbase_tag.len() + config.coinbase_worker_tag_size,
+        };
+        // the worker tag slot is zeroed here, and filled in for every channel
+        let mut additional_coinbase_script_data = full_coinbase_tag.into_bytes();
+        additional_coinbase_script_data.resize(worker_tag_range.end, 0);
 
         let range_1 = std::ops::Range {
             start: 0,
-            end: full_coinbase_tag.len() + 8,
+            end: worker_tag_range.end + config.extranonce_counter_size,
         };
         let range_2 = std::ops::Range {
-            start: full_coinbase_tag.len() + 8,
+            start: worker_tag_range.end + config.extranonce_counter_size,
             end: MAX_EXTRANONCE_LEN,
         };
+        info!(
+            "Extranonce layout: {} bytes of coinbase tag, {} bytes of worker tag, {} bytes of channel counter, {} bytes rollable by miners",
+            worker_tag_range.start,
+            worker_tag_range.len(),
+            config.extranonce_counter_size,
+            range_2.len()
+        );
+        let payout_rotator = match config.coinbase_output_descriptor {
+            Some(descriptor) => {
+                info!("Paying coinbase outputs to descriptor {}", descriptor);
+                Some(PayoutRotator::load(descriptor, config.payout_index_file)?)
+            }
+            None => None,
+        };
+        let coinbase_output_script = match &payout_rotator {
+            Some(payout_rotator) => payout_rotator.script().clone(),
+            None => config.coinbase_output_script,
+        };
+
+        let (block_journal, found_blocks) = BlockJournal::load(config.found_blocks_file)?;
         let clients = Arc::new(RwLock::new(HashMap::new()));
-        shared_state.write().await.clients = clients.clone();
+        let share_rate_limiter = config.share_rate_limit.map(|share_rate_limit| {
+            Arc::new(RwLock::new(ShareRateLimiter::new(
+                share_rate_limit,
+                config.expected_shares_per_minute,
+            )))
+        });
+        {
+            let mut state = shared_state.write().await;
+            state.clients = clients.clone();
+            state.share_rate_limiter = share_rate_limiter.clone();
+            state.blocks_found = found_blocks.len() as u64;
+            state.found_blocks = found_blocks;
+            state.network = Some(config.network);
+            state.payout_address = Address::from_script(&coinbase_output_script, config.network)
+                .ok()
+                .map(|address| address.to_string());
+            state.payout_derivation_index = payout_rotator.as_ref().map(|p| p.index());
+        }
 
-        Self {
+        Ok(Self {
             clients,
             shared_state,
+            network: config.network,
             coinbase_output_script,
+            payout_rotator: payout_rotator.map(|p| Arc::new(RwLock::new(p))),
+            payout_rotation: config.payout_rotation,
+            user_identity_payout: config.user_identity_payout,
+            access: config.access,
+            ban_list: config
+                .ban
+                .map(|ban| Arc::new(RwLock::new(BanList::new(ban)))),
+            limits: config.limits,
+            client_addresses: Arc::new(RwLock::new(HashMap::new())),
+            share_rate_limiter,
+            coinbase_output_splits: config.coinbase_output_splits,
+            coinbase_op_return: config.coinbase_op_return,
+            solution_sender: None,
+            block_submitter: None,
+            block_journal,
+            block_tracker: BlockTracker::new(shared_state.clone()),
             future_templates: Arc::new(RwLock::new(HashMap::new())),
+            tip_templates: Arc::new(RwLock::new(HashMap::new())),
+            template_payout_scripts: Arc::new(RwLock::new(HashMap::new())),
             last_activated_future_template: Arc::new(RwLock::new(None)),
             last_prev_hash: Arc::new(RwLock::new(None)),
             extranonce_prefix_factory_standard: Arc::new(RwLock::new(
-                ExtendedExtranonce::new(
-                    range_0.clone(),
-                    range_1.clone(),
-                    range_2.clone(),
-                    Some(full_coinbase_tag.as_bytes().to_vec()),
-                )
-                .expect("valid ExtendedExtranonce must not fail"),
+                ExtranoncePrefixFactory::new(
+                    ExtendedExtranonce::new(
+                        range_0.clone(),
+                        range_1.clone(),
+                        range_2.clone(),
+                        Some(additional_coinbase_script_data.clone()),
+                    )
+                    .expect("valid ExtendedExtranonce must not fail"),
+                ),
             )),
             extranonce_prefix_factory_extended: Arc::new(RwLock::new(
-                ExtendedExtranonce::new(
-                    range_0,
-                    range_1,
-                    range_2,
-                    Some(full_coinbase_tag.as_bytes().to_vec()),
-                )
-                .expect("valid ExtendedExtranonce must not fail"),
+                ExtranoncePrefixFactory::new(
+                    ExtendedExtranonce::new(
+                        range_0,
+                        range_1,
+                        range_2,
+                        Some(additional_coinbase_script_data),
+                    )
+                    .expect("valid ExtendedExtranonce must not fail"),
+                ),
             )),
-            share_batch_size,
-            expected_shares_per_minute,
+            worker_tag_range,
+            share_batch_size: config.share_batch_size,
+            expected_shares_per_minute: config.expected_shares_per_minute,
+            template_sanity: config.template_sanity,
+            vardiff: config.vardiff,
+            vardiff_states: Arc::new(RwLock::new(HashMap::new())),
+            sv1_clients: Arc::new(RwLock::new(HashMap::new())),
+            sv2_connections: Arc::new(RwLock::new(HashMap::new())),
+            pending_sv2_client: Arc::new(Mutex::new(None)),
+            pending_connections: Arc::new(RwLock::new(HashMap::new())),
+        })
+    }
+
+    /// Submits solutions through `solution_sender` instead of the Template Provider.
+    pub fn with_solution_sender(
+        mut self,
+        sender: UnboundedSender<SubmitSolution<'static>>,
+    ) -> Self {
+        self.solution_sender = Some(sender);
+        self
+    }
+
+    /// Also submits found blocks to a Bitcoin node, besides the Template Provider.
+    pub fn with_block_submitter(mut self, block_submitter: BlockSubmitter) -> Self {
+        self.block_submitter = Some(block_submitter);
+        self
+    }
+
+    /// Tracks found blocks with the Bitcoin node behind `rpc`, besides the chain tip.
+    pub fn with_block_tracker_rpc(mut self, rpc: BitcoinRpcClient) -> Self {
+        self.block_tracker = self.block_tracker.with_rpc(rpc);
+        self
+    }
+
+    /// Events propagating a block solution to the template source.
+    ///
+    /// With a Template Provider and a `block_submitter`, the full block is also rebuilt and
+    /// submitted to the Bitcoin node, so a solution isn't lost if the Template Provider is
+    /// unreachable.
+    async fn submit_solution(
+        &self,
+        solution: SubmitSolution<'static>,
+    ) -> Vec<Sv2ServerEvent<'static>> {
+        if let Some(sender) = &self.solution_sender {
+            info!("Propagating solution to the Bitcoin node.");
+            if sender.send(solution).is_err() {
+                error!("Failed to propagate solution: getblocktemplate source is gone");
+            }
+            return vec![];
+        }
+
+        let template_id = solution.template_id;
+        let block = match (&self.block_submitter, self.get_last_prev_hash().await) {
+            (Some(block_submitter), Some(prev_hash)) => {
+                Some(block_submitter.assemble_block(&prev_hash, &solution).await)
+            }
+            (Some(_), None) => Some(Err(anyhow::anyhow!("no prev hash received yet"))),
+            (None, _) => None,
+        };
+        let block_hash = block
+            .as_ref()
+            .and_then(|block| block.as_ref().ok())
+            .map(|block| block.block_hash());
+
+        info!("Propagating solution to the Template Provider.");
+        // the SubmitSolution is dispatched to the client service after this returns, without any
+        // feedback, so it can only be recorded as queued on a connection that was up
+        let template_provider_result = match self.shared_state.read().await.template_provider_status
+        {
+            TemplateProviderStatus::Connected => SubmissionResult::Queued,
+            _ => SubmissionResult::Failed("Template Provider is disconnected".to_string()),
+        };
+        record_block_submission(
+            &self.shared_state,
+            template_id,
+            block_hash,
+            SubmissionPath::TemplateProvider,
+            template_provider_result,
+        )
+        .await;
+
+        if let (Some(block_submitter), Some(block)) = (&self.block_submitter, block) {
+            match block {
+                Ok(block) => {
+                    // don't hold back the SubmitSolution while the Bitcoin node answers
+                    let block_submitter = block_submitter.clone();
+                    tokio::spawn(
+                        async move { block_submitter.submit_block(template_id, block).await },
+                    );
+                }
+                Err(e) => {
+                    record_block_submission(
+                        &self.shared_state,
+                        template_id,
+                        None,
+                        SubmissionPath::BitcoinRpc,
+                        SubmissionResult::Failed(format!("failed to rebuild block: {}", e)),
+                    )
+                    .await
+                }
+            }
+        }
+
+        vec![Sv2ServerEvent::SendEventToSiblingClientService(Box::new(
+            Sv2ClientEvent::TemplateDistributionTrigger(
+                TemplateDistributionClientTrigger::SubmitSolution(solution),
+            ),
+        ))]
+    }
+
+    /// Rebuilds a block found on a standard channel from the job it was mined on.
+    async fn rebuild_standard_block(
+        &self,
+        standard_channel: &StandardChannel<'static>,
+        m: &SubmitSharesStandard,
+        solution: &SubmitSolution<'static>,
+    ) -> anyhow::Result<RebuiltBlock> {
+        let job = standard_channel
+            .get_active_job()
+            .filter(|job| job.get_job_id() == m.job_id)
+            .or_else(|| standard_channel.get_past_jobs().get(&m.job_id))
+            .map(|job| job.get_job_message().clone())
+            .ok_or_else(|| anyhow::anyhow!("Job {} is unknown", m.job_id))?;
+        let template = self
+            .tip_templates
+            .read()
+            .await
+            .get(&solution.template_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Template {} is unknown", solution.template_id))?;
+        let prev_hash = self
+            .get_last_prev_hash()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No prev hash received yet"))?;
+        RebuiltBlock::from_standard_job(
+            &job,
+            &solution.coinbase_tx.to_vec(),
+            &template.merkle_path.to_vec(),
+            &prev_hash,
+            SubmittedHeaderFields {
+                version: m.version,
+                ntime: m.ntime,
+                nonce: m.nonce,
+            },
+        )
+    }
+
+    /// Rebuilds a block found on an extended channel from the job it was mined on.
+    async fn rebuild_extended_block(
+        &self,
+        extended_channel: &ExtendedChannel<'static>,
+        m: &SubmitSharesExtended<'static>,
+    ) -> anyhow::Result<RebuiltBlock> {
+        let job = extended_channel
+            .get_active_job()
+            .filter(|job| job.get_job_id() == m.job_id)
+            .or_else(|| extended_channel.get_past_jobs().get(&m.job_id))
+            .map(|job| job.get_job_message().clone())
+            .ok_or_else(|| anyhow::anyhow!("Job {} is unknown", m.job_id))?;
+        let prev_hash = self
+            .get_last_prev_hash()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No prev hash received yet"))?;
+        RebuiltBlock::from_extended_job(
+            &job,
+            extended_channel.get_extranonce_prefix(),
+            &m.extranonce.to_vec(),
+            &prev_hash,
+            SubmittedHeaderFields {
+                version: m.version,
+                ntime: m.ntime,
+                nonce: m.nonce,
+            },
+        )
+    }
+
+    /// Checks a found block rebuilt from its job, so a bug in the channel layer can't silently
+    /// submit a block paying somewhere else. Returns why the check failed, if it did.
+    ///
+    /// A block failing the check is still propagated: the Bitcoin network has the final word on
+    /// its validity, and a valid block can't be mined again.
+    async fn verify_found_block(
+        &self,
+        template_id: Option<u64>,
+        payout_script: Option<ScriptBuf>,
+        block: &anyhow::Result<RebuiltBlock>,
+    ) -> Option<String> {
+        let payout_scripts = match payout_script {
+            Some(payout_script) => vec![payout_script],
+            None => {
+                // the coinbase output script may have rotated since the job was built
+                let mut payout_scripts = vec![self.get_coinbase_output_script().await];
+                let template_payout_script = match template_id {
+                    Some(template_id) => self
+                        .template_payout_scripts
+                        .read()
+                        .await
+                        .get(&template_id)
+                        .cloned(),
+                    None => None,
+                };
+                if let Some(payout_script) = template_payout_script {
+                    if !payout_scripts.contains(&payout_script) {
+                        payout_scripts.push(payout_script);
+                    }
+                }
+                payout_scripts
+            }
+        };
+        let verification = match block {
+            Ok(block) => block.verify(&payout_scripts),
+            Err(e) => Err(anyhow::anyhow!("failed to rebuild block: {}", e)),
+        };
+        match verification {
+            Ok(block_hash) => {
+                info!("Block {} passed independent verification", block_hash);
+                None
+            }
+            Err(e) => {
+                error!("🚨 Found block FAILED independent verification: {} 🚨", e);
+                Some(e.to_string())
+            }
+        }
+    }
+
+    /// Writes the block found by `channel_id` of `client_id` on `template_id`, or on a custom
+    /// job, to the block journal.
+    async fn record_found_block(
+        &self,
+        client_id: u32,
+        channel_id: u32,
+        user_identity: &str,
+        template_id: Option<u64>,
+        block: anyhow::Result<(Header, Transaction)>,
+        verification_error: Option<String>,
+    ) {
+        let found_block = match block.and_then(|(header, coinbase)| {
+            Self::found_block(
+                client_id,
+                channel_id,
+                user_identity,
+                template_id,
+                header,
+                coinbase,
+                verification_error,
+            )
+        }) {
+            Ok(found_block) => found_block,
+            Err(e) => {
+                error!(
+                    "Failed to record block found by channel {} of client {}: {}",
+                    channel_id, client_id, e
+                );
+                return;
+            }
+        };
+        info!(
+            "Block {} at height {} found by {} (channel {} of client {})",
+            found_block.block_hash, found_block.height, user_identity, channel_id, client_id
+        );
+        if let Err(e) = self.block_journal.append(&found_block) {
+            error!("{}", e);
         }
+        self.shared_state
+            .write()
+            .await
+            .found_blocks
+            .push(found_block);
+    }
+
+    /// Header and coinbase of a block found on one of the templates built on the last prev
+    /// hash, as submitted by the channel.
+    async fn template_block(
+        &self,
+        solution: &SubmitSolution<'static>,
+    ) -> anyhow::Result<(Header, Transaction)> {
+        let coinbase: Transaction = deserialize(&solution.coinbase_tx.to_vec())
+            .map_err(|e| anyhow::anyhow!("Invalid coinbase: {}", e))?;
+        let template = self
+            .tip_templates
+            .read()
+            .await
+            .get(&solution.template_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Template {} is unknown", solution.template_id))?;
+        let prev_hash = self
+            .get_last_prev_hash()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No prev hash received yet"))?;
+        let prev_hash_bytes: [u8; 32] = prev_hash
+            .prev_hash
+            .to_vec()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Prev hash must be 32 bytes"))?;
+
+        let header = Header {
+            version: Version::from_consensus(solution.version as i32),
+            prev_blockhash: BlockHash::from_byte_array(prev_hash_bytes),
+            merkle_root: merkle_root_from_path(
+                coinbase.compute_txid(),
+                &template.merkle_path.to_vec(),
+            )?,
+            time: solution.header_timestamp,
+            bits: CompactTarget::from_consensus(prev_hash.n_bits),
+            nonce: solution.header_nonce,
+        };
+        Ok((header, coinbase))
+    }
+
+    fn found_block(
+        client_id: u32,
+        channel_id: u32,
+        user_identity: &str,
+        template_id: Option<u64>,
+        header: Header,
+        coinbase: Transaction,
+        verification_error: Option<String>,
+    ) -> anyhow::Result<FoundBlock> {
+        let coinbase_input = coinbase
+            .input
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Coinbase has no input"))?;
+        let height = bip34_block_height(coinbase_input.script_sig.as_bytes())?;
+
+        Ok(FoundBlock {
+            height,
+            block_hash: header.block_hash(),
+            version: header.version.to_consensus(),
+            prev_hash: header.prev_blockhash,
+            merkle_root: header.merkle_root,
+            time: header.time,
+            bits: header.bits.to_consensus(),
+            nonce: header.nonce,
+            template_id,
+            coinbase_tx: serialize_hex(&coinbase),
+            reward: coinbase
+                .output
+                .iter()
+                .map(|output| output.value.to_sat())
+                .sum(),
+            client_id,
+            channel_id,
+            user_identity: user_identity.to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            verification_error,
+        })
     }
 
     async fn get_client(
@@ -146,570 +645,1286 @@ impl PlebLotteryMiningServerHandler {
         last_prev_hash
     }
 
-    async fn get_coinbase_outputs(&self) -> Result<Vec<TxOut>, Sv2ServerEventError> {
-        let future_template = self.get_last_activated_template().await.ok_or(
-            Sv2ServerEventError::MiningHandlerError(format!("No last activated template found")),
-        )?;
-        let coinbase_txout = TxOut {
-            value: Amount::from_sat(future_template.coinbase_tx_value_remaining),
-            script_pubkey: self.coinbase_output_script.clone(),
-        };
-        Ok(vec![coinbase_txout])
+    /// Builds the coinbase outputs for a channel, paying the remainder after
+    /// `coinbase_output_splits` to `payout_script`, followed by `coinbase_op_return`.
+    fn coinbase_outputs(
+        &self,
+        coinbase_tx_value_remaining: u64,
+        payout_script: &ScriptBuf,
+    ) -> Vec<TxOut> {
+        build_coinbase_outputs(
+            coinbase_tx_value_remaining,
+            payout_script,
+            &self.coinbase_output_splits,
+            self.coinbase_op_return.as_ref(),
+        )
     }
 
-    async fn get_future_job_message_extended(
+    /// Builds the coinbase outputs of the last activated template for a channel, paying
+    /// `payout_script` if the channel has one, or the current coinbase output script otherwise.
+    async fn get_coinbase_outputs(
         &self,
-        extended_channel: &ExtendedChannel<'static>,
-    ) -> Result<(u32, NewExtendedMiningJob<'static>), Sv2ServerEventError> {
-        let template = self.get_last_activated_template().await.ok_or(
-            Sv2ServerEventError::MiningHandlerError(format!("No last activated template found")),
+        payout_script: Option<&ScriptBuf>,
+    ) -> Result<Vec<TxOut>, Sv2ServerEventError> {
+        let future_template = self.get_last_activated_template().await.ok_or(
+            Sv2ServerEventError::MiningHandlerError("No last activated template found".to_string()),
         )?;
-        let future_job_id = *extended_channel
-            .get_future_template_to_job_id()
-            .get(&template.template_id)
-            .ok_or(Sv2ServerEventError::MiningHandlerError(format!(
-                "Future job must be present"
-            )))?;
-        let future_job = extended_channel
-            .get_future_jobs()
-            .get(&future_job_id)
-            .ok_or(Sv2ServerEventError::MiningHandlerError(format!(
-                "Future job must be present"
-            )))?
-            .get_job_message()
-            .clone();
+        let coinbase_output_script = self.get_coinbase_output_script().await;
+        Ok(self.coinbase_outputs(
+            future_template.coinbase_tx_value_remaining,
+            payout_script.unwrap_or(&coinbase_output_script),
+        ))
+    }
 
-        Ok((future_job_id, future_job))
+    /// Coinbase output script paid by channels without a payout script of their own.
+    async fn get_coinbase_output_script(&self) -> ScriptBuf {
+        match &self.payout_rotator {
+            Some(payout_rotator) => payout_rotator.read().await.script().clone(),
+            None => self.coinbase_output_script.clone(),
+        }
     }
-    async fn get_future_job_message(
-        &self,
-        extended_channel: &StandardChannel<'static>,
-    ) -> Result<(u32, NewMiningJob<'static>), Sv2ServerEventError> {
-        let template = self.get_last_activated_template().await.ok_or(
-            Sv2ServerEventError::MiningHandlerError(format!("No last activated template found")),
-        )?;
-        let future_job_id = *extended_channel
-            .get_future_template_to_job_id()
-            .get(&template.template_id)
-            .ok_or(Sv2ServerEventError::MiningHandlerError(format!(
-                "Future job must be present"
-            )))?;
-        let future_job = extended_channel
-            .get_future_jobs()
-            .get(&future_job_id)
-            .ok_or(Sv2ServerEventError::MiningHandlerError(format!(
-                "Future job must be present"
-            )))?
-            .get_job_message()
-            .clone();
 
-        Ok((future_job_id, future_job))
+    /// Schedules a payout address rotation if `trigger` is the configured `payout_rotation`.
+    ///
+    /// The rotation only takes effect on the next future template, so every job built for a
+    /// given block height pays the same address.
+    async fn schedule_payout_rotation(&self, trigger: PayoutRotation) {
+        if self.payout_rotation != trigger {
+            return;
+        }
+        if let Some(payout_rotator) = &self.payout_rotator {
+            if let Err(e) = payout_rotator.write().await.schedule() {
+                error!("Error scheduling payout address rotation: {}", e);
+            }
+        }
     }
 
-    async fn register_extended_channel(
-        &self,
-        client_id: u32,
-        channel_id: u32,
-        extended_channel: ExtendedChannel<'static>,
-    ) -> Result<(), Sv2ServerEventError> {
-        // Register the new extended channel
-        let client_guard = self.get_client(client_id).await?;
-        let ext_channels_arc = &client_guard.read().await.extended_channels;
-        ext_channels_arc
-            .write()
-            .await
-            .insert(channel_id, Arc::new(RwLock::new(extended_channel)));
+    /// Applies a scheduled payout address rotation.
+    async fn rotate_payout_script(&self) -> Result<(), Sv2ServerEventError> {
+        let Some(payout_rotator) = &self.payout_rotator else {
+            return Ok(());
+        };
+        let mut payout_rotator = payout_rotator.write().await;
+        let rotated = payout_rotator.rotate_if_pending().map_err(|e| {
+            error!("Error rotating payout address: {}", e);
+            Sv2ServerEventError::MiningHandlerError(format!("Error rotating payout address: {}", e))
+        })?;
+        if rotated {
+            let payout_address = Address::from_script(payout_rotator.script(), self.network)
+                .ok()
+                .map(|address| address.to_string());
+            info!(
+                "Rotated payout address to {} (index {})",
+                payout_address.as_deref().unwrap_or("unknown"),
+                payout_rotator.index()
+            );
+            let mut state = self.shared_state.write().await;
+            state.payout_address = payout_address;
+            state.payout_derivation_index = Some(payout_rotator.index());
+        }
         Ok(())
     }
 
-    async fn register_standard_channel(
+    /// Writes the worker tag derived from `user_identity` into its slot of `extranonce_prefix`.
+    fn tag_extranonce_prefix(&self, extranonce_prefix: &mut [u8], user_identity: &str) {
+        if let Some(slot) = extranonce_prefix.get_mut(self.worker_tag_range.clone()) {
+            slot.copy_from_slice(&worker_tag(user_identity, slot.len()));
+        }
+    }
+
+    /// Removes a channel of `client`, releasing its extranonce prefix and its share of the total
+    /// hashrate. Returns whether the channel was found.
+    async fn close_channel(
         &self,
+        client: &Arc<RwLock<PleblotteryMiningClient>>,
         client_id: u32,
         channel_id: u32,
-        standard_channel: StandardChannel<'static>,
-    ) -> Result<u32, Sv2ServerEventError> {
-        // Register the new standard channel
-        let client_guard = self.get_client(client_id).await?;
-        let std_channels_arc = &client_guard.read().await.standard_channels;
-        std_channels_arc
+    ) -> bool {
+        {
+            let client_guard = client.read().await;
+            let standard_channel = client_guard
+                .standard_channels
+                .write()
+                .await
+                .remove(&channel_id);
+            let extended_channel = client_guard
+                .extended_channels
+                .write()
+                .await
+                .remove(&channel_id);
+            client_guard
+                .payout_scripts
+                .write()
+                .await
+                .remove(&channel_id);
+
+            match (standard_channel, extended_channel) {
+                (Some(standard_channel), _) => {
+                    if let Some(group_channel) = &client_guard.group_channel {
+                        group_channel
+                            .write()
+                            .await
+                            .remove_standard_channel_id(channel_id);
+                    }
+                    self.extranonce_prefix_factory_standard
+                        .write()
+                        .await
+                        .release_prefix(
+                            standard_channel
+                                .read()
+                                .await
+                                .get_extranonce_prefix()
+                                .clone(),
+                        );
+                    info!(
+                        "Closed standard channel {} of client {}",
+                        channel_id, client_id
+                    );
+                }
+                (None, Some(extended_channel)) => {
+                    self.extranonce_prefix_factory_extended
+                        .write()
+                        .await
+                        .release_prefix(
+                            extended_channel
+                                .read()
+                                .await
+                                .get_extranonce_prefix()
+                                .clone(),
+                        );
+                    info!(
+                        "Closed extended channel {} of client {}",
+                        channel_id, client_id
+                    );
+                }
+                (None, None) => {
+                    error!(
+                        "CloseChannel: channel {} of client {} not found",
+                        channel_id, client_id
+                    );
+                    return false;
+                }
+            }
+        }
+
+        if let Some(ban_list) = &self.ban_list {
+            ban_list.write().await.remove_channel(client_id, channel_id);
+        }
+
+        if let Some(share_rate_limiter) = &self.share_rate_limiter {
+            share_rate_limiter
+                .write()
+                .await
+                .remove_channel(client_id, channel_id, Instant::now());
+        }
+        self.shared_state
             .write()
             .await
-            .insert(channel_id, Arc::new(RwLock::new(standard_channel)));
+            .measured_hashrate
+            .remove_channel(client_id, channel_id);
+        self.update_total_hashrate().await;
+        true
+    }
 
-        // Add channel to group channel if present
-        if let Some(group_channel) = client_guard.read().await.group_channel.as_ref() {
-            let mut group_channel = group_channel.write().await;
-            group_channel.add_standard_channel_id(channel_id);
+    async fn is_client_banned(&self, client_id: u32) -> bool {
+        match &self.ban_list {
+            Some(ban_list) => ban_list
+                .read()
+                .await
+                .is_client_banned(client_id, SystemTime::now()),
+            None => false,
         }
-
-        // Prepare response
-        let group_channel_id = if let Some(gc) = client_guard.read().await.group_channel.as_ref() {
-            gc.read().await.get_group_channel_id()
-        } else {
-            0
-        };
-        Ok(group_channel_id)
     }
-}
 
-impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
-    fn setup_connection_success_flags(&self) -> u32 {
-        // no requirement for fixed version field
-        // no requirement for extended channel only
-        0
+    /// Whether the shares of `channel_id` are ignored, as its worker or the address of
+    /// `client_id` is banned.
+    async fn is_channel_banned(&self, client_id: u32, channel_id: u32) -> bool {
+        match &self.ban_list {
+            Some(ban_list) => {
+                ban_list
+                    .read()
+                    .await
+                    .is_channel_banned(client_id, channel_id, SystemTime::now())
+            }
+            None => false,
+        }
     }
 
-    async fn add_client(&mut self, client_id: u32, flags: u32) {
-        info!("Adding client with id: {}, flags: {:04b}", client_id, flags);
+    /// Whether channels are refused to `worker`, a `user_identity` without its token.
+    async fn is_worker_banned(&self, worker: &str) -> bool {
+        match &self.ban_list {
+            Some(ban_list) => ban_list
+                .read()
+                .await
+                .is_worker_banned(worker, SystemTime::now()),
+            None => false,
+        }
+    }
 
-        let channel_id_factory = AtomicU32::new(1);
+    /// Whether new connections from `address` are refused.
+    pub async fn is_ip_banned(&self, address: IpAddr) -> bool {
+        match &self.ban_list {
+            Some(ban_list) => ban_list
+                .read()
+                .await
+                .is_ip_banned(address, SystemTime::now()),
+            None => false,
+        }
+    }
 
-        let standard_channels = Arc::new(RwLock::new(HashMap::new()));
-        let extended_channels = Arc::new(RwLock::new(HashMap::new()));
-        // if SetupConnection.REQUIRES_STANDARD_JOBS is set
-        // client does not understand group channels
-        let group_channel = if flags & 0x0001 == 0x0001 {
-            None
-        } else {
-            let group_channel_id = channel_id_factory.fetch_add(1, Ordering::SeqCst);
-            info!("Adding group channel with id: {}", group_channel_id);
-            let job_store = Box::new(DefaultJobStore::new());
-            Some(Arc::new(RwLock::new(GroupChannel::new(
-                group_channel_id,
-                job_store,
-            ))))
+    /// Scores `offense` of `client_id` on `channel_id`, if it happened on a channel, disconnecting
+    /// the clients of any ban it causes. Returns the `CloseChannel` messages for banned Stratum V2
+    /// clients whose connection isn't relayed by the gate.
+    pub async fn record_offense(
+        &self,
+        client_id: u32,
+        channel_id: Option<u32>,
+        offense: Offense,
+    ) -> Vec<Sv2MessagesToClient<'static>> {
+        let Some(ban_list) = &self.ban_list else {
+            return vec![];
         };
-
-        let client = PleblotteryMiningClient {
-            client_id,
-            connection_flags: flags,
-            channel_id_factory,
-            group_channel,
-            standard_channels,
-            extended_channels,
+        let now = SystemTime::now();
+        let (bans, banned_clients, active_bans) = {
+            let mut ban_list = ban_list.write().await;
+            let bans = ban_list.record(client_id, channel_id, offense, now);
+            if bans.is_empty() {
+                return vec![];
+            }
+            let banned_clients: HashSet<u32> = bans
+                .iter()
+                .flat_map(|ban| ban_list.banned_clients(ban))
+                .collect();
+            (bans, banned_clients, ban_list.active_bans(now))
         };
+        for ban in &bans {
+            warn!(
+                "⛔ Banned {} for {} seconds: {} ⛔",
+                ban.target,
+                ban.expires_at - ban.banned_at,
+                ban.reason
+            );
+        }
+        self.shared_state.write().await.bans = active_bans;
 
-        self.clients
-            .write()
-            .await
-            .insert(client_id, Arc::new(RwLock::new(client)));
+        let mut messages_to_clients = Vec::new();
+        for banned_client_id in banned_clients {
+            if let Some(messages_to_client) = self.disconnect_banned_client(banned_client_id).await
+            {
+                messages_to_clients.push(messages_to_client);
+            }
+        }
+        messages_to_clients
+    }
 
-        {
-            let total_clients = self.clients.read().await.len() as u32;
-            let mut state = self.shared_state.write().await;
-            state.total_clients = total_clients;
+    /// Disconnects a banned client. Stratum V1 connections close once their sender is dropped,
+    /// and Stratum V2 connections relayed by the gate once their token is cancelled. Without the
+    /// gate, the server service owns the connections of Stratum V2 clients, so their channels are
+    /// closed instead. Either way, the client is refused until its ban expires.
+    async fn disconnect_banned_client(
+        &self,
+        client_id: u32,
+    ) -> Option<Sv2MessagesToClient<'static>> {
+        if self.sv1_clients.write().await.remove(&client_id).is_some() {
+            return None;
+        }
+        if let Some(connection) = self.sv2_connections.write().await.remove(&client_id) {
+            connection.cancel();
+            return None;
+        }
+        let client = self.get_client(client_id).await.ok()?;
+        let channel_ids: Vec<u32> = {
+            let client_guard = client.read().await;
+            let mut channel_ids: Vec<u32> = client_guard
+                .standard_channels
+                .read()
+                .await
+                .keys()
+                .copied()
+                .collect();
+            channel_ids.extend(client_guard.extended_channels.read().await.keys().copied());
+            channel_ids
+        };
+        let mut messages = Vec::new();
+        for channel_id in channel_ids {
+            self.close_channel(&client, client_id, channel_id).await;
+            messages.push(AnyMessage::Mining(Mining::CloseChannel(CloseChannel {
+                channel_id,
+                reason_code: "banned" //note: non-standard reason code
+                    .to_string()
+                    .try_into()
+                    .expect("reason code must be valid string"),
+            })));
         }
+        Some(Sv2MessagesToClient {
+            client_id,
+            messages,
+        })
     }
 
-    async fn remove_client(&mut self, client_id: u32) {
-        info!("Removing client with id: {}", client_id);
+    /// Scores the error message answering a message of `client_id` on `channel_id`, or the
+    /// error handling it, adding the `CloseChannel` messages of any ban it causes to the outcome.
+    async fn score_result(
+        &self,
+        client_id: u32,
+        channel_id: Option<u32>,
+        result: Result<Sv2ServerOutcome<'static>, Sv2ServerEventError>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        if self.ban_list.is_none() {
+            return result;
+        }
+        let offense = match &result {
+            Ok(outcome) => error_offense(outcome),
+            Err(_) => Some(Offense::ProtocolError),
+        };
+        let Some(offense) = offense else {
+            return result;
+        };
+        self.score_offense(client_id, channel_id, offense, result)
+            .await
+    }
 
-        let hashrate = {
-            let mut hash = 0.0;
-            let clients_guard = self.clients.read().await;
-            let client = match clients_guard.get(&client_id) {
-                Some(c) => c,
-                None => {
-                    info!(
-                        "Client {} not found in clients list — assuming already dropped.",
-                        client_id
-                    );
-                    return;
-                }
-            };
-            let client_guard = client.read().await;
+    /// Scores `offense` of `client_id` on `channel_id`, adding the `CloseChannel` messages of any
+    /// ban it causes to the outcome of `result`.
+    async fn score_offense(
+        &self,
+        client_id: u32,
+        channel_id: Option<u32>,
+        offense: Offense,
+        result: Result<Sv2ServerOutcome<'static>, Sv2ServerEventError>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        let messages_to_clients = self.record_offense(client_id, channel_id, offense).await;
+        if messages_to_clients.is_empty() {
+            return result;
+        }
+        let mut events: Vec<Sv2ServerEvent<'static>> = messages_to_clients
+            .into_iter()
+            .map(|messages_to_client| {
+                Sv2ServerEvent::SendMessagesToClient(Box::new(messages_to_client))
+            })
+            .collect();
+        if let Ok(Sv2ServerOutcome::TriggerNewEvent(event)) = result {
+            events.insert(0, *event);
+        }
+        Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+            Sv2ServerEvent::MultipleEvents(Box::new(events)),
+        )))
+    }
 
-            // Calculate hashrate from standard channels
-            let std_channels = client_guard.standard_channels.read().await;
-            for (_, channel) in std_channels.iter() {
-                hash += channel.read().await.get_nominal_hashrate();
+    /// Checks `user_identity` against the access configuration, returning the identity the
+    /// channel is opened with, stripped of its token.
+    ///
+    /// Rejected attempts are logged and counted on the dashboard, and `Err` holds the error code
+    /// of the `OpenMiningChannelError` answering them.
+    async fn authorize_user_identity(
+        &self,
+        client_id: u32,
+        user_identity: String,
+    ) -> Result<String, &'static str> {
+        let Some(access) = &self.access else {
+            return Ok(user_identity);
+        };
+        match access::authorize(access, &user_identity) {
+            Ok(identity) => Ok(identity),
+            Err(denied) => {
+                // the token, if any, is never logged
+                let (identity, _) = access::split_token(&user_identity);
+                error!(
+                    "🚫 Rejected channel of client {} for user_identity {}: {} 🚫",
+                    client_id,
+                    identity,
+                    denied.error_code()
+                );
+                self.count_rejected_channel_open(format!("{}: {}", identity, denied.error_code()))
+                    .await;
+                Err(denied.error_code())
             }
+        }
+    }
 
-            // Calculate hashrate from extended channels
-            let ext_channels = client_guard.extended_channels.read().await;
-            for (_, channel) in ext_channels.iter() {
-                hash += channel.read().await.get_nominal_hashrate();
-            }
+    /// Worker the offenses on a channel of `user_identity` are scored against, if its access
+    /// token authenticated it. Anyone can claim other identities, like a public payout address,
+    /// so banning them would let a client ban someone else.
+    fn authenticated_worker(&self, user_identity: &str) -> Option<String> {
+        self.access
+            .as_ref()
+            .filter(|access| access.tokens.contains_key(user_identity))
+            .map(|_| user_identity.to_string())
+    }
 
-            hash
-        };
-        self.clients.write().await.remove(&client_id);
+    /// Counts a channel refused by the access configuration or the limits on the dashboard.
+    async fn count_rejected_channel_open(&self, rejected: String) {
+        let mut state = self.shared_state.write().await;
+        state.rejected_channel_opens += 1;
+        state.last_rejected_channel_open = Some(rejected);
+    }
 
-        {
-            let total_clients = self.clients.read().await.len() as u32;
-            let mut state = self.shared_state.write().await;
-            state.total_clients = total_clients;
-            state.total_hashrate -= hashrate;
-            // Ensure hashrate doesn't go negative due to floating point precision
-            if state.total_hashrate < 0.0 {
-                state.total_hashrate = 0.0;
+    /// Admits a connection from `address`, unless there are as many clients as allowed, or the
+    /// address already has as many connections as allowed. Admitted connections count as
+    /// pending until their client is added, or they close. `Err` holds why the connection is
+    /// refused.
+    ///
+    /// The checks and the count happen at once, so connections accepted at the same time, on
+    /// either listener, can't all pass the limits.
+    pub async fn admit_connection(&self, address: IpAddr) -> Result<(), &'static str> {
+        let mut pending_connections = self.pending_connections.write().await;
+        if let Some(max_clients) = self.limits.max_clients {
+            let clients =
+                self.clients.read().await.len() + pending_connections.values().sum::<usize>();
+            if clients >= max_clients {
+                return Err("over the client limit");
             }
         }
+        if let Some(max_connections_per_ip) = self.limits.max_connections_per_ip {
+            let connections = self
+                .client_addresses
+                .read()
+                .await
+                .values()
+                .filter(|client_address| **client_address == address)
+                .count()
+                + pending_connections.get(&address).copied().unwrap_or(0);
+            if connections >= max_connections_per_ip {
+                return Err("over the connection limit of its address");
+            }
+        }
+        *pending_connections.entry(address).or_insert(0) += 1;
+        Ok(())
     }
 
-    async fn start(&mut self) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
-        Ok(Sv2ServerOutcome::Ok)
+    /// Stops counting a connection admitted from `address` that closed before its client was
+    /// added.
+    pub async fn remove_pending_connection(&self, address: IpAddr) {
+        let mut pending_connections = self.pending_connections.write().await;
+        Self::remove_pending(&mut pending_connections, address);
     }
 
-    async fn handle_open_standard_mining_channel(
-        &self,
-        client_id: u32,
-        m: OpenStandardMiningChannel<'static>,
-    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
-        info!("Received OpenStandardMiningChannel message");
-        let mut messages = Vec::new();
+    fn remove_pending(pending_connections: &mut HashMap<IpAddr, usize>, address: IpAddr) {
+        if let Some(pending) = pending_connections.get_mut(&address) {
+            *pending -= 1;
+            if *pending == 0 {
+                pending_connections.remove(&address);
+            }
+        }
+    }
 
-        let client = self.get_client(client_id).await?;
+    /// Records the address of the client of an admitted connection, which stops counting as
+    /// pending at the same time.
+    async fn add_client_address(&self, client_id: u32, address: IpAddr) {
+        let mut pending_connections = self.pending_connections.write().await;
+        self.client_addresses
+            .write()
+            .await
+            .insert(client_id, address);
+        Self::remove_pending(&mut pending_connections, address);
+        if let Some(ban_list) = &self.ban_list {
+            ban_list
+                .write()
+                .await
+                .add_client_address(client_id, address);
+        }
+    }
 
-        // Get extranonce prefix
-        let extranonce_prefix = {
-            let mut factory = self.extranonce_prefix_factory_standard.write().await;
-            match factory.next_prefix_standard() {
-                Ok(prefix) => prefix.to_vec(),
-                Err(e) => {
-                    error!(
-                        "Failed to get extranonce prefix for client {}: {:?}",
-                        client_id, e
-                    );
-                    return Err(Sv2ServerEventError::MiningHandlerError(format!(
-                        "Failed to get extranonce prefix: {:?}",
-                        e
-                    )));
-                }
+    /// Checks the connection and channel limits before `client_id` opens a channel, before any
+    /// extranonce prefix is taken for it. Returns the error code of the `OpenMiningChannelError`
+    /// refusing the channel.
+    async fn check_channel_limits(&self, client_id: u32) -> Result<(), &'static str> {
+        let limits = &self.limits;
+        let client = self.clients.read().await.get(&client_id).cloned();
+        let (channels, over_client_limit) = match client {
+            Some(client) => {
+                let client_guard = client.read().await;
+                (
+                    client_guard.standard_channels.read().await.len()
+                        + client_guard.extended_channels.read().await.len(),
+                    client_guard.over_client_limit,
+                )
             }
+            None => (0, false),
         };
 
-        let channel_id = {
-            let client_guard = client.read().await;
-            let channel_id = client_guard
-                .channel_id_factory
-                .fetch_add(1, Ordering::SeqCst);
-            channel_id
+        let error_code = if limits
+            .max_channels_per_client
+            .is_some_and(|max_channels| channels >= max_channels)
+        {
+            Some("too-many-channels") //note: non-standard error code
+        } else if over_client_limit {
+            Some("too-many-clients") //note: non-standard error code
+        } else {
+            // the earliest connections of an address are the ones allowed to mine
+            let client_addresses = self.client_addresses.read().await;
+            let earlier_connections = client_addresses.get(&client_id).map(|address| {
+                client_addresses
+                    .iter()
+                    .filter(|(other_id, other_address)| {
+                        *other_address == address && **other_id < client_id
+                    })
+                    .count()
+            });
+            match (earlier_connections, limits.max_connections_per_ip) {
+                (Some(earlier_connections), Some(max_connections))
+                    if earlier_connections >= max_connections =>
+                {
+                    Some("too-many-connections") //note: non-standard error code
+                }
+                _ => None,
+            }
         };
 
-        let user_identity = std::str::from_utf8(m.user_identity.as_ref())
-            .map(|s| s.to_string())
-            .map_err(|e| {
-                error!("Invalid UTF-8 in user_identity: {:?}", e);
-                Sv2ServerEventError::MiningHandlerError(format!(
-                    "Invalid UTF-8 in user_identity: {:?}",
-                    e
-                ))
-            })?;
-
-        // Clone max_target so m is not partially moved
-        let max_target = m.max_target.clone();
+        match error_code {
+            Some(error_code) => {
+                error!(
+                    "🚫 Rejected channel of client {}, over the limits: {} 🚫",
+                    client_id, error_code
+                );
+                self.count_rejected_channel_open(format!("client {}: {}", client_id, error_code))
+                    .await;
+                Err(error_code)
+            }
+            None => Ok(()),
+        }
+    }
 
-        // Create standard channel
-        let job_store = Box::new(DefaultJobStore::new());
+    /// Answers an `OpenStandardMiningChannel` or `OpenExtendedMiningChannel` with an
+    /// `OpenMiningChannelError`.
+    fn open_mining_channel_error(
+        client_id: u32,
+        request_id: u32,
+        error_code: &str,
+    ) -> Sv2ServerOutcome<'static> {
+        let error_message = OpenMiningChannelError {
+            request_id,
+            error_code: error_code
+                .to_string()
+                .try_into()
+                .expect("error code must be valid string"),
+        };
+        Sv2ServerOutcome::TriggerNewEvent(Box::new(Sv2ServerEvent::SendMessagesToClient(Box::new(
+            Sv2MessagesToClient {
+                client_id,
+                messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                    error_message,
+                ))],
+            },
+        ))))
+    }
 
-        let mut standard_channel = match StandardChannel::new(
-            channel_id,
-            user_identity,
-            extranonce_prefix,
-            max_target.into(),
-            m.nominal_hash_rate,
-            self.share_batch_size,
-            self.expected_shares_per_minute,
-            job_store,
-        ) {
-            Ok(channel) => channel,
-            Err(e) => match e {
-                StandardChannelError::InvalidNominalHashrate => {
-                    error!("OpenMiningChannelError: invalid-nominal-hashrate");
-                    let error_message = OpenMiningChannelError {
-                        request_id: m.get_request_id_as_u32(),
-                        error_code: "invalid-nominal-hashrate".to_string().try_into().unwrap(),
-                    };
-                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                            client_id,
-                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
-                                error_message,
-                            ))],
-                        })),
-                    )));
-                }
-                StandardChannelError::RequestedMaxTargetOutOfRange => {
-                    error!("OpenMiningChannelError: requested-max-target-out-of-range");
-                    let error_message = OpenMiningChannelError {
-                        request_id: m.get_request_id_as_u32(),
-                        error_code: "max-target-out-of-range".to_string().try_into().unwrap(),
-                    };
-                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                            client_id,
-                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
-                                error_message,
-                            ))],
-                        })),
-                    )));
+    /// Resolves the payout script of a new channel from its `user_identity`.
+    ///
+    /// Returns `Ok(None)` if the channel should pay `coinbase_output_script`, and `Err` if the
+    /// channel must be rejected.
+    fn resolve_payout_script(&self, user_identity: &str) -> Result<Option<ScriptBuf>, ()> {
+        if self.user_identity_payout == UserIdentityPayoutMode::Disabled {
+            return Ok(None);
+        }
+        match user_identity_payout_script(user_identity, self.network) {
+            Ok(script) => Ok(Some(script)),
+            Err(e) => match self.user_identity_payout {
+                UserIdentityPayoutMode::Reject => {
+                    error!("Rejecting user_identity {}: {}", user_identity, e);
+                    Err(())
                 }
                 _ => {
-                    error!("error in handle_open_standard_mining_channel: {:?}", e);
-                    return Err(Sv2ServerEventError::MiningHandlerError(format!(
-                        "Error creating standard channel: {:?}",
-                        e
-                    )));
+                    info!(
+                        "user_identity {} is not a valid payout address, falling back to coinbase_output_address: {}",
+                        user_identity, e
+                    );
+                    Ok(None)
                 }
             },
-        };
+        }
+    }
 
-        // Extract needed fields before mutably borrowing standard_channel
-        let target = standard_channel.get_target().clone().into();
-        let extranonce_prefix = standard_channel
-            .get_extranonce_prefix()
-            .clone()
-            .try_into()
-            .map_err(|e| {
-                error!("Failed to convert extranonce prefix: {:?}", e);
-                Sv2ServerEventError::MiningHandlerError(format!(
-                    "Failed to convert extranonce prefix: {:?}",
-                    e
-                ))
-            })?;
+    async fn register_payout_script(
+        &self,
+        client_id: u32,
+        channel_id: u32,
+        payout_script: Option<ScriptBuf>,
+    ) -> Result<(), Sv2ServerEventError> {
+        if let Some(payout_script) = payout_script {
+            let client_guard = self.get_client(client_id).await?;
+            let payout_scripts_arc = &client_guard.read().await.payout_scripts;
+            payout_scripts_arc
+                .write()
+                .await
+                .insert(channel_id, payout_script);
+        }
+        Ok(())
+    }
 
-        // Get last activated future template
-        let last_activated_future_template = match self.get_last_activated_template().await {
-            Some(template) => template,
-            None => {
-                error!("Unable to open standard mining channel with client {}: No last activated future template available", client_id);
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id: client_id,
-                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
-                            OpenMiningChannelError {
-                                request_id: m.get_request_id_as_u32(),
-                                error_code: "not-ready-to-open-channel" //note: non-standard error code
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            },
-                        ))],
-                    })),
-                )));
+    /// Registers `worker`, the authenticated `user_identity` of a channel without its token, so
+    /// offenses on the channel are scored against it.
+    async fn register_worker(&self, client_id: u32, channel_id: u32, worker: Option<String>) {
+        if let (Some(ban_list), Some(worker)) = (&self.ban_list, worker) {
+            ban_list
+                .write()
+                .await
+                .add_channel(client_id, channel_id, worker);
+        }
+    }
+
+    /// Makes sure the Template Provider is serving the configured network before any job is
+    /// built on top of `prev_hash`.
+    async fn check_network(
+        &self,
+        prev_hash: &SetNewPrevHash<'static>,
+    ) -> Result<(), Sv2ServerEventError> {
+        let height = match self
+            .future_templates
+            .read()
+            .await
+            .get(&prev_hash.template_id)
+        {
+            Some(template) => {
+                bip34_block_height(&template.coinbase_prefix.to_vec()).map_err(|e| {
+                    error!("Error reading BIP34 block height: {:?}", e);
+                    Sv2ServerEventError::MiningHandlerError(format!(
+                        "Error reading BIP34 block height: {:?}",
+                        e
+                    ))
+                })?
             }
+            // missing future templates are handled by on_set_new_prev_hash
+            None => return Ok(()),
         };
-        let coinbase_output = self.get_coinbase_outputs().await?;
+        let prev_hash_bytes: [u8; 32] = prev_hash.prev_hash.to_vec().try_into().map_err(|e| {
+            Sv2ServerEventError::MiningHandlerError(format!("Invalid prev hash: {:?}", e))
+        })?;
+
+        if let Err(e) = check_chain_tip_network(
+            self.network,
+            height,
+            BlockHash::from_byte_array(prev_hash_bytes),
+            prev_hash.n_bits,
+        ) {
+            error!(
+                "Template Provider is not serving {}: {} ❌",
+                network_name(self.network),
+                e
+            );
+            self.shared_state.write().await.network_mismatch = Some(e.to_string());
+            return Err(Sv2ServerEventError::MiningHandlerError(format!(
+                "Template Provider is not serving {}: {}",
+                network_name(self.network),
+                e
+            )));
+        }
+        Ok(())
+    }
 
-        // Call on_new_template before moving standard_channel
-        standard_channel
-            .on_new_template(last_activated_future_template.clone(), coinbase_output)
-            .map_err(|e| {
-                error!("Error processing new template on standard channel: {:?}", e);
-                Sv2ServerEventError::MiningHandlerError(format!(
-                    "Error processing new template on standard channel: {:?}",
-                    e
-                ))
-            })?;
-
-        let (future_standard_job_id, future_job_message) =
-            self.get_future_job_message(&standard_channel).await?;
+    /// Height of the template mined on the current chain tip.
+    async fn get_tip_template_height(&self) -> Option<u64> {
+        let template = self.get_last_activated_template().await?;
+        bip34_block_height(&template.coinbase_prefix.to_vec()).ok()
+    }
 
-        let last_prev_hash = match self.get_last_prev_hash().await {
-            Some(prev_hash) => prev_hash,
-            None => {
-                error!("Unable to open standard mining channel with client {}: No last activated prev hash available", client_id);
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id: client_id,
-                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
-                            OpenMiningChannelError {
-                                request_id: m.get_request_id_as_u32(),
-                                error_code: "not-ready-to-open-channel" //note: non-standard error code
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            },
-                        ))],
-                    })),
-                )));
+    /// Rejects `template` if it fails one of the `template_sanity` checks, raising an alert.
+    async fn check_template_sanity(
+        &self,
+        template: &NewTemplate<'static>,
+    ) -> Result<(), Sv2ServerEventError> {
+        if !self.template_sanity.check_subsidy && !self.template_sanity.check_height {
+            return Ok(());
+        }
+        let tip_template_height = self.get_tip_template_height().await;
+        let result = bip34_block_height(&template.coinbase_prefix.to_vec()).and_then(|height| {
+            if self.template_sanity.check_subsidy {
+                check_subsidy(self.network, height, template.coinbase_tx_value_remaining)?;
             }
-        };
-        standard_channel
-            .on_set_new_prev_hash(last_prev_hash.clone())
-            .map_err(|e| {
-                error!(
-                    "Error processing SetNewPrevHash on standard channel: {:?}",
-                    e
+            if self.template_sanity.check_height {
+                check_template_height(height, template.future_template, tip_template_height)?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => Err(self
+                .reject_from_template_provider(format!("template {}", template.template_id), e)
+                .await),
+        }
+    }
+
+    /// Rejects `prev_hash` if it fails one of the `template_sanity` checks, raising an alert.
+    async fn check_prev_hash_sanity(
+        &self,
+        prev_hash: &SetNewPrevHash<'static>,
+    ) -> Result<(), Sv2ServerEventError> {
+        let mut result = Ok(());
+        if self.template_sanity.check_time_drift {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            result = check_time_drift(
+                prev_hash.header_timestamp,
+                now,
+                self.template_sanity.max_time_drift,
+            );
+        }
+        if self.template_sanity.check_nbits && result.is_ok() {
+            let activated_template = self
+                .future_templates
+                .read()
+                .await
+                .get(&prev_hash.template_id)
+                .cloned();
+            // the first prev hash has nothing to compare with, and missing future templates are
+            // handled by on_set_new_prev_hash
+            if let (Some(last_prev_hash), Some(last_height), Some(activated_template)) = (
+                self.get_last_prev_hash().await,
+                self.get_tip_template_height().await,
+                activated_template,
+            ) {
+                result = bip34_block_height(&activated_template.coinbase_prefix.to_vec()).and_then(
+                    |height| {
+                        check_nbits(
+                            self.network,
+                            last_height,
+                            last_prev_hash.n_bits,
+                            height,
+                            prev_hash.n_bits,
+                        )
+                    },
                 );
-                Sv2ServerEventError::MiningHandlerError(format!(
-                    "Error processing SetNewPrevHash on standard channel: {:?}",
-                    e
-                ))
-            })?;
+            }
+        }
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => Err(self
+                .reject_from_template_provider(
+                    format!("prev hash for template {}", prev_hash.template_id),
+                    e,
+                )
+                .await),
+        }
+    }
 
-        // Prepare SetNewPrevHashMp message
-        let set_new_prev_hash_mp = SetNewPrevHashMp {
-            channel_id,
-            job_id: future_standard_job_id,
-            prev_hash: last_prev_hash.prev_hash.clone(),
-            min_ntime: last_prev_hash.header_timestamp,
-            nbits: last_prev_hash.n_bits,
-        };
+    /// Logs a template or prev hash that failed a sanity check, and shows it on the dashboard.
+    async fn reject_from_template_provider(
+        &self,
+        rejected: String,
+        e: anyhow::Error,
+    ) -> Sv2ServerEventError {
+        error!(
+            "🚨 Rejected {} from the Template Provider: {} 🚨",
+            rejected, e
+        );
+        {
+            let mut state = self.shared_state.write().await;
+            state.rejected_templates += 1;
+            state.last_rejected_template = Some(format!("{}: {}", rejected, e));
+        }
+        Sv2ServerEventError::MiningHandlerError(format!(
+            "Rejected {} from the Template Provider: {}",
+            rejected, e
+        ))
+    }
 
-        let nominal_hashrate = standard_channel.get_nominal_hashrate();
+    async fn get_future_job_message_extended(
+        &self,
+        extended_channel: &ExtendedChannel<'static>,
+    ) -> Result<(u32, NewExtendedMiningJob<'static>), Sv2ServerEventError> {
+        let template = self.get_last_activated_template().await.ok_or(
+            Sv2ServerEventError::MiningHandlerError("No last activated template found".to_string()),
+        )?;
+        let future_job_id = *extended_channel
+            .get_future_template_to_job_id()
+            .get(&template.template_id)
+            .ok_or(Sv2ServerEventError::MiningHandlerError(format!(
+                "Future job must be present"
+            )))?;
+        let future_job = extended_channel
+            .get_future_jobs()
+            .get(&future_job_id)
+            .ok_or(Sv2ServerEventError::MiningHandlerError(format!(
+                "Future job must be present"
+            )))?
+            .get_job_message()
+            .clone();
 
-        let group_channel_id = self
-            .register_standard_channel(client_id, channel_id, standard_channel)
-            .await?;
+        Ok((future_job_id, future_job))
+    }
+    async fn get_future_job_message(
+        &self,
+        extended_channel: &StandardChannel<'static>,
+    ) -> Result<(u32, NewMiningJob<'static>), Sv2ServerEventError> {
+        let template = self.get_last_activated_template().await.ok_or(
+            Sv2ServerEventError::MiningHandlerError("No last activated template found".to_string()),
+        )?;
+        let future_job_id = *extended_channel
+            .get_future_template_to_job_id()
+            .get(&template.template_id)
+            .ok_or(Sv2ServerEventError::MiningHandlerError(format!(
+                "Future job must be present"
+            )))?;
+        let future_job = extended_channel
+            .get_future_jobs()
+            .get(&future_job_id)
+            .ok_or(Sv2ServerEventError::MiningHandlerError(format!(
+                "Future job must be present"
+            )))?
+            .get_job_message()
+            .clone();
 
-        let open_standard_mining_channel_response = OpenStandardMiningChannelSuccess {
-            request_id: m.request_id,
-            channel_id,
-            target,
-            extranonce_prefix,
-            group_channel_id,
+        Ok((future_job_id, future_job))
+    }
+
+    async fn register_extended_channel(
+        &self,
+        client_id: u32,
+        channel_id: u32,
+        extended_channel: ExtendedChannel<'static>,
+    ) -> Result<(), Sv2ServerEventError> {
+        let target: U256 = extended_channel.get_target().clone().into();
+        let nominal_hashrate = extended_channel.get_nominal_hashrate();
+
+        // Register the new extended channel
+        let client_guard = self.get_client(client_id).await?;
+        let ext_channels_arc = &client_guard.read().await.extended_channels;
+        ext_channels_arc
+            .write()
+            .await
+            .insert(channel_id, Arc::new(RwLock::new(extended_channel)));
+
+        // only channels that opened get a bucket
+        self.limit_share_rate(client_id, channel_id, nominal_hashrate, &target.to_vec())
+            .await;
+        Ok(())
+    }
+
+    fn set_custom_mining_job_error(
+        client_id: u32,
+        m: &SetCustomMiningJob<'static>,
+        error_code: &str,
+    ) -> Sv2ServerOutcome<'static> {
+        error!(
+            "SetCustomMiningJobError: channel_id: {}, request_id: {}, error_code: {} ❌",
+            m.channel_id, m.request_id, error_code
+        );
+        Sv2ServerOutcome::TriggerNewEvent(Box::new(Sv2ServerEvent::SendMessagesToClient(Box::new(
+            Sv2MessagesToClient {
+                client_id,
+                messages: vec![AnyMessage::Mining(Mining::SetCustomMiningJobError(
+                    SetCustomMiningJobError {
+                        channel_id: m.channel_id,
+                        request_id: m.request_id,
+                        error_code: error_code
+                            .to_string()
+                            .try_into()
+                            .expect("error code must be valid string"),
+                    },
+                ))],
+            },
+        ))))
+    }
+
+    /// Adds a client with the flags of its `SetupConnection`.
+    async fn insert_client(&mut self, client_id: u32, flags: u32) {
+        info!("Adding client with id: {}, flags: {:04b}", client_id, flags);
+
+        let channel_id_factory = AtomicU32::new(1);
+
+        let standard_channels = Arc::new(RwLock::new(HashMap::new()));
+        let extended_channels = Arc::new(RwLock::new(HashMap::new()));
+        // if SetupConnection.REQUIRES_STANDARD_JOBS is set
+        // client does not understand group channels
+        // group jobs can't pay to per-channel addresses, so they are also disabled
+        // when channels are paid to their user_identity
+        let group_channel = if flags & 0x0001 == 0x0001
+            || self.user_identity_payout != UserIdentityPayoutMode::Disabled
+        {
+            None
+        } else {
+            let group_channel_id = channel_id_factory.fetch_add(1, Ordering::SeqCst);
+            info!("Adding group channel with id: {}", group_channel_id);
+            let job_store = Box::new(DefaultJobStore::new());
+            Some(Arc::new(RwLock::new(GroupChannel::new(
+                group_channel_id,
+                job_store,
+            ))))
+        };
+
+        let mut clients = self.clients.write().await;
+        // connections are refused over max_clients, clients added past them anyway get no channels
+        let over_client_limit = self
+            .limits
+            .max_clients
+            .is_some_and(|max_clients| clients.len() >= max_clients);
+        if over_client_limit {
+            warn!(
+                "Client {} was added over the client limit, its channels are refused",
+                client_id
+            );
+        }
+        let client = PleblotteryMiningClient {
+            client_id,
+            connection_flags: flags,
+            channel_id_factory,
+            group_channel,
+            standard_channels,
+            extended_channels,
+            payout_scripts: Arc::new(RwLock::new(HashMap::new())),
+            over_client_limit,
         };
+        clients.insert(client_id, Arc::new(RwLock::new(client)));
+        drop(clients);
 
         {
+            let total_clients = self.clients.read().await.len() as u32;
             let mut state = self.shared_state.write().await;
-            state.total_hashrate += nominal_hashrate;
+            state.total_clients = total_clients;
         }
+    }
 
-        messages.push(AnyMessage::Mining(
-            Mining::OpenStandardMiningChannelSuccess(open_standard_mining_channel_response),
-        ));
-        messages.push(AnyMessage::Mining(Mining::NewMiningJob(future_job_message)));
-        messages.push(AnyMessage::Mining(Mining::SetNewPrevHash(
-            set_new_prev_hash_mp,
-        )));
+    /// Adds a client for a Stratum V1 connection from `address`, whose messages are sent through
+    /// `sender` instead of the server service.
+    pub async fn add_sv1_client(
+        &mut self,
+        client_id: u32,
+        address: IpAddr,
+        sender: UnboundedSender<AnyMessage<'static>>,
+    ) {
+        self.sv1_clients.write().await.insert(client_id, sender);
+        // REQUIRES_STANDARD_JOBS, as Stratum V1 has no group channels
+        self.insert_client(client_id, 0x0001).await;
+        // only stops counting as pending once it counts as a client
+        self.add_client_address(client_id, address).await;
+    }
 
-        info!(
-            "Opened standard mining channel with id: {} for client: {}",
-            channel_id, client_id
-        );
+    /// Maps the Stratum V2 connection the gate relays from `address` onto `client_id`, so it's
+    /// scored by address and dropped by cancelling `connection` once banned.
+    pub async fn add_sv2_connection(
+        &self,
+        client_id: u32,
+        address: IpAddr,
+        connection: CancellationToken,
+    ) {
+        self.add_client_address(client_id, address).await;
+        self.sv2_connections
+            .write()
+            .await
+            .insert(client_id, connection);
+    }
 
-        Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                client_id,
-                messages,
-            })),
-        )))
+    pub async fn remove_sv1_client(&mut self, client_id: u32) {
+        self.remove_client(client_id).await;
+        self.sv1_clients.write().await.remove(&client_id);
     }
 
-    async fn handle_open_extended_mining_channel(
+    /// Sends the messages for Stratum V1 clients to their connections, returning the ones left
+    /// for the server service.
+    async fn dispatch_sv1_messages(
         &self,
-        client_id: u32,
-        m: OpenExtendedMiningChannel<'static>,
-    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
-        info!("Received OpenExtendedMiningChannel message");
+        messages_to_clients: Vec<Sv2MessagesToClient<'static>>,
+    ) -> Vec<Sv2MessagesToClient<'static>> {
+        let sv1_clients = self.sv1_clients.read().await;
+        let mut sv2_messages_to_clients = Vec::new();
+        for messages_to_client in messages_to_clients {
+            match sv1_clients.get(&messages_to_client.client_id) {
+                Some(sender) => {
+                    for message in messages_to_client.messages {
+                        // the connection is gone, and its client about to be removed
+                        let _ = sender.send(message);
+                    }
+                }
+                None => sv2_messages_to_clients.push(messages_to_client),
+            }
+        }
+        sv2_messages_to_clients
+    }
 
-        let mut messages = Vec::new();
+    /// Measures the share rate of every channel since the last call, and retargets the channels
+    /// whose rate is off from `expected_shares_per_minute`. Returns the `SetTarget` messages for
+    /// their clients.
+    ///
+    /// Shares are submitted on the standard channels of a group, so groups are retargeted
+    /// channel by channel.
+    pub async fn retarget_channels(&self) -> Vec<Sv2MessagesToClient<'static>> {
+        let clients: Vec<(u32, Arc<RwLock<PleblotteryMiningClient>>)> = self
+            .clients
+            .read()
+            .await
+            .iter()
+            .map(|(client_id, client)| (*client_id, client.clone()))
+            .collect();
+
+        let mut vardiff_states = self.vardiff_states.write().await;
+        let mut measured = HashSet::new();
+        let mut messages_to_clients = Vec::new();
+        for (client_id, client) in clients {
+            let (standard_channels, extended_channels) = {
+                let client_guard = client.read().await;
+                let standard_channels: Vec<_> = client_guard
+                    .standard_channels
+                    .read()
+                    .await
+                    .iter()
+                    .map(|(channel_id, channel)| (*channel_id, channel.clone()))
+                    .collect();
+                let extended_channels: Vec<_> = client_guard
+                    .extended_channels
+                    .read()
+                    .await
+                    .iter()
+                    .map(|(channel_id, channel)| (*channel_id, channel.clone()))
+                    .collect();
+                (standard_channels, extended_channels)
+            };
 
-        let client = self.get_client(client_id).await?;
+            let mut messages = Vec::new();
+            for (channel_id, standard_channel) in standard_channels {
+                measured.insert((client_id, channel_id));
+                let mut channel = standard_channel.write().await;
+                let shares_accepted = channel.get_share_accounting().get_shares_accepted();
+                let Some(state) = vardiff_states.get_mut(&(client_id, channel_id)) else {
+                    vardiff_states
+                        .insert((client_id, channel_id), VardiffState::new(shares_accepted));
+                    continue;
+                };
+                let (shares, elapsed) = state.measure(shares_accepted);
+                let nominal_hashrate = channel.get_nominal_hashrate();
+                let Some(hashrate) = retarget(
+                    nominal_hashrate,
+                    shares,
+                    elapsed,
+                    self.expected_shares_per_minute,
+                    &self.vardiff,
+                ) else {
+                    continue;
+                };
+                if let Err(e) = channel.update_channel(hashrate, None) {
+                    error!(
+                        "Error retargeting standard channel {} of client {}: {:?}",
+                        channel_id, client_id, e
+                    );
+                    continue;
+                }
+                info!(
+                    "Retargeted standard channel {} of client {}: {} shares in {}s, nominal hashrate {} -> {}",
+                    channel_id, client_id, shares, elapsed.as_secs(), nominal_hashrate, hashrate
+                );
+                let target: U256 = channel.get_target().clone().into();
+                self.limit_share_rate(client_id, channel_id, hashrate, &target.to_vec())
+                    .await;
+                messages.push(AnyMessage::Mining(Mining::SetTarget(SetTarget {
+                    channel_id,
+                    maximum_target: channel.get_target().clone().into(),
+                })));
+            }
+            for (channel_id, extended_channel) in extended_channels {
+                measured.insert((client_id, channel_id));
+                let mut channel = extended_channel.write().await;
+                let shares_accepted = channel.get_share_accounting().get_shares_accepted();
+                let Some(state) = vardiff_states.get_mut(&(client_id, channel_id)) else {
+                    vardiff_states
+                        .insert((client_id, channel_id), VardiffState::new(shares_accepted));
+                    continue;
+                };
+                let (shares, elapsed) = state.measure(shares_accepted);
+                let nominal_hashrate = channel.get_nominal_hashrate();
+                let Some(hashrate) = retarget(
+                    nominal_hashrate,
+                    shares,
+                    elapsed,
+                    self.expected_shares_per_minute,
+                    &self.vardiff,
+                ) else {
+                    continue;
+                };
+                if let Err(e) = channel.update_channel(hashrate, None) {
+                    error!(
+                        "Error retargeting extended channel {} of client {}: {:?}",
+                        channel_id, client_id, e
+                    );
+                    continue;
+                }
+                info!(
+                    "Retargeted extended channel {} of client {}: {} shares in {}s, nominal hashrate {} -> {}",
+                    channel_id, client_id, shares, elapsed.as_secs(), nominal_hashrate, hashrate
+                );
+                let target: U256 = channel.get_target().clone().into();
+                self.limit_share_rate(client_id, channel_id, hashrate, &target.to_vec())
+                    .await;
+                messages.push(AnyMessage::Mining(Mining::SetTarget(SetTarget {
+                    channel_id,
+                    maximum_target: channel.get_target().clone().into(),
+                })));
+            }
+            if !messages.is_empty() {
+                messages_to_clients.push(Sv2MessagesToClient {
+                    client_id,
+                    messages,
+                });
+            }
+        }
+        // forget closed channels
+        vardiff_states.retain(|key, _| measured.contains(key));
+        drop(vardiff_states);
 
-        let channel_id = {
-            let client_guard = client.read().await;
-            let channel_id = client_guard
-                .channel_id_factory
-                .fetch_add(1, Ordering::SeqCst);
-            channel_id
-        };
+        if !messages_to_clients.is_empty() {
+            self.update_total_hashrate().await;
+        }
+        self.dispatch_sv1_messages(messages_to_clients).await
+    }
 
-        let user_identity = std::str::from_utf8(m.user_identity.as_ref())
-            .unwrap()
-            .to_string();
+    /// Sizes the share rate limit of `channel_id` of `client_id` from its `nominal_hashrate` and
+    /// `target`, as little-endian bytes.
+    async fn limit_share_rate(
+        &self,
+        client_id: u32,
+        channel_id: u32,
+        nominal_hashrate: f32,
+        target: &[u8],
+    ) {
+        if let Some(share_rate_limiter) = &self.share_rate_limiter {
+            share_rate_limiter.write().await.set_channel(
+                client_id,
+                channel_id,
+                expected_share_rate(nominal_hashrate, target),
+                Instant::now(),
+            );
+        }
+    }
 
-        let extranonce_prefix = {
-            self.extranonce_prefix_factory_extended
-                .write()
-                .await
-                .next_prefix_standard()
-                .map_err(|e| {
-                    error!("Could not get extranonce prefix: {:?}", e);
-                    Sv2ServerEventError::MiningHandlerError(format!(
-                        "Could not get extranonce prefix: {:?}",
-                        e
-                    ))
-                })?
-                .to_vec()
-        };
+    /// Takes a share out of the share rate limits of its channel and of `client_id`, before it
+    /// takes any other lock. Returns the `SubmitSharesError` answering it when over either.
+    async fn rate_limit_share(
+        &self,
+        client_id: u32,
+        channel_id: u32,
+        sequence_number: u32,
+    ) -> Option<Sv2ServerOutcome<'static>> {
+        let share_rate_limiter = self.share_rate_limiter.as_ref()?;
+        let rate_limited = share_rate_limiter
+            .write()
+            .await
+            .take_share(client_id, channel_id, Instant::now())
+            .err()?;
+        // logging every share of a flood would flood the logs too
+        if rate_limited.first {
+            warn!(
+                "🚦 Rejecting shares of client {} over its {} share rate limit, starting on channel {} 🚦",
+                client_id, rate_limited.limit, channel_id
+            );
+        }
+        Some(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                client_id,
+                messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
+                    SubmitSharesError {
+                        channel_id,
+                        sequence_number,
+                        error_code: "share-rate-limited" //note: non-standard error code
+                            .to_string()
+                            .try_into()
+                            .expect("error code must be valid string"),
+                    },
+                ))],
+            })),
+        )))
+    }
 
-        let job_store = Box::new(DefaultJobStore::new());
-        let mut extended_channel = match ExtendedChannel::new(
+    /// Accounts a share accepted on `channel_id` of `client_id` with `target`, as little-endian
+    /// bytes, in the measured hashrate.
+    async fn record_share_work(
+        &self,
+        client_id: u32,
+        channel_id: u32,
+        user_identity: &str,
+        target: &[u8],
+    ) {
+        self.shared_state.write().await.measured_hashrate.add_share(
+            client_id,
             channel_id,
             user_identity,
-            extranonce_prefix,
-            m.max_target.to_owned().into(),
-            m.nominal_hash_rate,
-            true,
-            m.min_extranonce_size,
-            self.share_batch_size,
-            self.expected_shares_per_minute,
-            job_store,
-        ) {
-            Ok(channel) => channel,
-            Err(e) => match e {
-                ExtendedChannelError::InvalidNominalHashrate => {
-                    error!("OpenMiningChannelError: invalid-nominal-hashrate");
-                    let error_message = OpenMiningChannelError {
-                        request_id: m.get_request_id_as_u32(),
-                        error_code: "invalid-nominal-hashrate".to_string().try_into().unwrap(),
-                    };
-                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                            client_id,
-                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
-                                error_message,
-                            ))],
-                        })),
-                    )));
-                }
-                ExtendedChannelError::RequestedMaxTargetOutOfRange => {
-                    error!("OpenMiningChannelError: max-target-out-of-range");
-                    let error_message = OpenMiningChannelError {
-                        request_id: m.get_request_id_as_u32(),
-                        error_code: "max-target-out-of-range".to_string().try_into().unwrap(),
-                    };
-                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                            client_id,
-                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
-                                error_message,
-                            ))],
-                        })),
-                    )));
-                }
-                ExtendedChannelError::RequestedMinExtranonceSizeTooLarge => {
-                    error!("OpenMiningChannelError: min-extranonce-size-too-large");
-                    let error_message = OpenMiningChannelError {
-                        request_id: m.get_request_id_as_u32(),
-                        error_code: "min-extranonce-size-too-large"
-                            .to_string()
-                            .try_into()
-                            .unwrap(),
-                    };
-                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                            client_id,
-                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
-                                error_message,
-                            ))],
-                        })),
-                    )));
-                }
-                _ => {
-                    error!("Error in handle_open_extended_mining_channel: {:?}", e);
-                    return Err(Sv2ServerEventError::MiningHandlerError(format!(
-                        "Error creating extended channel: {:?}",
-                        e
-                    )));
-                }
-            },
+            share_work(target),
+            Instant::now(),
+        );
+    }
+
+    /// Sets `total_hashrate` to the sum of the nominal hashrate of every open channel.
+    async fn update_total_hashrate(&self) {
+        let mut total_hashrate = 0.0;
+        for client in self.clients.read().await.values() {
+            let client_guard = client.read().await;
+            for channel in client_guard.standard_channels.read().await.values() {
+                total_hashrate += channel.read().await.get_nominal_hashrate();
+            }
+            for channel in client_guard.extended_channels.read().await.values() {
+                total_hashrate += channel.read().await.get_nominal_hashrate();
+            }
+        }
+        self.shared_state.write().await.total_hashrate = total_hashrate;
+    }
+
+    async fn register_standard_channel(
+        &self,
+        client_id: u32,
+        channel_id: u32,
+        standard_channel: StandardChannel<'static>,
+    ) -> Result<u32, Sv2ServerEventError> {
+        let target: U256 = standard_channel.get_target().clone().into();
+        let nominal_hashrate = standard_channel.get_nominal_hashrate();
+
+        // Register the new standard channel
+        let client_guard = self.get_client(client_id).await?;
+        let std_channels_arc = &client_guard.read().await.standard_channels;
+        std_channels_arc
+            .write()
+            .await
+            .insert(channel_id, Arc::new(RwLock::new(standard_channel)));
+
+        // only channels that opened get a bucket
+        self.limit_share_rate(client_id, channel_id, nominal_hashrate, &target.to_vec())
+            .await;
+
+        // Add channel to group channel if present
+        if let Some(group_channel) = client_guard.read().await.group_channel.as_ref() {
+            let mut group_channel = group_channel.write().await;
+            group_channel.add_standard_channel_id(channel_id);
+        }
+
+        // Prepare response
+        let group_channel_id = if let Some(gc) = client_guard.read().await.group_channel.as_ref() {
+            gc.read().await.get_group_channel_id()
+        } else {
+            0
         };
+        Ok(group_channel_id)
+    }
 
-        let last_activated_future_template = match self.get_last_activated_template().await {
-            Some(template) => template,
+    /// Validates a share of a standard channel, answering it with `SubmitSharesSuccess` once a
+    /// batch is accepted or with `SubmitSharesError` when invalid.
+    async fn submit_shares_standard(
+        &self,
+        client_id: u32,
+        m: SubmitSharesStandard,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        info!("Received SubmitSharesStandard message");
+        let clients_guard = self.clients.read().await;
+        let client = match clients_guard.get(&client_id) {
+            Some(client) => client,
             None => {
-                error!("Unable to open standard mining channel with client {}: No last activated future template available", client_id);
+                error!("Client with id {} not found", client_id);
+                return Err(Sv2ServerEventError::IdNotFound);
+            }
+        };
+
+        let client_guard = client.read().await;
+        let standard_channels_arc = &client_guard.standard_channels;
+        let std_channels_guard = standard_channels_arc.read().await;
+        let standard_channel_arc = match std_channels_guard.get(&m.channel_id) {
+            Some(channel) => channel,
+            None => {
+                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: invalid-channel-id ❌", m.channel_id, m.sequence_number);
                 return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
                     Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id: client_id,
-                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
-                            OpenMiningChannelError {
-                                request_id: m.get_request_id_as_u32(),
-                                error_code: "not-ready-to-open-channel" //note: non-standard error code
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
+                            SubmitSharesError {
+                                channel_id: m.channel_id,
+                                sequence_number: m.sequence_number,
+                                error_code: "invalid-channel-id"
                                     .to_string()
                                     .try_into()
                                     .expect("error code must be valid string"),
@@ -719,302 +1934,136 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
                 )));
             }
         };
-        let coinbase_outputs = self.get_coinbase_outputs().await?;
 
-        extended_channel
-            .on_new_template(last_activated_future_template.clone(), coinbase_outputs)
-            .map_err(|e| {
-                error!("Error processing new template on extended channel: {:?}", e);
-                Sv2ServerEventError::MiningHandlerError(format!(
-                    "Error processing new template on extended channel: {:?}",
-                    e
-                ))
-            })?;
+        let mut standard_channel = standard_channel_arc.write().await;
+        let share_validation_result = standard_channel.validate_share(m.clone());
+        if share_validation_result.is_ok() {
+            let target: U256 = standard_channel.get_target().clone().into();
+            self.record_share_work(
+                client_id,
+                m.channel_id,
+                standard_channel.get_user_identity(),
+                &target.to_vec(),
+            )
+            .await;
+        }
 
-        let (future_job_id, future_job_message) = self
-            .get_future_job_message_extended(&extended_channel)
-            .await?;
+        match share_validation_result {
+            Ok(ShareValidationResult::Valid) => {
+                info!(
+                    "SubmitSharesStandard: valid share | channel_id: {}, sequence_number: {} ☑️",
+                    m.channel_id, m.sequence_number
+                );
+                {
+                    let mut state = self.shared_state.write().await;
+                    state.total_shares_submitted += 1;
+                }
+                return Ok(Sv2ServerOutcome::Ok);
+            }
+            Ok(ShareValidationResult::ValidWithAcknowledgement(
+                last_sequence_number,
+                new_submits_accepted_count,
+                new_shares_sum,
+            )) => {
+                let success = SubmitSharesSuccess {
+                    channel_id: m.channel_id,
+                    last_sequence_number,
+                    new_submits_accepted_count,
+                    new_shares_sum,
+                };
+                info!("SubmitSharesExtended: {} ✅", success);
 
-        let last_prev_hash = match self.get_last_prev_hash().await {
-            Some(prev_hash) => prev_hash,
-            None => {
-                error!("Unable to open standard mining channel with client {}: No last activated prev hash available", client_id);
+                {
+                    let share_accounting = standard_channel.get_share_accounting();
+                    let mut state = self.shared_state.write().await;
+                    let best_share = share_accounting.get_best_diff();
+                    state.best_share = if best_share > state.best_share {
+                        best_share
+                    } else {
+                        state.best_share
+                    };
+                    state.total_shares_submitted += 1;
+                }
                 return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
                     Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id: client_id,
-                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
-                            OpenMiningChannelError {
-                                request_id: m.get_request_id_as_u32(),
-                                error_code: "not-ready-to-open-channel" //note: non-standard error code
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            },
-                        ))],
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesSuccess(success))],
                     })),
                 )));
             }
-        };
+            Ok(ShareValidationResult::BlockFound(template_id, coinbase)) => {
+                info!("SubmitSharesStandard: 💰 Block Found!!! 💰");
+                // custom jobs can only be set on extended channels
+                let Some(template_id) = template_id else {
+                    error!(
+                        "Block found on standard channel {} of client {} without a template ❌",
+                        m.channel_id, client_id
+                    );
+                    return Err(Sv2ServerEventError::MiningHandlerError(format!(
+                        "Block found on standard channel {} without a template",
+                        m.channel_id
+                    )));
+                };
 
-        // Now mutably borrow extended_channel
-        extended_channel
-            .on_set_new_prev_hash(last_prev_hash.clone())
-            .expect("Error processing SetNewPrevHash on extended channel");
+                {
+                    let mut state = self.shared_state.write().await;
+                    state.blocks_found += 1;
+                }
+                self.schedule_payout_rotation(PayoutRotation::BlockFound)
+                    .await;
 
-        let oxmcs = OpenExtendedMiningChannelSuccess {
-            request_id: m.request_id.clone(),
-            channel_id,
-            target: extended_channel.get_target().clone().into(),
-            extranonce_size: extended_channel.get_rollable_extranonce_size(),
-            extranonce_prefix: extended_channel
-                .get_extranonce_prefix()
-                .clone()
-                .try_into()
-                .expect("could not parse extranonce prefix"),
-        };
-
-        messages.push(AnyMessage::Mining(
-            Mining::OpenExtendedMiningChannelSuccess(oxmcs),
-        ));
-        messages.push(AnyMessage::Mining(Mining::NewExtendedMiningJob(
-            future_job_message,
-        )));
-
-        //get set new prev hash message
-        let snphmp = SetNewPrevHashMp {
-            channel_id,
-            job_id: future_job_id,
-            prev_hash: last_prev_hash.prev_hash,
-            min_ntime: last_prev_hash.header_timestamp,
-            nbits: last_prev_hash.n_bits,
-        };
-        messages.push(AnyMessage::Mining(Mining::SetNewPrevHash(snphmp)));
-
-        let nominal_hashrate = extended_channel.get_nominal_hashrate();
-
-        // Register the new extended channel
-        self.register_extended_channel(client_id, channel_id, extended_channel)
-            .await?;
-
-        {
-            let mut state = self.shared_state.write().await;
-            state.total_hashrate += nominal_hashrate;
-        }
-
-        Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                client_id,
-                messages,
-            })),
-        )))
-    }
-
-    async fn handle_update_channel(
-        &self,
-        client_id: u32,
-        m: UpdateChannel<'static>,
-    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
-        info!("Received UpdateChannel message");
-        let client = self.get_client(client_id).await?;
-        let is_standard_channel = client
-            .read()
-            .await
-            .standard_channels
-            .read()
-            .await
-            .contains_key(&m.channel_id);
-
-        let is_extended_channel = client
-            .read()
-            .await
-            .extended_channels
-            .read()
-            .await
-            .contains_key(&m.channel_id);
-
-        if is_standard_channel {
-            let client_read_guard = client.read().await;
-            let std_channels_read_guard = client_read_guard.standard_channels.read().await;
-            let standard_channel = std_channels_read_guard
-                .get(&m.channel_id)
-                .expect("Standard channel must exist");
+                let share_accounting = standard_channel.get_share_accounting();
 
-            match standard_channel.write().await.update_channel(
-                m.nominal_hash_rate,
-                Some(m.maximum_target.into_static().into()),
-            ) {
-                Ok(()) => {
-                    info!("Updated standard channel | channel_id: {}", m.channel_id);
-                    return Ok(Sv2ServerOutcome::Ok);
-                }
-                Err(e) => match e {
-                    StandardChannelError::InvalidNominalHashrate => {
-                        error!("UpdateChannelError: invalid-nominal-hashrate");
-                        let update_channel_error = UpdateChannelError {
-                            channel_id: m.channel_id,
-                            error_code: "invalid-nominal-hashrate"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                                client_id,
-                                messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
-                                    update_channel_error,
-                                ))],
-                            })),
-                        )));
-                    }
-                    StandardChannelError::RequestedMaxTargetOutOfRange => {
-                        error!("UpdateChannelError: requested-max-target-out-of-range");
-                        let update_channel_error = UpdateChannelError {
-                            channel_id: m.channel_id,
-                            error_code: "requested-max-target-out-of-range"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                                client_id,
-                                messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
-                                    update_channel_error,
-                                ))],
-                            })),
-                        )));
-                    }
-                    _ => {
-                        return Err(Sv2ServerEventError::MiningHandlerError(format!(
-                            "Error updating standard channel: {:?}",
-                            e
-                        )));
-                    }
-                },
-            };
-        } else if is_extended_channel {
-            // Scope the client_read_guard so it is dropped before the await
-            let extended_channel = {
-                let client_read_guard = client.read().await;
-                let ext_channels_read_guard = client_read_guard.extended_channels.read().await;
-                ext_channels_read_guard
+                let solution = SubmitSolution {
+                    template_id,
+                    version: m.version,
+                    header_timestamp: m.ntime,
+                    header_nonce: m.nonce,
+                    coinbase_tx: coinbase.try_into().expect("coinbase tx must be valid"),
+                };
+                let block = self
+                    .rebuild_standard_block(&standard_channel, &m, &solution)
+                    .await;
+                let payout_script = client_guard
+                    .payout_scripts
+                    .read()
+                    .await
                     .get(&m.channel_id)
-                    .expect("Extended channel must exist")
-                    .clone()
-            };
-
-            let update_result = {
-                let mut channel = extended_channel.write().await;
-                channel.update_channel(
-                    m.nominal_hash_rate,
-                    Some(m.maximum_target.into_static().into()),
-                )
-            };
-
-            match update_result {
-                Ok(()) => {
-                    info!("Updated extended channel | channel_id: {}", m.channel_id);
-                    return Ok(Sv2ServerOutcome::Ok);
-                }
-                Err(e) => match e {
-                    ExtendedChannelError::InvalidNominalHashrate => {
-                        error!("UpdateChannelError: invalid-nominal-hashrate");
-                        let update_channel_error = UpdateChannelError {
-                            channel_id: m.channel_id,
-                            error_code: "invalid-nominal-hashrate"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                                client_id,
-                                messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
-                                    update_channel_error,
-                                ))],
-                            })),
-                        )));
-                    }
-                    ExtendedChannelError::RequestedMaxTargetOutOfRange => {
-                        error!("UpdateChannelError: requested-max-target-out-of-range");
-                        let update_channel_error = UpdateChannelError {
-                            channel_id: m.channel_id,
-                            error_code: "requested-max-target-out-of-range"
-                                .to_string()
-                                .try_into()
-                                .expect("error code must be valid string"),
-                        };
-                        return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                                client_id,
-                                messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
-                                    update_channel_error,
-                                ))],
-                            })),
-                        )));
-                    }
-                    _ => {
-                        return Err(Sv2ServerEventError::MiningHandlerError(format!(
-                            "Error updating extended channel: {:?}",
-                            e
-                        )));
-                    }
-                },
-            }
-        } else {
-            error!(
-                "UpdateChannelError: channel_id: {}, error_code: invalid-channel-id ❌",
-                m.channel_id
-            );
-            let update_channel_error = UpdateChannelError {
-                channel_id: m.channel_id,
-                error_code: "invalid-channel-id"
-                    .to_string()
-                    .try_into()
-                    .expect("error code must be valid string"),
-            };
-            return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                    .cloned();
+                let verification_error = self
+                    .verify_found_block(Some(template_id), payout_script, &block)
+                    .await;
+                self.record_found_block(
                     client_id,
-                    messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
-                        update_channel_error,
-                    ))],
-                })),
-            )));
-        }
-    }
-
-    async fn handle_close_channel(
-        &self,
-        _client_id: u32,
-        _m: CloseChannel<'static>,
-    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
-        info!("Received CloseChannel message");
-        Ok(Sv2ServerOutcome::Ok)
-    }
+                    m.channel_id,
+                    standard_channel.get_user_identity(),
+                    Some(template_id),
+                    self.template_block(&solution).await,
+                    verification_error,
+                )
+                .await;
+                let mut events = self.submit_solution(solution).await;
+                events.push(Sv2ServerEvent::SendMessagesToClient(Box::new(
+                    Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesSuccess(
+                            SubmitSharesSuccess {
+                                channel_id: m.channel_id,
+                                last_sequence_number: share_accounting
+                                    .get_last_share_sequence_number(),
+                                new_submits_accepted_count: share_accounting.get_shares_accepted(),
+                                new_shares_sum: share_accounting.get_share_work_sum(),
+                            },
+                        ))],
+                    },
+                )));
 
-    async fn handle_submit_shares_standard(
-        &self,
-        client_id: u32,
-        m: SubmitSharesStandard,
-    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
-        info!("Received SubmitSharesStandard message");
-        let clients_guard = self.clients.read().await;
-        let client = match clients_guard.get(&client_id) {
-            Some(client) => client,
-            None => {
-                error!("Client with id {} not found", client_id);
-                return Err(Sv2ServerEventError::IdNotFound);
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::MultipleEvents(Box::new(events)),
+                )));
             }
-        };
-
-        let client_guard = client.read().await;
-        let standard_channels_arc = &client_guard.standard_channels;
-        let std_channels_guard = standard_channels_arc.read().await;
-        let standard_channel_arc = match std_channels_guard.get(&m.channel_id) {
-            Some(channel) => channel,
-            None => {
-                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: invalid-channel-id ❌", m.channel_id, m.sequence_number);
+            Err(ShareValidationError::Invalid) => {
+                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: invalid-share ❌", m.channel_id, m.sequence_number);
                 return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
                     Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
                         client_id,
@@ -1022,118 +2071,7 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
                             SubmitSharesError {
                                 channel_id: m.channel_id,
                                 sequence_number: m.sequence_number,
-                                error_code: "invalid-channel-id"
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            },
-                        ))],
-                    })),
-                )));
-            }
-        };
-
-        let mut standard_channel = standard_channel_arc.write().await;
-        let share_validation_result = standard_channel.validate_share(m.clone());
-
-        match share_validation_result {
-            Ok(ShareValidationResult::Valid) => {
-                info!(
-                    "SubmitSharesStandard: valid share | channel_id: {}, sequence_number: {} ☑️",
-                    m.channel_id, m.sequence_number
-                );
-                {
-                    let mut state = self.shared_state.write().await;
-                    state.total_shares_submitted += 1;
-                }
-                return Ok(Sv2ServerOutcome::Ok);
-            }
-            Ok(ShareValidationResult::ValidWithAcknowledgement(
-                last_sequence_number,
-                new_submits_accepted_count,
-                new_shares_sum,
-            )) => {
-                let success = SubmitSharesSuccess {
-                    channel_id: m.channel_id,
-                    last_sequence_number,
-                    new_submits_accepted_count,
-                    new_shares_sum,
-                };
-                info!("SubmitSharesExtended: {} ✅", success);
-
-                {
-                    let share_accounting = standard_channel.get_share_accounting();
-                    let mut state = self.shared_state.write().await;
-                    let best_share = share_accounting.get_best_diff();
-                    state.best_share = if best_share > state.best_share {
-                        best_share
-                    } else {
-                        state.best_share
-                    };
-                    state.total_shares_submitted += 1;
-                }
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id,
-                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesSuccess(success))],
-                    })),
-                )));
-            }
-            Ok(ShareValidationResult::BlockFound(template_id, coinbase)) => {
-                info!("SubmitSharesStandard: 💰 Block Found!!! 💰");
-                let template_id = template_id
-                    .expect("Pleblottery does not support custom jobs. Something weird happened.");
-
-                info!("SubmitSharesStandard: Propagating solution to the Template Provider.");
-
-                {
-                    let mut state = self.shared_state.write().await;
-                    state.blocks_found += 1;
-                }
-
-                let share_accounting = standard_channel.get_share_accounting();
-
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::MultipleEvents(Box::new(vec![
-                        Sv2ServerEvent::SendEventToSiblingClientService(Box::new(
-                            Sv2ClientEvent::TemplateDistributionTrigger(
-                                TemplateDistributionClientTrigger::SubmitSolution(SubmitSolution {
-                                    template_id,
-                                    version: m.version,
-                                    header_timestamp: m.ntime,
-                                    header_nonce: m.nonce,
-                                    coinbase_tx: coinbase
-                                        .try_into()
-                                        .expect("coinbase tx must be valid"),
-                                }),
-                            ),
-                        )),
-                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                            client_id,
-                            messages: vec![AnyMessage::Mining(Mining::SubmitSharesSuccess(
-                                SubmitSharesSuccess {
-                                    channel_id: m.channel_id,
-                                    last_sequence_number: share_accounting
-                                        .get_last_share_sequence_number(),
-                                    new_submits_accepted_count: share_accounting
-                                        .get_shares_accepted(),
-                                    new_shares_sum: share_accounting.get_share_work_sum(),
-                                },
-                            ))],
-                        })),
-                    ])),
-                )));
-            }
-            Err(ShareValidationError::Invalid) => {
-                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: invalid-share ❌", m.channel_id, m.sequence_number);
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id,
-                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
-                            SubmitSharesError {
-                                channel_id: m.channel_id,
-                                sequence_number: m.sequence_number,
-                                error_code: "invalid-share"
+                                error_code: "invalid-share"
                                     .to_string()
                                     .try_into()
                                     .expect("error code must be valid string"),
@@ -1228,7 +2166,9 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
         }
     }
 
-    async fn handle_submit_shares_extended(
+    /// Validates a share of an extended channel, answering it with `SubmitSharesSuccess` once a
+    /// batch is accepted or with `SubmitSharesError` when invalid.
+    async fn submit_shares_extended(
         &self,
         client_id: u32,
         m: SubmitSharesExtended<'static>,
@@ -1270,6 +2210,16 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
 
         let mut extended_channel = extended_channel_arc.write().await;
         let share_validation_result = extended_channel.validate_share(m.clone());
+        if share_validation_result.is_ok() {
+            let target: U256 = extended_channel.get_target().clone().into();
+            self.record_share_work(
+                client_id,
+                m.channel_id,
+                extended_channel.get_user_identity(),
+                &target.to_vec(),
+            )
+            .await;
+        }
 
         match share_validation_result {
             Ok(ShareValidationResult::Valid) => {
@@ -1316,188 +2266,1208 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
             }
             Ok(ShareValidationResult::BlockFound(template_id, coinbase)) => {
                 info!("SubmitSharesExtended: 💰 Block Found!!! 💰");
-                let template_id = template_id
-                    .expect("Pleblottery does not support custom jobs. Something weird happened.");
 
-                info!("SubmitSharesExtended: Propagating solution to the Template Provider.");
+                {
+                    let mut state = self.shared_state.write().await;
+                    state.blocks_found += 1;
+                }
+                self.schedule_payout_rotation(PayoutRotation::BlockFound)
+                    .await;
+
+                let share_accounting = extended_channel.get_share_accounting();
+
+                let block = self.rebuild_extended_block(&extended_channel, &m).await;
+                let payout_script = client_guard
+                    .payout_scripts
+                    .read()
+                    .await
+                    .get(&m.channel_id)
+                    .cloned();
+                let verification_error = self
+                    .verify_found_block(template_id, payout_script, &block)
+                    .await;
+
+                let mut events = match template_id {
+                    Some(template_id) => {
+                        let solution = SubmitSolution {
+                            template_id,
+                            version: m.version,
+                            header_timestamp: m.ntime,
+                            header_nonce: m.nonce,
+                            coinbase_tx: coinbase.try_into().expect("coinbase tx must be valid"),
+                        };
+                        self.record_found_block(
+                            client_id,
+                            m.channel_id,
+                            extended_channel.get_user_identity(),
+                            Some(template_id),
+                            self.template_block(&solution).await,
+                            verification_error,
+                        )
+                        .await;
+                        self.submit_solution(solution).await
+                    }
+                    None => {
+                        // no template is known for custom jobs, so the block can't be rebuilt
+                        // and submitted here. Only the Job Declarator Client that set the job has
+                        // its transactions, and propagating it is left to the JDC's own node
+                        info!(
+                            "Block found on custom job {} of channel {}, propagated by its Job Declarator Client",
+                            m.job_id, m.channel_id
+                        );
+                        self.record_found_block(
+                            client_id,
+                            m.channel_id,
+                            extended_channel.get_user_identity(),
+                            None,
+                            block.map(|block| (block.header, block.coinbase)),
+                            verification_error,
+                        )
+                        .await;
+                        vec![]
+                    }
+                };
+                events.push(Sv2ServerEvent::SendMessagesToClient(Box::new(
+                    Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesSuccess(
+                            SubmitSharesSuccess {
+                                channel_id: m.channel_id,
+                                last_sequence_number: share_accounting
+                                    .get_last_share_sequence_number(),
+                                new_submits_accepted_count: share_accounting.get_shares_accepted(),
+                                new_shares_sum: share_accounting.get_share_work_sum(),
+                            },
+                        ))],
+                    },
+                )));
+
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::MultipleEvents(Box::new(events)),
+                )));
+            }
+            Err(ShareValidationError::Invalid) => {
+                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: invalid-share ❌", m.channel_id, m.sequence_number);
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
+                            SubmitSharesError {
+                                channel_id: m.channel_id,
+                                sequence_number: m.sequence_number,
+                                error_code: "invalid-share"
+                                    .to_string()
+                                    .try_into()
+                                    .expect("error code must be valid string"),
+                            },
+                        ))],
+                    })),
+                )));
+            }
+            Err(ShareValidationError::Stale) => {
+                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: stale-share ❌", m.channel_id, m.sequence_number);
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
+                            SubmitSharesError {
+                                channel_id: m.channel_id,
+                                sequence_number: m.sequence_number,
+                                error_code: "stale-share"
+                                    .to_string()
+                                    .try_into()
+                                    .expect("error code must be valid string"),
+                            },
+                        ))],
+                    })),
+                )));
+            }
+            Err(ShareValidationError::InvalidJobId) => {
+                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: invalid-job-id ❌", m.channel_id, m.sequence_number);
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
+                            SubmitSharesError {
+                                channel_id: m.channel_id,
+                                sequence_number: m.sequence_number,
+                                error_code: "invalid-job-id"
+                                    .to_string()
+                                    .try_into()
+                                    .expect("error code must be valid string"),
+                            },
+                        ))],
+                    })),
+                )));
+            }
+            Err(ShareValidationError::DoesNotMeetTarget) => {
+                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: difficulty-too-low ❌", m.channel_id, m.sequence_number);
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
+                            SubmitSharesError {
+                                channel_id: m.channel_id,
+                                sequence_number: m.sequence_number,
+                                error_code: "difficulty-too-low"
+                                    .to_string()
+                                    .try_into()
+                                    .expect("error code must be valid string"),
+                            },
+                        ))],
+                    })),
+                )));
+            }
+            Err(ShareValidationError::DuplicateShare) => {
+                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: duplicate-share ❌", m.channel_id, m.sequence_number);
+
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
+                            SubmitSharesError {
+                                channel_id: m.channel_id,
+                                sequence_number: m.sequence_number,
+                                error_code: "duplicate-share"
+                                    .to_string()
+                                    .try_into()
+                                    .expect("error code must be valid string"),
+                            },
+                        ))],
+                    })),
+                )));
+            }
+            _ => {
+                error!(
+                    "Unhandled share validation result for client: {}",
+                    client_id
+                );
+                return Err(Sv2ServerEventError::MiningHandlerError(format!(
+                    "Unhandled share validation result for client: {}",
+                    client_id
+                )));
+            }
+        }
+    }
+
+    /// Opens a standard channel, answering with `OpenStandardMiningChannelSuccess` and its first
+    /// job, or with `OpenMiningChannelError`.
+    async fn open_standard_mining_channel(
+        &self,
+        client_id: u32,
+        m: OpenStandardMiningChannel<'static>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        info!("Received OpenStandardMiningChannel message");
+        if self.is_client_banned(client_id).await {
+            error!("OpenMiningChannelError: banned");
+            return Ok(Self::open_mining_channel_error(
+                client_id,
+                m.get_request_id_as_u32(),
+                "banned", //note: non-standard error code
+            ));
+        }
+        if let Err(error_code) = self.check_channel_limits(client_id).await {
+            return Ok(Self::open_mining_channel_error(
+                client_id,
+                m.get_request_id_as_u32(),
+                error_code,
+            ));
+        }
+        let mut messages = Vec::new();
+
+        let client = self.get_client(client_id).await?;
+
+        let user_identity = std::str::from_utf8(m.user_identity.as_ref())
+            .map(|s| s.to_string())
+            .map_err(|e| {
+                error!("Invalid UTF-8 in user_identity: {:?}", e);
+                Sv2ServerEventError::MiningHandlerError(format!(
+                    "Invalid UTF-8 in user_identity: {:?}",
+                    e
+                ))
+            })?;
+        let user_identity = match self.authorize_user_identity(client_id, user_identity).await {
+            Ok(user_identity) => user_identity,
+            Err(error_code) => {
+                return Ok(Self::open_mining_channel_error(
+                    client_id,
+                    m.get_request_id_as_u32(),
+                    error_code,
+                ))
+            }
+        };
+        // bans of workers hold across connections
+        if self.is_worker_banned(&user_identity).await {
+            error!("OpenMiningChannelError: banned");
+            return Ok(Self::open_mining_channel_error(
+                client_id,
+                m.get_request_id_as_u32(),
+                "banned", //note: non-standard error code
+            ));
+        }
+        let worker = self.authenticated_worker(&user_identity);
+        let payout_script = match self.resolve_payout_script(&user_identity) {
+            Ok(payout_script) => payout_script,
+            Err(()) => {
+                error!("OpenMiningChannelError: invalid-user-identity");
+                let error_message = OpenMiningChannelError {
+                    request_id: m.get_request_id_as_u32(),
+                    error_code: "invalid-user-identity" //note: non-standard error code
+                        .to_string()
+                        .try_into()
+                        .expect("error code must be valid string"),
+                };
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                            error_message,
+                        ))],
+                    })),
+                )));
+            }
+        };
+
+        // check the channel can be opened before taking an extranonce prefix, which every
+        // rejection would otherwise hold on to
+        let last_activated_future_template = match self.get_last_activated_template().await {
+            Some(template) => template,
+            None => {
+                error!("Unable to open standard mining channel with client {}: No last activated future template available", client_id);
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                            OpenMiningChannelError {
+                                request_id: m.get_request_id_as_u32(),
+                                error_code: "not-ready-to-open-channel" //note: non-standard error code
+                                    .to_string()
+                                    .try_into()
+                                    .expect("error code must be valid string"),
+                            },
+                        ))],
+                    })),
+                )));
+            }
+        };
+        let last_prev_hash = match self.get_last_prev_hash().await {
+            Some(prev_hash) => prev_hash,
+            None => {
+                error!("Unable to open standard mining channel with client {}: No last activated prev hash available", client_id);
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                            OpenMiningChannelError {
+                                request_id: m.get_request_id_as_u32(),
+                                error_code: "not-ready-to-open-channel" //note: non-standard error code
+                                    .to_string()
+                                    .try_into()
+                                    .expect("error code must be valid string"),
+                            },
+                        ))],
+                    })),
+                )));
+            }
+        };
+
+        // Get extranonce prefix, released on every early return until the channel is registered
+        let mut prefix_lease =
+            match ExtranoncePrefixLease::take(&self.extranonce_prefix_factory_standard).await {
+                Ok(lease) => lease,
+                Err(e) => {
+                    error!(
+                        "Failed to get extranonce prefix for client {}: {:?}",
+                        client_id, e
+                    );
+                    return Err(Sv2ServerEventError::MiningHandlerError(format!(
+                        "Failed to get extranonce prefix: {:?}",
+                        e
+                    )));
+                }
+            };
+        self.tag_extranonce_prefix(prefix_lease.prefix_mut(), &user_identity);
+
+        let channel_id = {
+            let client_guard = client.read().await;
+            client_guard
+                .channel_id_factory
+                .fetch_add(1, Ordering::SeqCst)
+        };
+
+        // Clone max_target so m is not partially moved
+        let max_target = m.max_target.clone();
+
+        // Create standard channel
+        let job_store = Box::new(DefaultJobStore::new());
+
+        let mut standard_channel = match StandardChannel::new(
+            channel_id,
+            user_identity,
+            prefix_lease.prefix().to_vec(),
+            max_target.into(),
+            m.nominal_hash_rate,
+            self.share_batch_size,
+            self.expected_shares_per_minute,
+            job_store,
+        ) {
+            Ok(channel) => channel,
+            Err(e) => match e {
+                StandardChannelError::InvalidNominalHashrate => {
+                    error!("OpenMiningChannelError: invalid-nominal-hashrate");
+                    let error_message = OpenMiningChannelError {
+                        request_id: m.get_request_id_as_u32(),
+                        error_code: "invalid-nominal-hashrate".to_string().try_into().unwrap(),
+                    };
+                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                            client_id,
+                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                                error_message,
+                            ))],
+                        })),
+                    )));
+                }
+                StandardChannelError::RequestedMaxTargetOutOfRange => {
+                    error!("OpenMiningChannelError: requested-max-target-out-of-range");
+                    let error_message = OpenMiningChannelError {
+                        request_id: m.get_request_id_as_u32(),
+                        error_code: "max-target-out-of-range".to_string().try_into().unwrap(),
+                    };
+                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                            client_id,
+                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                                error_message,
+                            ))],
+                        })),
+                    )));
+                }
+                _ => {
+                    error!("error in handle_open_standard_mining_channel: {:?}", e);
+                    return Err(Sv2ServerEventError::MiningHandlerError(format!(
+                        "Error creating standard channel: {:?}",
+                        e
+                    )));
+                }
+            },
+        };
+
+        // Extract needed fields before mutably borrowing standard_channel
+        let target = standard_channel.get_target().clone().into();
+        let extranonce_prefix = standard_channel
+            .get_extranonce_prefix()
+            .clone()
+            .try_into()
+            .map_err(|e| {
+                error!("Failed to convert extranonce prefix: {:?}", e);
+                Sv2ServerEventError::MiningHandlerError(format!(
+                    "Failed to convert extranonce prefix: {:?}",
+                    e
+                ))
+            })?;
+
+        let coinbase_output = self.get_coinbase_outputs(payout_script.as_ref()).await?;
+
+        // Call on_new_template before moving standard_channel
+        standard_channel
+            .on_new_template(last_activated_future_template.clone(), coinbase_output)
+            .map_err(|e| {
+                error!("Error processing new template on standard channel: {:?}", e);
+                Sv2ServerEventError::MiningHandlerError(format!(
+                    "Error processing new template on standard channel: {:?}",
+                    e
+                ))
+            })?;
+
+        let (future_standard_job_id, future_job_message) =
+            self.get_future_job_message(&standard_channel).await?;
+
+        standard_channel
+            .on_set_new_prev_hash(last_prev_hash.clone())
+            .map_err(|e| {
+                error!(
+                    "Error processing SetNewPrevHash on standard channel: {:?}",
+                    e
+                );
+                Sv2ServerEventError::MiningHandlerError(format!(
+                    "Error processing SetNewPrevHash on standard channel: {:?}",
+                    e
+                ))
+            })?;
+
+        // Prepare SetNewPrevHashMp message
+        let set_new_prev_hash_mp = SetNewPrevHashMp {
+            channel_id,
+            job_id: future_standard_job_id,
+            prev_hash: last_prev_hash.prev_hash.clone(),
+            min_ntime: last_prev_hash.header_timestamp,
+            nbits: last_prev_hash.n_bits,
+        };
+
+        let group_channel_id = self
+            .register_standard_channel(client_id, channel_id, standard_channel)
+            .await?;
+        // closing the channel releases its prefix from now on
+        prefix_lease.keep();
+        self.register_payout_script(client_id, channel_id, payout_script)
+            .await?;
+        self.register_worker(client_id, channel_id, worker).await;
+
+        let open_standard_mining_channel_response = OpenStandardMiningChannelSuccess {
+            request_id: m.request_id,
+            channel_id,
+            target,
+            extranonce_prefix,
+            group_channel_id,
+        };
+
+        self.update_total_hashrate().await;
+
+        messages.push(AnyMessage::Mining(
+            Mining::OpenStandardMiningChannelSuccess(open_standard_mining_channel_response),
+        ));
+        messages.push(AnyMessage::Mining(Mining::NewMiningJob(future_job_message)));
+        messages.push(AnyMessage::Mining(Mining::SetNewPrevHash(
+            set_new_prev_hash_mp,
+        )));
+
+        info!(
+            "Opened standard mining channel with id: {} for client: {}",
+            channel_id, client_id
+        );
+
+        Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                client_id,
+                messages,
+            })),
+        )))
+    }
+
+    /// Opens an extended channel, answering with `OpenExtendedMiningChannelSuccess` and its
+    /// first job, or with `OpenMiningChannelError`.
+    async fn open_extended_mining_channel(
+        &self,
+        client_id: u32,
+        m: OpenExtendedMiningChannel<'static>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        info!("Received OpenExtendedMiningChannel message");
+        if self.is_client_banned(client_id).await {
+            error!("OpenMiningChannelError: banned");
+            return Ok(Self::open_mining_channel_error(
+                client_id,
+                m.get_request_id_as_u32(),
+                "banned", //note: non-standard error code
+            ));
+        }
+        if let Err(error_code) = self.check_channel_limits(client_id).await {
+            return Ok(Self::open_mining_channel_error(
+                client_id,
+                m.get_request_id_as_u32(),
+                error_code,
+            ));
+        }
+
+        let mut messages = Vec::new();
+
+        let client = self.get_client(client_id).await?;
+
+        let user_identity = std::str::from_utf8(m.user_identity.as_ref())
+            .map(|s| s.to_string())
+            .map_err(|e| {
+                error!("Invalid UTF-8 in user_identity: {:?}", e);
+                Sv2ServerEventError::MiningHandlerError(format!(
+                    "Invalid UTF-8 in user_identity: {:?}",
+                    e
+                ))
+            })?;
+        let user_identity = match self.authorize_user_identity(client_id, user_identity).await {
+            Ok(user_identity) => user_identity,
+            Err(error_code) => {
+                return Ok(Self::open_mining_channel_error(
+                    client_id,
+                    m.get_request_id_as_u32(),
+                    error_code,
+                ))
+            }
+        };
+        // bans of workers hold across connections
+        if self.is_worker_banned(&user_identity).await {
+            error!("OpenMiningChannelError: banned");
+            return Ok(Self::open_mining_channel_error(
+                client_id,
+                m.get_request_id_as_u32(),
+                "banned", //note: non-standard error code
+            ));
+        }
+        let worker = self.authenticated_worker(&user_identity);
+
+        let payout_script = match self.resolve_payout_script(&user_identity) {
+            Ok(payout_script) => payout_script,
+            Err(()) => {
+                error!("OpenMiningChannelError: invalid-user-identity");
+                let error_message = OpenMiningChannelError {
+                    request_id: m.get_request_id_as_u32(),
+                    error_code: "invalid-user-identity" //note: non-standard error code
+                        .to_string()
+                        .try_into()
+                        .expect("error code must be valid string"),
+                };
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                            error_message,
+                        ))],
+                    })),
+                )));
+            }
+        };
+
+        // check the channel can be opened before taking an extranonce prefix, which every
+        // rejection would otherwise hold on to
+        let last_activated_future_template = match self.get_last_activated_template().await {
+            Some(template) => template,
+            None => {
+                error!("Unable to open standard mining channel with client {}: No last activated future template available", client_id);
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                            OpenMiningChannelError {
+                                request_id: m.get_request_id_as_u32(),
+                                error_code: "not-ready-to-open-channel" //note: non-standard error code
+                                    .to_string()
+                                    .try_into()
+                                    .expect("error code must be valid string"),
+                            },
+                        ))],
+                    })),
+                )));
+            }
+        };
+        let last_prev_hash = match self.get_last_prev_hash().await {
+            Some(prev_hash) => prev_hash,
+            None => {
+                error!("Unable to open standard mining channel with client {}: No last activated prev hash available", client_id);
+                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                        client_id,
+                        messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                            OpenMiningChannelError {
+                                request_id: m.get_request_id_as_u32(),
+                                error_code: "not-ready-to-open-channel" //note: non-standard error code
+                                    .to_string()
+                                    .try_into()
+                                    .expect("error code must be valid string"),
+                            },
+                        ))],
+                    })),
+                )));
+            }
+        };
+
+        let channel_id = {
+            let client_guard = client.read().await;
+            client_guard
+                .channel_id_factory
+                .fetch_add(1, Ordering::SeqCst)
+        };
+
+        // released on every early return until the channel is registered
+        let mut prefix_lease =
+            ExtranoncePrefixLease::take(&self.extranonce_prefix_factory_extended)
+                .await
+                .map_err(|e| {
+                    error!("Could not get extranonce prefix: {:?}", e);
+                    Sv2ServerEventError::MiningHandlerError(format!(
+                        "Could not get extranonce prefix: {:?}",
+                        e
+                    ))
+                })?;
+        self.tag_extranonce_prefix(prefix_lease.prefix_mut(), &user_identity);
+
+        let job_store = Box::new(DefaultJobStore::new());
+        let mut extended_channel = match ExtendedChannel::new(
+            channel_id,
+            user_identity,
+            prefix_lease.prefix().to_vec(),
+            m.max_target.to_owned().into(),
+            m.nominal_hash_rate,
+            true,
+            m.min_extranonce_size,
+            self.share_batch_size,
+            self.expected_shares_per_minute,
+            job_store,
+        ) {
+            Ok(channel) => channel,
+            Err(e) => match e {
+                ExtendedChannelError::InvalidNominalHashrate => {
+                    error!("OpenMiningChannelError: invalid-nominal-hashrate");
+                    let error_message = OpenMiningChannelError {
+                        request_id: m.get_request_id_as_u32(),
+                        error_code: "invalid-nominal-hashrate".to_string().try_into().unwrap(),
+                    };
+                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                            client_id,
+                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                                error_message,
+                            ))],
+                        })),
+                    )));
+                }
+                ExtendedChannelError::RequestedMaxTargetOutOfRange => {
+                    error!("OpenMiningChannelError: max-target-out-of-range");
+                    let error_message = OpenMiningChannelError {
+                        request_id: m.get_request_id_as_u32(),
+                        error_code: "max-target-out-of-range".to_string().try_into().unwrap(),
+                    };
+                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                            client_id,
+                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                                error_message,
+                            ))],
+                        })),
+                    )));
+                }
+                ExtendedChannelError::RequestedMinExtranonceSizeTooLarge => {
+                    error!("OpenMiningChannelError: min-extranonce-size-too-large");
+                    let error_message = OpenMiningChannelError {
+                        request_id: m.get_request_id_as_u32(),
+                        error_code: "min-extranonce-size-too-large"
+                            .to_string()
+                            .try_into()
+                            .unwrap(),
+                    };
+                    return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                            client_id,
+                            messages: vec![AnyMessage::Mining(Mining::OpenMiningChannelError(
+                                error_message,
+                            ))],
+                        })),
+                    )));
+                }
+                _ => {
+                    error!("Error in handle_open_extended_mining_channel: {:?}", e);
+                    return Err(Sv2ServerEventError::MiningHandlerError(format!(
+                        "Error creating extended channel: {:?}",
+                        e
+                    )));
+                }
+            },
+        };
+
+        let coinbase_outputs = self.get_coinbase_outputs(payout_script.as_ref()).await?;
+
+        extended_channel
+            .on_new_template(last_activated_future_template.clone(), coinbase_outputs)
+            .map_err(|e| {
+                error!("Error processing new template on extended channel: {:?}", e);
+                Sv2ServerEventError::MiningHandlerError(format!(
+                    "Error processing new template on extended channel: {:?}",
+                    e
+                ))
+            })?;
+
+        let (future_job_id, future_job_message) = self
+            .get_future_job_message_extended(&extended_channel)
+            .await?;
+
+        // Now mutably borrow extended_channel
+        extended_channel
+            .on_set_new_prev_hash(last_prev_hash.clone())
+            .expect("Error processing SetNewPrevHash on extended channel");
+
+        let oxmcs = OpenExtendedMiningChannelSuccess {
+            request_id: m.request_id.clone(),
+            channel_id,
+            target: extended_channel.get_target().clone().into(),
+            extranonce_size: extended_channel.get_rollable_extranonce_size(),
+            extranonce_prefix: extended_channel
+                .get_extranonce_prefix()
+                .clone()
+                .try_into()
+                .expect("could not parse extranonce prefix"),
+        };
+
+        messages.push(AnyMessage::Mining(
+            Mining::OpenExtendedMiningChannelSuccess(oxmcs),
+        ));
+        messages.push(AnyMessage::Mining(Mining::NewExtendedMiningJob(
+            future_job_message,
+        )));
+
+        //get set new prev hash message
+        let snphmp = SetNewPrevHashMp {
+            channel_id,
+            job_id: future_job_id,
+            prev_hash: last_prev_hash.prev_hash,
+            min_ntime: last_prev_hash.header_timestamp,
+            nbits: last_prev_hash.n_bits,
+        };
+        messages.push(AnyMessage::Mining(Mining::SetNewPrevHash(snphmp)));
+
+        // Register the new extended channel
+        self.register_extended_channel(client_id, channel_id, extended_channel)
+            .await?;
+        // closing the channel releases its prefix from now on
+        prefix_lease.keep();
+        self.register_payout_script(client_id, channel_id, payout_script)
+            .await?;
+        self.register_worker(client_id, channel_id, worker).await;
+
+        self.update_total_hashrate().await;
+
+        Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                client_id,
+                messages,
+            })),
+        )))
+    }
+
+    /// Updates the nominal hashrate and maximum target of a channel, answering with `SetTarget`
+    /// or with `UpdateChannelError`.
+    async fn update_channel(
+        &self,
+        client_id: u32,
+        m: UpdateChannel<'static>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        info!("Received UpdateChannel message");
+        let client = self.get_client(client_id).await?;
+        let is_standard_channel = client
+            .read()
+            .await
+            .standard_channels
+            .read()
+            .await
+            .contains_key(&m.channel_id);
+
+        let is_extended_channel = client
+            .read()
+            .await
+            .extended_channels
+            .read()
+            .await
+            .contains_key(&m.channel_id);
+
+        if is_standard_channel {
+            // Scope the client_read_guard so it is dropped before the hashrate is updated
+            let standard_channel = {
+                let client_read_guard = client.read().await;
+                let std_channels_read_guard = client_read_guard.standard_channels.read().await;
+                std_channels_read_guard
+                    .get(&m.channel_id)
+                    .expect("Standard channel must exist")
+                    .clone()
+            };
+
+            let update_result = standard_channel.write().await.update_channel(
+                m.nominal_hash_rate,
+                Some(m.maximum_target.into_static().into()),
+            );
+
+            match update_result {
+                Ok(()) => {
+                    info!("Updated standard channel | channel_id: {}", m.channel_id);
+                    let target: U256 = standard_channel.read().await.get_target().clone().into();
+                    self.limit_share_rate(
+                        client_id,
+                        m.channel_id,
+                        m.nominal_hash_rate,
+                        &target.to_vec(),
+                    )
+                    .await;
+                    self.update_total_hashrate().await;
+                    return Ok(Sv2ServerOutcome::Ok);
+                }
+                Err(e) => match e {
+                    StandardChannelError::InvalidNominalHashrate => {
+                        error!("UpdateChannelError: invalid-nominal-hashrate");
+                        let update_channel_error = UpdateChannelError {
+                            channel_id: m.channel_id,
+                            error_code: "invalid-nominal-hashrate"
+                                .to_string()
+                                .try_into()
+                                .expect("error code must be valid string"),
+                        };
+                        return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                                client_id,
+                                messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
+                                    update_channel_error,
+                                ))],
+                            })),
+                        )));
+                    }
+                    StandardChannelError::RequestedMaxTargetOutOfRange => {
+                        error!("UpdateChannelError: requested-max-target-out-of-range");
+                        let update_channel_error = UpdateChannelError {
+                            channel_id: m.channel_id,
+                            error_code: "requested-max-target-out-of-range"
+                                .to_string()
+                                .try_into()
+                                .expect("error code must be valid string"),
+                        };
+                        return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                                client_id,
+                                messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
+                                    update_channel_error,
+                                ))],
+                            })),
+                        )));
+                    }
+                    _ => {
+                        return Err(Sv2ServerEventError::MiningHandlerError(format!(
+                            "Error updating standard channel: {:?}",
+                            e
+                        )));
+                    }
+                },
+            };
+        } else if is_extended_channel {
+            // Scope the client_read_guard so it is dropped before the await
+            let extended_channel = {
+                let client_read_guard = client.read().await;
+                let ext_channels_read_guard = client_read_guard.extended_channels.read().await;
+                ext_channels_read_guard
+                    .get(&m.channel_id)
+                    .expect("Extended channel must exist")
+                    .clone()
+            };
+
+            let update_result = {
+                let mut channel = extended_channel.write().await;
+                channel.update_channel(
+                    m.nominal_hash_rate,
+                    Some(m.maximum_target.into_static().into()),
+                )
+            };
+
+            match update_result {
+                Ok(()) => {
+                    info!("Updated extended channel | channel_id: {}", m.channel_id);
+                    let target: U256 = extended_channel.read().await.get_target().clone().into();
+                    self.limit_share_rate(
+                        client_id,
+                        m.channel_id,
+                        m.nominal_hash_rate,
+                        &target.to_vec(),
+                    )
+                    .await;
+                    self.update_total_hashrate().await;
+                    return Ok(Sv2ServerOutcome::Ok);
+                }
+                Err(e) => match e {
+                    ExtendedChannelError::InvalidNominalHashrate => {
+                        error!("UpdateChannelError: invalid-nominal-hashrate");
+                        let update_channel_error = UpdateChannelError {
+                            channel_id: m.channel_id,
+                            error_code: "invalid-nominal-hashrate"
+                                .to_string()
+                                .try_into()
+                                .expect("error code must be valid string"),
+                        };
+                        return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                                client_id,
+                                messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
+                                    update_channel_error,
+                                ))],
+                            })),
+                        )));
+                    }
+                    ExtendedChannelError::RequestedMaxTargetOutOfRange => {
+                        error!("UpdateChannelError: requested-max-target-out-of-range");
+                        let update_channel_error = UpdateChannelError {
+                            channel_id: m.channel_id,
+                            error_code: "requested-max-target-out-of-range"
+                                .to_string()
+                                .try_into()
+                                .expect("error code must be valid string"),
+                        };
+                        return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                            Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                                client_id,
+                                messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
+                                    update_channel_error,
+                                ))],
+                            })),
+                        )));
+                    }
+                    _ => {
+                        return Err(Sv2ServerEventError::MiningHandlerError(format!(
+                            "Error updating extended channel: {:?}",
+                            e
+                        )));
+                    }
+                },
+            }
+        } else {
+            error!(
+                "UpdateChannelError: channel_id: {}, error_code: invalid-channel-id ❌",
+                m.channel_id
+            );
+            let update_channel_error = UpdateChannelError {
+                channel_id: m.channel_id,
+                error_code: "invalid-channel-id"
+                    .to_string()
+                    .try_into()
+                    .expect("error code must be valid string"),
+            };
+            return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
+                    client_id,
+                    messages: vec![AnyMessage::Mining(Mining::UpdateChannelError(
+                        update_channel_error,
+                    ))],
+                })),
+            )));
+        }
+    }
+}
+
+impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
+    fn setup_connection_success_flags(&self) -> u32 {
+        // no requirement for fixed version field
+        // no requirement for extended channel only
+        0
+    }
 
-                {
-                    let mut state = self.shared_state.write().await;
-                    state.blocks_found += 1;
-                }
+    async fn add_client(&mut self, client_id: u32, flags: u32) {
+        self.insert_client(client_id, flags).await;
+        // the gate waits for the id of the connection it's setting up
+        if let Some(sender) = self.pending_sv2_client.lock().await.take() {
+            let _ = sender.send(client_id);
+        }
+    }
 
-                let share_accounting = extended_channel.get_share_accounting();
+    async fn remove_client(&mut self, client_id: u32) {
+        info!("Removing client with id: {}", client_id);
+        if let Some(ban_list) = &self.ban_list {
+            ban_list.write().await.remove_client(client_id);
+        }
+        if let Some(share_rate_limiter) = &self.share_rate_limiter {
+            share_rate_limiter.write().await.remove_client(client_id);
+        }
+        self.client_addresses.write().await.remove(&client_id);
+        self.sv2_connections.write().await.remove(&client_id);
 
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::MultipleEvents(Box::new(vec![
-                        Sv2ServerEvent::SendEventToSiblingClientService(Box::new(
-                            Sv2ClientEvent::TemplateDistributionTrigger(
-                                TemplateDistributionClientTrigger::SubmitSolution(SubmitSolution {
-                                    template_id,
-                                    version: m.version,
-                                    header_timestamp: m.ntime,
-                                    header_nonce: m.nonce,
-                                    coinbase_tx: coinbase
-                                        .try_into()
-                                        .expect("coinbase tx must be valid"),
-                                }),
-                            ),
-                        )),
-                        Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                            client_id,
-                            messages: vec![AnyMessage::Mining(Mining::SubmitSharesSuccess(
-                                SubmitSharesSuccess {
-                                    channel_id: m.channel_id,
-                                    last_sequence_number: share_accounting
-                                        .get_last_share_sequence_number(),
-                                    new_submits_accepted_count: share_accounting
-                                        .get_shares_accepted(),
-                                    new_shares_sum: share_accounting.get_share_work_sum(),
-                                },
-                            ))],
-                        })),
-                    ])),
-                )));
-            }
-            Err(ShareValidationError::Invalid) => {
-                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: invalid-share ❌", m.channel_id, m.sequence_number);
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id,
-                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
-                            SubmitSharesError {
-                                channel_id: m.channel_id,
-                                sequence_number: m.sequence_number,
-                                error_code: "invalid-share"
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            },
-                        ))],
-                    })),
-                )));
-            }
-            Err(ShareValidationError::Stale) => {
-                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: stale-share ❌", m.channel_id, m.sequence_number);
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id,
-                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
-                            SubmitSharesError {
-                                channel_id: m.channel_id,
-                                sequence_number: m.sequence_number,
-                                error_code: "stale-share"
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            },
-                        ))],
-                    })),
-                )));
-            }
-            Err(ShareValidationError::InvalidJobId) => {
-                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: invalid-job-id ❌", m.channel_id, m.sequence_number);
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id,
-                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
-                            SubmitSharesError {
-                                channel_id: m.channel_id,
-                                sequence_number: m.sequence_number,
-                                error_code: "invalid-job-id"
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            },
-                        ))],
-                    })),
-                )));
-            }
-            Err(ShareValidationError::DoesNotMeetTarget) => {
-                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: difficulty-too-low ❌", m.channel_id, m.sequence_number);
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id,
-                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
-                            SubmitSharesError {
-                                channel_id: m.channel_id,
-                                sequence_number: m.sequence_number,
-                                error_code: "difficulty-too-low"
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            },
-                        ))],
-                    })),
-                )));
+        let client = match self.clients.write().await.remove(&client_id) {
+            Some(client) => client,
+            None => {
+                info!(
+                    "Client {} not found in clients list — assuming already dropped.",
+                    client_id
+                );
+                return;
             }
-            Err(ShareValidationError::DuplicateShare) => {
-                error!("SubmitSharesError: channel_id: {}, sequence_number: {}, error_code: duplicate-share ❌", m.channel_id, m.sequence_number);
+        };
 
-                return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-                    Sv2ServerEvent::SendMessagesToClient(Box::new(Sv2MessagesToClient {
-                        client_id,
-                        messages: vec![AnyMessage::Mining(Mining::SubmitSharesError(
-                            SubmitSharesError {
-                                channel_id: m.channel_id,
-                                sequence_number: m.sequence_number,
-                                error_code: "duplicate-share"
-                                    .to_string()
-                                    .try_into()
-                                    .expect("error code must be valid string"),
-                            },
-                        ))],
-                    })),
-                )));
+        {
+            let client_guard = client.read().await;
+            for (_, channel) in client_guard.standard_channels.read().await.iter() {
+                self.extranonce_prefix_factory_standard
+                    .write()
+                    .await
+                    .release_prefix(channel.read().await.get_extranonce_prefix().clone());
             }
-            _ => {
-                error!(
-                    "Unhandled share validation result for client: {}",
-                    client_id
-                );
-                return Err(Sv2ServerEventError::MiningHandlerError(format!(
-                    "Unhandled share validation result for client: {}",
-                    client_id
-                )));
+            for (_, channel) in client_guard.extended_channels.read().await.iter() {
+                self.extranonce_prefix_factory_extended
+                    .write()
+                    .await
+                    .release_prefix(channel.read().await.get_extranonce_prefix().clone());
             }
         }
+
+        {
+            let total_clients = self.clients.read().await.len() as u32;
+            let mut state = self.shared_state.write().await;
+            state.total_clients = total_clients;
+            state
+                .measured_hashrate
+                .remove_client(client_id, Instant::now());
+        }
+        self.update_total_hashrate().await;
+    }
+
+    async fn start(&mut self) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        Ok(Sv2ServerOutcome::Ok)
+    }
+
+    async fn handle_open_standard_mining_channel(
+        &self,
+        client_id: u32,
+        m: OpenStandardMiningChannel<'static>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        let result = self.open_standard_mining_channel(client_id, m).await;
+        self.score_result(client_id, None, result).await
+    }
+
+    async fn handle_open_extended_mining_channel(
+        &self,
+        client_id: u32,
+        m: OpenExtendedMiningChannel<'static>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        let result = self.open_extended_mining_channel(client_id, m).await;
+        self.score_result(client_id, None, result).await
+    }
+
+    async fn handle_update_channel(
+        &self,
+        client_id: u32,
+        m: UpdateChannel<'static>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        let channel_id = m.channel_id;
+        let result = self.update_channel(client_id, m).await;
+        self.score_result(client_id, Some(channel_id), result).await
+    }
+
+    async fn handle_close_channel(
+        &self,
+        client_id: u32,
+        m: CloseChannel<'static>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        info!(
+            "Received CloseChannel message | channel_id: {}, reason_code: {}",
+            m.channel_id,
+            String::from_utf8_lossy(m.reason_code.as_ref())
+        );
+        let client = self.get_client(client_id).await?;
+
+        if self.close_channel(&client, client_id, m.channel_id).await || self.ban_list.is_none() {
+            return Ok(Sv2ServerOutcome::Ok);
+        }
+        self.score_offense(
+            client_id,
+            None,
+            Offense::UnknownChannel,
+            Ok(Sv2ServerOutcome::Ok),
+        )
+        .await
+    }
+
+    async fn handle_submit_shares_standard(
+        &self,
+        client_id: u32,
+        m: SubmitSharesStandard,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        // shares of banned workers aren't worth the locks they take
+        if self.is_channel_banned(client_id, m.channel_id).await {
+            return Ok(Sv2ServerOutcome::Ok);
+        }
+        if let Some(outcome) = self
+            .rate_limit_share(client_id, m.channel_id, m.sequence_number)
+            .await
+        {
+            return Ok(outcome);
+        }
+        let channel_id = m.channel_id;
+        let result = self.submit_shares_standard(client_id, m).await;
+        self.score_result(client_id, Some(channel_id), result).await
+    }
+
+    async fn handle_submit_shares_extended(
+        &self,
+        client_id: u32,
+        m: SubmitSharesExtended<'static>,
+    ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        if self.is_channel_banned(client_id, m.channel_id).await {
+            return Ok(Sv2ServerOutcome::Ok);
+        }
+        if let Some(outcome) = self
+            .rate_limit_share(client_id, m.channel_id, m.sequence_number)
+            .await
+        {
+            return Ok(outcome);
+        }
+        let channel_id = m.channel_id;
+        let result = self.submit_shares_extended(client_id, m).await;
+        self.score_result(client_id, Some(channel_id), result).await
     }
 
+    /// Work selection isn't advertised and no Job Declaration Server is served, so no mining job
+    /// token is ever allocated and every custom job is rejected. Either way the client broke the
+    /// protocol, so both rejections are scored.
     async fn handle_set_custom_mining_job(
         &self,
-        _client_id: u32,
-        _m: SetCustomMiningJob<'static>,
+        client_id: u32,
+        m: SetCustomMiningJob<'static>,
     ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
-        panic!("Pleblottery does not support custom mining jobs.");
+        info!("Received SetCustomMiningJob message");
+        let client = self.get_client(client_id).await?;
+        let is_extended_channel = client
+            .read()
+            .await
+            .extended_channels
+            .read()
+            .await
+            .contains_key(&m.channel_id);
+
+        // custom jobs can only be set on extended channels
+        if !is_extended_channel {
+            let error = Self::set_custom_mining_job_error(client_id, &m, "invalid-channel-id");
+            return self
+                .score_result(client_id, Some(m.channel_id), Ok(error))
+                .await;
+        }
+        warn!(
+            "Client {} set a custom job on channel {}, but work selection is not supported",
+            client_id, m.channel_id
+        );
+        let error = Self::set_custom_mining_job_error(client_id, &m, "invalid-mining-job-token");
+        self.score_result(client_id, Some(m.channel_id), Ok(error))
+            .await
     }
 
     async fn on_new_template(
         &self,
         template: NewTemplate<'static>,
     ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        self.check_template_sanity(&template).await?;
+
         {
             let mut state = self.shared_state.write().await;
             state.latest_template = Some(template.clone());
         }
 
+        if template.future_template {
+            self.rotate_payout_script().await?;
+        }
+
         let mut messages_to_clients: Vec<Sv2MessagesToClient> = Vec::new();
 
-        let coinbase_output_script = self.coinbase_output_script.clone();
-        let coinbase_output_value = template.coinbase_tx_value_remaining;
-        let coinbase_tx_output = TxOut {
-            value: Amount::from_sat(coinbase_output_value),
-            script_pubkey: coinbase_output_script,
-        };
+        let coinbase_output_script = self.get_coinbase_output_script().await;
+        let coinbase_outputs = self.coinbase_outputs(
+            template.coinbase_tx_value_remaining,
+            &coinbase_output_script,
+        );
+
+        self.template_payout_scripts
+            .write()
+            .await
+            .insert(template.template_id, coinbase_output_script.clone());
 
         if template.future_template {
             self.future_templates
                 .write()
                 .await
                 .insert(template.template_id, template.clone());
+        } else {
+            self.tip_templates
+                .write()
+                .await
+                .insert(template.template_id, template.clone());
         }
 
         for (client_id, client_guard) in self.clients.read().await.iter() {
             let client = client_guard.write().await;
+            let payout_scripts = client.payout_scripts.read().await;
             let mut messages_to_client = Vec::new();
             match template.future_template {
                 true => {
@@ -1505,7 +3475,7 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
                     if let Some(group_channel_guard) = &client.group_channel {
                         let mut group_channel = group_channel_guard.write().await;
                         group_channel
-                            .on_new_template(template.clone(), vec![coinbase_tx_output.clone()])
+                            .on_new_template(template.clone(), coinbase_outputs.clone())
                             .map_err(|e| {
                                 error!("Error sending new template to group channel: {:?}", e);
                                 Sv2ServerEventError::MiningHandlerError(format!(
@@ -1534,10 +3504,20 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
 
                     // process standard channels
                     let standard_channels_arc = &client.standard_channels;
-                    for (_, standard_channel_guard) in standard_channels_arc.read().await.iter() {
+                    for (channel_id, standard_channel_guard) in
+                        standard_channels_arc.read().await.iter()
+                    {
                         let mut standard_channel = standard_channel_guard.write().await;
                         standard_channel
-                            .on_new_template(template.clone(), vec![coinbase_tx_output.clone()])
+                            .on_new_template(
+                                template.clone(),
+                                self.coinbase_outputs(
+                                    template.coinbase_tx_value_remaining,
+                                    payout_scripts
+                                        .get(channel_id)
+                                        .unwrap_or(&coinbase_output_script),
+                                ),
+                            )
                             .map_err(|e| {
                                 error!(
                                     "Error sending new future template to standard channel: {:?}",
@@ -1583,10 +3563,20 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
 
                     //process extended channels
                     let extended_channels_arc = &client.extended_channels;
-                    for (_, extended_channel_guard) in extended_channels_arc.read().await.iter() {
+                    for (channel_id, extended_channel_guard) in
+                        extended_channels_arc.read().await.iter()
+                    {
                         let mut extended_channel = extended_channel_guard.write().await;
                         extended_channel
-                            .on_new_template(template.clone(), vec![coinbase_tx_output.clone()])
+                            .on_new_template(
+                                template.clone(),
+                                self.coinbase_outputs(
+                                    template.coinbase_tx_value_remaining,
+                                    payout_scripts
+                                        .get(channel_id)
+                                        .unwrap_or(&coinbase_output_script),
+                                ),
+                            )
                             .map_err(|e| {
                                 error!(
                                     "Error sending new future template to extended  channel: {:?}",
@@ -1638,7 +3628,7 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
                     if let Some(group_channel_guard) = &client.group_channel {
                         let mut group_channel = group_channel_guard.write().await;
                         group_channel
-                            .on_new_template(template.clone(), vec![coinbase_tx_output.clone()])
+                            .on_new_template(template.clone(), coinbase_outputs.clone())
                             .map_err(|e| {
                                 error!("Error sending new template to group channel: {:?}", e);
                                 Sv2ServerEventError::MiningHandlerError(format!(
@@ -1665,10 +3655,19 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
 
                     // process standard channels
                     let std_channels_arc = &client.standard_channels;
-                    for (_, standard_channel_guard) in std_channels_arc.read().await.iter() {
+                    for (channel_id, standard_channel_guard) in std_channels_arc.read().await.iter()
+                    {
                         let mut standard_channel = standard_channel_guard.write().await;
                         standard_channel
-                            .on_new_template(template.clone(), vec![coinbase_tx_output.clone()])
+                            .on_new_template(
+                                template.clone(),
+                                self.coinbase_outputs(
+                                    template.coinbase_tx_value_remaining,
+                                    payout_scripts
+                                        .get(channel_id)
+                                        .unwrap_or(&coinbase_output_script),
+                                ),
+                            )
                             .map_err(|e| {
                                 error!("Error sending new template to standard channel: {:?}", e);
                                 Sv2ServerEventError::MiningHandlerError(format!(
@@ -1691,10 +3690,20 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
                     }
                     //process extended channels
                     let extended_channels_arc = &client.extended_channels;
-                    for (_, extended_channel_guard) in extended_channels_arc.read().await.iter() {
+                    for (channel_id, extended_channel_guard) in
+                        extended_channels_arc.read().await.iter()
+                    {
                         let mut extended_channel = extended_channel_guard.write().await;
                         extended_channel
-                            .on_new_template(template.clone(), vec![coinbase_tx_output.clone()])
+                            .on_new_template(
+                                template.clone(),
+                                self.coinbase_outputs(
+                                    template.coinbase_tx_value_remaining,
+                                    payout_scripts
+                                        .get(channel_id)
+                                        .unwrap_or(&coinbase_output_script),
+                                ),
+                            )
                             .map_err(|e| {
                                 error!("Error sending new template to extended channel: {:?}", e);
                                 Sv2ServerEventError::MiningHandlerError(format!(
@@ -1725,15 +3734,34 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
             }
         }
 
-        Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
-            Sv2ServerEvent::SendMessagesToClients(Box::new(messages_to_clients)),
-        )))
+        let messages_to_clients = self.dispatch_sv1_messages(messages_to_clients).await;
+        let send_messages = Sv2ServerEvent::SendMessagesToClients(Box::new(messages_to_clients));
+        // the transactions are needed to rebuild the full block for submitblock
+        if self.block_submitter.is_some() && self.solution_sender.is_none() {
+            return Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
+                Sv2ServerEvent::MultipleEvents(Box::new(vec![
+                    send_messages,
+                    Sv2ServerEvent::SendEventToSiblingClientService(Box::new(
+                        Sv2ClientEvent::TemplateDistributionTrigger(
+                            TemplateDistributionClientTrigger::TransactionDataNeeded(
+                                template.template_id,
+                            ),
+                        ),
+                    )),
+                ])),
+            )));
+        }
+
+        Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(send_messages)))
     }
 
     async fn on_set_new_prev_hash(
         &self,
         prev_hash: SetNewPrevHash<'static>,
     ) -> Result<Sv2ServerOutcome<'static>, Sv2ServerEventError> {
+        self.check_network(&prev_hash).await?;
+        self.check_prev_hash_sanity(&prev_hash).await?;
+
         {
             let mut state = self.shared_state.write().await;
             state.latest_prev_hash = Some(prev_hash.clone());
@@ -1757,8 +3785,45 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
             self.last_activated_future_template.write().await;
         *last_activated_future_template_guard = Some(activated_future_template.clone());
 
+        {
+            let mut tip_templates = self.tip_templates.write().await;
+            tip_templates.clear();
+            tip_templates.insert(prev_hash.template_id, activated_future_template.clone());
+        }
+        self.template_payout_scripts
+            .write()
+            .await
+            .retain(|template_id, _| *template_id == prev_hash.template_id);
+
+        // the activated template builds on top of the new chain tip
+        match bip34_block_height(&activated_future_template.coinbase_prefix.to_vec()) {
+            Ok(height) => {
+                let prev_hash_bytes: [u8; 32] =
+                    prev_hash.prev_hash.to_vec().try_into().map_err(|e| {
+                        Sv2ServerEventError::MiningHandlerError(format!(
+                            "Invalid prev hash: {:?}",
+                            e
+                        ))
+                    })?;
+                self.block_tracker
+                    .on_chain_tip(
+                        height.saturating_sub(1),
+                        BlockHash::from_byte_array(prev_hash_bytes),
+                    )
+                    .await;
+            }
+            Err(e) => error!("Error reading BIP34 block height: {:?}", e),
+        }
+
         future_templates_guard.clear();
 
+        if let Some(block_submitter) = &self.block_submitter {
+            block_submitter.retain_template(prev_hash.template_id).await;
+        }
+
+        self.schedule_payout_rotation(PayoutRotation::PrevHash)
+            .await;
+
         let mut messages_to_clients: Vec<Sv2MessagesToClient> = Vec::new();
 
         for (client_id, client_guard) in self.clients.read().await.iter() {
@@ -1912,6 +3977,7 @@ impl Sv2MiningServerHandler for PlebLotteryMiningServerHandler {
             messages_to_clients.push(messages_to_client);
         }
 
+        let messages_to_clients = self.dispatch_sv1_messages(messages_to_clients).await;
         Ok(Sv2ServerOutcome::TriggerNewEvent(Box::new(
             Sv2ServerEvent::SendMessagesToClients(Box::new(messages_to_clients)),
         )))
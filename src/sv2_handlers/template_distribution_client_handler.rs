@@ -1,4 +1,6 @@
 use anyhow::Result;
+use bitcoin::consensus::encode::deserialize;
+use bitcoin::Transaction;
 use sv2_services::client::service::event::{Sv2ClientEvent, Sv2ClientEventError};
 use sv2_services::client::service::outcome::Sv2ClientOutcome;
 use sv2_services::client::service::subprotocols::template_distribution::handler::Sv2TemplateDistributionClientHandler;
@@ -8,11 +10,14 @@ use sv2_services::roles_logic_sv2::template_distribution_sv2::{
 };
 use sv2_services::server::service::event::Sv2ServerEvent;
 use sv2_services::server::service::subprotocols::mining::trigger::MiningServerTrigger;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
 
+use crate::block_submission::BlockSubmitter;
+use crate::state::{SharedStateHandle, TemplateProviderStatus};
 use crate::utils::bip34_block_height;
 
 #[derive(Debug, Clone)]
@@ -20,17 +25,34 @@ pub struct PlebLotteryTemplateDistributionClientHandler {
     current_height: Arc<RwLock<u64>>,
     coinbase_output_max_additional_size: u32,
     coinbase_output_max_additional_sigops: u16,
+    shared_state: SharedStateHandle,
+    block_submitter: Option<BlockSubmitter>, // keeps the transactions of every template
 }
 
 impl PlebLotteryTemplateDistributionClientHandler {
     pub fn new(
         coinbase_output_max_additional_size: u32,
         coinbase_output_max_additional_sigops: u16,
+        shared_state: SharedStateHandle,
+        block_submitter: Option<BlockSubmitter>,
     ) -> Self {
         Self {
             current_height: Arc::new(RwLock::new(0)),
             coinbase_output_max_additional_size,
             coinbase_output_max_additional_sigops,
+            shared_state,
+            block_submitter,
+        }
+    }
+
+    /// Marks the Template Provider as connected once it is serving templates again.
+    async fn set_connected(&self) {
+        let mut state = self.shared_state.write().await;
+        state.template_provider_last_message = Some(Instant::now());
+        if state.template_provider_status != TemplateProviderStatus::Connected {
+            info!("Connected to the Template Provider");
+            state.template_provider_status = TemplateProviderStatus::Connected;
+            state.template_provider_reconnect_attempts = 0;
         }
     }
 }
@@ -55,6 +77,7 @@ impl Sv2TemplateDistributionClientHandler for PlebLotteryTemplateDistributionCli
             "Received NewTemplate message from Template Provider: {}",
             template
         );
+        self.set_connected().await;
         let current_height = match bip34_block_height(&template.coinbase_prefix.to_vec()) {
             Ok(height) => height.checked_sub(1).unwrap_or(0), // Subtract 1 to get the **current** height
             Err(_) => 0,
@@ -84,6 +107,7 @@ impl Sv2TemplateDistributionClientHandler for PlebLotteryTemplateDistributionCli
             "Received SetNewPrevHash message from Template Provider: {}",
             prev_hash
         );
+        self.set_connected().await;
         let outcome = Sv2ClientOutcome::TriggerNewEvent(Box::new(
             Sv2ClientEvent::SendEventToSiblingServerService(Box::new(
                 Sv2ServerEvent::MiningTrigger(MiningServerTrigger::SetNewPrevHash(prev_hash)),
@@ -94,17 +118,51 @@ impl Sv2TemplateDistributionClientHandler for PlebLotteryTemplateDistributionCli
 
     async fn handle_request_transaction_data_success(
         &self,
-        _transaction_data: RequestTransactionDataSuccess<'static>,
+        transaction_data: RequestTransactionDataSuccess<'static>,
     ) -> Result<Sv2ClientOutcome<'static>, Sv2ClientEventError> {
-        error!("Received unexpected RequestTransactionDataSuccess");
-        Err(Sv2ClientEventError::UnsupportedMessage)
+        let Some(block_submitter) = &self.block_submitter else {
+            error!("Received unexpected RequestTransactionDataSuccess");
+            return Err(Sv2ClientEventError::UnsupportedMessage);
+        };
+        let transactions = transaction_data
+            .transaction_list
+            .into_inner()
+            .iter()
+            .map(|transaction| deserialize::<Transaction>(&transaction.to_vec()))
+            .collect::<Result<Vec<_>, _>>();
+        match transactions {
+            Ok(transactions) => {
+                info!(
+                    "Received {} transactions of template {}",
+                    transactions.len(),
+                    transaction_data.template_id
+                );
+                block_submitter
+                    .add_template_transactions(transaction_data.template_id, transactions)
+                    .await;
+            }
+            Err(e) => warn!(
+                "Invalid transaction data for template {}: {}",
+                transaction_data.template_id, e
+            ),
+        }
+        Ok(Sv2ClientOutcome::Ok)
     }
 
     async fn handle_request_transaction_data_error(
         &self,
-        _error: RequestTransactionDataError<'static>,
+        error: RequestTransactionDataError<'static>,
     ) -> Result<Sv2ClientOutcome<'static>, Sv2ClientEventError> {
-        error!("Received unexpected RequestTransactionDataError");
-        Err(Sv2ClientEventError::UnsupportedMessage)
+        if self.block_submitter.is_none() {
+            error!("Received unexpected RequestTransactionDataError");
+            return Err(Sv2ClientEventError::UnsupportedMessage);
+        }
+        // blocks found on this template can only be submitted through the Template Provider
+        warn!(
+            "Template Provider has no transaction data for template {}: {}",
+            error.template_id,
+            String::from_utf8_lossy(&error.error_code.to_vec())
+        );
+        Ok(Sv2ClientOutcome::Ok)
     }
 }
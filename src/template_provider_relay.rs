@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Notify, RwLock};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::state::SharedStateHandle;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Local TCP relay between the Template Distribution client and a list of Template Providers.
+///
+/// The client always connects to the relay, which forwards the (encrypted) stream to the
+/// highest priority Template Provider that is reachable. To fail over, the relay drops the
+/// connection and the client reconnects through it to the next Template Provider. This happens
+/// when the active Template Provider disconnects, when it is silent for longer than
+/// `silence_timeout`, and when a Template Provider with a higher priority becomes reachable again.
+#[derive(Clone)]
+pub struct TemplateProviderRelay {
+    providers: Vec<SocketAddr>,
+    silence_timeout: Option<Duration>,
+    listener: Arc<TcpListener>,
+    shared_state: SharedStateHandle,
+    active: Arc<RwLock<Option<(usize, Instant)>>>, // index in providers and connection time
+    cooldowns: Arc<RwLock<HashMap<usize, Instant>>>, // silent providers are skipped until then
+    switch: Arc<Notify>,
+}
+
+impl TemplateProviderRelay {
+    pub async fn bind(
+        providers: Vec<SocketAddr>,
+        silence_timeout: Option<Duration>,
+        shared_state: SharedStateHandle,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(|e| anyhow!("Failed to bind Template Provider relay: {}", e))?;
+        Ok(Self {
+            providers,
+            silence_timeout,
+            listener: Arc::new(listener),
+            shared_state,
+            active: Arc::new(RwLock::new(None)),
+            cooldowns: Arc::new(RwLock::new(HashMap::new())),
+            switch: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Address the Template Distribution client should connect to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub async fn run(&self, cancellation_token: CancellationToken) -> Result<()> {
+        tokio::select! {
+            result = self.accept_loop() => result,
+            _ = self.watch_loop() => Ok(()),
+            _ = cancellation_token.cancelled() => Ok(()),
+        }
+    }
+
+    async fn accept_loop(&self) -> Result<()> {
+        loop {
+            let (downstream, _) = self
+                .listener
+                .accept()
+                .await
+                .map_err(|e| anyhow!("Template Provider relay failed to accept: {}", e))?;
+            // the client keeps a single connection at a time
+            self.relay(downstream).await;
+        }
+    }
+
+    async fn relay(&self, mut downstream: TcpStream) {
+        let Some((index, mut upstream)) = self.connect_upstream().await else {
+            warn!("No Template Provider is reachable");
+            return;
+        };
+        let provider = self.providers[index];
+        info!("Using Template Provider {}", provider);
+        *self.active.write().await = Some((index, Instant::now()));
+        self.shared_state.write().await.active_template_provider = Some(provider);
+
+        tokio::select! {
+            result = tokio::io::copy_bidirectional(&mut downstream, &mut upstream) => {
+                if let Err(e) = result {
+                    warn!("Connection to Template Provider {} failed: {}", provider, e);
+                }
+            }
+            _ = self.switch.notified() => {
+                info!("Switching away from Template Provider {}", provider);
+            }
+        }
+
+        *self.active.write().await = None;
+        self.shared_state.write().await.active_template_provider = None;
+    }
+
+    async fn connect_upstream(&self) -> Option<(usize, TcpStream)> {
+        for (index, provider) in self.providers.iter().enumerate() {
+            if self.in_cooldown(index).await {
+                continue;
+            }
+            match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(provider)).await {
+                Ok(Ok(upstream)) => return Some((index, upstream)),
+                Ok(Err(e)) => warn!("Template Provider {} is unreachable: {}", provider, e),
+                Err(_) => warn!("Template Provider {} is unreachable: timed out", provider),
+            }
+        }
+        None
+    }
+
+    async fn in_cooldown(&self, index: usize) -> bool {
+        self.cooldowns
+            .read()
+            .await
+            .get(&index)
+            .is_some_and(|until| Instant::now() < *until)
+    }
+
+    /// Fails over from silent Template Providers, and back to recovered ones with a higher
+    /// priority.
+    async fn watch_loop(&self) {
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some((active, connected_at)) = *self.active.read().await else {
+                continue;
+            };
+
+            if let Some(silence_timeout) = self.silence_timeout {
+                let last_message = self
+                    .shared_state
+                    .read()
+                    .await
+                    .template_provider_last_message
+                    .map_or(connected_at, |last_message| last_message.max(connected_at));
+                if last_message.elapsed() > silence_timeout {
+                    warn!(
+                        "Template Provider {} sent nothing for {:?}",
+                        self.providers[active], silence_timeout
+                    );
+                    self.cooldowns
+                        .write()
+                        .await
+                        .insert(active, Instant::now() + silence_timeout);
+                    self.switch.notify_waiters();
+                    continue;
+                }
+            }
+
+            for index in 0..active {
+                if self.in_cooldown(index).await {
+                    continue;
+                }
+                let provider = self.providers[index];
+                if let Ok(Ok(_)) =
+                    tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(provider)).await
+                {
+                    info!("Template Provider {} is reachable again", provider);
+                    self.switch.notify_waiters();
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Fake Template Provider answering every connection with `name`.
+    async fn fake_provider(name: &'static [u8]) -> (SocketAddr, tokio::task::JoinHandle<()>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    stream.write_all(name).await.unwrap();
+                    let mut buf = [0u8; 1];
+                    let _ = stream.read(&mut buf).await;
+                });
+            }
+        });
+        (addr, handle)
+    }
+
+    async fn read_provider_name(relay_addr: SocketAddr) -> Vec<u8> {
+        let mut stream = TcpStream::connect(relay_addr).await.unwrap();
+        let mut name = vec![0u8; 7];
+        stream.read_exact(&mut name).await.unwrap();
+        name
+    }
+
+    #[tokio::test]
+    async fn test_relay_fails_over_in_priority_order() {
+        let (primary, primary_handle) = fake_provider(b"primary").await;
+        let (backup, _backup_handle) = fake_provider(b"backup_").await;
+        let shared_state = SharedStateHandle::default();
+
+        let relay = TemplateProviderRelay::bind(vec![primary, backup], None, shared_state.clone())
+            .await
+            .unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let cancellation_token = CancellationToken::new();
+        let relay_token = cancellation_token.clone();
+        tokio::spawn(async move { relay.run(relay_token).await });
+
+        assert_eq!(read_provider_name(relay_addr).await, b"primary");
+        assert_eq!(
+            shared_state.read().await.active_template_provider,
+            Some(primary)
+        );
+
+        // the primary goes away, the next connection is relayed to the backup
+        primary_handle.abort();
+        let _ = primary_handle.await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(read_provider_name(relay_addr).await, b"backup_");
+        assert_eq!(
+            shared_state.read().await.active_template_provider,
+            Some(backup)
+        );
+
+        cancellation_token.cancel();
+    }
+}
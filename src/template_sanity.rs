@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Result};
+use bitcoin::{Amount, Network};
+
+/// Blocks between difficulty adjustments.
+pub const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 2016;
+
+/// nbits of the proof of work limit, which testnets fall back to after 20 minutes without a block.
+const TESTNET_MIN_DIFFICULTY_BITS: u32 = 0x1d00ffff;
+
+/// Subsidy of a block at `height`, without fees.
+pub fn block_subsidy(network: Network, height: u64) -> Amount {
+    let halving_interval = match network {
+        Network::Regtest => 150,
+        _ => 210_000,
+    };
+    let halvings = height / halving_interval;
+    if halvings >= 64 {
+        return Amount::ZERO;
+    }
+    Amount::from_sat(Amount::from_int_btc(50).to_sat() >> halvings)
+}
+
+/// Checks that a template at `height` pays at least the block subsidy.
+pub fn check_subsidy(
+    network: Network,
+    height: u64,
+    coinbase_tx_value_remaining: u64,
+) -> Result<()> {
+    let subsidy = block_subsidy(network, height);
+    if Amount::from_sat(coinbase_tx_value_remaining) < subsidy {
+        return Err(anyhow!(
+            "coinbase value {} is below the subsidy of {} at height {}",
+            Amount::from_sat(coinbase_tx_value_remaining),
+            subsidy,
+            height
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the height of a new template against the height of the template mined on the current
+/// chain tip: templates for the current tip must have the same height, and future templates,
+/// which build on the next tip, a greater one.
+pub fn check_template_height(
+    height: u64,
+    future_template: bool,
+    tip_template_height: Option<u64>,
+) -> Result<()> {
+    let Some(tip_template_height) = tip_template_height else {
+        return Ok(());
+    };
+    match future_template {
+        true if height <= tip_template_height => Err(anyhow!(
+            "future template at height {} does not build on top of height {}",
+            height,
+            tip_template_height
+        )),
+        false if height != tip_template_height => Err(anyhow!(
+            "template at height {} does not build on the current chain tip at height {}",
+            height,
+            tip_template_height.saturating_sub(1)
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the header timestamp of a `SetNewPrevHash` is at most `max_time_drift` seconds
+/// away from local time `now`.
+pub fn check_time_drift(header_timestamp: u32, now: u64, max_time_drift: u64) -> Result<()> {
+    let drift = (header_timestamp as u64).abs_diff(now);
+    if drift > max_time_drift {
+        return Err(anyhow!(
+            "header timestamp {} is {} seconds away from local time {}",
+            header_timestamp,
+            drift,
+            now
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that nbits only changes from `last_bits` at `last_height` to `bits` at `height` when a
+/// difficulty adjustment happened in between.
+///
+/// On testnets, blocks may also fall back to the minimum difficulty, and back from it.
+pub fn check_nbits(
+    network: Network,
+    last_height: u64,
+    last_bits: u32,
+    height: u64,
+    bits: u32,
+) -> Result<()> {
+    if bits == last_bits {
+        return Ok(());
+    }
+    if height / DIFFICULTY_ADJUSTMENT_INTERVAL != last_height / DIFFICULTY_ADJUSTMENT_INTERVAL {
+        return Ok(());
+    }
+    if matches!(network, Network::Testnet | Network::Testnet4)
+        && (bits == TESTNET_MIN_DIFFICULTY_BITS || last_bits == TESTNET_MIN_DIFFICULTY_BITS)
+    {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "nbits changed from {:08x} at height {} to {:08x} at height {}, outside of a difficulty adjustment",
+        last_bits,
+        last_height,
+        bits,
+        height
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_subsidy() {
+        assert_eq!(block_subsidy(Network::Bitcoin, 0), Amount::from_int_btc(50));
+        assert_eq!(
+            block_subsidy(Network::Bitcoin, 840_000),
+            Amount::from_sat(312_500_000)
+        );
+        assert_eq!(
+            block_subsidy(Network::Regtest, 150),
+            Amount::from_int_btc(25)
+        );
+        assert_eq!(block_subsidy(Network::Bitcoin, 64 * 210_000), Amount::ZERO);
+
+        assert!(check_subsidy(Network::Bitcoin, 840_000, 312_500_000).is_ok());
+        assert!(check_subsidy(Network::Bitcoin, 840_000, 320_000_000).is_ok());
+        assert!(check_subsidy(Network::Bitcoin, 840_000, 312_499_999).is_err());
+    }
+
+    #[test]
+    fn test_check_template_height() {
+        assert!(check_template_height(100, true, None).is_ok());
+        assert!(check_template_height(100, false, Some(100)).is_ok());
+        assert!(check_template_height(101, true, Some(100)).is_ok());
+        assert!(check_template_height(99, false, Some(100)).is_err());
+        assert!(check_template_height(100, true, Some(100)).is_err());
+        assert!(check_template_height(99, true, Some(100)).is_err());
+    }
+
+    #[test]
+    fn test_check_time_drift() {
+        assert!(check_time_drift(1_700_000_000, 1_700_000_000, 7200).is_ok());
+        assert!(check_time_drift(1_700_007_200, 1_700_000_000, 7200).is_ok());
+        assert!(check_time_drift(1_699_992_800, 1_700_000_000, 7200).is_ok());
+        assert!(check_time_drift(1_700_007_201, 1_700_000_000, 7200).is_err());
+        assert!(check_time_drift(0, 1_700_000_000, 7200).is_err());
+    }
+
+    #[test]
+    fn test_check_nbits() {
+        assert!(check_nbits(Network::Bitcoin, 100, 0x1703_0000, 101, 0x1703_0000).is_ok());
+        assert!(check_nbits(Network::Bitcoin, 100, 0x1703_0000, 101, 0x1702_0000).is_err());
+        // difficulty adjustment at height 2016
+        assert!(check_nbits(Network::Bitcoin, 2015, 0x1703_0000, 2016, 0x1702_0000).is_ok());
+        assert!(check_nbits(Network::Bitcoin, 2016, 0x1703_0000, 2017, 0x1702_0000).is_err());
+        // the chain moved past an adjustment
+        assert!(check_nbits(Network::Bitcoin, 2010, 0x1703_0000, 2020, 0x1702_0000).is_ok());
+        // a reorg back past an adjustment
+        assert!(check_nbits(Network::Bitcoin, 2016, 0x1703_0000, 2015, 0x1702_0000).is_ok());
+        // minimum difficulty blocks on testnets
+        assert!(check_nbits(Network::Testnet4, 100, 0x1903_0000, 101, 0x1d00ffff).is_ok());
+        assert!(check_nbits(Network::Testnet4, 101, 0x1d00ffff, 102, 0x1903_0000).is_ok());
+        assert!(check_nbits(Network::Bitcoin, 100, 0x1903_0000, 101, 0x1d00ffff).is_err());
+    }
+}
@@ -1,5 +1,12 @@
 use anyhow::Result;
-use bitcoin::{blockdata::script, ScriptBuf};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::Params;
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::{
+    blockdata::script, Address, BlockHash, CompactTarget, Network, ScriptBuf, Target, TxMerkleNode,
+    Txid,
+};
+use std::str::FromStr;
 
 pub fn bip34_block_height(coinbase_prefix: &[u8]) -> Result<u64> {
     let script = ScriptBuf::from_bytes(coinbase_prefix.to_owned());
@@ -21,3 +28,323 @@ pub fn bip34_block_height(coinbase_prefix: &[u8]) -> Result<u64> {
         )),
     }
 }
+
+/// Computes the merkle root of a block from the txid of its coinbase and the merkle path of the
+/// coinbase, as found in `NewTemplate`.
+pub fn merkle_root_from_path(coinbase_txid: Txid, merkle_path: &[Vec<u8>]) -> Result<TxMerkleNode> {
+    let mut root = coinbase_txid.to_byte_array();
+    for node in merkle_path {
+        let node: [u8; 32] = node
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Merkle path nodes must be 32 bytes"))?;
+        let mut engine = sha256d::Hash::engine();
+        engine.input(&root);
+        engine.input(&node);
+        root = sha256d::Hash::from_engine(engine).to_byte_array();
+    }
+    Ok(TxMerkleNode::from_byte_array(root))
+}
+
+/// Parses a `user_identity` of the form `address` or `address.workername` into the script
+/// pubkey of `address`, as long as `address` is valid for `network`.
+pub fn user_identity_payout_script(user_identity: &str, network: Network) -> Result<ScriptBuf> {
+    let address = user_identity
+        .split_once('.')
+        .map_or(user_identity, |(address, _worker)| address);
+    let address = Address::from_str(address)
+        .map_err(|e| anyhow::anyhow!("Invalid address in user_identity: {}", e))?
+        .require_network(network)
+        .map_err(|e| anyhow::anyhow!("Invalid address in user_identity: {}", e))?;
+    Ok(address.script_pubkey())
+}
+
+/// Derives the `size` bytes identifying a worker in the coinbase from its `user_identity`.
+///
+/// The worker name of an `address.workername` identity is used, or the whole identity if it has
+/// no worker name. It is truncated or zero-padded to `size` bytes.
+pub fn worker_tag(user_identity: &str, size: usize) -> Vec<u8> {
+    let worker_name = user_identity
+        .split_once('.')
+        .map_or(user_identity, |(_address, worker)| worker);
+    let mut tag: Vec<u8> = worker_name.bytes().take(size).collect();
+    tag.resize(size, 0);
+    tag
+}
+
+/// Block hashes known to be in the chain of a network, besides its genesis block, by height.
+/// Taken from the checkpoints of Bitcoin Core.
+const CHECKPOINTS: &[(Network, u64, &str)] = &[
+    (
+        Network::Bitcoin,
+        11_111,
+        "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d",
+    ),
+    (
+        Network::Bitcoin,
+        33_333,
+        "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6",
+    ),
+    (
+        Network::Bitcoin,
+        210_000,
+        "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e",
+    ),
+    (
+        Network::Bitcoin,
+        295_000,
+        "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983",
+    ),
+    (
+        Network::Testnet,
+        546,
+        "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70",
+    ),
+];
+
+/// Networks with a chain of their own, anchored by their genesis block.
+const NETWORKS: [Network; 5] = [
+    Network::Bitcoin,
+    Network::Testnet,
+    Network::Testnet4,
+    Network::Signet,
+    Network::Regtest,
+];
+
+/// Known `(network, height, block hash)` anchors: the genesis block of every network and the
+/// [`CHECKPOINTS`].
+fn chain_anchors() -> impl Iterator<Item = (Network, u64, BlockHash)> {
+    NETWORKS
+        .into_iter()
+        .map(|network| (network, 0, genesis_block(network).block_hash()))
+        .chain(CHECKPOINTS.iter().map(|(network, height, block_hash)| {
+            (
+                *network,
+                *height,
+                BlockHash::from_str(block_hash).expect("checkpoints must be valid block hashes"),
+            )
+        }))
+}
+
+/// Checks that a chain tip served by the Template Provider plausibly belongs to `network`.
+///
+/// `height` is the height of the block being mined on top of `prev_hash`. Whenever `prev_hash`
+/// is at the height of a known block of some network (genesis blocks and a few checkpoints),
+/// it must be the block of `network`, and not the one of another network. Otherwise, `n_bits`
+/// is checked against the network's proof of work limit and `height` against the network's
+/// BIP34 activation height, which only reliably tell regtest apart: the test networks and
+/// signet share the proof of work limit. With `bitcoin_rpc_config` set, the genesis block of
+/// the node is also checked on startup.
+pub fn check_chain_tip_network(
+    network: Network,
+    height: u64,
+    prev_hash: BlockHash,
+    n_bits: u32,
+) -> Result<()> {
+    let params = Params::new(network);
+
+    if let Some(prev_height) = height.checked_sub(1) {
+        for (anchor_network, anchor_height, anchor_hash) in chain_anchors() {
+            if anchor_height != prev_height {
+                continue;
+            }
+            if anchor_network == network && anchor_hash != prev_hash {
+                return Err(anyhow::anyhow!(
+                    "block {} is {}, not the {} block {}",
+                    prev_height,
+                    prev_hash,
+                    network,
+                    anchor_hash
+                ));
+            }
+            if anchor_network != network && anchor_hash == prev_hash {
+                return Err(anyhow::anyhow!(
+                    "block {} is the {} block {}",
+                    prev_height,
+                    anchor_network,
+                    anchor_hash
+                ));
+            }
+        }
+    }
+
+    let max_n_bits = params
+        .max_attainable_target
+        .to_compact_lossy()
+        .to_consensus();
+    if params.no_pow_retargeting {
+        if n_bits != max_n_bits {
+            return Err(anyhow::anyhow!(
+                "nBits {:08x} does not match the {} nBits {:08x}",
+                n_bits,
+                network,
+                max_n_bits
+            ));
+        }
+    } else {
+        if Target::from_compact(CompactTarget::from_consensus(n_bits))
+            > params.max_attainable_target
+        {
+            return Err(anyhow::anyhow!(
+                "nBits {:08x} is above the {} proof of work limit {:08x}",
+                n_bits,
+                network,
+                max_n_bits
+            ));
+        }
+        if height < params.bip34_height as u64 {
+            return Err(anyhow::anyhow!(
+                "height {} is below the {} BIP34 activation height {}",
+                height,
+                network,
+                params.bip34_height
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_identity_payout_script() {
+        let address = "tb1qw8rnkgnk7s48h6w5c0mg7we7gvzykeyp2sze82";
+        let expected = "001471c73b2276f42a7be9d4c3f68f3b3e43044b6481";
+
+        let script = user_identity_payout_script(address, Network::Testnet4).unwrap();
+        assert_eq!(script.to_hex_string(), expected);
+
+        let script =
+            user_identity_payout_script(&format!("{address}.bitaxe01"), Network::Testnet4).unwrap();
+        assert_eq!(script.to_hex_string(), expected);
+    }
+
+    #[test]
+    fn test_user_identity_payout_script_invalid() {
+        // wrong network
+        assert!(user_identity_payout_script(
+            "tb1qw8rnkgnk7s48h6w5c0mg7we7gvzykeyp2sze82",
+            Network::Bitcoin
+        )
+        .is_err());
+        // not an address
+        assert!(user_identity_payout_script("username", Network::Testnet4).is_err());
+        assert!(user_identity_payout_script("", Network::Testnet4).is_err());
+    }
+
+    #[test]
+    fn test_worker_tag() {
+        assert_eq!(
+            worker_tag("tb1qw8rnkgnk7s48h6w5c0mg7we7gvzykeyp2sze82.bitaxe01", 8),
+            b"bitaxe01"
+        );
+        assert_eq!(worker_tag("bitaxe.rack-a-shelf-3", 6), b"rack-a");
+        assert_eq!(worker_tag("nano", 6), b"nano\0\0");
+        assert!(worker_tag("nano", 0).is_empty());
+    }
+
+    #[test]
+    fn test_check_chain_tip_network() {
+        let regtest_genesis = genesis_block(Network::Regtest).block_hash();
+        let regtest_n_bits = 0x207fffff;
+        let mainnet_n_bits = 0x17023a04;
+
+        // regtest Template Provider
+        assert!(
+            check_chain_tip_network(Network::Regtest, 1, regtest_genesis, regtest_n_bits).is_ok()
+        );
+        assert!(
+            check_chain_tip_network(Network::Regtest, 500, regtest_genesis, regtest_n_bits).is_ok()
+        );
+        assert!(
+            check_chain_tip_network(Network::Bitcoin, 500, regtest_genesis, regtest_n_bits)
+                .is_err()
+        );
+        assert!(
+            check_chain_tip_network(Network::Testnet4, 1, regtest_genesis, 0x1d00ffff).is_err()
+        );
+        assert!(
+            check_chain_tip_network(Network::Signet, 500, regtest_genesis, regtest_n_bits).is_err()
+        );
+
+        // mainnet Template Provider
+        let tip = genesis_block(Network::Bitcoin).block_hash();
+        assert!(check_chain_tip_network(Network::Bitcoin, 900_000, tip, mainnet_n_bits).is_ok());
+        assert!(check_chain_tip_network(Network::Regtest, 900_000, tip, mainnet_n_bits).is_err());
+        assert!(check_chain_tip_network(Network::Bitcoin, 1_000, tip, mainnet_n_bits).is_err());
+    }
+
+    #[test]
+    fn test_check_chain_tip_network_mainnet_testnet4() {
+        let mainnet_checkpoint =
+            BlockHash::from_str("00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983")
+                .unwrap();
+        let mainnet_n_bits = 0x1900896c;
+        assert!(check_chain_tip_network(
+            Network::Bitcoin,
+            295_001,
+            mainnet_checkpoint,
+            mainnet_n_bits
+        )
+        .is_ok());
+        // mainnet shares its proof of work limit and BIP34 rules with testnet4 past block 1
+        let e = check_chain_tip_network(
+            Network::Testnet4,
+            295_001,
+            mainnet_checkpoint,
+            mainnet_n_bits,
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("is the bitcoin block"));
+        // a different block at the height of a mainnet checkpoint
+        let testnet4_genesis = genesis_block(Network::Testnet4).block_hash();
+        assert!(check_chain_tip_network(
+            Network::Bitcoin,
+            295_001,
+            testnet4_genesis,
+            mainnet_n_bits
+        )
+        .is_err());
+
+        let testnet_n_bits = 0x1d00ffff;
+        assert!(
+            check_chain_tip_network(Network::Testnet4, 1, testnet4_genesis, testnet_n_bits).is_ok()
+        );
+        assert!(
+            check_chain_tip_network(Network::Bitcoin, 1, testnet4_genesis, testnet_n_bits).is_err()
+        );
+    }
+
+    #[test]
+    fn test_check_chain_tip_network_testnet3_testnet4() {
+        let testnet_n_bits = 0x1d00ffff;
+        let testnet3_genesis = genesis_block(Network::Testnet).block_hash();
+        let testnet4_genesis = genesis_block(Network::Testnet4).block_hash();
+        assert!(
+            check_chain_tip_network(Network::Testnet4, 1, testnet3_genesis, testnet_n_bits)
+                .is_err()
+        );
+        assert!(
+            check_chain_tip_network(Network::Testnet, 1, testnet4_genesis, testnet_n_bits).is_err()
+        );
+
+        // testnet3 and testnet4 share their proof of work limit, so only the checkpoint tells them apart
+        let testnet3_checkpoint =
+            BlockHash::from_str("000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70")
+                .unwrap();
+        let e =
+            check_chain_tip_network(Network::Testnet4, 547, testnet3_checkpoint, testnet_n_bits)
+                .unwrap_err();
+        assert!(e.to_string().contains("is the testnet block"));
+        assert!(check_chain_tip_network(
+            Network::Testnet4,
+            548,
+            testnet3_checkpoint,
+            testnet_n_bits
+        )
+        .is_ok());
+    }
+}
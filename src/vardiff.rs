@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+use crate::config::VardiffConfig;
+use crate::hashrate::HASHES_PER_DIFFICULTY;
+
+/// Largest factor the nominal hashrate of a channel changes by in a single retarget, so a few
+/// unlucky measurements don't swing its difficulty too far.
+pub const MAX_RETARGET_FACTOR: f32 = 4.0;
+
+/// Accepted shares of a channel at its last measurement.
+#[derive(Debug, Clone, Copy)]
+pub struct VardiffState {
+    pub shares_accepted: u32,
+    pub measured_at: Instant,
+}
+
+impl VardiffState {
+    pub fn new(shares_accepted: u32) -> Self {
+        Self {
+            shares_accepted,
+            measured_at: Instant::now(),
+        }
+    }
+
+    /// Shares accepted since the last measurement, and the time it took. Starts a new
+    /// measurement at `shares_accepted`.
+    pub fn measure(&mut self, shares_accepted: u32) -> (u32, Duration) {
+        // the share accounting of the channel was reset
+        let shares = shares_accepted
+            .checked_sub(self.shares_accepted)
+            .unwrap_or(shares_accepted);
+        let elapsed = self.measured_at.elapsed();
+        *self = Self::new(shares_accepted);
+        (shares, elapsed)
+    }
+}
+
+/// Hashrate at which shares of `difficulty` are found `shares_per_minute` times a minute.
+pub fn difficulty_to_hashrate(difficulty: f64, shares_per_minute: f32) -> f32 {
+    (difficulty * HASHES_PER_DIFFICULTY * shares_per_minute as f64 / 60.0) as f32
+}
+
+/// New nominal hashrate for a channel that submitted `shares` over `elapsed` with
+/// `nominal_hashrate`, so it submits `expected_shares_per_minute` on its new target.
+///
+/// Returns `None` if the share rate is within the hysteresis of `config`, or if the difficulty
+/// bounds of `config` keep the hashrate where it is.
+pub fn retarget(
+    nominal_hashrate: f32,
+    shares: u32,
+    elapsed: Duration,
+    expected_shares_per_minute: f32,
+    config: &VardiffConfig,
+) -> Option<f32> {
+    let minutes = elapsed.as_secs_f32() / 60.0;
+    if minutes <= 0.0 || expected_shares_per_minute <= 0.0 || nominal_hashrate <= 0.0 {
+        return None;
+    }
+    let ratio = shares as f32 / minutes / expected_shares_per_minute;
+    if (1.0 - config.hysteresis..=1.0 + config.hysteresis).contains(&ratio) {
+        return None;
+    }
+
+    let mut hashrate =
+        nominal_hashrate * ratio.clamp(1.0 / MAX_RETARGET_FACTOR, MAX_RETARGET_FACTOR);
+    if let Some(min_difficulty) = config.min_difficulty {
+        hashrate = hashrate.max(difficulty_to_hashrate(
+            min_difficulty,
+            expected_shares_per_minute,
+        ));
+    }
+    if let Some(max_difficulty) = config.max_difficulty {
+        hashrate = hashrate.min(difficulty_to_hashrate(
+            max_difficulty,
+            expected_shares_per_minute,
+        ));
+    }
+    (hashrate != nominal_hashrate).then_some(hashrate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retarget() {
+        let config = VardiffConfig::default();
+        let minute = Duration::from_secs(60);
+
+        // within hysteresis
+        assert_eq!(retarget(1e12, 10, minute, 10.0, &config), None);
+        assert_eq!(retarget(1e12, 12, minute, 10.0, &config), None);
+        assert_eq!(retarget(1e12, 8, minute, 10.0, &config), None);
+        // twice as many shares as expected
+        assert_eq!(retarget(1e12, 20, minute, 10.0, &config), Some(2e12));
+        assert_eq!(retarget(1e12, 10, minute / 2, 10.0, &config), Some(2e12));
+        // half as many
+        assert_eq!(retarget(1e12, 5, minute, 10.0, &config), Some(5e11));
+        // capped at MAX_RETARGET_FACTOR
+        assert_eq!(retarget(1e12, 1_000, minute, 10.0, &config), Some(4e12));
+        assert_eq!(retarget(1e12, 0, minute, 10.0, &config), Some(2.5e11));
+        assert_eq!(retarget(1e12, 0, Duration::ZERO, 10.0, &config), None);
+    }
+
+    #[test]
+    fn test_retarget_difficulty_bounds() {
+        let config = VardiffConfig {
+            min_difficulty: Some(1.0),
+            max_difficulty: Some(1_000.0),
+            ..VardiffConfig::default()
+        };
+        let minute = Duration::from_secs(60);
+        let min_hashrate = difficulty_to_hashrate(1.0, 60.0);
+        let max_hashrate = difficulty_to_hashrate(1_000.0, 60.0);
+        assert_eq!(min_hashrate, 4_294_967_296.0);
+
+        assert_eq!(
+            retarget(min_hashrate * 2.0, 0, minute, 60.0, &config),
+            Some(min_hashrate)
+        );
+        // already at the minimum difficulty
+        assert_eq!(retarget(min_hashrate, 0, minute, 60.0, &config), None);
+        assert_eq!(retarget(max_hashrate, 240, minute, 60.0, &config), None);
+        assert_eq!(
+            retarget(max_hashrate / 2.0, 240, minute, 60.0, &config),
+            Some(max_hashrate)
+        );
+    }
+
+    #[test]
+    fn test_measure() {
+        let mut state = VardiffState::new(10);
+        assert_eq!(state.measure(25).0, 15);
+        assert_eq!(state.shares_accepted, 25);
+        assert_eq!(state.measure(3).0, 3);
+    }
+}
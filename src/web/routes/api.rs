@@ -1,11 +1,31 @@
-use crate::state::SharedStateHandle;
-use crate::{config::PleblotteryConfig, utils::bip34_block_height};
-use axum::{extract::State, response::Html, Router};
+use crate::ban::Ban;
+use crate::block_journal::FoundBlock;
+use crate::block_submission::SubmissionResult;
+use crate::block_tracker::FoundBlockStatus;
+use crate::hashrate::{format_hashrate, HashrateReport};
+use crate::state::{SharedState, SharedStateHandle, TemplateProviderStatus};
+use crate::{
+    config::{network_name, PleblotteryConfig},
+    utils::bip34_block_height,
+};
+use axum::{extract::State, response::Html, Json, Router};
+use bitcoin::{Address, Amount};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 pub async fn serve_config_htmx() -> Html<String> {
     match PleblotteryConfig::from_file("./config.toml") {
         Ok(config) => {
             let rows = [format!(
+                    r#"<tr class="hover:bg-gray-100">
+                        <td class="border px-4 py-2 font-bold">Network</td>
+                        <td class="border px-4 py-2">{}</td>
+                        <td class="border px-4 py-2">Bitcoin network that pleblottery mines on. <br><br> Coinbase output addresses must belong to it, and the Template Provider must be serving it.</td>
+                    </tr>"#,
+                    network_name(config.mining_server_config.network)
+                ),
+                format!(
                     r#"<tr class="hover:bg-gray-100">
                         <td class="border px-4 py-2 font-bold">Sv2 Mining Port</td>
                         <td class="border px-4 py-2">{}</td>
@@ -37,6 +57,80 @@ pub async fn serve_config_htmx() -> Html<String> {
                     </tr>"#,
                     config.mining_server_config.inactivity_limit
                 ),
+                format!(
+                    r#"<tr class="hover:bg-gray-100">
+                        <td class="border px-4 py-2 font-bold">User Identity Payout</td>
+                        <td class="border px-4 py-2">{:?}</td>
+                        <td class="border px-4 py-2">Whether channels are paid to the address in their <code>user_identity</code> (<code>address</code> or <code>address.workername</code>). <br><br> With <code>Fallback</code>, invalid identities are paid to the configured coinbase output address. With <code>Reject</code>, they can't open channels.</td>
+                    </tr>"#,
+                    config.mining_server_config.user_identity_payout
+                ),
+                format!(
+                    r#"<tr class="hover:bg-gray-100">
+                        <td class="border px-4 py-2 font-bold">Coinbase Output Splits</td>
+                        <td class="border px-4 py-2">{}</td>
+                        <td class="border px-4 py-2">Extra coinbase outputs, each paid a fixed share of the block reward. <br><br> The remainder is paid to the coinbase output address.</td>
+                    </tr>"#,
+                    if config.mining_server_config.coinbase_output_splits.is_empty() {
+                        "None".to_string()
+                    } else {
+                        config
+                            .mining_server_config
+                            .coinbase_output_splits
+                            .iter()
+                            .map(|split| {
+                                let address = Address::from_script(
+                                    &split.script_pubkey,
+                                    config.mining_server_config.network,
+                                )
+                                .map(|address| address.to_string())
+                                .unwrap_or_else(|_| split.script_pubkey.to_hex_string());
+                                format!("{}: {:.2}%", address, split.basis_points as f64 / 100.0)
+                            })
+                            .collect::<Vec<_>>()
+                            .join("<br>")
+                    }
+                ),
+                format!(
+                    r#"<tr class="hover:bg-gray-100">
+                        <td class="border px-4 py-2 font-bold">Coinbase Output Descriptor</td>
+                        <td class="border px-4 py-2">{}</td>
+                        <td class="border px-4 py-2">Output descriptor that a fresh coinbase output address is derived from, instead of a fixed address. <br><br> The active address is shown on the dashboard, and the next derivation index is persisted across restarts.</td>
+                    </tr>"#,
+                    config
+                        .mining_server_config
+                        .coinbase_output_descriptor
+                        .as_ref()
+                        .map(|descriptor| format!(
+                            "{} (rotated per {:?})",
+                            descriptor, config.mining_server_config.payout_rotation
+                        ))
+                        .unwrap_or_else(|| "None".to_string())
+                ),
+                format!(
+                    r#"<tr class="hover:bg-gray-100">
+                        <td class="border px-4 py-2 font-bold">Coinbase Tag</td>
+                        <td class="border px-4 py-2">{}</td>
+                        <td class="border px-4 py-2">Tag placed in the extranonce prefix of every channel, followed by {} bytes of worker tag and {} bytes of channel counter. <br><br> The remaining {} bytes are rolled by miners on extended channels.</td>
+                    </tr>"#,
+                    config.mining_server_config.full_coinbase_tag(),
+                    config.mining_server_config.coinbase_worker_tag_size,
+                    config.mining_server_config.extranonce_counter_size,
+                    config.mining_server_config.rollable_extranonce_size()
+                ),
+                format!(
+                    r#"<tr class="hover:bg-gray-100">
+                        <td class="border px-4 py-2 font-bold">Coinbase OP_RETURN</td>
+                        <td class="border px-4 py-2">{}</td>
+                        <td class="border px-4 py-2">Zero-value OP_RETURN output added to the coinbase of every job, carrying up to 80 bytes of text or data.</td>
+                    </tr>"#,
+                    config
+                        .mining_server_config
+                        .coinbase_op_return
+                        .as_ref()
+                        .map(|script| script.to_asm_string())
+                        .unwrap_or_else(|| "None".to_string())
+                ),
                 // Template Distribution Config
                 format!(
                     r#"<tr class="hover:bg-gray-100">
@@ -46,6 +140,29 @@ pub async fn serve_config_htmx() -> Html<String> {
                     </tr>"#,
                     config.template_distribution_config.server_addr
                 ),
+                format!(
+                    r#"<tr class="hover:bg-gray-100">
+                        <td class="border px-4 py-2 font-bold">Fallback Template Distribution Servers</td>
+                        <td class="border px-4 py-2">{}</td>
+                        <td class="border px-4 py-2">Backup template distribution servers, in order of priority. <br><br> pleblottery switches to the next one when the active server disconnects or sends no templates for <code>silence_timeout</code> ({}), and back as soon as a server with a higher priority is reachable again.</td>
+                    </tr>"#,
+                    if config.template_distribution_config.fallback_server_addrs.is_empty() {
+                        "None".to_string()
+                    } else {
+                        config
+                            .template_distribution_config
+                            .fallback_server_addrs
+                            .iter()
+                            .map(|addr| addr.to_string())
+                            .collect::<Vec<_>>()
+                            .join("<br>")
+                    },
+                    config
+                        .template_distribution_config
+                        .silence_timeout
+                        .map(|timeout| format!("{} seconds", timeout))
+                        .unwrap_or_else(|| "disabled".to_string())
+                ),
                 format!(
                     r#"<tr class="hover:bg-gray-100">
                         <td class="border px-4 py-2 font-bold">Sv2 Template Distribution Server Public Key</td>
@@ -71,6 +188,98 @@ pub async fn serve_config_htmx() -> Html<String> {
     }
 }
 
+pub async fn get_network(State(shared_state): State<SharedStateHandle>) -> Html<String> {
+    let state = shared_state.read().await;
+    let network = state
+        .network
+        .map(network_name)
+        .unwrap_or("unknown")
+        .to_string();
+
+    match &state.network_mismatch {
+        Some(mismatch) => Html(format!(
+            r#"<h2 style="color: #E0474C">⛓️ Network: {} ⛓️</h2>
+            <span style="color: #E0474C">⚠️ The Template Provider is not serving {}: {} ⚠️</span>"#,
+            network, network, mismatch
+        )),
+        None => Html(format!(
+            r#"<h2>⛓️ Network: <span style="color: #D6AF46">{}</span> ⛓️</h2>"#,
+            network
+        )),
+    }
+}
+
+pub async fn get_template_provider(State(shared_state): State<SharedStateHandle>) -> Html<String> {
+    let state = shared_state.read().await;
+    let status = match state.template_provider_status {
+        TemplateProviderStatus::Connecting => "⏳ Connecting".to_string(),
+        TemplateProviderStatus::Connected => "✅ Connected".to_string(),
+        TemplateProviderStatus::Reconnecting => format!(
+            r#"<span style="color: #E0474C">🔄 Reconnecting (attempt {})</span>"#,
+            state.template_provider_reconnect_attempts
+        ),
+    };
+    Html(format!(
+        r#"
+            <tr>
+                <td>Active Provider</td>
+                <td>{}</td>
+            </tr>
+            <tr>
+                <td>Status</td>
+                <td>{}</td>
+            </tr>
+            <tr>
+                <td>Last Error</td>
+                <td>{}</td>
+            </tr>
+            <tr>
+                <td>Rejected Templates</td>
+                <td>{}</td>
+            </tr>"#,
+        state
+            .active_template_provider
+            .map(|provider| provider.to_string())
+            .unwrap_or_else(|| "None".to_string()),
+        status,
+        state
+            .template_provider_last_error
+            .as_deref()
+            .unwrap_or("None"),
+        match &state.last_rejected_template {
+            Some(last_rejected_template) => format!(
+                r#"<span style="color: #E0474C">⚠️ {} (last: {})</span>"#,
+                state.rejected_templates,
+                escape_html(last_rejected_template)
+            ),
+            None => "0".to_string(),
+        }
+    ))
+}
+
+pub async fn get_payout(State(shared_state): State<SharedStateHandle>) -> Html<String> {
+    let state = shared_state.read().await;
+    let mut rows = format!(
+        r#"
+            <tr>
+                <td>Payout Address</td>
+                <td>{}</td>
+            </tr>"#,
+        state.payout_address.as_deref().unwrap_or("unknown")
+    );
+    if let Some(index) = state.payout_derivation_index {
+        rows.push_str(&format!(
+            r#"
+            <tr>
+                <td>Derivation Index</td>
+                <td>{}</td>
+            </tr>"#,
+            index
+        ));
+    }
+    Html(rows)
+}
+
 pub async fn get_latest_template(State(shared_state): State<SharedStateHandle>) -> Html<String> {
     let state = shared_state.read().await;
     if let Some(template) = &state.latest_template {
@@ -170,12 +379,31 @@ pub async fn get_mining_stats(State(shared_state): State<SharedStateHandle>) ->
     let state = shared_state.read().await;
     let mut rows = String::new();
 
-    if let Some(_) = &state.latest_prev_hash {
+    if state.latest_prev_hash.is_some() {
         let hashrate_display = if state.total_clients == 0 {
             "0.00 h/s".to_string()
         } else {
             state.format_hashrate()
         };
+        let measured_hashrate = HashrateReport::new(
+            state.total_hashrate as f64,
+            Some(&state.measured_hashrate.total),
+            Instant::now(),
+        );
+        let rate_limited_shares = match &state.share_rate_limiter {
+            Some(share_rate_limiter) => {
+                let rate_limited_shares = share_rate_limiter.read().await.rate_limited_shares();
+                if rate_limited_shares > 0 {
+                    format!(
+                        r#"<span style="color: #E0474C">🚦 {}</span>"#,
+                        rate_limited_shares
+                    )
+                } else {
+                    "0".to_string()
+                }
+            }
+            None => "disabled".to_string(),
+        };
 
         rows.push_str(&format!(
             r#"
@@ -183,16 +411,28 @@ pub async fn get_mining_stats(State(shared_state): State<SharedStateHandle>) ->
                     <td>Total Clients</td>
                     <td>{}</td>
                 </tr>
+                <tr>
+                    <td>Rejected Channels</td>
+                    <td>{}</td>
+                </tr>
                 <tr>
                     <td>Total shares</td>
                     <td>{}</td>
                 </tr>
+                <tr>
+                    <td>Rate Limited Shares</td>
+                    <td>{}</td>
+                </tr>
                 <tr>
                     <td>Best Share</td>
                     <td>{}</td>
                 </tr>
                 <tr>
-                    <td>Total Hashrate</td>
+                    <td>Nominal Hashrate</td>
+                    <td>{}</td>
+                </tr>
+                <tr>
+                    <td>Measured Hashrate (1m / 5m / 1h / 24h)</td>
                     <td>{}</td>
                 </tr>
                 <tr>
@@ -201,9 +441,19 @@ pub async fn get_mining_stats(State(shared_state): State<SharedStateHandle>) ->
                 </tr>
             "#,
             state.total_clients,
+            match &state.last_rejected_channel_open {
+                Some(last_rejected_channel_open) => format!(
+                    r#"<span style="color: #E0474C">🚫 {} (last: {})</span>"#,
+                    state.rejected_channel_opens,
+                    escape_html(last_rejected_channel_open)
+                ),
+                None => "0".to_string(),
+            },
             state.total_shares_submitted,
+            rate_limited_shares,
             state.format_best_share(),
             hashrate_display,
+            measured_hashrate.format_measured(),
             state.blocks_found
         ));
     } else {
@@ -217,14 +467,439 @@ pub async fn get_mining_stats(State(shared_state): State<SharedStateHandle>) ->
     Html(rows)
 }
 
+pub async fn get_block_submissions(State(shared_state): State<SharedStateHandle>) -> Html<String> {
+    let state = shared_state.read().await;
+    if state.block_submissions.is_empty() {
+        return Html(
+            r#"<tr>
+                <td colspan="2">No blocks submitted yet</td>
+            </tr>"#
+                .to_string(),
+        );
+    }
+
+    let rows = state
+        .block_submissions
+        .iter()
+        .rev()
+        .map(|submission| {
+            let result = match &submission.result {
+                SubmissionResult::Queued => format!("⏳ {}", submission.result),
+                SubmissionResult::Accepted => format!("✅ {}", submission.result),
+                SubmissionResult::Rejected(_) | SubmissionResult::Failed(_) => format!(
+                    r#"<span style="color: #E0474C">❌ {}</span>"#,
+                    submission.result
+                ),
+            };
+            format!(
+                r#"
+            <tr>
+                <td>{} via {}</td>
+                <td>{}</td>
+            </tr>"#,
+                submission
+                    .block_hash
+                    .map(|block_hash| block_hash.to_string())
+                    .unwrap_or_else(|| format!("Template {}", submission.template_id)),
+                submission.path,
+                result
+            )
+        })
+        .collect::<String>();
+    Html(rows)
+}
+
+/// Escapes text sent by miners, such as `user_identity`, before it is embedded in HTML.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn format_found_block_status(status: FoundBlockStatus) -> String {
+    match status {
+        FoundBlockStatus::Pending => format!("⏳ {}", status),
+        FoundBlockStatus::Accepted { .. } => format!("✅ {}", status),
+        FoundBlockStatus::Stale => format!("⚠️ {}", status),
+        FoundBlockStatus::Orphaned => {
+            format!(r#"<span style="color: #E0474C">❌ {}</span>"#, status)
+        }
+        FoundBlockStatus::Unknown => format!("❔ {}", status),
+    }
+}
+
+/// Status of `found_block`, followed by a warning if it failed verification before submission.
+fn format_found_block(state: &SharedState, found_block: &FoundBlock) -> String {
+    let status = format_found_block_status(
+        state
+            .found_block_statuses
+            .get(&found_block.block_hash)
+            .copied()
+            .unwrap_or_default(),
+    );
+    match &found_block.verification_error {
+        Some(verification_error) => format!(
+            r#"{}<br><span style="color: #E0474C">⚠️ failed verification: {}</span>"#,
+            status,
+            escape_html(verification_error)
+        ),
+        None => status,
+    }
+}
+
+pub async fn get_found_block_statuses(
+    State(shared_state): State<SharedStateHandle>,
+) -> Html<String> {
+    let state = shared_state.read().await;
+    if state.found_blocks.is_empty() {
+        return Html(
+            r#"<tr>
+                <td colspan="2">No blocks found yet</td>
+            </tr>"#
+                .to_string(),
+        );
+    }
+
+    let rows = state
+        .found_blocks
+        .iter()
+        .rev()
+        .map(|found_block| {
+            format!(
+                r#"
+            <tr>
+                <td>{} at height {}</td>
+                <td>{}</td>
+            </tr>"#,
+                found_block.block_hash,
+                found_block.height,
+                format_found_block(&state, found_block)
+            )
+        })
+        .collect::<String>();
+    Html(rows)
+}
+
+pub async fn get_found_blocks(State(shared_state): State<SharedStateHandle>) -> Html<String> {
+    let state = shared_state.read().await;
+    if state.found_blocks.is_empty() {
+        return Html(
+            r#"<tr>
+                <td colspan="7">No blocks found yet</td>
+            </tr>"#
+                .to_string(),
+        );
+    }
+
+    let rows = state
+        .found_blocks
+        .iter()
+        .rev()
+        .map(|found_block| {
+            format!(
+                r#"
+            <tr>
+                <td>{}</td>
+                <td>{}<br><br>{}</td>
+                <td>version {:08x} | time {} | bits {:08x} | nonce {:08x}<br><br>prev hash {}<br>merkle root {}</td>
+                <td>{}</td>
+                <td>{}<br><br>channel {} of client {}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>
+            <tr>
+                <td colspan="7"><details><summary>Coinbase Transaction</summary><code style="word-break: break-all">{}</code></details></td>
+            </tr>"#,
+                found_block.height,
+                found_block.block_hash,
+                found_block
+                    .template_id
+                    .map(|template_id| format!("template {}", template_id))
+                    .unwrap_or_else(|| "custom job".to_string()),
+                found_block.version,
+                found_block.time,
+                found_block.bits,
+                found_block.nonce,
+                found_block.prev_hash,
+                found_block.merkle_root,
+                Amount::from_sat(found_block.reward),
+                escape_html(&found_block.user_identity),
+                found_block.channel_id,
+                found_block.client_id,
+                found_block.timestamp,
+                format_found_block(&state, found_block),
+                found_block.coinbase_tx,
+            )
+        })
+        .collect::<String>();
+    Html(rows)
+}
+
+pub async fn get_found_blocks_json(
+    State(shared_state): State<SharedStateHandle>,
+) -> Json<Vec<FoundBlock>> {
+    Json(shared_state.read().await.found_blocks.clone())
+}
+
+/// Bans that didn't expire yet.
+async fn active_bans(shared_state: &SharedStateHandle) -> Vec<Ban> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    shared_state
+        .read()
+        .await
+        .bans
+        .iter()
+        .filter(|ban| ban.expires_at > now)
+        .cloned()
+        .collect()
+}
+
+pub async fn get_bans(State(shared_state): State<SharedStateHandle>) -> Html<String> {
+    let bans = active_bans(&shared_state).await;
+    if bans.is_empty() {
+        return Html(
+            r#"<tr>
+                <td colspan="4">No bans</td>
+            </tr>"#
+                .to_string(),
+        );
+    }
+
+    let rows = bans
+        .iter()
+        .map(|ban| {
+            format!(
+                r#"
+            <tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+                escape_html(&ban.target.to_string()),
+                ban.reason,
+                ban.banned_at,
+                ban.expires_at
+            )
+        })
+        .collect::<String>();
+    Html(rows)
+}
+
+pub async fn get_bans_json(State(shared_state): State<SharedStateHandle>) -> Json<Vec<Ban>> {
+    Json(active_bans(&shared_state).await)
+}
+
+/// Nominal and measured hashrate of an open channel.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelHashrate {
+    pub client_id: u32,
+    pub channel_id: u32,
+    pub user_identity: String,
+    pub hashrate: HashrateReport,
+}
+
+/// Nominal and measured hashrate of the mining server, as served by `/api/hashrate.json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct HashrateSummary {
+    pub total: HashrateReport,
+    pub clients: BTreeMap<u32, HashrateReport>,
+    pub channels: Vec<ChannelHashrate>,
+    pub workers: BTreeMap<String, HashrateReport>, // by user_identity
+}
+
+/// Collects the hashrate of every open channel, client and worker. The shared state is released
+/// before channels are locked, as share handling locks them the other way around.
+async fn hashrate_summary(shared_state: &SharedStateHandle) -> HashrateSummary {
+    let (clients, measured, total_nominal) = {
+        let state = shared_state.read().await;
+        (
+            state.clients.clone(),
+            state.measured_hashrate.clone(),
+            state.total_hashrate as f64,
+        )
+    };
+    let clients: Vec<_> = clients
+        .read()
+        .await
+        .iter()
+        .map(|(client_id, client)| (*client_id, client.clone()))
+        .collect();
+
+    let mut open_channels = Vec::new();
+    let mut client_ids = Vec::new();
+    for (client_id, client) in clients {
+        client_ids.push(client_id);
+        let (standard_channels, extended_channels) = {
+            let client_guard = client.read().await;
+            let standard_channels: Vec<_> = client_guard
+                .standard_channels
+                .read()
+                .await
+                .iter()
+                .map(|(channel_id, channel)| (*channel_id, channel.clone()))
+                .collect();
+            let extended_channels: Vec<_> = client_guard
+                .extended_channels
+                .read()
+                .await
+                .iter()
+                .map(|(channel_id, channel)| (*channel_id, channel.clone()))
+                .collect();
+            (standard_channels, extended_channels)
+        };
+        for (channel_id, channel) in standard_channels {
+            let channel = channel.read().await;
+            open_channels.push((
+                client_id,
+                channel_id,
+                channel.get_user_identity().to_string(),
+                channel.get_nominal_hashrate() as f64,
+            ));
+        }
+        for (channel_id, channel) in extended_channels {
+            let channel = channel.read().await;
+            open_channels.push((
+                client_id,
+                channel_id,
+                channel.get_user_identity().to_string(),
+                channel.get_nominal_hashrate() as f64,
+            ));
+        }
+    }
+    open_channels.sort_by_key(|(client_id, channel_id, _, _)| (*client_id, *channel_id));
+
+    let mut client_nominal: BTreeMap<u32, f64> = client_ids
+        .into_iter()
+        .map(|client_id| (client_id, 0.0))
+        .collect();
+    // workers that disconnected still count towards the measured hashrate of their windows
+    let mut worker_nominal: BTreeMap<String, f64> = measured
+        .workers
+        .keys()
+        .map(|user_identity| (user_identity.clone(), 0.0))
+        .collect();
+    for (client_id, _, user_identity, nominal) in &open_channels {
+        *client_nominal.entry(*client_id).or_default() += nominal;
+        *worker_nominal.entry(user_identity.clone()).or_default() += nominal;
+    }
+
+    let now = Instant::now();
+    HashrateSummary {
+        total: HashrateReport::new(total_nominal, Some(&measured.total), now),
+        clients: client_nominal
+            .into_iter()
+            .map(|(client_id, nominal)| {
+                (
+                    client_id,
+                    HashrateReport::new(nominal, measured.clients.get(&client_id), now),
+                )
+            })
+            .collect(),
+        channels: open_channels
+            .into_iter()
+            .map(
+                |(client_id, channel_id, user_identity, nominal)| ChannelHashrate {
+                    client_id,
+                    channel_id,
+                    hashrate: HashrateReport::new(
+                        nominal,
+                        measured.channels.get(&(client_id, channel_id)),
+                        now,
+                    ),
+                    user_identity,
+                },
+            )
+            .collect(),
+        workers: worker_nominal
+            .into_iter()
+            .map(|(user_identity, nominal)| {
+                let report =
+                    HashrateReport::new(nominal, measured.workers.get(&user_identity), now);
+                (user_identity, report)
+            })
+            .collect(),
+    }
+}
+
+pub async fn get_hashrate_json(
+    State(shared_state): State<SharedStateHandle>,
+) -> Json<HashrateSummary> {
+    Json(hashrate_summary(&shared_state).await)
+}
+
+pub async fn get_workers(State(shared_state): State<SharedStateHandle>) -> Html<String> {
+    let summary = hashrate_summary(&shared_state).await;
+    if summary.workers.is_empty() {
+        return Html(
+            r#"<tr>
+                <td colspan="3">No workers yet</td>
+            </tr>"#
+                .to_string(),
+        );
+    }
+
+    let rows = summary
+        .workers
+        .iter()
+        .map(|(user_identity, hashrate)| {
+            format!(
+                r#"
+            <tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+                escape_html(user_identity),
+                format_hashrate(hashrate.nominal),
+                hashrate.format_measured()
+            )
+        })
+        .collect::<String>();
+    Html(rows)
+}
+
 pub async fn get_clients_stats(State(shared_state): State<SharedStateHandle>) -> Html<String> {
+    let summary = hashrate_summary(&shared_state).await;
     let state = shared_state.read().await;
     let mut rows = String::new();
 
-    if state.clients.read().await.len() > 0 as usize {
+    if !state.clients.read().await.is_empty() {
         let clients = state.clients.read().await;
         for (_, client) in clients.iter() {
             let client = client.read().await;
+            let client_hashrate = summary.clients.get(&client.client_id);
+            let rate_limited_shares = match &state.share_rate_limiter {
+                Some(share_rate_limiter) => share_rate_limiter
+                    .read()
+                    .await
+                    .client_rate_limited_shares(client.client_id)
+                    .to_string(),
+                None => "disabled".to_string(),
+            };
+            let channel_rows = summary
+                .channels
+                .iter()
+                .filter(|channel| channel.client_id == client.client_id)
+                .map(|channel| {
+                    format!(
+                        r#"
+                            <tr>
+                                <td>Channel {} ({})</td>
+                                <td>{} nominal | {}</td>
+                            </tr>"#,
+                        channel.channel_id,
+                        escape_html(&channel.user_identity),
+                        format_hashrate(channel.hashrate.nominal),
+                        channel.hashrate.format_measured()
+                    )
+                })
+                .collect::<String>();
             rows.push_str(&format!(
                 r#"
                 <div>
@@ -255,6 +930,18 @@ pub async fn get_clients_stats(State(shared_state): State<SharedStateHandle>) ->
                                 <td>Extended Channels</td>
                                 <td>{}</td>
                             </tr>
+                            <tr>
+                                <td>Nominal Hashrate</td>
+                                <td>{}</td>
+                            </tr>
+                            <tr>
+                                <td>Measured Hashrate (1m / 5m / 1h / 24h)</td>
+                                <td>{}</td>
+                            </tr>
+                            <tr>
+                                <td>Rate Limited Shares</td>
+                                <td>{}</td>
+                            </tr>{}
                         </tbody>
                     </table>
                 </div>
@@ -262,13 +949,19 @@ pub async fn get_clients_stats(State(shared_state): State<SharedStateHandle>) ->
                 client.client_id,
                 client.client_id,
                 client.connection_flags,
-                client
-                    .group_channel
-                    .is_some()
-                    .then(|| "Yes")
-                    .unwrap_or("No"),
+                if client.group_channel.is_some() {
+                    "Yes"
+                } else {
+                    "No"
+                },
                 client.standard_channels.read().await.len(),
-                client.extended_channels.read().await.len()
+                client.extended_channels.read().await.len(),
+                format_hashrate(client_hashrate.map_or(0.0, |hashrate| hashrate.nominal)),
+                client_hashrate
+                    .map(|hashrate| hashrate.format_measured())
+                    .unwrap_or_default(),
+                rate_limited_shares,
+                channel_rows
             ));
         }
     }
@@ -279,6 +972,12 @@ pub async fn get_clients_stats(State(shared_state): State<SharedStateHandle>) ->
 pub fn api_routes(shared_state: SharedStateHandle) -> Router {
     Router::new()
         .route("/api/config", axum::routing::get(serve_config_htmx))
+        .route("/api/network", axum::routing::get(get_network))
+        .route("/api/payout", axum::routing::get(get_payout))
+        .route(
+            "/api/template-provider",
+            axum::routing::get(get_template_provider),
+        )
         .route(
             "/api/latest-template",
             axum::routing::get(get_latest_template),
@@ -289,5 +988,22 @@ pub fn api_routes(shared_state: SharedStateHandle) -> Router {
         )
         .route("/api/mining-stats", axum::routing::get(get_mining_stats))
         .route("/api/clients", axum::routing::get(get_clients_stats))
+        .route("/api/workers", axum::routing::get(get_workers))
+        .route("/api/bans", axum::routing::get(get_bans))
+        .route("/api/bans.json", axum::routing::get(get_bans_json))
+        .route("/api/hashrate.json", axum::routing::get(get_hashrate_json))
+        .route(
+            "/api/block-submissions",
+            axum::routing::get(get_block_submissions),
+        )
+        .route("/api/found-blocks", axum::routing::get(get_found_blocks))
+        .route(
+            "/api/found-block-statuses",
+            axum::routing::get(get_found_block_statuses),
+        )
+        .route(
+            "/api/found-blocks.json",
+            axum::routing::get(get_found_blocks_json),
+        )
         .with_state(shared_state)
 }
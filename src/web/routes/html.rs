@@ -35,6 +35,8 @@ pub async fn serve_index() -> Html<&'static str> {
             <br>
             <a href="/config">Configuration</a>
             <br>
+            <a href="/blocks">Found Blocks</a>
+            <br>
             <a href="https://github.com/vinteumorg/pleblottery">Source Code</a>
             <br><br>
             <hr>
@@ -114,6 +116,74 @@ pub async fn serve_config_html() -> Html<&'static str> {
     )
 }
 
+// Serve the HTML page for /blocks
+pub async fn serve_blocks_html() -> Html<&'static str> {
+    Html(
+        r#"
+    <!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta charset="UTF-8">
+        <meta name="viewport" content="width=device-width, initial-scale=1.0">
+        <title>pleblottery - Found Blocks</title>
+        <style type="text/css">
+            .tg {border-collapse:collapse;border-spacing:0;}
+            .tg td{border-color:white;border-style:solid;border-width:1px;font-family:Comic Sans MS, sans-serif;font-size:14px;
+                overflow:hidden;padding:10px 5px;word-break:normal;text-align:center;}
+            .tg th{border-color:white;border-style:solid;border-width:1px;font-family:Comic Sans MS, sans-serif;font-size:14px;
+                font-weight:normal;overflow:hidden;padding:10px 5px;word-break:normal;text-align:center;}
+            .tb {}
+            .tb td{border-width: 0}
+            body {background-color:#051426;color:white;font-family:Comic Sans MS, sans-serif;}
+            a {color:white}
+        </style>
+        <script src="https://unpkg.com/htmx.org"></script>
+    </head>
+    <body>
+        <center>
+            <div style="background-color:#051426;color:white;"> 
+                <br>
+                <b><span style="color: #3CAD65">$</span> pleblottery <span style="color: #D6AF46">#</span></b>
+                <br><br>
+            </div>
+            <br>
+            <a href="/">Home</a>
+            <br><br>
+            <hr>
+            <br>
+            <div id="found-blocks-container" class="mt-4">
+                <table class="tg">
+                    <thead>
+                        <tr>
+                            <th><b>Height</b></th>
+                            <th><b>Block Hash</b></th>
+                            <th><b>Header</b></th>
+                            <th><b>Reward</b></th>
+                            <th><b>Found By</b></th>
+                            <th><b>Found At (Unix Time)</b></th>
+                            <th><b>Status</b></th>
+                        </tr>
+                    </thead>
+                    <tbody hx-get="/api/found-blocks" hx-trigger="load, every 10s" hx-target="this">
+                        <!-- Rows will be dynamically loaded here -->
+                    </tbody>
+                </table>
+                <br>
+                Every block found is kept in <code>found_blocks_file</code>, and also available as JSON at <a href="/api/found-blocks.json">/api/found-blocks.json</a>.
+                <br>
+            </div>
+            <br>
+            <hr>
+            <br>
+             ⛏️ plebs be hashin ⚡
+            <br><br>
+        </center>
+    </body>
+    </html>
+    "#,
+    )
+}
+
 pub async fn serve_dashboard_html() -> Html<&'static str> {
     Html(
         r#"
@@ -246,6 +316,25 @@ pub async fn serve_dashboard_html() -> Html<&'static str> {
         <a href="/">Home</a>
         <br><br>
         <hr>
+        <div id="network-container" hx-get="/api/network" hx-trigger="load, every 2s" hx-target="this" hx-swap="innerHTML">
+            <h2>⛓️ Network: Loading... ⛓️</h2>
+        </div>
+        <br>
+        <div id="template-provider-container" class="responsive-table">
+            <table class="tg">
+                <thead>
+                    <tr>
+                        <th colspan="2">Template Provider</th>
+                    </tr>
+                </thead>
+                <tbody hx-get="/api/template-provider" hx-trigger="load, every 2s" hx-target="this" hx-swap="innerHTML">
+                    <tr>
+                        <td>Status</td>
+                        <td>Loading ...</td>
+                    </tr>
+                </tbody>
+            </table>
+        </div>
         <br>
         <div class="table-container">
             <div id="block-height-container" class="responsive-table">
@@ -318,7 +407,11 @@ pub async fn serve_dashboard_html() -> Html<&'static str> {
                         <td>Loading ...</td>
                     </tr>
                     <tr>
-                        <td>Total Hashrate</td>
+                        <td>Nominal Hashrate</td>
+                        <td>Loading ...</td>
+                    </tr>
+                    <tr>
+                        <td>Measured Hashrate (1m / 5m / 1h / 24h)</td>
                         <td>Loading ...</td>
                     </tr>
                     <tr>
@@ -329,6 +422,87 @@ pub async fn serve_dashboard_html() -> Html<&'static str> {
             </table>
         </div>
         <br><br>
+        <div id="payout-container" class="responsive-table">
+            <table class="tg">
+                <thead>
+                    <tr>
+                        <th colspan="2">Payout</th>
+                    </tr>
+                </thead>
+                <tbody hx-get="/api/payout" hx-trigger="load, every 2s" hx-target="this" hx-swap="innerHTML">
+                    <tr>
+                        <td>Payout Address</td>
+                        <td>Loading ...</td>
+                    </tr>
+                </tbody>
+            </table>
+        </div>
+        <br><br>
+        <div id="found-blocks-container" class="responsive-table">
+            <table class="tg">
+                <thead>
+                    <tr>
+                        <th colspan="2"><a href="/blocks">Found Blocks</a></th>
+                    </tr>
+                </thead>
+                <tbody hx-get="/api/found-block-statuses" hx-trigger="load, every 2s" hx-target="this" hx-swap="innerHTML">
+                    <tr>
+                        <td colspan="2">Loading ...</td>
+                    </tr>
+                </tbody>
+            </table>
+        </div>
+        <br><br>
+        <div id="block-submissions-container" class="responsive-table">
+            <table class="tg">
+                <thead>
+                    <tr>
+                        <th colspan="2">Block Submissions</th>
+                    </tr>
+                </thead>
+                <tbody hx-get="/api/block-submissions" hx-trigger="load, every 2s" hx-target="this" hx-swap="innerHTML">
+                    <tr>
+                        <td colspan="2">Loading ...</td>
+                    </tr>
+                </tbody>
+            </table>
+        </div>
+        <br><br>
+        <div id="workers-container" class="responsive-table">
+            <table class="tg">
+                <thead>
+                    <tr>
+                        <th>Worker</th>
+                        <th>Nominal Hashrate</th>
+                        <th>Measured Hashrate (1m / 5m / 1h / 24h)</th>
+                    </tr>
+                </thead>
+                <tbody hx-get="/api/workers" hx-trigger="load, every 2s" hx-target="this" hx-swap="innerHTML">
+                    <tr>
+                        <td colspan="3">Loading ...</td>
+                    </tr>
+                </tbody>
+            </table>
+        </div>
+        <br><br>
+        <div id="bans-container" class="responsive-table">
+            <table class="tg">
+                <thead>
+                    <tr>
+                        <th>Banned</th>
+                        <th>Reason</th>
+                        <th>Since (Unix Time)</th>
+                        <th>Until (Unix Time)</th>
+                    </tr>
+                </thead>
+                <tbody hx-get="/api/bans" hx-trigger="load, every 2s" hx-target="this" hx-swap="innerHTML">
+                    <tr>
+                        <td colspan="4">Loading ...</td>
+                    </tr>
+                </tbody>
+            </table>
+        </div>
+        <br><br>
         <div id="clients-container" hx-get="/api/clients" hx-trigger="every 2s" hx-target="this" hx-swap="innerHTML">
             <!-- Client tables will be dynamically loaded here -->
         </div>
@@ -350,5 +524,6 @@ pub fn html_routes() -> Router {
     Router::new()
         .route("/", axum::routing::get(serve_index))
         .route("/config", axum::routing::get(serve_config_html))
+        .route("/blocks", axum::routing::get(serve_blocks_html))
         .route("/dashboard", axum::routing::get(serve_dashboard_html))
 }
@@ -1,15 +1,23 @@
+use axum::{extract::State, routing::post, Json, Router};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::Block;
 use once_cell::sync::Lazy;
+use serde_json::{json, Value};
 use std::{
     collections::HashSet,
     net::{SocketAddr, TcpListener},
     str::FromStr,
-    sync::Mutex,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use sv2_cpu_miner::config::Sv2CpuMinerConfig;
 
-use bitcoin::Address;
+use bitcoin::{Address, Network};
 use pleblottery::config::{
-    PlebLotteryMiningServerConfig, PlebLotteryTemplateDistributionClientConfig,
+    LimitsConfig, PayoutRotation, PlebLotteryMiningServerConfig,
+    PlebLotteryTemplateDistributionClientConfig, TemplateSanityConfig, UserIdentityPayoutMode,
+    VardiffConfig,
 };
 use pleblottery::config::{PlebLotteryWebConfig, PleblotteryConfig};
 
@@ -21,7 +29,7 @@ fn get_available_address() -> SocketAddr {
     SocketAddr::from(([127, 0, 0, 1], port))
 }
 
-fn get_available_port() -> u16 {
+pub fn get_available_port() -> u16 {
     let mut unique_ports = UNIQUE_PORTS.lock().unwrap();
 
     loop {
@@ -52,20 +60,47 @@ pub fn load_config() -> PleblotteryConfig {
                 .expect("Invalid private key"),
             cert_validity: 3600,
             inactivity_limit: 3600,
+            network: Network::Regtest,
             coinbase_output_script: Address::from_str(
                 "bcrt1q2nfxmhd4n3c8834pj72xagvyr9gl57n5r94fsl",
             )
             .unwrap()
             .assume_checked()
             .script_pubkey(),
+            coinbase_output_descriptor: None,
+            payout_rotation: PayoutRotation::BlockFound,
+            payout_index_file: "payout_index".into(),
+            // every test gets a journal of its own
+            found_blocks_file: std::env::temp_dir().join(format!(
+                "pleblottery-found-blocks-{}-{}.jsonl",
+                std::process::id(),
+                mining_server_available_addr.port()
+            )),
+            user_identity_payout: UserIdentityPayoutMode::Disabled,
+            coinbase_output_splits: vec![],
+            coinbase_op_return: None,
             coinbase_tag: "pleblottery".to_string(),
+            coinbase_worker_tag_size: 0,
+            extranonce_counter_size: 8,
             share_batch_size: 10,
             expected_shares_per_minute: 1.0,
+            template_sanity: TemplateSanityConfig::default(),
+            vardiff: VardiffConfig::default(),
+            sv1: None,
+            access: None,
+            ban: None,
+            limits: LimitsConfig::default(),
+            share_rate_limit: None,
         },
         template_distribution_config: PlebLotteryTemplateDistributionClientConfig {
             server_addr: "127.0.0.1:8442".parse().expect("Invalid server address"),
+            fallback_server_addrs: vec![],
             auth_pk: None,
+            silence_timeout: None,
+            reconnect_min_delay: 1,
+            reconnect_max_delay: 60,
         },
+        bitcoin_rpc_config: None,
         web_config: PlebLotteryWebConfig {
             listening_port: web_server_available_addr.port(),
         },
@@ -90,3 +125,84 @@ pub fn load_miner_config() -> Sv2CpuMinerConfig {
         nominal_hashrate_multiplier: 1.0,
     }
 }
+
+#[allow(dead_code)]
+pub type SubmittedBlocks = Arc<Mutex<Vec<String>>>;
+
+/// Answers `getblocktemplate` with an empty regtest block on top of the genesis block, and
+/// records every `submitblock`.
+#[allow(dead_code)]
+async fn mock_bitcoind(
+    State(submitted_blocks): State<SubmittedBlocks>,
+    Json(request): Json<Value>,
+) -> Json<Value> {
+    let result = match request["method"].as_str() {
+        Some("getblocktemplate") => json!({
+            "version": 0x2000_0000,
+            "previousblockhash": genesis_block(Network::Regtest).block_hash().to_string(),
+            "transactions": [],
+            "coinbasevalue": 5_000_000_000u64,
+            "curtime": SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            "bits": "207fffff",
+            "height": 1,
+            "default_witness_commitment": "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9",
+        }),
+        Some("getblockhash") => {
+            assert_eq!(request["params"][0], 0);
+            json!(genesis_block(Network::Regtest).block_hash().to_string())
+        }
+        Some("submitblock") => {
+            let block = request["params"][0].as_str().unwrap().to_string();
+            submitted_blocks.lock().unwrap().push(block);
+            Value::Null
+        }
+        // submitted blocks are at the tip of the chain
+        Some("getblockheader") => {
+            let block_hash = request["params"][0].as_str().unwrap();
+            let submitted = submitted_blocks.lock().unwrap().iter().any(|block_hex| {
+                deserialize_hex::<Block>(block_hex)
+                    .unwrap()
+                    .block_hash()
+                    .to_string()
+                    == block_hash
+            });
+            if !submitted {
+                return Json(json!({
+                    "result": null,
+                    "error": { "code": -5, "message": "Block not found" },
+                    "id": request["id"],
+                }));
+            }
+            json!({ "hash": block_hash, "confirmations": 1 })
+        }
+        _ => panic!("unexpected RPC request {}", request),
+    };
+    Json(json!({ "result": result, "error": null, "id": request["id"] }))
+}
+
+#[allow(dead_code)]
+pub async fn start_mock_bitcoind() -> (SocketAddr, SubmittedBlocks) {
+    let submitted_blocks = SubmittedBlocks::default();
+    let app = Router::new()
+        .route("/", post(mock_bitcoind))
+        .with_state(submitted_blocks.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (address, submitted_blocks)
+}
+
+#[allow(dead_code)]
+pub async fn wait_for_submitted_block(submitted_blocks: &SubmittedBlocks) -> Block {
+    let block_hex = tokio::time::timeout(Duration::from_secs(60), async {
+        loop {
+            if let Some(block_hex) = submitted_blocks.lock().unwrap().first().cloned() {
+                return block_hex;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("pleblottery never submitted a block");
+    deserialize_hex(&block_hex).unwrap()
+}
@@ -1,7 +1,14 @@
 use std::vec;
 
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
 use integration_tests_sv2::*;
-use pleblottery::{service::PlebLotteryService, state::SharedStateHandle};
+use pleblottery::{
+    config::{BanConfig, LimitsConfig},
+    service::PlebLotteryService,
+    state::SharedStateHandle,
+};
 use sv2_services::roles_logic_sv2::{
     common_messages_sv2::{MESSAGE_TYPE_SETUP_CONNECTION, MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS},
     mining_sv2::{
@@ -108,6 +115,99 @@ async fn test_connection_with_sv2_minig_device() {
     pleblottery_service.shutdown().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_connection_with_sv2_minig_device_behind_gate() {
+    start_tracing();
+    let (_tp, tp_address) = start_template_provider(None);
+
+    let mut config = load_config();
+    config.template_distribution_config.server_addr = tp_address;
+    // bans need the addresses of Stratum V2 connections, so miners connect through the gate
+    config.mining_server_config.ban = Some(BanConfig::default());
+
+    let shared_state: SharedStateHandle = SharedStateHandle::default();
+
+    let mut pleblottery_service = PlebLotteryService::new(
+        config.mining_server_config.clone(),
+        config.template_distribution_config.clone(),
+        shared_state,
+    )
+    .await
+    .expect("Failed to create PlebLotteryService");
+
+    let mut pleblottery_service_clone = pleblottery_service.clone();
+    tokio::spawn(async move {
+        pleblottery_service_clone.start().await.unwrap();
+    });
+
+    // wait for the service to start
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let pleblottery_address = format!("0.0.0.0:{}", config.mining_server_config.listening_port);
+
+    let (sniffer, sniffer_address) = start_sniffer(
+        "sv2_device gate",
+        pleblottery_address.parse().unwrap(),
+        false,
+        vec![],
+    );
+
+    let mut miner_config = load_miner_config();
+    miner_config.server_addr = sniffer_address;
+    miner_config.n_extended_channels = 0;
+    tokio::spawn(async move {
+        sv2_cpu_miner::client::Sv2CpuMiner::new(miner_config)
+            .await
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+    });
+
+    sniffer
+        .wait_for_message_type(
+            interceptor::MessageDirection::ToUpstream,
+            MESSAGE_TYPE_SETUP_CONNECTION,
+        )
+        .await;
+    sniffer
+        .wait_for_message_type(
+            interceptor::MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS,
+        )
+        .await;
+
+    sniffer
+        .wait_for_message_type(
+            interceptor::MessageDirection::ToUpstream,
+            MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL,
+        )
+        .await;
+
+    sniffer
+        .wait_for_message_type(
+            interceptor::MessageDirection::ToDownstream,
+            MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS,
+        )
+        .await;
+
+    sniffer
+        .wait_for_message_type(
+            interceptor::MessageDirection::ToDownstream,
+            MESSAGE_TYPE_NEW_MINING_JOB,
+        )
+        .await;
+
+    sniffer
+        .wait_for_message_type(
+            interceptor::MessageDirection::ToDownstream,
+            MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH,
+        )
+        .await;
+
+    pleblottery_service.shutdown().await.unwrap();
+}
+
 #[tokio::test]
 async fn test_connection_with_sv2_minig_device_with_extended_channel() {
     start_tracing();
@@ -376,3 +476,49 @@ async fn test_connection_with_sv2_minig_device_when_tp_not_send_new_prev_hash()
 
     pleblottery_service.shutdown().await.unwrap();
 }
+
+#[tokio::test]
+async fn test_sv2_connection_limit() {
+    start_tracing();
+    let (_tp, tp_address) = start_template_provider(None);
+
+    let mut config = load_config();
+    config.template_distribution_config.server_addr = tp_address;
+    config.mining_server_config.limits = LimitsConfig {
+        max_connections_per_ip: Some(1),
+        ..LimitsConfig::default()
+    };
+
+    let shared_state: SharedStateHandle = SharedStateHandle::default();
+
+    let mut pleblottery_service = PlebLotteryService::new(
+        config.mining_server_config.clone(),
+        config.template_distribution_config.clone(),
+        shared_state,
+    )
+    .await
+    .expect("Failed to create PlebLotteryService");
+
+    let mut pleblottery_service_clone = pleblottery_service.clone();
+    tokio::spawn(async move {
+        pleblottery_service_clone.start().await.unwrap();
+    });
+
+    // wait for the service to start
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let pleblottery_address = format!("127.0.0.1:{}", config.mining_server_config.listening_port);
+
+    // a connection still in its handshake counts against the limit too
+    let _first = TcpStream::connect(&pleblottery_address).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut second = TcpStream::connect(&pleblottery_address).await.unwrap();
+    let mut buffer = [0u8; 1];
+    let read = tokio::time::timeout(std::time::Duration::from_secs(30), second.read(&mut buffer))
+        .await
+        .expect("pleblottery must close the connection");
+    assert!(!matches!(read, Ok(n) if n > 0));
+
+    pleblottery_service.shutdown().await.unwrap();
+}
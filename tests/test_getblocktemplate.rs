@@ -0,0 +1,130 @@
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::Network;
+use pleblottery::block_journal::BlockJournal;
+use pleblottery::config::PlebLotteryBitcoinRpcConfig;
+use pleblottery::state::TemplateProviderStatus;
+use pleblottery::{service::PlebLotteryService, state::SharedStateHandle};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+mod common;
+use common::{load_config, load_miner_config, start_mock_bitcoind, wait_for_submitted_block};
+
+/// Integration test to verify that pleblottery mines on templates from `getblocktemplate` and
+/// submits found blocks via `submitblock`.
+#[tokio::test]
+async fn test_getblocktemplate() {
+    let (bitcoind_address, submitted_blocks) = start_mock_bitcoind().await;
+
+    let mut config = load_config();
+    // Set a high expected shares per minute to ensure we can submit shares quickly
+    config.mining_server_config.expected_shares_per_minute = 100.0;
+    let found_blocks_file = config.mining_server_config.found_blocks_file.clone();
+    let bitcoin_rpc_config = PlebLotteryBitcoinRpcConfig {
+        url: format!("http://{}", bitcoind_address),
+        rpc_user: Some("pleb".to_string()),
+        rpc_password: Some("pleb".to_string()),
+        cookie_file: None,
+        getblocktemplate: true,
+        poll_interval: 1,
+    };
+
+    let shared_state: SharedStateHandle = SharedStateHandle::default();
+
+    let mut pleblottery_service = PlebLotteryService::new_with_getblocktemplate(
+        config.mining_server_config.clone(),
+        bitcoin_rpc_config,
+        shared_state.clone(),
+    )
+    .await
+    .unwrap();
+
+    let mut pleblottery_service_clone = pleblottery_service.clone();
+    tokio::spawn(async move {
+        pleblottery_service_clone.start().await.unwrap();
+    });
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while shared_state.read().await.template_provider_status
+            != TemplateProviderStatus::Connected
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("pleblottery never got a block template");
+
+    let mut miner_config = load_miner_config();
+    miner_config.server_addr =
+        SocketAddr::from(([127, 0, 0, 1], config.mining_server_config.listening_port));
+    miner_config.n_extended_channels = 0;
+    tokio::spawn(async move {
+        sv2_cpu_miner::client::Sv2CpuMiner::new(miner_config)
+            .await
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+    });
+
+    let block = wait_for_submitted_block(&submitted_blocks).await;
+    assert_eq!(
+        block.header.prev_blockhash,
+        genesis_block(Network::Regtest).block_hash()
+    );
+    assert!(block.header.validate_pow(block.header.target()).is_ok());
+    assert!(block.check_merkle_root());
+    assert!(block.check_witness_commitment());
+    assert!(shared_state.read().await.blocks_found >= 1);
+
+    // the block was written to the journal before it was submitted
+    let (_, found_blocks) = BlockJournal::load(found_blocks_file.clone()).unwrap();
+    let found_block = found_blocks
+        .iter()
+        .find(|found_block| found_block.block_hash == block.block_hash())
+        .expect("found block must be in the journal");
+    assert_eq!(found_block.height, 1);
+    assert_eq!(found_block.merkle_root, block.header.merkle_root);
+    assert_eq!(found_block.nonce, block.header.nonce);
+    assert_eq!(
+        found_block.reward,
+        block.txdata[0]
+            .output
+            .iter()
+            .map(|output| output.value.to_sat())
+            .sum::<u64>()
+    );
+    assert_eq!(found_block.user_identity, "username");
+    assert_eq!(found_block.verification_error, None);
+    assert!(shared_state.read().await.found_blocks.contains(found_block));
+
+    pleblottery_service.shutdown().await.unwrap();
+    std::fs::remove_file(found_blocks_file).unwrap();
+}
+
+/// Integration test to verify that pleblottery refuses to start with a Bitcoin node on another
+/// network than the configured one.
+#[tokio::test]
+async fn test_getblocktemplate_network_mismatch() {
+    let (bitcoind_address, _submitted_blocks) = start_mock_bitcoind().await;
+
+    let mut config = load_config();
+    config.mining_server_config.network = Network::Testnet4;
+    let bitcoin_rpc_config = PlebLotteryBitcoinRpcConfig {
+        url: format!("http://{}", bitcoind_address),
+        rpc_user: Some("pleb".to_string()),
+        rpc_password: Some("pleb".to_string()),
+        cookie_file: None,
+        getblocktemplate: true,
+        poll_interval: 1,
+    };
+
+    let result = PlebLotteryService::new_with_getblocktemplate(
+        config.mining_server_config.clone(),
+        bitcoin_rpc_config,
+        SharedStateHandle::default(),
+    )
+    .await;
+    let e = result.err().expect("the regtest node must be refused");
+    assert!(e.to_string().contains("not on testnet4"));
+}
@@ -1,4 +1,5 @@
-use pleblottery::config::PleblotteryConfig;
+use bitcoin::{Address, Network};
+use pleblottery::config::{PayoutRotation, PleblotteryConfig};
 
 fn config_path(name: &str) -> std::path::PathBuf {
     [env!("CARGO_MANIFEST_DIR"), "tests", "test_data", name]
@@ -29,3 +30,174 @@ fn test_good_config() {
 fn test_bad_address() {
     let _ = PleblotteryConfig::from_file(config_path("bad_address.toml")).unwrap();
 }
+
+#[test]
+#[should_panic(expected = "Invalid coinbase output address")]
+fn test_wrong_network() {
+    let _ = PleblotteryConfig::from_file(config_path("wrong_network.toml")).unwrap();
+}
+
+#[test]
+fn test_descriptor_config() {
+    let config = PleblotteryConfig::from_file(config_path("descriptor_config.toml"))
+        .expect("Should load descriptor config");
+    let descriptor = config
+        .mining_server_config
+        .coinbase_output_descriptor
+        .expect("descriptor must be set");
+    assert_eq!(
+        config.mining_server_config.payout_rotation,
+        PayoutRotation::PrevHash
+    );
+    assert_eq!(
+        config.mining_server_config.coinbase_output_script,
+        descriptor.script_pubkey(0).unwrap()
+    );
+    assert_eq!(
+        Address::from_script(&descriptor.script_pubkey(1).unwrap(), Network::Testnet4)
+            .unwrap()
+            .to_string(),
+        "tb1qfjzk0kjrwyecrkngvm2x783rz8utxxchcgfrwm"
+    );
+}
+
+#[test]
+fn test_op_return_config() {
+    let config = PleblotteryConfig::from_file(config_path("op_return_config.toml"))
+        .expect("Should load OP_RETURN config");
+    assert_eq!(
+        config
+            .mining_server_config
+            .coinbase_op_return
+            .as_ref()
+            .expect("OP_RETURN must be set")
+            .to_hex_string(),
+        "6a0f706c6562732062652068617368696e"
+    );
+    // P2WPKH output + OP_RETURN output
+    assert_eq!(
+        config.mining_server_config.coinbase_output_constraints(),
+        (31 + 26, 0)
+    );
+}
+
+#[test]
+#[should_panic(expected = "Invalid coinbase OP_RETURN")]
+fn test_bad_op_return() {
+    let _ = PleblotteryConfig::from_file(config_path("bad_op_return.toml")).unwrap();
+}
+
+#[test]
+fn test_extranonce_layout() {
+    let config = PleblotteryConfig::from_file(config_path("extranonce_layout.toml"))
+        .expect("Should load extranonce layout config");
+    assert_eq!(
+        config.mining_server_config.full_coinbase_tag(),
+        "pleblottery plebs hash hard"
+    );
+    // 32 bytes - 27 bytes of coinbase tag - 1 byte of worker tag - 2 bytes of counter
+    assert_eq!(config.mining_server_config.rollable_extranonce_size(), 2);
+}
+
+#[test]
+#[should_panic(expected = "must add up to at most 20 bytes")]
+fn test_bad_coinbase_tag() {
+    let _ = PleblotteryConfig::from_file(config_path("bad_coinbase_tag.toml")).unwrap();
+}
+
+#[test]
+fn test_getblocktemplate_config() {
+    let config = PleblotteryConfig::from_file(config_path("getblocktemplate_config.toml"))
+        .expect("Should load getblocktemplate config");
+    let bitcoin_rpc_config = config
+        .bitcoin_rpc_config
+        .expect("bitcoin_rpc_config must be set");
+    assert!(bitcoin_rpc_config.getblocktemplate);
+    assert_eq!(bitcoin_rpc_config.url, "http://127.0.0.1:48332");
+    assert_eq!(bitcoin_rpc_config.poll_interval, 5);
+    assert!(bitcoin_rpc_config.rpc_user.is_none());
+}
+
+#[test]
+fn test_sv1_config() {
+    let config = PleblotteryConfig::from_file(config_path("sv1_config.toml"))
+        .expect("Should load Stratum V1 config");
+    let sv1_config = config
+        .mining_server_config
+        .sv1
+        .expect("sv1 config must be set");
+    assert_eq!(sv1_config.listening_port, 3332);
+    assert_eq!(sv1_config.nominal_hashrate, 1e12);
+}
+
+#[test]
+#[should_panic(expected = "the Stratum V1 listener needs at least 2 bytes")]
+fn test_bad_sv1_config() {
+    let _ = PleblotteryConfig::from_file(config_path("bad_sv1_config.toml")).unwrap();
+}
+
+#[test]
+fn test_access_config() {
+    let config = PleblotteryConfig::from_file(config_path("access_config.toml"))
+        .expect("Should load access config");
+    let access = config
+        .mining_server_config
+        .access
+        .expect("access config must be set");
+    assert_eq!(access.allowed_identities.len(), 2);
+    assert_eq!(
+        access.tokens.get("bob.bitaxe").map(String::as_str),
+        Some("hunter2")
+    );
+}
+
+#[test]
+#[should_panic(expected = "the access token of bob:bitaxe must be non-empty")]
+fn test_bad_access_config() {
+    let _ = PleblotteryConfig::from_file(config_path("bad_access_config.toml")).unwrap();
+}
+
+#[test]
+fn test_ban_config() {
+    let config = PleblotteryConfig::from_file(config_path("ban_config.toml"))
+        .expect("Should load ban config");
+    let ban = config
+        .mining_server_config
+        .ban
+        .expect("ban config must be set");
+    assert_eq!(ban.client_ban_score, 50.0);
+    assert_eq!(ban.ip_ban_duration, 86400);
+    // unset values keep their defaults
+    assert_eq!(ban.client_ban_duration, 600);
+    assert_eq!(ban.scores.stale_share, 0.0);
+    assert_eq!(ban.scores.duplicate_share, 10.0);
+}
+
+#[test]
+fn test_limits_config() {
+    let config = PleblotteryConfig::from_file(config_path("limits_config.toml"))
+        .expect("Should load limits config");
+    let limits = config.mining_server_config.limits;
+    assert_eq!(limits.max_clients, Some(100));
+    assert_eq!(limits.max_channels_per_client, None);
+    assert_eq!(limits.max_connections_per_ip, Some(4));
+}
+
+#[test]
+#[should_panic(expected = "connection and channel limits must be at least 1")]
+fn test_bad_limits_config() {
+    let _ = PleblotteryConfig::from_file(config_path("bad_limits_config.toml")).unwrap();
+}
+
+#[test]
+fn test_share_rate_limit_config() {
+    let config = PleblotteryConfig::from_file(config_path("share_rate_limit_config.toml"))
+        .expect("Should load share rate limit config");
+    let share_rate_limit = config
+        .mining_server_config
+        .share_rate_limit
+        .expect("share rate limit config must be set");
+    assert_eq!(share_rate_limit.rate_factor, 4.0);
+    // unset values keep their defaults
+    assert_eq!(share_rate_limit.burst_seconds, 30.0);
+}
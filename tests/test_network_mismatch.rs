@@ -0,0 +1,91 @@
+use bitcoin::Network;
+use integration_tests_sv2::{interceptor, start_sniffer, start_template_provider};
+use pleblottery::web::server::start_web_server;
+use pleblottery::{service::PlebLotteryService, state::SharedStateHandle};
+use reqwest::Client;
+use sv2_services::roles_logic_sv2::template_distribution_sv2::MESSAGE_TYPE_SET_NEW_PREV_HASH;
+
+mod common;
+use common::load_config;
+
+/// Integration test to verify that pleblottery refuses to mine when the Template Provider
+/// is serving a different network than the configured one, and that the mismatch is shown
+/// on the dashboard.
+#[tokio::test]
+async fn test_network_mismatch() {
+    // the Template Provider serves regtest
+    let (_tp, tp_address) = start_template_provider(None);
+    let (tp_sniffer, tp_sniffer_addr) = start_sniffer("", tp_address, false, vec![]);
+    let mut config = load_config();
+    config.template_distribution_config.server_addr = tp_sniffer_addr;
+    config.mining_server_config.network = Network::Bitcoin;
+
+    // Give sniffer some time to initialize before starting the service
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let shared_state: SharedStateHandle = SharedStateHandle::default();
+
+    let mut pleblottery_service = PlebLotteryService::new(
+        config.mining_server_config.clone(),
+        config.template_distribution_config.clone(),
+        shared_state.clone(),
+    )
+    .await
+    .expect("Failed to create PlebLotteryService");
+
+    let mut pleblottery_service_clone = pleblottery_service.clone();
+    tokio::spawn(async move {
+        let _ = pleblottery_service_clone.start().await;
+    });
+
+    let web_config = config.web_config.clone();
+    let web_shared_state = shared_state.clone();
+    tokio::spawn(async move {
+        start_web_server(&web_config, web_shared_state)
+            .await
+            .unwrap();
+    });
+
+    tp_sniffer
+        .wait_for_message_type(
+            interceptor::MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SET_NEW_PREV_HASH,
+        )
+        .await;
+
+    // wait for the SetNewPrevHash to be processed
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    {
+        let state = shared_state.read().await;
+        assert!(
+            state.network_mismatch.is_some(),
+            "Network mismatch should have been detected"
+        );
+        assert!(
+            state.latest_prev_hash.is_none(),
+            "SetNewPrevHash from the wrong network should not be used"
+        );
+    }
+
+    let client = Client::new();
+    let resp_text = client
+        .get(format!(
+            "http://localhost:{}/api/network",
+            config.web_config.listening_port
+        ))
+        .send()
+        .await
+        .expect("Failed to query web server")
+        .text()
+        .await
+        .expect("Failed to read response text");
+
+    assert!(
+        resp_text.contains("mainnet") && resp_text.contains("not serving"),
+        "Response does not show the network mismatch: {}",
+        resp_text
+    );
+
+    pleblottery_service.shutdown().await.unwrap();
+}
@@ -0,0 +1,109 @@
+use bitcoin::{Address, Network};
+use integration_tests_sv2::{interceptor, start_sniffer, start_template_provider};
+use pleblottery::config::PayoutRotation;
+use pleblottery::descriptor::PayoutDescriptor;
+use pleblottery::web::server::start_web_server;
+use pleblottery::{service::PlebLotteryService, state::SharedStateHandle};
+use reqwest::Client;
+use sv2_services::roles_logic_sv2::template_distribution_sv2::MESSAGE_TYPE_SET_NEW_PREV_HASH;
+
+mod common;
+use common::load_config;
+
+const DESCRIPTOR: &str = "wpkh(tpubDDPRy5xWxJTuVmsh7YRzK8o2EdMWgn4t41fTLxXRgyRN7EKvN2L8BKCFC1gUfPu8Xp6rr667Yc26zrXsiBZsgBc8dQiYnhPNk2Q7CsBrer5/0/*)";
+
+/// Integration test to verify that pleblottery pays to an address derived from the configured
+/// descriptor, persists the next derivation index on a new prev hash, and shows the active
+/// address through the `/api/payout` endpoint.
+#[tokio::test]
+async fn test_payout_rotation() {
+    let (_tp, tp_address) = start_template_provider(None);
+    let (tp_sniffer, tp_sniffer_addr) = start_sniffer("", tp_address, false, vec![]);
+    let descriptor = PayoutDescriptor::parse(DESCRIPTOR, Network::Regtest).unwrap();
+    let index_file = std::env::temp_dir().join(format!(
+        "pleblottery-test-payout-index-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&index_file);
+
+    let mut config = load_config();
+    config.template_distribution_config.server_addr = tp_sniffer_addr;
+    config.mining_server_config.coinbase_output_script = descriptor.script_pubkey(0).unwrap();
+    config.mining_server_config.coinbase_output_descriptor = Some(descriptor.clone());
+    config.mining_server_config.payout_rotation = PayoutRotation::PrevHash;
+    config.mining_server_config.payout_index_file = index_file.clone();
+
+    // Give sniffer some time to initialize before starting the service
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let shared_state: SharedStateHandle = SharedStateHandle::default();
+
+    let mut pleblottery_service = PlebLotteryService::new(
+        config.mining_server_config.clone(),
+        config.template_distribution_config.clone(),
+        shared_state.clone(),
+    )
+    .await
+    .expect("Failed to create PlebLotteryService");
+
+    let mut pleblottery_service_clone = pleblottery_service.clone();
+    tokio::spawn(async move {
+        let _ = pleblottery_service_clone.start().await;
+    });
+
+    let web_config = config.web_config.clone();
+    let web_shared_state = shared_state.clone();
+    tokio::spawn(async move {
+        start_web_server(&web_config, web_shared_state)
+            .await
+            .unwrap();
+    });
+
+    tp_sniffer
+        .wait_for_message_type(
+            interceptor::MessageDirection::ToDownstream,
+            MESSAGE_TYPE_SET_NEW_PREV_HASH,
+        )
+        .await;
+
+    // wait for the SetNewPrevHash to be processed
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    let address = Address::from_script(&descriptor.script_pubkey(0).unwrap(), Network::Regtest)
+        .unwrap()
+        .to_string();
+    {
+        let state = shared_state.read().await;
+        assert_eq!(state.payout_address.as_deref(), Some(address.as_str()));
+        assert_eq!(state.payout_derivation_index, Some(0));
+    }
+
+    // the address for the next block is reserved as soon as the prev hash changes
+    assert_eq!(
+        std::fs::read_to_string(&index_file).unwrap().trim(),
+        "1",
+        "Next derivation index should have been persisted"
+    );
+
+    let client = Client::new();
+    let resp_text = client
+        .get(format!(
+            "http://localhost:{}/api/payout",
+            config.web_config.listening_port
+        ))
+        .send()
+        .await
+        .expect("Failed to query web server")
+        .text()
+        .await
+        .expect("Failed to read response text");
+
+    assert!(
+        resp_text.contains(&address),
+        "Response does not contain the payout address: {}",
+        resp_text
+    );
+
+    pleblottery_service.shutdown().await.unwrap();
+    let _ = std::fs::remove_file(index_file);
+}
@@ -0,0 +1,123 @@
+use integration_tests_sv2::start_template_provider;
+use pleblottery::block_submission::{SubmissionPath, SubmissionResult};
+use pleblottery::block_tracker::FoundBlockStatus;
+use pleblottery::config::PlebLotteryBitcoinRpcConfig;
+use pleblottery::state::TemplateProviderStatus;
+use pleblottery::{service::PlebLotteryService, state::SharedStateHandle};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+mod common;
+use common::{load_config, load_miner_config, start_mock_bitcoind, wait_for_submitted_block};
+
+/// Integration test to verify that blocks found on Template Provider templates are also rebuilt
+/// and submitted via `submitblock`, that both submissions are recorded, and that found blocks are
+/// tracked into the chain.
+#[tokio::test]
+async fn test_redundant_block_submission() {
+    let (_tp, tp_address) = start_template_provider(None);
+    let (bitcoind_address, submitted_blocks) = start_mock_bitcoind().await;
+
+    let mut config = load_config();
+    config.template_distribution_config.server_addr = tp_address;
+    // Set a high expected shares per minute to ensure we can submit shares quickly
+    config.mining_server_config.expected_shares_per_minute = 100.0;
+    let bitcoin_rpc_config = PlebLotteryBitcoinRpcConfig {
+        url: format!("http://{}", bitcoind_address),
+        rpc_user: Some("pleb".to_string()),
+        rpc_password: Some("pleb".to_string()),
+        cookie_file: None,
+        getblocktemplate: false,
+        poll_interval: 5,
+    };
+
+    let shared_state: SharedStateHandle = SharedStateHandle::default();
+
+    let mut pleblottery_service = PlebLotteryService::new_with_submitblock(
+        config.mining_server_config.clone(),
+        config.template_distribution_config.clone(),
+        Some(bitcoin_rpc_config),
+        shared_state.clone(),
+    )
+    .await
+    .unwrap();
+
+    let mut pleblottery_service_clone = pleblottery_service.clone();
+    tokio::spawn(async move {
+        pleblottery_service_clone.start().await.unwrap();
+    });
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while shared_state.read().await.template_provider_status
+            != TemplateProviderStatus::Connected
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("pleblottery never got a template");
+
+    let mut miner_config = load_miner_config();
+    miner_config.server_addr =
+        SocketAddr::from(([127, 0, 0, 1], config.mining_server_config.listening_port));
+    miner_config.n_standard_channels = 0;
+    tokio::spawn(async move {
+        sv2_cpu_miner::client::Sv2CpuMiner::new(miner_config)
+            .await
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+    });
+
+    let block = wait_for_submitted_block(&submitted_blocks).await;
+    assert!(block.check_merkle_root());
+    assert!(block.header.validate_pow(block.header.target()).is_ok());
+
+    // the submission is recorded once the Bitcoin node has answered
+    let block_hash = Some(block.block_hash());
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !shared_state
+            .read()
+            .await
+            .block_submissions
+            .iter()
+            .any(|submission| {
+                submission.path == SubmissionPath::BitcoinRpc
+                    && submission.result == SubmissionResult::Accepted
+                    && submission.block_hash == block_hash
+            })
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("submitblock result was never recorded");
+
+    assert!(shared_state
+        .read()
+        .await
+        .block_submissions
+        .iter()
+        .any(|submission| {
+            submission.path == SubmissionPath::TemplateProvider
+                && submission.result == SubmissionResult::Queued
+        }));
+
+    // the Template Provider moves on to the found block
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while !shared_state
+            .read()
+            .await
+            .found_block_statuses
+            .values()
+            .any(|status| matches!(status, FoundBlockStatus::Accepted { .. }))
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("found block was never accepted");
+
+    pleblottery_service.shutdown().await.unwrap();
+}
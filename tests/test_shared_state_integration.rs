@@ -115,7 +115,7 @@ async fn test_shared_state_between_service_and_web() {
     );
 
     assert!(
-        resp_text.contains(&format!("{:02x}", set_new_prev_hash.n_bits).to_string()),
+        resp_text.contains(&format!("{:02x}", set_new_prev_hash.n_bits)),
         "Response does not contain expected n_bits."
     );
 
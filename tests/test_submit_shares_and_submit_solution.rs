@@ -1,3 +1,4 @@
+use std::time::Instant;
 use std::vec;
 
 use integration_tests_sv2::*;
@@ -33,7 +34,7 @@ async fn test_submit_share_and_submit_solution() {
     let mut pleblottery_service = PlebLotteryService::new(
         config.mining_server_config.clone(),
         config.template_distribution_config.clone(),
-        shared_state,
+        shared_state.clone(),
     )
     .await
     .unwrap();
@@ -100,6 +101,11 @@ async fn test_submit_share_and_submit_solution() {
             MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
         )
         .await;
+
+    // accepted shares are accounted in the measured hashrate
+    let measured_hashrate = shared_state.read().await.measured_hashrate.clone();
+    assert!(measured_hashrate.total.hashrates(Instant::now())[0] > 0.0);
+    assert!(!measured_hashrate.channels.is_empty());
     pleblottery_service.shutdown().await.unwrap();
 }
 
@@ -0,0 +1,420 @@
+use bitcoin::block::{Header, Version};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::{BlockHash, CompactTarget, Network, TxMerkleNode};
+use pleblottery::ban::BanTarget;
+use pleblottery::block_journal::BlockJournal;
+use pleblottery::config::{
+    BanConfig, LimitsConfig, PlebLotteryBitcoinRpcConfig, PleblotteryConfig, ShareRateLimitConfig,
+    Sv1Config,
+};
+use pleblottery::state::TemplateProviderStatus;
+use pleblottery::sv1_server::SV1_CLIENT_ID_START;
+use pleblottery::{service::PlebLotteryService, state::SharedStateHandle};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+mod common;
+use common::{
+    get_available_port, load_config, start_mock_bitcoind, wait_for_submitted_block, SubmittedBlocks,
+};
+
+async fn send(writer: &mut OwnedWriteHalf, request: Value) {
+    writer
+        .write_all(format!("{}\n", request).as_bytes())
+        .await
+        .unwrap();
+}
+
+async fn read_message(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Value {
+    let line = tokio::time::timeout(Duration::from_secs(30), lines.next_line())
+        .await
+        .expect("pleblottery sent nothing")
+        .unwrap()
+        .expect("pleblottery closed the connection");
+    serde_json::from_str(&line).unwrap()
+}
+
+/// Reads messages until the response to request `id`, returning it along with the last
+/// `mining.notify` read on the way.
+async fn read_response(
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    id: u64,
+    notify: &mut Option<Value>,
+) -> Value {
+    loop {
+        let message = read_message(lines).await;
+        if message["id"] == json!(id) {
+            return message;
+        }
+        if message["method"] == "mining.notify" {
+            *notify = Some(message["params"].clone());
+        }
+    }
+}
+
+fn double_sha256(data: &[u8]) -> [u8; 32] {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(data);
+    sha256d::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Mines the job of a `mining.notify` the way Stratum V1 firmware does, returning the submit
+/// params of a share meeting the network target.
+fn mine(notify: &Value, extranonce1: &str, extranonce2_size: usize) -> Vec<Value> {
+    let param = |index: usize| notify[index].as_str().unwrap().to_string();
+    let hex_u32 = |index: usize| u32::from_str_radix(&param(index), 16).unwrap();
+
+    let extranonce2 = vec![0x42; extranonce2_size].to_lower_hex_string();
+    let coinbase = Vec::<u8>::from_hex(&format!(
+        "{}{}{}{}",
+        param(2),
+        extranonce1,
+        extranonce2,
+        param(3)
+    ))
+    .unwrap();
+    let mut merkle_root = double_sha256(&coinbase);
+    for branch in notify[4].as_array().unwrap() {
+        let mut node = merkle_root.to_vec();
+        node.extend(Vec::<u8>::from_hex(branch.as_str().unwrap()).unwrap());
+        merkle_root = double_sha256(&node);
+    }
+    // every 4 byte word of the prev hash is reversed
+    let prev_hash: Vec<u8> = Vec::<u8>::from_hex(&param(1))
+        .unwrap()
+        .chunks(4)
+        .flat_map(|word| word.iter().rev().copied().collect::<Vec<u8>>())
+        .collect();
+
+    let mut header = Header {
+        version: Version::from_consensus(hex_u32(5) as i32),
+        prev_blockhash: BlockHash::from_byte_array(prev_hash.try_into().unwrap()),
+        merkle_root: TxMerkleNode::from_byte_array(merkle_root),
+        time: hex_u32(7),
+        bits: CompactTarget::from_consensus(hex_u32(6)),
+        nonce: 0,
+    };
+    while header.validate_pow(header.target()).is_err() {
+        header.nonce += 1;
+    }
+    vec![
+        json!("username"),
+        notify[0].clone(),
+        json!(extranonce2),
+        json!(format!("{:08x}", header.time)),
+        json!(format!("{:08x}", header.nonce)),
+    ]
+}
+
+/// Starts pleblottery with `config` on templates from a mock `getblocktemplate`, and waits for
+/// its first template.
+async fn start_pleblottery(
+    config: &PleblotteryConfig,
+) -> (PlebLotteryService, SharedStateHandle, SubmittedBlocks) {
+    let (bitcoind_address, submitted_blocks) = start_mock_bitcoind().await;
+    let bitcoin_rpc_config = PlebLotteryBitcoinRpcConfig {
+        url: format!("http://{}", bitcoind_address),
+        rpc_user: Some("pleb".to_string()),
+        rpc_password: Some("pleb".to_string()),
+        cookie_file: None,
+        getblocktemplate: true,
+        poll_interval: 1,
+    };
+
+    let shared_state: SharedStateHandle = SharedStateHandle::default();
+
+    let pleblottery_service = PlebLotteryService::new_with_getblocktemplate(
+        config.mining_server_config.clone(),
+        bitcoin_rpc_config,
+        shared_state.clone(),
+    )
+    .await
+    .unwrap();
+
+    let mut pleblottery_service_clone = pleblottery_service.clone();
+    tokio::spawn(async move {
+        pleblottery_service_clone.start().await.unwrap();
+    });
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while shared_state.read().await.template_provider_status
+            != TemplateProviderStatus::Connected
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("pleblottery never got a block template");
+
+    (pleblottery_service, shared_state, submitted_blocks)
+}
+
+/// Loads the test config with a Stratum V1 listener on a port of its own.
+fn load_sv1_config() -> (PleblotteryConfig, u16) {
+    let mut config = load_config();
+    config.mining_server_config.expected_shares_per_minute = 100.0;
+    let sv1_port = get_available_port();
+    config.mining_server_config.sv1 = Some(Sv1Config {
+        listening_port: sv1_port,
+        nominal_hashrate: 1_000_000.0,
+    });
+    (config, sv1_port)
+}
+
+/// Integration test to verify that a Stratum V1 miner gets jobs through the Stratum V1 listener,
+/// and that its blocks are submitted like the ones of Stratum V2 miners.
+#[tokio::test]
+async fn test_sv1_miner() {
+    let (config, sv1_port) = load_sv1_config();
+    let found_blocks_file = config.mining_server_config.found_blocks_file.clone();
+    let (mut pleblottery_service, shared_state, submitted_blocks) =
+        start_pleblottery(&config).await;
+
+    let stream = TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], sv1_port)))
+        .await
+        .unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut notify = None;
+
+    send(
+        &mut writer,
+        json!({ "id": 1, "method": "mining.subscribe", "params": ["test-miner/1.0"] }),
+    )
+    .await;
+    let subscribe = read_response(&mut lines, 1, &mut notify).await;
+    let extranonce1 = subscribe["result"][1].as_str().unwrap().to_string();
+    let extranonce2_size = subscribe["result"][2].as_u64().unwrap() as usize;
+    assert_eq!(
+        extranonce2_size,
+        config.mining_server_config.rollable_extranonce_size()
+    );
+
+    send(
+        &mut writer,
+        json!({ "id": 2, "method": "mining.authorize", "params": ["username", "x"] }),
+    )
+    .await;
+    let authorize = read_response(&mut lines, 2, &mut notify).await;
+    assert_eq!(authorize["result"], json!(true));
+    while notify.is_none() {
+        let message = read_message(&mut lines).await;
+        if message["method"] == "mining.notify" {
+            notify = Some(message["params"].clone());
+        }
+    }
+
+    let params = mine(notify.as_ref().unwrap(), &extranonce1, extranonce2_size);
+    send(
+        &mut writer,
+        json!({ "id": 3, "method": "mining.submit", "params": params }),
+    )
+    .await;
+    let submit = read_response(&mut lines, 3, &mut notify).await;
+    assert_eq!(submit["result"], json!(true), "share rejected: {}", submit);
+
+    let block = wait_for_submitted_block(&submitted_blocks).await;
+    assert_eq!(
+        block.header.prev_blockhash,
+        genesis_block(Network::Regtest).block_hash()
+    );
+    assert!(block.header.validate_pow(block.header.target()).is_ok());
+    assert!(block.check_merkle_root());
+    assert!(block.check_witness_commitment());
+
+    // the connection is a client of the mining server, with an extended channel
+    let clients = shared_state.read().await.clients.clone();
+    let client = clients
+        .read()
+        .await
+        .get(&SV1_CLIENT_ID_START)
+        .cloned()
+        .expect("Stratum V1 client must be added");
+    assert_eq!(client.read().await.extended_channels.read().await.len(), 1);
+
+    let (_, found_blocks) = BlockJournal::load(found_blocks_file.clone()).unwrap();
+    let found_block = found_blocks
+        .iter()
+        .find(|found_block| found_block.block_hash == block.block_hash())
+        .expect("found block must be in the journal");
+    assert_eq!(found_block.user_identity, "username");
+
+    pleblottery_service.shutdown().await.unwrap();
+    std::fs::remove_file(found_blocks_file).unwrap();
+}
+
+/// Integration test to verify that an address sending garbage to the Stratum V1 listener gets
+/// banned, and its next connections refused.
+#[tokio::test]
+async fn test_sv1_ban() {
+    let (mut config, sv1_port) = load_sv1_config();
+    // every protocol error scores 25
+    config.mining_server_config.ban = Some(BanConfig {
+        client_ban_score: 50.0,
+        ip_ban_score: 50.0,
+        ..BanConfig::default()
+    });
+    let found_blocks_file = config.mining_server_config.found_blocks_file.clone();
+    let (mut pleblottery_service, shared_state, _) = start_pleblottery(&config).await;
+    let sv1_address = SocketAddr::from(([127, 0, 0, 1], sv1_port));
+
+    // both connections are dropped on their invalid request, the second one getting the address
+    // banned
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(sv1_address).await.unwrap();
+        let _ = stream.write_all(b"plebs be spammin\n").await;
+        let mut lines = BufReader::new(stream).lines();
+        let closed = tokio::time::timeout(Duration::from_secs(30), lines.next_line())
+            .await
+            .expect("pleblottery must close the connection");
+        assert!(!matches!(closed, Ok(Some(_))));
+    }
+
+    let bans = shared_state.read().await.bans.clone();
+    assert!(bans
+        .iter()
+        .any(|ban| ban.target == BanTarget::Ip("127.0.0.1".parse().unwrap())));
+
+    pleblottery_service.shutdown().await.unwrap();
+    let _ = std::fs::remove_file(found_blocks_file);
+}
+
+/// Integration test to verify that Stratum V1 connections over the limit of their address are
+/// refused.
+#[tokio::test]
+async fn test_sv1_connection_limit() {
+    let (mut config, sv1_port) = load_sv1_config();
+    config.mining_server_config.limits = LimitsConfig {
+        max_connections_per_ip: Some(1),
+        ..LimitsConfig::default()
+    };
+    let found_blocks_file = config.mining_server_config.found_blocks_file.clone();
+    let (mut pleblottery_service, _, _) = start_pleblottery(&config).await;
+    let sv1_address = SocketAddr::from(([127, 0, 0, 1], sv1_port));
+
+    let (reader, mut writer) = TcpStream::connect(sv1_address).await.unwrap().into_split();
+    let mut lines = BufReader::new(reader).lines();
+    send(
+        &mut writer,
+        json!({ "id": 1, "method": "mining.subscribe", "params": [] }),
+    )
+    .await;
+    let subscribe = read_response(&mut lines, 1, &mut None).await;
+    assert!(subscribe["error"].is_null());
+
+    let second = TcpStream::connect(sv1_address).await.unwrap();
+    let mut second_lines = BufReader::new(second).lines();
+    let closed = tokio::time::timeout(Duration::from_secs(30), second_lines.next_line())
+        .await
+        .expect("pleblottery must close the connection");
+    assert!(!matches!(closed, Ok(Some(_))));
+
+    pleblottery_service.shutdown().await.unwrap();
+    let _ = std::fs::remove_file(found_blocks_file);
+}
+
+/// Integration test to verify that Stratum V1 connections over the client limit are refused,
+/// before the first client opens any channel.
+#[tokio::test]
+async fn test_sv1_client_limit() {
+    let (mut config, sv1_port) = load_sv1_config();
+    config.mining_server_config.limits = LimitsConfig {
+        max_clients: Some(1),
+        ..LimitsConfig::default()
+    };
+    let found_blocks_file = config.mining_server_config.found_blocks_file.clone();
+    let (mut pleblottery_service, _, _) = start_pleblottery(&config).await;
+    let sv1_address = SocketAddr::from(([127, 0, 0, 1], sv1_port));
+
+    let _first = TcpStream::connect(sv1_address).await.unwrap();
+    let second = TcpStream::connect(sv1_address).await.unwrap();
+    let mut second_lines = BufReader::new(second).lines();
+    let closed = tokio::time::timeout(Duration::from_secs(30), second_lines.next_line())
+        .await
+        .expect("pleblottery must close the connection");
+    assert!(!matches!(closed, Ok(Some(_))));
+
+    pleblottery_service.shutdown().await.unwrap();
+    let _ = std::fs::remove_file(found_blocks_file);
+}
+
+/// Integration test to verify that shares over the share rate limit of a Stratum V1 miner are
+/// rejected before they are validated.
+#[tokio::test]
+async fn test_sv1_share_rate_limit() {
+    let (mut config, sv1_port) = load_sv1_config();
+    // 100 shares a minute, with a burst of a single share
+    config.mining_server_config.share_rate_limit = Some(ShareRateLimitConfig {
+        rate_factor: 1.0,
+        burst_seconds: 1.0,
+    });
+    let found_blocks_file = config.mining_server_config.found_blocks_file.clone();
+    let (mut pleblottery_service, shared_state, _) = start_pleblottery(&config).await;
+
+    let stream = TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], sv1_port)))
+        .await
+        .unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut notify = None;
+
+    send(
+        &mut writer,
+        json!({ "id": 1, "method": "mining.subscribe", "params": [] }),
+    )
+    .await;
+    let subscribe = read_response(&mut lines, 1, &mut notify).await;
+    let extranonce1 = subscribe["result"][1].as_str().unwrap().to_string();
+    let extranonce2_size = subscribe["result"][2].as_u64().unwrap() as usize;
+    send(
+        &mut writer,
+        json!({ "id": 2, "method": "mining.authorize", "params": ["username", "x"] }),
+    )
+    .await;
+    read_response(&mut lines, 2, &mut notify).await;
+    while notify.is_none() {
+        let message = read_message(&mut lines).await;
+        if message["method"] == "mining.notify" {
+            notify = Some(message["params"].clone());
+        }
+    }
+
+    // the same share over and over, faster than the limit
+    let params = mine(notify.as_ref().unwrap(), &extranonce1, extranonce2_size);
+    let mut rate_limited = 0;
+    for id in 3..8 {
+        send(
+            &mut writer,
+            json!({ "id": id, "method": "mining.submit", "params": params }),
+        )
+        .await;
+        let submit = read_response(&mut lines, id, &mut notify).await;
+        if submit["error"][1] == "share-rate-limited" {
+            rate_limited += 1;
+        }
+    }
+    assert!(rate_limited > 0);
+
+    let share_rate_limiter = shared_state
+        .read()
+        .await
+        .share_rate_limiter
+        .clone()
+        .expect("share rate limiter must be enabled");
+    assert_eq!(
+        share_rate_limiter
+            .read()
+            .await
+            .client_rate_limited_shares(SV1_CLIENT_ID_START),
+        rate_limited
+    );
+
+    pleblottery_service.shutdown().await.unwrap();
+    let _ = std::fs::remove_file(found_blocks_file);
+}
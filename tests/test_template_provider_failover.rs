@@ -0,0 +1,62 @@
+use integration_tests_sv2::start_template_provider;
+use pleblottery::state::TemplateProviderStatus;
+use pleblottery::{service::PlebLotteryService, state::SharedStateHandle};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+mod common;
+use common::load_config;
+
+async fn wait_for_active_provider(shared_state: &SharedStateHandle, provider: SocketAddr) {
+    tokio::time::timeout(Duration::from_secs(30), async {
+        loop {
+            {
+                let state = shared_state.read().await;
+                if state.active_template_provider == Some(provider)
+                    && state.template_provider_status == TemplateProviderStatus::Connected
+                {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("Template Provider {} never became active", provider));
+}
+
+/// Integration test to verify that pleblottery fails over to the next Template Provider when
+/// the active one goes away.
+#[tokio::test]
+async fn test_template_provider_failover() {
+    let (primary_tp, primary_address) = start_template_provider(None);
+    let (_backup_tp, backup_address) = start_template_provider(None);
+
+    let mut config = load_config();
+    config.template_distribution_config.server_addr = primary_address;
+    config.template_distribution_config.fallback_server_addrs = vec![backup_address];
+
+    let shared_state: SharedStateHandle = SharedStateHandle::default();
+
+    let mut pleblottery_service = PlebLotteryService::new(
+        config.mining_server_config.clone(),
+        config.template_distribution_config.clone(),
+        shared_state.clone(),
+    )
+    .await
+    .unwrap();
+
+    let mut pleblottery_service_clone = pleblottery_service.clone();
+    tokio::spawn(async move {
+        let _ = pleblottery_service_clone.start().await;
+    });
+
+    wait_for_active_provider(&shared_state, primary_address).await;
+
+    // the primary Template Provider goes away
+    drop(primary_tp);
+
+    wait_for_active_provider(&shared_state, backup_address).await;
+
+    pleblottery_service.shutdown().await.unwrap();
+}
@@ -0,0 +1,62 @@
+use integration_tests_sv2::start_template_provider;
+use pleblottery::state::TemplateProviderStatus;
+use pleblottery::{service::PlebLotteryService, state::SharedStateHandle};
+use std::time::Duration;
+
+mod common;
+use common::load_config;
+
+async fn wait_for_status(shared_state: &SharedStateHandle, status: TemplateProviderStatus) {
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while shared_state.read().await.template_provider_status != status {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("Template Provider status never became {:?}", status));
+}
+
+/// Integration test to verify that losing the Template Provider puts pleblottery in reconnecting
+/// mode instead of stopping it, while the mining server keeps accepting connections.
+#[tokio::test]
+async fn test_template_provider_reconnection() {
+    let (tp, tp_address) = start_template_provider(None);
+
+    let mut config = load_config();
+    config.template_distribution_config.server_addr = tp_address;
+
+    let shared_state: SharedStateHandle = SharedStateHandle::default();
+
+    let mut pleblottery_service = PlebLotteryService::new(
+        config.mining_server_config.clone(),
+        config.template_distribution_config.clone(),
+        shared_state.clone(),
+    )
+    .await
+    .unwrap();
+
+    let mut pleblottery_service_clone = pleblottery_service.clone();
+    let service_handle = tokio::spawn(async move { pleblottery_service_clone.start().await });
+
+    wait_for_status(&shared_state, TemplateProviderStatus::Connected).await;
+
+    // the Template Provider goes away
+    drop(tp);
+
+    wait_for_status(&shared_state, TemplateProviderStatus::Reconnecting).await;
+    {
+        let state = shared_state.read().await;
+        assert!(state.template_provider_last_error.is_some());
+        assert!(state.template_provider_reconnect_attempts >= 1);
+    }
+
+    assert!(
+        !service_handle.is_finished(),
+        "PlebLotteryService should keep running without the Template Provider"
+    );
+    tokio::net::TcpStream::connect(("127.0.0.1", config.mining_server_config.listening_port))
+        .await
+        .expect("Mining server should keep accepting connections");
+
+    pleblottery_service.shutdown().await.unwrap();
+}